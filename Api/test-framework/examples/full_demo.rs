@@ -0,0 +1,215 @@
+/*
+ * full_demo - The Framework End to End Without Hardware
+ * Copyright (C) 2024
+ */
+
+//! Runs suites against fakes and simulated interfaces and writes every
+//! report format into a temporary directory:
+//!
+//! ```text
+//! cargo run --example full_demo
+//! ```
+//!
+//! The run asserts that its outputs exist and its counts add up, so it
+//! fails loudly when features stop composing.
+
+use hardware_test_framework::{
+    builtin_suite, hw_assert, hw_assert_eq, suite, AdviceRegistry, ArtifactCollector, Budget, BuiltinTarget,
+    ChannelObserver, ConsoleReporter, CriteriaTest, CriteriaValues, FakeLoopback, FakeRegisterMap,
+    HardwareError, HardwareInterface, ManualStep, Quarantine, Readable, RegisterAccess, RunnerEvent,
+    SuiteManifest, TestFn, TestRunner, TestStatus, TestSuiteResult, Writable,
+};
+use std::path::Path;
+use std::time::Duration;
+
+/// Value of the fake sensor's WHO_AM_I register
+const CHIP_ID: u8 = 0x42;
+
+const MANIFEST: &str = r#"
+suites = ["lifecycle", "conformance"]
+
+[[target]]
+name = "eeprom"
+interface = "i2c"
+bus = 1
+address = 0x50
+
+[[target]]
+name = "flash"
+interface = "spi"
+
+[[target]]
+name = "gps"
+interface = "uart"
+baud = 9600
+
+[[criteria]]
+name = "clean_bus"
+expression = "error_count == 0"
+
+[budget]
+max_wall_time_secs = 30
+
+[reports]
+directory = "manifest"
+"#;
+
+suite!(loopback_smoke(uart: FakeLoopback) {
+    "echo" [tags: smoke] => {
+        uart.initialize().await?;
+        uart.write_all(b"PING").await?;
+        let mut echo = [0u8; 4];
+        uart.read_exact(&mut echo, Duration::from_millis(10)).await?;
+        hw_assert_eq!(&echo, b"PING");
+        Ok(())
+    },
+    "drained" [tags: smoke] => {
+        hw_assert!(uart.pending() == 0, "{} bytes left unread", uart.pending());
+        Ok(())
+    },
+    "framing errors" [tags: flaky] => {
+        Err(HardwareError::CommunicationError("framing error on byte 3".to_string()))
+    },
+});
+
+/// Tests of built-in suite `name` for `T`
+fn builtin<T: BuiltinTarget>(name: &str) -> Vec<(String, TestFn<T>)> {
+    builtin_suite::<T>(name).expect("built-in suite")
+}
+
+fn chip_id_criteria() -> (String, TestFn<FakeRegisterMap>) {
+    CriteriaTest::new("chip_id == 66 and status < 16", &["chip_id", "status"], |interface| {
+        Box::pin(async move {
+            let mut registers = [0u8; 2];
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            interface.read_registers(0x00, &mut registers).await?;
+            let values: CriteriaValues = [
+                ("chip_id".to_string(), registers[0] as f64),
+                ("status".to_string(), registers[1] as f64),
+            ]
+            .into_iter()
+            .collect();
+            Ok(values)
+        })
+    })
+    .expect("valid criteria")
+    .case("criteria::chip_id")
+}
+
+/// Write the JSON, JUnit and Markdown reports of `result` into `dir`
+fn write_reports(dir: &Path, result: &TestSuiteResult) {
+    result.write_json(&dir.join(format!("{}.json", result.name))).unwrap();
+    result.write_junit_xml(&dir.join(format!("{}.xml", result.name))).unwrap();
+    result
+        .write_markdown(&dir.join(format!("{}.md", result.name)), &AdviceRegistry::new())
+        .unwrap();
+}
+
+/// Every test is counted exactly once
+fn assert_counts(result: &TestSuiteResult) {
+    assert_eq!(
+        result.total_tests,
+        result.passed_tests
+            + result.failed_tests
+            + result.skipped_tests
+            + result.error_tests
+            + result.quarantined_failures,
+        "counts of {} do not add up",
+        result.name
+    );
+    assert_eq!(result.total_tests, result.results.len());
+}
+
+#[tokio::main]
+async fn main() {
+    let out = tempfile::tempdir().expect("temporary directory");
+    let reports = out.path().join("reports");
+    std::fs::create_dir_all(&reports).unwrap();
+
+    // Declarative suite on a loopback UART; the known-flaky case is
+    // quarantined and leaves an artifact bundle behind
+    let (observer, mut events) = ChannelObserver::new();
+    let mut collector = ArtifactCollector::new(out.path().join("artifacts")).with_seed(7);
+    collector.register("line_settings", || Ok(b"9600 8N1".to_vec()));
+    let uart = TestRunner::new(FakeLoopback::new(), Duration::from_secs(1), 0, Duration::ZERO)
+        .with_budget(Budget::new().max_wall_time(Duration::from_secs(10)).max_errors(5))
+        .with_quarantine(Quarantine::new().with_tag("flaky"))
+        .with_artifacts(collector)
+        .with_observer(ConsoleReporter::stdout())
+        .with_observer(observer);
+    let smoke = uart.run_cases("loopback_smoke", loopback_smoke()).await;
+
+    // Generated conformance tests, a criteria check and a manual step on
+    // a fake register map; without an operator the step is skipped
+    let sensor = TestRunner::new(
+        FakeRegisterMap::new().with_registers(0x00, &[CHIP_ID, 0x03]),
+        Duration::from_secs(1),
+        0,
+        Duration::ZERO,
+    )
+    .with_budget(Budget::new().max_operations(1_000))
+    .with_observer(ConsoleReporter::stdout());
+    let mut tests = builtin::<FakeRegisterMap>("lifecycle");
+    tests.extend(builtin::<FakeRegisterMap>("conformance"));
+    tests.push(chip_id_criteria());
+    tests.push((
+        "manual::probe_tp3".to_string(),
+        sensor.manual_step(ManualStep::new("Probe TP3 and confirm 3.3 V").with_timeout(Duration::from_secs(30))),
+    ));
+    let names: Vec<String> = tests.iter().map(|(name, _)| name.clone()).collect();
+    let tests = names.iter().map(String::as_str).zip(tests.into_iter().map(|(_, f)| f)).collect();
+    let registers = sensor.run_test_suite("register_map", tests).await;
+
+    for result in [&smoke, &registers] {
+        write_reports(&reports, result);
+    }
+
+    // Several simulated targets from one manifest, with its own reports
+    let manifest = SuiteManifest::from_toml(MANIFEST).expect("valid manifest");
+    let targets = manifest.run(out.path(), true).await.expect("manifest run");
+
+    // Outputs exist
+    for result in [&smoke, &registers] {
+        for ext in ["json", "xml", "md"] {
+            let path = reports.join(format!("{}.{}", result.name, ext));
+            assert!(path.is_file(), "missing {}", path.display());
+        }
+    }
+    for target in ["eeprom", "flash", "gps"] {
+        for ext in ["json", "xml", "md"] {
+            let path = out.path().join("manifest").join(format!("{}.{}", target, ext));
+            assert!(path.is_file(), "missing {}", path.display());
+        }
+    }
+    let bundle = out.path().join("artifacts").join("framing_errors");
+    assert!(bundle.join("manifest.json").is_file(), "missing artifact bundle");
+    assert!(bundle.join("line_settings.bin").is_file());
+
+    // Counts add up
+    for result in [&smoke, &registers].into_iter().chain(&targets) {
+        assert_counts(result);
+    }
+    assert_eq!((smoke.passed_tests, smoke.quarantined_failures), (2, 1));
+    assert!(smoke.succeeded() && registers.succeeded());
+    assert_eq!((registers.total_tests, registers.passed_tests, registers.skipped_tests), (9, 8, 1));
+    let manual = registers.results.iter().find(|r| r.name == "manual::probe_tp3").unwrap();
+    assert!(manual.manual && matches!(manual.status, TestStatus::Skipped(_)));
+    assert_eq!(targets.len(), 3);
+
+    // The observer saw every test of the suite it watched
+    let mut finished = 0;
+    while let Ok(event) = events.try_recv() {
+        if let RunnerEvent::TestFinished(_) = event {
+            finished += 1;
+        }
+    }
+    assert_eq!(finished, smoke.total_tests);
+
+    println!(
+        "\nfull_demo: {} suites, {} tests, outputs in {}",
+        2 + targets.len(),
+        smoke.total_tests + registers.total_tests + targets.iter().map(|t| t.total_tests).sum::<usize>(),
+        out.path().display()
+    );
+}
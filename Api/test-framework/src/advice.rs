@@ -0,0 +1,294 @@
+/*
+ * Operator Guidance for Hardware Errors
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareErrorKind, TestResult, TestStatus};
+use std::fmt;
+
+/// How urgently an operator should act on a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AdviceSeverity {
+    /// Likely a bench setup issue, retry after checking the setup
+    Low,
+    /// Needs investigation before the run is trusted
+    Medium,
+    /// Stop testing and escalate
+    High,
+}
+
+impl fmt::Display for AdviceSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdviceSeverity::Low => write!(f, "low"),
+            AdviceSeverity::Medium => write!(f, "medium"),
+            AdviceSeverity::High => write!(f, "high"),
+        }
+    }
+}
+
+/// Operator-facing explanation of a failure
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorAdvice {
+    pub probable_causes: Vec<String>,
+    pub suggested_actions: Vec<String>,
+    pub severity: AdviceSeverity,
+}
+
+impl ErrorAdvice {
+    pub fn new(severity: AdviceSeverity) -> Self {
+        Self {
+            probable_causes: Vec::new(),
+            suggested_actions: Vec::new(),
+            severity,
+        }
+    }
+
+    pub fn cause(mut self, cause: &str) -> Self {
+        self.probable_causes.push(cause.to_string());
+        self
+    }
+
+    pub fn action(mut self, action: &str) -> Self {
+        self.suggested_actions.push(action.to_string());
+        self
+    }
+}
+
+/// Selects which failures a piece of advice applies to
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdviceMatcher {
+    /// Any error of the given kind, whatever context it carries
+    Kind(HardwareErrorKind),
+    /// Any failure whose message contains the text
+    Message(String),
+}
+
+impl AdviceMatcher {
+    pub fn message(pattern: &str) -> Self {
+        AdviceMatcher::Message(pattern.to_string())
+    }
+
+    fn matches(&self, kind: Option<HardwareErrorKind>, message: &str) -> bool {
+        match self {
+            AdviceMatcher::Kind(want) => kind == Some(*want),
+            AdviceMatcher::Message(pattern) => message.contains(pattern.as_str()),
+        }
+    }
+}
+
+/// Lookup table from failures to advice
+///
+/// Message patterns take precedence over kind matchers, and within each
+/// kind the most recently registered entry wins, so suites can override the
+/// built-in defaults.
+#[derive(Debug, Clone)]
+pub struct AdviceRegistry {
+    entries: Vec<(AdviceMatcher, ErrorAdvice)>,
+}
+
+impl Default for AdviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdviceRegistry {
+    /// Registry with advice for every `HardwareErrorKind`
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for (matcher, advice) in default_advice() {
+            registry.register(matcher, advice);
+        }
+        registry
+    }
+
+    pub fn empty() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn register(&mut self, matcher: AdviceMatcher, advice: ErrorAdvice) {
+        self.entries.push((matcher, advice));
+    }
+
+    fn lookup(&self, kind: Option<HardwareErrorKind>, message: &str) -> Option<&ErrorAdvice> {
+        let find = |want_message: bool| {
+            self.entries
+                .iter()
+                .rev()
+                .filter(|(m, _)| matches!(m, AdviceMatcher::Message(_)) == want_message)
+                .find(|(m, _)| m.matches(kind, message))
+                .map(|(_, advice)| advice)
+        };
+        find(true).or_else(|| find(false))
+    }
+
+    pub fn advice_for_error(&self, error: &HardwareError) -> Option<&ErrorAdvice> {
+        self.lookup(Some(error.kind()), &error.to_string())
+    }
+
+    /// Advice for a failed or errored result, by the kind of error it ended
+    /// with and its status message
+    pub fn advice_for_result(&self, result: &TestResult) -> Option<&ErrorAdvice> {
+        let message = match &result.status {
+            TestStatus::Failed(msg) | TestStatus::Error(msg) => msg,
+            TestStatus::Passed | TestStatus::Skipped(_) => return None,
+        };
+        self.lookup(result.error_kind, message)
+    }
+}
+
+fn default_advice() -> Vec<(AdviceMatcher, ErrorAdvice)> {
+    vec![
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::CommunicationError),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("Loose or damaged harness between bench and device")
+                .cause("Bus speed or framing settings don't match the device")
+                .action("Reseat the cable and check connector pins")
+                .action("Compare the interface configuration with the device datasheet"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::TimeoutError),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("Device is unpowered, held in reset or busy")
+                .cause("Timeout is too short for the operation")
+                .action("Check the supply rail and reset line")
+                .action("Rerun once; if it persists, raise the test timeout and report it"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::InvalidParameter),
+            ErrorAdvice::new(AdviceSeverity::High)
+                .cause("Test or configuration passes a value the interface rejects")
+                .action("File a bug against the test suite with the result message"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::DeviceNotFound),
+            ErrorAdvice::new(AdviceSeverity::Low)
+                .cause("Device is not connected or not enumerated by the host")
+                .cause("Wrong bus number or device address configured")
+                .action("Check the device is connected and powered")
+                .action("Verify the device path or address in the bench configuration"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::PermissionDenied),
+            ErrorAdvice::new(AdviceSeverity::Low)
+                .cause("Bench user lacks access to the device node")
+                .action("Add the bench user to the device group or run with the bench account"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::NotInitialized),
+            ErrorAdvice::new(AdviceSeverity::High)
+                .cause("Test uses the interface before initializing it")
+                .action("File a bug against the test suite"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::AlreadyInitialized),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("A previous test left the interface open")
+                .action("Power-cycle the bench interface and rerun the suite")
+                .action("If it recurs, file a bug against the test suite"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::OperationFailed),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("Device rejected or could not complete the operation")
+                .action("Check the result message and device state, then rerun once")
+                .action("Attach the artifact bundle when reporting the failure"),
+        ),
+        (
+            AdviceMatcher::Kind(HardwareErrorKind::ConfigurationError),
+            ErrorAdvice::new(AdviceSeverity::High)
+                .cause("Suite manifest or test setup is invalid")
+                .action("Fix the configuration named in the result message and rerun"),
+        ),
+        (
+            AdviceMatcher::message("errors reported"),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("Interface recorded bus errors although the test itself completed")
+                .action("Check the harness for noise or marginal connections and rerun"),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InterfaceKind, Operation};
+    use std::time::Duration;
+
+    #[test]
+    fn test_defaults_cover_every_variant() {
+        let registry = AdviceRegistry::new();
+        for kind in HardwareErrorKind::ALL {
+            let advice = registry
+                .entries
+                .iter()
+                .find(|(matcher, _)| *matcher == AdviceMatcher::Kind(kind));
+            assert!(advice.is_some(), "{}", kind);
+        }
+        let errors = [
+            HardwareError::CommunicationError("x".to_string()),
+            HardwareError::TimeoutError,
+            HardwareError::InvalidParameter("x".to_string()),
+            HardwareError::DeviceNotFound,
+            HardwareError::PermissionDenied,
+            HardwareError::NotInitialized,
+            HardwareError::AlreadyInitialized,
+            HardwareError::OperationFailed("x".to_string()),
+            HardwareError::ConfigurationError("x".to_string()),
+        ];
+
+        for error in &errors {
+            let advice = registry.advice_for_error(error).unwrap();
+            assert!(!advice.suggested_actions.is_empty(), "{:?}", error);
+        }
+        assert!(AdviceRegistry::empty().advice_for_error(&HardwareError::TimeoutError).is_none());
+    }
+
+    #[test]
+    fn test_message_pattern_beats_variant() {
+        let mut registry = AdviceRegistry::new();
+        registry.register(
+            AdviceMatcher::message("Failed to read exact number of bytes"),
+            ErrorAdvice::new(AdviceSeverity::Low).action("Reseat the UART cable"),
+        );
+
+        let failed = |message: &str| {
+            let mut result = TestResult::new("read", TestStatus::Error(message.to_string()), Duration::ZERO);
+            result.error_kind = Some(HardwareErrorKind::CommunicationError);
+            result
+        };
+        let short_read = failed("Test failed: Failed to read exact number of bytes");
+        let other = failed("Test failed: NACK");
+
+        assert_eq!(registry.advice_for_result(&short_read).unwrap().suggested_actions, vec!["Reseat the UART cable"]);
+        assert_eq!(registry.advice_for_result(&other).unwrap().severity, AdviceSeverity::Medium);
+        let passed = TestResult::new("read", TestStatus::Passed, Duration::ZERO);
+        assert!(registry.advice_for_result(&passed).is_none());
+    }
+
+    #[test]
+    fn test_kind_not_sniffed_from_message() {
+        let registry = AdviceRegistry::new();
+        // A message naming a variant is not an error of that kind
+        let mismatch = TestResult::new(
+            "compare",
+            TestStatus::Failed("expected TimeoutError, got data".to_string()),
+            Duration::ZERO,
+        );
+        assert!(registry.advice_for_result(&mismatch).is_none());
+
+        let contextual = HardwareError::TimeoutError.with_context(InterfaceKind::Uart, Operation::Read);
+        assert_eq!(registry.advice_for_error(&contextual), registry.advice_for_error(&HardwareError::TimeoutError));
+    }
+
+    #[test]
+    fn test_later_registration_overrides_default() {
+        let mut registry = AdviceRegistry::new();
+        registry.register(AdviceMatcher::Kind(HardwareErrorKind::TimeoutError), ErrorAdvice::new(AdviceSeverity::High));
+
+        let advice = registry.advice_for_error(&HardwareError::TimeoutError).unwrap();
+        assert_eq!(advice.severity, AdviceSeverity::High);
+    }
+}
@@ -0,0 +1,295 @@
+/*
+ * Numbered Run Archive
+ * Copyright (C) 2024
+ */
+
+use crate::{AdviceRegistry, TestSuiteResult};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in the archive root holding the last assigned run ID
+pub const RUN_COUNTER_FILE: &str = "run_counter";
+
+/// Marker file in the archive root naming the newest run directory
+pub const LATEST_MARKER: &str = "latest";
+
+/// A run stored by `RunArchive::archive`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedRun {
+    pub id: u64,
+    pub directory: PathBuf,
+    /// JSON, Markdown and JUnit XML reports written into `directory`
+    pub reports: Vec<PathBuf>,
+    /// Older run directories removed by the retention policy
+    pub pruned: Vec<PathBuf>,
+}
+
+/// Archive of suite reports in numbered per-run directories,
+/// e.g. `runs/0007-2024-05-01T02-00/`
+pub struct RunArchive {
+    root: PathBuf,
+    keep_last: Option<usize>,
+    advice: AdviceRegistry,
+}
+
+impl RunArchive {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            keep_last: None,
+            advice: AdviceRegistry::new(),
+        }
+    }
+
+    /// Keep only the newest `runs` run directories after each archive
+    pub fn with_retention(mut self, runs: usize) -> Self {
+        self.keep_last = Some(runs);
+        self
+    }
+
+    /// Operator guidance used in the Markdown report
+    pub fn with_advice(mut self, advice: AdviceRegistry) -> Self {
+        self.advice = advice;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Store the reports for `result` and copy the given artifact bundle
+    /// directories into a new run directory.
+    ///
+    /// The run ID is reserved before anything else is written, so a failed
+    /// report write loses that ID but never reuses or corrupts the counter.
+    pub fn archive(&self, result: &TestSuiteResult, bundles: &[PathBuf]) -> io::Result<ArchivedRun> {
+        fs::create_dir_all(&self.root)?;
+        let id = self.next_id()?;
+        let name = format!("{:04}-{}", id, format_timestamp(SystemTime::now()));
+        let directory = self.root.join(&name);
+        fs::create_dir_all(&directory)?;
+
+        let reports = vec![directory.join("report.json"), directory.join("report.md"), directory.join("report.xml")];
+        result.write_json(&reports[0])?;
+        result.write_markdown(&reports[1], &self.advice)?;
+        result.write_junit_xml(&reports[2])?;
+        for bundle in bundles {
+            if let Some(bundle_name) = bundle.file_name() {
+                copy_dir(bundle, &directory.join("artifacts").join(bundle_name))?;
+            }
+        }
+
+        write_atomic(&self.root.join(LATEST_MARKER), name.as_bytes())?;
+
+        let pruned = match self.keep_last {
+            Some(_) => self.prune(false)?,
+            None => Vec::new(),
+        };
+        Ok(ArchivedRun {
+            id,
+            directory,
+            reports,
+            pruned,
+        })
+    }
+
+    /// Run IDs and directories, oldest first
+    pub fn runs(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut runs = Vec::new();
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(runs),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(id) = entry.file_name().to_str().and_then(parse_run_id) {
+                runs.push((id, entry.path()));
+            }
+        }
+        runs.sort();
+        Ok(runs)
+    }
+
+    /// Directory named by the `latest` marker
+    pub fn latest(&self) -> io::Result<Option<PathBuf>> {
+        match fs::read_to_string(self.root.join(LATEST_MARKER)) {
+            Ok(name) => Ok(Some(self.root.join(name.trim()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove runs beyond the retention limit, returning the affected
+    /// directories. With `dry_run` nothing is deleted.
+    pub fn prune(&self, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+        let keep = match self.keep_last {
+            Some(keep) => keep,
+            None => return Ok(Vec::new()),
+        };
+        let runs = self.runs()?;
+        let excess = runs.len().saturating_sub(keep);
+        let doomed: Vec<PathBuf> = runs.into_iter().take(excess).map(|(_, dir)| dir).collect();
+        if !dry_run {
+            for dir in &doomed {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(doomed)
+    }
+
+    /// Reserve the next run ID. A missing counter resumes after the highest
+    /// existing run; an unreadable one is an error rather than a reset.
+    fn next_id(&self) -> io::Result<u64> {
+        let path = self.root.join(RUN_COUNTER_FILE);
+        let last = match fs::read_to_string(&path) {
+            Ok(text) => text.trim().parse::<u64>().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.runs()?.last().map(|(id, _)| *id).unwrap_or(0)
+            }
+            Err(e) => return Err(e),
+        };
+        let id = last + 1;
+        write_atomic(&path, id.to_string().as_bytes())?;
+        Ok(id)
+    }
+}
+
+/// Write through a temporary file so readers never see a partial file
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_run_id(name: &str) -> Option<u64> {
+    let (id, _) = name.split_once('-')?;
+    id.parse().ok()
+}
+
+/// UTC timestamp as `2024-05-01T02-00`, safe for directory names
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let minutes = secs.rem_euclid(86_400) / 60;
+    format!("{:04}-{:02}-{:02}T{:02}-{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// Proleptic Gregorian date of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestResult, TestStatus};
+    use std::time::Duration;
+
+    fn suite() -> TestSuiteResult {
+        let results = vec![TestResult::new("read", TestStatus::Failed("crc".to_string()), Duration::ZERO)];
+        TestSuiteResult::from_results("nightly", results, Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_528_800);
+        assert_eq!(format_timestamp(time), "2024-05-01T02-00");
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00-00");
+    }
+
+    #[test]
+    fn test_ids_monotonic_across_instances() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let first = RunArchive::new(dir.path()).archive(&suite(), &[]).unwrap();
+        let second = RunArchive::new(dir.path()).archive(&suite(), &[]).unwrap();
+
+        assert_eq!((first.id, second.id), (1, 2));
+        assert!(second.directory.file_name().unwrap().to_str().unwrap().starts_with("0002-"));
+        assert!(second.directory.join("report.json").exists());
+        assert!(second.directory.join("report.md").exists());
+        assert!(second.directory.join("report.xml").exists());
+        assert_eq!(second.reports.len(), 3);
+        assert!(second.reports.iter().all(|path| path.starts_with(&second.directory)));
+        assert_eq!(RunArchive::new(dir.path()).latest().unwrap(), Some(second.directory));
+
+        // A lost counter resumes after the newest run instead of restarting
+        fs::remove_file(dir.path().join(RUN_COUNTER_FILE)).unwrap();
+        assert_eq!(RunArchive::new(dir.path()).archive(&suite(), &[]).unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_retention_pruning() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = RunArchive::new(dir.path());
+        for _ in 0..4 {
+            archive.archive(&suite(), &[]).unwrap();
+        }
+
+        let archive = RunArchive::new(dir.path()).with_retention(2);
+        assert_eq!(archive.prune(true).unwrap().len(), 2);
+        assert_eq!(archive.runs().unwrap().len(), 4);
+
+        let run = archive.archive(&suite(), &[]).unwrap();
+        assert_eq!(run.pruned.len(), 3);
+        let ids: Vec<u64> = archive.runs().unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_failed_report_write_keeps_counter() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = RunArchive::new(dir.path());
+        archive.archive(&suite(), &[]).unwrap();
+
+        let missing_bundle = dir.path().join("no-such-bundle");
+        assert!(archive.archive(&suite(), &[missing_bundle]).is_err());
+
+        let counter = fs::read_to_string(dir.path().join(RUN_COUNTER_FILE)).unwrap();
+        assert_eq!(counter, "2");
+        assert_eq!(archive.archive(&suite(), &[]).unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_artifact_bundles_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundles").join("read");
+        fs::create_dir_all(&bundle).unwrap();
+        fs::write(bundle.join("manifest.json"), "{}").unwrap();
+
+        let run = RunArchive::new(dir.path().join("runs")).archive(&suite(), &[bundle]).unwrap();
+
+        assert!(run.directory.join("artifacts").join("read").join("manifest.json").exists());
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Failure Artifact Collection for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use crate::{crc32, EventTimeline, HardwareResult, InterfaceStatus, OperationStats, TestResult};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Closure producing a named artifact, e.g. a register dump
+pub type ArtifactProducer = Box<dyn Fn() -> HardwareResult<Vec<u8>> + Send + Sync>;
+
+/// Manifest entry for a single collected artifact
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ArtifactEntry {
+    pub name: String,
+    pub file: String,
+    pub size: usize,
+}
+
+/// Manifest written as `manifest.json` into every bundle
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ArtifactManifest {
+    pub test: String,
+    pub status: String,
+    pub seed: Option<u64>,
+    pub artifacts: Vec<ArtifactEntry>,
+    pub notes: Vec<String>,
+}
+
+/// Artifacts collected for one failed test
+#[derive(Debug, Clone)]
+pub struct ArtifactBundle {
+    pub directory: PathBuf,
+    pub manifest: ArtifactManifest,
+}
+
+/// Collects debugging artifacts into a per-test directory when a test fails
+pub struct ArtifactCollector {
+    output_dir: PathBuf,
+    producers: Vec<(String, ArtifactProducer)>,
+    seed: Option<u64>,
+    timeline: Option<EventTimeline>,
+    collected: Mutex<Vec<PathBuf>>,
+}
+
+impl ArtifactCollector {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            producers: Vec::new(),
+            seed: None,
+            timeline: None,
+            collected: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record the RNG seed of the run so failures can be reproduced
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Store the transaction log of `Traced` interfaces feeding `timeline`
+    /// as `transactions.csv` in every bundle
+    pub fn with_timeline(mut self, timeline: &EventTimeline) -> Self {
+        self.timeline = Some(timeline.clone());
+        self
+    }
+
+    /// Register a producer whose output is stored as `name` in every bundle
+    pub fn register<F>(&mut self, name: &str, producer: F)
+    where
+        F: Fn() -> HardwareResult<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.producers.push((name.to_string(), Box::new(producer)));
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Directory the bundle for `test_name` is written to
+    pub fn bundle_dir(&self, test_name: &str) -> PathBuf {
+        self.output_dir.join(file_stem(test_name))
+    }
+
+    /// Bundle directories written by this collector, oldest first; bundles
+    /// left in the output directory by earlier runs are not included
+    pub fn collected(&self) -> Vec<PathBuf> {
+        self.collected.lock().unwrap().clone()
+    }
+
+    /// Write the bundle for `result`, replacing any earlier bundle of the
+    /// same test. Individual artifact failures are recorded as manifest
+    /// notes; only failing to create the bundle directory or manifest is an
+    /// error.
+    pub fn collect(
+        &self,
+        result: &TestResult,
+        status: Option<&InterfaceStatus>,
+        stats: &OperationStats,
+    ) -> io::Result<ArtifactBundle> {
+        let directory = self.bundle_dir(&result.name);
+        match fs::remove_dir_all(&directory) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        fs::create_dir_all(&directory)?;
+
+        let mut writer = BundleWriter {
+            directory: &directory,
+            artifacts: Vec::new(),
+            notes: Vec::new(),
+        };
+
+        writer.store("result", "result.txt", result.to_string().as_bytes());
+        if let Some(status) = status {
+            writer.store("interface_status", "interface_status.txt", format!("{:#?}", status).as_bytes());
+        }
+        let stats_json = serde_json::json!({
+            "operations": stats.operations(),
+            "errors": stats.errors(),
+        });
+        writer.store("operation_stats", "operation_stats.json", stats_json.to_string().as_bytes());
+        if let Some(timeline) = &self.timeline {
+            writer.store("transactions", "transactions.csv", timeline.snapshot().to_csv().as_bytes());
+        }
+
+        for (name, producer) in &self.producers {
+            match producer() {
+                Ok(data) => writer.store(name, &format!("{}.bin", file_stem(name)), &data),
+                Err(e) => writer.notes.push(format!("artifact producer '{}' failed: {}", name, e)),
+            }
+        }
+        let BundleWriter { artifacts, notes, .. } = writer;
+
+        let manifest = ArtifactManifest {
+            test: result.name.clone(),
+            status: format!("{:?}", result.status),
+            seed: self.seed,
+            artifacts,
+            notes,
+        };
+        let json = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(directory.join("manifest.json"), json)?;
+
+        let mut collected = self.collected.lock().unwrap();
+        if !collected.contains(&directory) {
+            collected.push(directory.clone());
+        }
+        drop(collected);
+        Ok(ArtifactBundle { directory, manifest })
+    }
+}
+
+struct BundleWriter<'a> {
+    directory: &'a Path,
+    artifacts: Vec<ArtifactEntry>,
+    notes: Vec<String>,
+}
+
+impl BundleWriter<'_> {
+    fn store(&mut self, name: &str, file: &str, data: &[u8]) {
+        match fs::write(self.directory.join(file), data) {
+            Ok(_) => self.artifacts.push(ArtifactEntry {
+                name: name.to_string(),
+                file: file.to_string(),
+                size: data.len(),
+            }),
+            Err(e) => self.notes.push(format!("failed to write {}: {}", file, e)),
+        }
+    }
+}
+
+/// Make a test or artifact name safe to use as a file name. The CRC of
+/// the original name keeps names that sanitize alike, such as "a/b" and
+/// "a_b", apart.
+fn file_stem(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}-{:08x}", sanitized, crc32(name.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, HardwareError, TestStatus};
+    use std::time::Duration;
+
+    fn failed_result() -> TestResult {
+        TestResult::new("read <reg>", TestStatus::Failed("mismatch".to_string()), Duration::from_millis(5))
+    }
+
+    #[test]
+    fn test_collect_writes_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut collector = ArtifactCollector::new(dir.path()).with_seed(42);
+        collector.register("register dump", || Ok(vec![0x12, 0x34]));
+
+        let bundle = collector.collect(&failed_result(), None, &OperationStats::default()).unwrap();
+
+        assert_eq!(bundle.directory, dir.path().join(file_stem("read <reg>")));
+        assert!(file_stem("read <reg>").starts_with("read__reg_-"));
+        let names: Vec<_> = bundle.manifest.artifacts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["result", "operation_stats", "register dump"]);
+        assert_eq!(bundle.manifest.seed, Some(42));
+        assert_eq!(fs::read(bundle.directory.join(format!("{}.bin", file_stem("register dump")))).unwrap(), vec![0x12, 0x34]);
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(bundle.directory.join("manifest.json")).unwrap()).unwrap();
+        assert_eq!(manifest["test"], "read <reg>");
+        assert_eq!(manifest["artifacts"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_producer_failure_becomes_note() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut collector = ArtifactCollector::new(dir.path());
+        collector.register("dump", || Err(HardwareError::DeviceNotFound));
+
+        let bundle = collector.collect(&failed_result(), None, &OperationStats::default()).unwrap();
+
+        assert_eq!(bundle.manifest.artifacts.len(), 2);
+        assert_eq!(bundle.manifest.notes, vec!["artifact producer 'dump' failed: Device not found".to_string()]);
+    }
+
+    #[test]
+    fn test_names_sanitizing_alike_kept_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let collector = ArtifactCollector::new(dir.path());
+        let result = |name: &str| TestResult::new(name, TestStatus::Failed("mismatch".to_string()), Duration::ZERO);
+
+        let slash = collector.collect(&result("a/b"), None, &OperationStats::default()).unwrap();
+        let underscore = collector.collect(&result("a_b"), None, &OperationStats::default()).unwrap();
+
+        assert_ne!(slash.directory, underscore.directory);
+        assert_eq!(collector.collected(), vec![slash.directory, underscore.directory]);
+    }
+
+    #[test]
+    fn test_collect_replaces_earlier_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = ArtifactCollector::new(dir.path()).bundle_dir("read <reg>");
+        fs::create_dir_all(&stale).unwrap();
+        fs::write(stale.join("old.bin"), [0u8]).unwrap();
+
+        let collector = ArtifactCollector::new(dir.path());
+        assert!(collector.collected().is_empty());
+        let bundle = collector.collect(&failed_result(), None, &OperationStats::default()).unwrap();
+
+        assert_eq!(bundle.directory, stale);
+        assert!(!stale.join("old.bin").exists());
+        assert_eq!(collector.collected(), vec![stale]);
+    }
+
+    #[test]
+    fn test_bundle_includes_transaction_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let timeline = EventTimeline::new(16);
+        let collector = ArtifactCollector::new(dir.path()).with_timeline(&timeline);
+        timeline.record("i2c", Direction::Tx, &[0x50, 0x00]);
+
+        let bundle = collector.collect(&failed_result(), None, &OperationStats::default()).unwrap();
+
+        let names: Vec<_> = bundle.manifest.artifacts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["result", "operation_stats", "transactions"]);
+        let log = fs::read_to_string(bundle.directory.join("transactions.csv")).unwrap();
+        assert_eq!(log, timeline.snapshot().to_csv());
+        assert!(log.contains("i2c"));
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * hwtest - Run Built-in Suites from a Manifest
+ * Copyright (C) 2024
+ */
+
+use hardware_test_framework::{exit_code, run_manifest_file, watch_manifest, PollingWatcher};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Exit code for usage and manifest errors
+const MANIFEST_ERROR: u8 = 2;
+
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+const USAGE: &str = "usage: hwtest <manifest.toml> [--watch [--keep-initialized] [PATH...]]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args_os().skip(1);
+    let path = match args.next() {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(MANIFEST_ERROR);
+        }
+    };
+
+    let rest: Vec<PathBuf> = args.map(PathBuf::from).collect();
+    match rest.split_first() {
+        None => run_once(&path).await,
+        Some((flag, extra)) if flag.as_os_str() == "--watch" => match extra.split_first() {
+            Some((keep, extra)) if keep.as_os_str() == "--keep-initialized" => run_watch(&path, extra, true).await,
+            _ => run_watch(&path, extra, false).await,
+        },
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(MANIFEST_ERROR)
+        }
+    }
+}
+
+async fn run_once(path: &Path) -> ExitCode {
+    match run_manifest_file(path, true).await {
+        Ok(results) => ExitCode::from(exit_code(&results)),
+        Err(e) => {
+            eprintln!("hwtest: {}", e);
+            ExitCode::from(MANIFEST_ERROR)
+        }
+    }
+}
+
+/// Re-run the manifest whenever it or one of `extra` changes, until Ctrl-C,
+/// keeping the hardware initialized between runs if asked to
+async fn run_watch(path: &Path, extra: &[PathBuf], keep_initialized: bool) -> ExitCode {
+    let mut paths = vec![path.to_path_buf()];
+    paths.extend_from_slice(extra);
+    let mut watcher = PollingWatcher::new(paths, WATCH_POLL_INTERVAL);
+    let cancel = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    let mut out = std::io::stdout();
+    match watch_manifest(&mut watcher, path, WATCH_DEBOUNCE, keep_initialized, true, &mut out, cancel).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("hwtest: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
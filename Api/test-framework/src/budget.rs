@@ -0,0 +1,125 @@
+/*
+ * Suite Resource Budgets for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use std::fmt;
+use std::time::Duration;
+
+/// Resource limits for a single suite run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Budget {
+    pub max_wall_time: Option<Duration>,
+    pub max_operations: Option<u64>,
+    pub max_errors: Option<u32>,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_wall_time(mut self, limit: Duration) -> Self {
+        self.max_wall_time = Some(limit);
+        self
+    }
+
+    pub fn max_operations(mut self, limit: u64) -> Self {
+        self.max_operations = Some(limit);
+        self
+    }
+
+    pub fn max_errors(mut self, limit: u32) -> Self {
+        self.max_errors = Some(limit);
+        self
+    }
+
+    /// Check the usage so far against every configured limit
+    pub fn check(&self, elapsed: Duration, operations: u64, errors: u32) -> Option<BudgetExceeded> {
+        if let Some(limit) = self.max_wall_time {
+            if elapsed >= limit {
+                return Some(BudgetExceeded::WallTime { limit, used: elapsed });
+            }
+        }
+        if let Some(limit) = self.max_operations {
+            if operations >= limit {
+                return Some(BudgetExceeded::Operations { limit, used: operations });
+            }
+        }
+        if let Some(limit) = self.max_errors {
+            if errors >= limit {
+                return Some(BudgetExceeded::Errors { limit, used: errors });
+            }
+        }
+        None
+    }
+
+    /// Wall time left before the budget trips, if one is configured
+    pub fn remaining_wall_time(&self, elapsed: Duration) -> Option<Duration> {
+        self.max_wall_time.map(|limit| limit.saturating_sub(elapsed))
+    }
+}
+
+/// The budget that stopped a suite
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetExceeded {
+    WallTime { limit: Duration, used: Duration },
+    Operations { limit: u64, used: u64 },
+    Errors { limit: u32, used: u32 },
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetExceeded::WallTime { limit, used } => {
+                write!(f, "wall time {:?} reached limit {:?}", used, limit)
+            }
+            BudgetExceeded::Operations { limit, used } => {
+                write!(f, "{} bus operations reached limit {}", used, limit)
+            }
+            BudgetExceeded::Errors { limit, used } => {
+                write!(f, "{} errors reached limit {}", used, limit)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_unlimited() {
+        let budget = Budget::new();
+        assert_eq!(budget.check(Duration::from_secs(3600), u64::MAX, u32::MAX), None);
+        assert_eq!(budget.remaining_wall_time(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_budget_check_order() {
+        let budget = Budget::new()
+            .max_wall_time(Duration::from_secs(10))
+            .max_operations(100)
+            .max_errors(5);
+
+        assert_eq!(budget.check(Duration::from_secs(1), 10, 1), None);
+        assert!(matches!(
+            budget.check(Duration::from_secs(10), 100, 5),
+            Some(BudgetExceeded::WallTime { .. })
+        ));
+        assert!(matches!(
+            budget.check(Duration::from_secs(1), 100, 5),
+            Some(BudgetExceeded::Operations { .. })
+        ));
+        assert!(matches!(
+            budget.check(Duration::from_secs(1), 10, 5),
+            Some(BudgetExceeded::Errors { .. })
+        ));
+    }
+
+    #[test]
+    fn test_budget_display() {
+        let exceeded = BudgetExceeded::Operations { limit: 100, used: 120 };
+        assert_eq!(exceeded.to_string(), "120 bus operations reached limit 100");
+    }
+}
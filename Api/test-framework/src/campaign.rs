@@ -0,0 +1,644 @@
+/*
+ * Campaign Reports Merged from Several Bench Computers
+ * Copyright (C) 2024
+ */
+
+use crate::{TestEnvironmentInfo, REPORT_SCHEMA_VERSION};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Environment `extra` key naming the bench computer a suite ran on
+pub const HOST_KEY: &str = "host";
+
+/// Environment `extra` key holding the run ID of a suite
+pub const RUN_ID_KEY: &str = "run_id";
+
+/// Errors that stop a campaign from being assembled
+#[derive(Debug)]
+pub enum CampaignError {
+    Read { path: PathBuf, source: io::Error },
+    /// Report written with a different layout than this build reads
+    SchemaVersion { source: String, found: Option<u64> },
+    Write { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for CampaignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CampaignError::Read { path, source } => write!(f, "cannot read report {}: {}", path.display(), source),
+            CampaignError::SchemaVersion { source, found: Some(found) } => write!(
+                f,
+                "report {} has schema version {}, expected {}; regenerate it with a matching framework",
+                source, found, REPORT_SCHEMA_VERSION
+            ),
+            CampaignError::SchemaVersion { source, found: None } => write!(
+                f,
+                "report {} has no schema version, expected {}",
+                source, REPORT_SCHEMA_VERSION
+            ),
+            CampaignError::Write { path, source } => {
+                write!(f, "cannot write campaign report {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for CampaignError {}
+
+/// One test of an ingested suite report
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignTest {
+    pub name: String,
+    /// Status keyword as in the suite report, e.g. "passed"
+    pub status: String,
+    pub message: Option<String>,
+    pub quarantined: bool,
+}
+
+impl CampaignTest {
+    fn is_failure(&self) -> bool {
+        self.status == "failed" || self.status == "error"
+    }
+}
+
+/// Suite report contributed by one bench computer
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignInput {
+    /// File the report was read from, or the label it was ingested under
+    pub source: String,
+    /// From the environment's `host` entry, else the source
+    pub host: String,
+    pub run_id: Option<String>,
+    pub suite: String,
+    pub environment: Option<TestEnvironmentInfo>,
+    pub tests: Vec<CampaignTest>,
+}
+
+/// Outcome tally over every input of a campaign
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CampaignTotals {
+    pub hosts: usize,
+    pub suites: usize,
+    pub tests: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub errors: usize,
+    /// Failed or errored quarantined tests, left out of `failed` and `errors`
+    pub quarantined_failures: usize,
+}
+
+/// Test name reported by more than one input
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateTest {
+    pub name: String,
+    /// `host/suite` of every input containing the test
+    pub inputs: Vec<String>,
+}
+
+/// Where a requirement stands across the whole campaign
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoverageStatus {
+    /// Every traced test ran and passed everywhere it ran
+    Verified,
+    /// A traced test failed or errored somewhere
+    Failing,
+    /// A traced test never ran or was only skipped
+    Incomplete,
+}
+
+impl fmt::Display for CoverageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoverageStatus::Verified => write!(f, "verified"),
+            CoverageStatus::Failing => write!(f, "failing"),
+            CoverageStatus::Incomplete => write!(f, "incomplete"),
+        }
+    }
+}
+
+/// Coverage of one requirement by the tests traced to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequirementCoverage {
+    pub requirement: String,
+    pub status: CoverageStatus,
+    pub tests: Vec<String>,
+    /// Traced tests no input ran
+    pub missing: Vec<String>,
+}
+
+/// Campaign summary merged from suite reports written on several bench
+/// computers, e.g. one per interface type
+///
+/// Inputs are the JSON reports of `TestSuiteResult::write_json`. Files that
+/// are not valid reports, and malformed results within a report, are
+/// skipped and listed as warnings; a report with another schema version is
+/// an error since its fields cannot be trusted.
+#[derive(Debug, Clone, Default)]
+pub struct CampaignReport {
+    pub name: String,
+    inputs: Vec<CampaignInput>,
+    warnings: Vec<String>,
+    requirements: BTreeMap<String, Vec<String>>,
+}
+
+impl CampaignReport {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Trace `requirement` to the tests verifying it, by test name
+    pub fn with_requirement(mut self, requirement: &str, tests: &[&str]) -> Self {
+        self.requirements
+            .insert(requirement.to_string(), tests.iter().map(|t| t.to_string()).collect());
+        self
+    }
+
+    /// Ingest every report in `paths`
+    pub fn load(mut self, paths: &[PathBuf]) -> Result<Self, CampaignError> {
+        for path in paths {
+            self.ingest_file(path)?;
+        }
+        Ok(self)
+    }
+
+    pub fn ingest_file(&mut self, path: &Path) -> Result<(), CampaignError> {
+        let text = fs::read_to_string(path).map_err(|source| CampaignError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.ingest_str(&path.display().to_string(), &text)
+    }
+
+    /// Ingest report text read from `source`
+    pub fn ingest_str(&mut self, source: &str, text: &str) -> Result<(), CampaignError> {
+        let report: Value = match serde_json::from_str(text) {
+            Ok(report) => report,
+            Err(e) => {
+                self.warnings.push(format!("{}: skipped, not a JSON report ({})", source, e));
+                return Ok(());
+            }
+        };
+        let found = report.get("schema_version").and_then(Value::as_u64);
+        if found != Some(u64::from(REPORT_SCHEMA_VERSION)) {
+            return Err(CampaignError::SchemaVersion {
+                source: source.to_string(),
+                found,
+            });
+        }
+        let (suite, results) = match (report["name"].as_str(), report["results"].as_array()) {
+            (Some(suite), Some(results)) => (suite, results),
+            _ => {
+                self.warnings.push(format!("{}: skipped, missing suite name or results", source));
+                return Ok(());
+            }
+        };
+
+        let environment: Option<TestEnvironmentInfo> = match serde_json::from_value(report["environment"].clone()) {
+            Ok(environment) => environment,
+            Err(e) => {
+                self.warnings.push(format!("{}: ignored malformed environment ({})", source, e));
+                None
+            }
+        };
+        let extra = |key: &str| environment.as_ref().and_then(|env| env.extra.get(key).cloned());
+
+        let mut tests = Vec::new();
+        for (index, result) in results.iter().enumerate() {
+            match parse_test(result) {
+                Some(test) => tests.push(test),
+                None => self.warnings.push(format!("{}: skipped malformed result #{}", source, index)),
+            }
+        }
+
+        self.inputs.push(CampaignInput {
+            source: source.to_string(),
+            host: extra(HOST_KEY).unwrap_or_else(|| source.to_string()),
+            run_id: extra(RUN_ID_KEY),
+            suite: suite.to_string(),
+            environment,
+            tests,
+        });
+        Ok(())
+    }
+
+    pub fn inputs(&self) -> &[CampaignInput] {
+        &self.inputs
+    }
+
+    /// Problems in the inputs that were skipped over
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn totals(&self) -> CampaignTotals {
+        let mut hosts: Vec<&str> = self.inputs.iter().map(|input| input.host.as_str()).collect();
+        hosts.sort_unstable();
+        hosts.dedup();
+        let mut totals = CampaignTotals {
+            hosts: hosts.len(),
+            suites: self.inputs.len(),
+            ..CampaignTotals::default()
+        };
+        for test in self.inputs.iter().flat_map(|input| &input.tests) {
+            totals.tests += 1;
+            match test.status.as_str() {
+                "passed" => totals.passed += 1,
+                "skipped" => totals.skipped += 1,
+                _ if test.quarantined => totals.quarantined_failures += 1,
+                "failed" => totals.failed += 1,
+                _ => totals.errors += 1,
+            }
+        }
+        totals
+    }
+
+    /// No test outside the quarantine failed or errored on any host
+    pub fn succeeded(&self) -> bool {
+        let totals = self.totals();
+        totals.failed + totals.errors == 0
+    }
+
+    /// Test names appearing in more than one input, in name order
+    pub fn duplicates(&self) -> Vec<DuplicateTest> {
+        let mut seen: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for input in &self.inputs {
+            let label = format!("{}/{}", input.host, input.suite);
+            for test in &input.tests {
+                let inputs = seen.entry(&test.name).or_default();
+                if !inputs.contains(&label) {
+                    inputs.push(label.clone());
+                }
+            }
+        }
+        seen.into_iter()
+            .filter(|(_, inputs)| inputs.len() > 1)
+            .map(|(name, inputs)| DuplicateTest {
+                name: name.to_string(),
+                inputs,
+            })
+            .collect()
+    }
+
+    /// Coverage of every traced requirement over all inputs combined
+    pub fn coverage(&self) -> Vec<RequirementCoverage> {
+        self.requirements
+            .iter()
+            .map(|(requirement, tests)| {
+                let runs = |name: &str| -> Vec<&CampaignTest> {
+                    self.inputs
+                        .iter()
+                        .flat_map(|input| &input.tests)
+                        .filter(|test| test.name == name)
+                        .collect()
+                };
+                let missing: Vec<String> = tests.iter().filter(|name| runs(name).is_empty()).cloned().collect();
+                let failing = tests.iter().any(|name| runs(name).iter().any(|test| test.is_failure()));
+                let unverified = tests
+                    .iter()
+                    .any(|name| !runs(name).iter().any(|test| test.status == "passed"));
+                let status = if failing {
+                    CoverageStatus::Failing
+                } else if unverified {
+                    CoverageStatus::Incomplete
+                } else {
+                    CoverageStatus::Verified
+                };
+                RequirementCoverage {
+                    requirement: requirement.clone(),
+                    status,
+                    tests: tests.clone(),
+                    missing,
+                }
+            })
+            .collect()
+    }
+
+    /// Share of traced requirements that are verified, `None` without any
+    pub fn coverage_ratio(&self) -> Option<f64> {
+        let coverage = self.coverage();
+        if coverage.is_empty() {
+            return None;
+        }
+        let verified = coverage.iter().filter(|c| c.status == CoverageStatus::Verified).count();
+        Some(verified as f64 / coverage.len() as f64)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let totals = self.totals();
+        json!({
+            "schema_version": REPORT_SCHEMA_VERSION,
+            "name": self.name,
+            "hosts": totals.hosts,
+            "suites": totals.suites,
+            "total_tests": totals.tests,
+            "passed_tests": totals.passed,
+            "failed_tests": totals.failed,
+            "skipped_tests": totals.skipped,
+            "error_tests": totals.errors,
+            "quarantined_failures": totals.quarantined_failures,
+            "warnings": self.warnings,
+            "duplicates": self.duplicates().iter()
+                .map(|d| json!({ "name": d.name, "inputs": d.inputs }))
+                .collect::<Vec<_>>(),
+            "coverage_ratio": self.coverage_ratio(),
+            "requirements": self.coverage().iter()
+                .map(|c| json!({
+                    "requirement": c.requirement,
+                    "status": c.status.to_string(),
+                    "tests": c.tests,
+                    "missing": c.missing,
+                }))
+                .collect::<Vec<_>>(),
+            "inputs": self.inputs.iter()
+                .map(|input| json!({
+                    "source": input.source,
+                    "host": input.host,
+                    "run_id": input.run_id,
+                    "suite": input.suite,
+                    "environment": input.environment,
+                    "results": input.tests.iter()
+                        .map(|t| json!({
+                            "name": t.name,
+                            "status": t.status,
+                            "message": t.message,
+                            "quarantined": t.quarantined,
+                        }))
+                        .collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), CampaignError> {
+        let text = serde_json::to_string_pretty(&self.to_json())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .and_then(|text| fs::write(path, text));
+        text.map_err(|source| CampaignError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Campaign summary followed by one section per input
+    pub fn to_markdown(&self) -> String {
+        let totals = self.totals();
+        let mut md = format!("# {}\n\n", self.name);
+        md.push_str(&format!(
+            "{} hosts, {} suites: {} passed, {} failed, {} skipped, {} errors\n\n",
+            totals.hosts, totals.suites, totals.passed, totals.failed, totals.skipped, totals.errors
+        ));
+        if totals.quarantined_failures > 0 {
+            md.push_str(&format!(
+                "{} quarantined failures, not failing the campaign\n\n",
+                totals.quarantined_failures
+            ));
+        }
+
+        if !self.warnings.is_empty() {
+            md.push_str("## Warnings\n\n");
+            for warning in &self.warnings {
+                md.push_str(&format!("- {}\n", warning));
+            }
+            md.push('\n');
+        }
+
+        let duplicates = self.duplicates();
+        if !duplicates.is_empty() {
+            md.push_str("## Duplicate tests\n\n");
+            for duplicate in &duplicates {
+                md.push_str(&format!("- {}: {}\n", duplicate.name, duplicate.inputs.join(", ")));
+            }
+            md.push('\n');
+        }
+
+        let coverage = self.coverage();
+        if let Some(ratio) = self.coverage_ratio() {
+            let verified = coverage.iter().filter(|c| c.status == CoverageStatus::Verified).count();
+            md.push_str("## Requirements coverage\n\n");
+            md.push_str(&format!(
+                "{} of {} requirements verified ({:.1}%)\n\n",
+                verified,
+                coverage.len(),
+                ratio * 100.0
+            ));
+            md.push_str("| Requirement | Status | Tests | Missing |\n|---|---|---|---|\n");
+            for c in &coverage {
+                md.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    c.requirement,
+                    c.status,
+                    c.tests.join(", "),
+                    c.missing.join(", ")
+                ));
+            }
+            md.push('\n');
+        }
+
+        for input in &self.inputs {
+            md.push_str(&format!("## {}: {}", input.host, input.suite));
+            if let Some(run_id) = &input.run_id {
+                md.push_str(&format!(" (run {})", run_id));
+            }
+            md.push_str("\n\n");
+            if let Some(environment) = &input.environment {
+                md.push_str(&format!("Environment: {}\n\n", environment));
+            }
+            md.push_str("| Test | Status | Details |\n|---|---|---|\n");
+            for test in &input.tests {
+                md.push_str(&format!(
+                    "| {} | {}{} | {} |\n",
+                    test.name.replace('|', "\\|"),
+                    test.status,
+                    if test.quarantined { " (quarantined)" } else { "" },
+                    test.message.as_deref().unwrap_or("").replace('|', "\\|").replace('\n', " ")
+                ));
+            }
+            md.push('\n');
+        }
+        md
+    }
+
+    pub fn write_markdown(&self, path: &Path) -> Result<(), CampaignError> {
+        fs::write(path, self.to_markdown()).map_err(|source| CampaignError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+fn parse_test(result: &Value) -> Option<CampaignTest> {
+    let status = result["status"].as_str()?;
+    if !["passed", "failed", "skipped", "error"].contains(&status) {
+        return None;
+    }
+    Some(CampaignTest {
+        name: result["name"].as_str()?.to_string(),
+        status: status.to_string(),
+        message: result["message"].as_str().map(str::to_string),
+        quarantined: result["quarantined"].as_bool().unwrap_or(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestResult, TestStatus, TestSuiteResult};
+    use std::time::Duration;
+
+    fn report(host: &str, suite: &str, results: Vec<(&str, TestStatus)>) -> String {
+        let results = results
+            .into_iter()
+            .map(|(name, status)| TestResult::new(name, status, Duration::from_millis(10)))
+            .collect();
+        let mut suite = TestSuiteResult::from_results(suite, results, Duration::from_secs(1));
+        suite.environment = Some(
+            TestEnvironmentInfo::new()
+                .temperature_c(25.0)
+                .with(HOST_KEY, host)
+                .with(RUN_ID_KEY, "0007"),
+        );
+        suite.to_json().to_string()
+    }
+
+    fn failed(message: &str) -> TestStatus {
+        TestStatus::Failed(message.to_string())
+    }
+
+    /// Three benches: I2C, SPI and a UART bench whose report is half broken
+    fn campaign(dir: &Path) -> CampaignReport {
+        let i2c = report(
+            "bench-1",
+            "i2c",
+            vec![("init", TestStatus::Passed), ("eeprom read", TestStatus::Passed)],
+        );
+        let spi = report(
+            "bench-2",
+            "spi",
+            vec![
+                ("init", TestStatus::Passed),
+                ("flash erase", failed("verify failed")),
+                ("flash id", TestStatus::Skipped("no flash".to_string())),
+            ],
+        );
+        let mut uart: Value = serde_json::from_str(&report("bench-3", "uart", vec![("loopback", TestStatus::Passed)])).unwrap();
+        uart["results"].as_array_mut().unwrap().push(json!({ "name": "break", "status": "exploded" }));
+
+        let paths = vec![dir.join("i2c.json"), dir.join("spi.json"), dir.join("uart.json"), dir.join("truncated.json")];
+        fs::write(&paths[0], i2c).unwrap();
+        fs::write(&paths[1], spi).unwrap();
+        fs::write(&paths[2], uart.to_string()).unwrap();
+        fs::write(&paths[3], "{\"schema_version\": 1, \"name\": \"gpio\", \"res").unwrap();
+
+        CampaignReport::new("Qualification campaign")
+            .with_requirement("REQ-I2C-1", &["eeprom read", "init"])
+            .with_requirement("REQ-SPI-4", &["flash erase", "flash id"])
+            .with_requirement("REQ-UART-2", &["loopback", "break"])
+            .with_requirement("REQ-UART-3", &["loopback"])
+            .load(&paths)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge_totals_and_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let campaign = campaign(dir.path());
+
+        assert_eq!(campaign.inputs().len(), 3);
+        assert_eq!(campaign.inputs()[0].host, "bench-1");
+        assert_eq!(campaign.inputs()[0].run_id.as_deref(), Some("0007"));
+        assert_eq!(
+            campaign.totals(),
+            CampaignTotals {
+                hosts: 3,
+                suites: 3,
+                tests: 6,
+                passed: 4,
+                failed: 1,
+                skipped: 1,
+                errors: 0,
+                quarantined_failures: 0,
+            }
+        );
+        assert!(!campaign.succeeded());
+        assert_eq!(
+            campaign.duplicates(),
+            vec![DuplicateTest {
+                name: "init".to_string(),
+                inputs: vec!["bench-1/i2c".to_string(), "bench-2/spi".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_corrupt_inputs_are_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        let campaign = campaign(dir.path());
+
+        let warnings = campaign.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].ends_with("uart.json: skipped malformed result #1"));
+        assert!(warnings[1].contains("truncated.json: skipped, not a JSON report"));
+        assert!(campaign.to_markdown().contains("## Warnings\n\n- "));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let dir = tempfile::tempdir().unwrap();
+        let campaign = campaign(dir.path());
+
+        let coverage = campaign.coverage();
+        let statuses: Vec<_> = coverage.iter().map(|c| (c.requirement.as_str(), c.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("REQ-I2C-1", CoverageStatus::Verified),
+                ("REQ-SPI-4", CoverageStatus::Failing),
+                ("REQ-UART-2", CoverageStatus::Incomplete),
+                ("REQ-UART-3", CoverageStatus::Verified),
+            ]
+        );
+        assert_eq!(coverage[2].missing, vec!["break"]);
+        assert_eq!(campaign.coverage_ratio(), Some(0.5));
+
+        let md = campaign.to_markdown();
+        assert!(md.contains("2 of 4 requirements verified (50.0%)\n"));
+        assert!(md.contains("| REQ-UART-2 | incomplete | loopback, break | break |\n"));
+        assert!(md.contains("## bench-2: spi (run 0007)\n"));
+
+        let json = campaign.to_json();
+        assert_eq!(json["coverage_ratio"], 0.5);
+        assert_eq!(json["requirements"][1]["status"], "failing");
+        assert_eq!(json["duplicates"][0]["name"], "init");
+        assert_eq!(json["inputs"][1]["results"][1]["message"], "verify failed");
+    }
+
+    #[test]
+    fn test_schema_mismatch_is_an_error() {
+        let mut campaign = CampaignReport::new("campaign");
+        let mut old: Value = serde_json::from_str(&report("bench-1", "i2c", vec![])).unwrap();
+        old["schema_version"] = json!(0);
+
+        let error = campaign.ingest_str("old.json", &old.to_string()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "report old.json has schema version 0, expected {}; regenerate it with a matching framework",
+                REPORT_SCHEMA_VERSION
+            )
+        );
+        assert!(matches!(
+            campaign.ingest_str("bare.json", "{\"name\": \"x\", \"results\": []}"),
+            Err(CampaignError::SchemaVersion { found: None, .. })
+        ));
+        assert!(campaign.inputs().is_empty());
+    }
+}
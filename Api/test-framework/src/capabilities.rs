@@ -0,0 +1,236 @@
+/*
+ * Optional Interface Capabilities
+ * Copyright (C) 2024
+ */
+
+use crate::{DiagMutex, HardwareInterface, TestFn};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex as StdMutex};
+
+tokio::task_local! {
+    static SKIP_REASON: Arc<StdMutex<Option<String>>>;
+}
+
+/// Optional feature an interface may support beyond `HardwareInterface`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    /// `read_exact` fills the whole buffer or fails
+    ReadExact,
+    /// Baud rate, modem lines and breaks through `LineControl`
+    LineControl,
+    /// Full-duplex `transfer`
+    Transfer,
+    /// Payloads above the maximum transfer size go through
+    /// `read_chunked`/`write_chunked`
+    ChunkedIo,
+    /// Device power can be switched by the test bench
+    PowerManaged,
+    /// Device can run a built-in self test
+    SelfTest,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::ReadExact,
+        Capability::LineControl,
+        Capability::Transfer,
+        Capability::ChunkedIo,
+        Capability::PowerManaged,
+        Capability::SelfTest,
+    ];
+
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Set of `Capability`s, usually built with `capabilities!`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CapabilitySet(u32);
+
+impl CapabilitySet {
+    pub const EMPTY: CapabilitySet = CapabilitySet(0);
+
+    pub const fn with(self, capability: Capability) -> Self {
+        CapabilitySet(self.0 | capability.bit())
+    }
+
+    pub const fn contains(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    pub const fn union(self, other: CapabilitySet) -> Self {
+        CapabilitySet(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: CapabilitySet) -> Self {
+        CapabilitySet(self.0 & other.0)
+    }
+
+    /// Capabilities of `required` not in this set
+    pub const fn missing(self, required: CapabilitySet) -> Self {
+        CapabilitySet(required.0 & !self.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Capability> {
+        Capability::ALL.into_iter().filter(move |capability| self.contains(*capability))
+    }
+}
+
+impl From<Capability> for CapabilitySet {
+    fn from(capability: Capability) -> Self {
+        CapabilitySet::EMPTY.with(capability)
+    }
+}
+
+impl FromIterator<Capability> for CapabilitySet {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        iter.into_iter().fold(CapabilitySet::EMPTY, CapabilitySet::with)
+    }
+}
+
+impl fmt::Display for CapabilitySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<String> = self.iter().map(|capability| capability.to_string()).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// Build a `CapabilitySet` from `Capability` names
+///
+/// ```ignore
+/// fn capabilities(&self) -> CapabilitySet {
+///     capabilities![ReadExact, Transfer]
+/// }
+/// ```
+#[macro_export]
+macro_rules! capabilities {
+    ($($capability:ident),* $(,)?) => {
+        $crate::CapabilitySet::EMPTY $(.with($crate::Capability::$capability))*
+    };
+}
+
+/// Skip the running test unless `available` covers `required`, returning
+/// whether it was skipped. The runner reports the test as skipped with the
+/// missing capabilities as reason once it returns `Ok`.
+pub fn skip_unless_supported(available: CapabilitySet, required: CapabilitySet) -> bool {
+    let missing = available.missing(required);
+    if missing.is_empty() {
+        return false;
+    }
+    let reason = format!("requires {}, not supported by the interface", missing);
+    // Outside a runner there is nobody to report the skip to
+    let _ = SKIP_REASON.try_with(|skip| *skip.lock().unwrap() = Some(reason));
+    true
+}
+
+/// Run `test` only if the interface supports `required`, skipping it
+/// otherwise
+pub fn requires_capability<T>(required: impl Into<CapabilitySet>, test: TestFn<T>) -> TestFn<T>
+where
+    T: HardwareInterface + Send + 'static,
+{
+    let required = required.into();
+    Box::new(move |interface: Arc<DiagMutex<T>>| {
+        Box::pin(async move {
+            let available = interface.lock().await.capabilities();
+            if skip_unless_supported(available, required) {
+                return Ok(());
+            }
+            test(interface).await
+        })
+    })
+}
+
+/// Run `future` noting whether the test it runs skipped itself
+pub(crate) async fn record_skip<F: Future>(future: F) -> (F::Output, Option<String>) {
+    let reason = Arc::new(StdMutex::new(None));
+    let output = SKIP_REASON.scope(reason.clone(), future).await;
+    let reason = reason.lock().unwrap().take();
+    (output, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{FakeLoopback, FakeRegisterMap};
+    use crate::{
+        suite, Counted, EventTimeline, I2CInterface, OperationStats, Profiled, Redundant, SPIInterface, TestRunner,
+        TestStatus, Traced, UARTInterface,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_set_operations() {
+        let set = capabilities![ReadExact, Transfer];
+        assert!(set.contains(Capability::Transfer) && !set.contains(Capability::SelfTest));
+        assert_eq!(set.missing(capabilities![Transfer, SelfTest]), Capability::SelfTest.into());
+        assert_eq!(set.intersection(capabilities![Transfer, ChunkedIo]), capabilities![Transfer]);
+        assert_eq!(set.to_string(), "ReadExact, Transfer");
+        assert_eq!(CapabilitySet::EMPTY.to_string(), "none");
+        assert_eq!(Capability::ALL.into_iter().collect::<CapabilitySet>().iter().count(), 6);
+    }
+
+    #[test]
+    fn test_builtin_interfaces() {
+        assert_eq!(I2CInterface::with_default_config().capabilities(), capabilities![ReadExact, Transfer, ChunkedIo]);
+        assert_eq!(UARTInterface::with_default_config().capabilities(), capabilities![ReadExact, LineControl, ChunkedIo]);
+        assert_eq!(SPIInterface::with_default_config().capabilities(), capabilities![Transfer]);
+    }
+
+    #[test]
+    fn test_wrappers_forward() {
+        let registers = FakeRegisterMap::new().capabilities();
+        assert_eq!(Counted::new(FakeRegisterMap::new(), OperationStats::new()).capabilities(), registers);
+        let timeline = EventTimeline::new(16);
+        assert_eq!(Traced::new(FakeRegisterMap::new(), "sensor", &timeline).capabilities(), registers);
+        assert_eq!(Profiled::new(FakeRegisterMap::new()).capabilities(), registers);
+
+        // Either side may be active, so a pair only supports what both do
+        let pair = Redundant::new(UARTInterface::with_default_config(), UARTInterface::with_default_config());
+        assert_eq!(pair.capabilities(), capabilities![ReadExact, LineControl, ChunkedIo]);
+        assert!(!Redundant::new(FakeLoopback::new(), FakeLoopback::new()).capabilities().contains(Capability::Transfer));
+    }
+
+    suite!(gated(uart: FakeLoopback) {
+        "echo" [requires: ReadExact] => { uart.initialize().await },
+        "self test" [tags: slow] [requires: SelfTest, ReadExact] => {
+            Err(crate::HardwareError::OperationFailed("must not run".to_string()))
+        },
+    });
+
+    #[tokio::test]
+    async fn test_capability_gated_skipping() {
+        let runner = TestRunner::new(FakeLoopback::new(), Duration::from_secs(1), 0, Duration::ZERO);
+        let suite = runner.run_cases("gated", gated()).await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert_eq!(
+            suite.results[1].status,
+            TestStatus::Skipped("requires SelfTest, not supported by the interface".to_string())
+        );
+
+        let runner = TestRunner::new(FakeLoopback::new(), Duration::from_secs(1), 0, Duration::ZERO);
+        let transfer = requires_capability(Capability::Transfer, Box::new(|_| Box::pin(async { Ok(()) })));
+        let suite = runner.run_test_suite("gated", vec![("transfer", transfer)]).await;
+        assert_eq!(
+            suite.results[0].status,
+            TestStatus::Skipped("requires Transfer, not supported by the interface".to_string())
+        );
+        assert_eq!(suite.skipped_tests, 1);
+    }
+}
@@ -0,0 +1,399 @@
+/*
+ * Timer Drift and Timestamp Sanity Checks for Onboard Clocks
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    declare_test_timeout, DiagMutex, HardwareError, HardwareInterface, HardwareResult, ReportAppendix, TestFn,
+    TestRunner, TestSuiteResult, TestTimeout,
+};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// Boxed future returned by a device clock closure
+pub type ClockFuture = Pin<Box<dyn Future<Output = HardwareResult<Duration>> + Send>>;
+
+type DeviceClockFn<T> = Box<dyn Fn(Arc<DiagMutex<T>>) -> ClockFuture + Send + Sync>;
+type ReferenceClockFn = Box<dyn Fn() -> Duration + Send + Sync>;
+
+/// Paired reading of the device clock and the reference clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSample {
+    pub reference: Duration,
+    pub device: Duration,
+}
+
+impl ClockSample {
+    /// Device minus reference time, in seconds
+    fn offset(&self) -> f64 {
+        self.device.as_secs_f64() - self.reference.as_secs_f64()
+    }
+}
+
+/// Jump of the device clock between two samples beyond the fitted drift
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockStep {
+    /// Reference time of the sample after the jump
+    pub at: Duration,
+    /// Size of the jump in seconds, negative if the clock went back
+    pub seconds: f64,
+}
+
+/// Outcome of a clock drift run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockDriftReport {
+    pub samples: usize,
+    /// Fitted drift rate with steps removed, positive if the device runs fast
+    pub drift_ppm: f64,
+    /// Offset accumulated over the run, steps included, in seconds
+    pub end_offset: f64,
+    pub steps: Vec<ClockStep>,
+    /// Reference time covered by the samples
+    pub span: Duration,
+}
+
+impl ClockDriftReport {
+    /// Fit the drift of `samples`, flagging interval offsets that deviate
+    /// from the typical rate by more than `step_threshold` as steps
+    pub fn analyze(samples: &[ClockSample], step_threshold: Duration) -> Self {
+        let (first, last) = match (samples.first(), samples.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return Self {
+                    samples: 0,
+                    drift_ppm: 0.0,
+                    end_offset: 0.0,
+                    steps: Vec::new(),
+                    span: Duration::ZERO,
+                }
+            }
+        };
+
+        // The median interval rate is robust against the intervals holding steps
+        let mut rates: Vec<f64> = samples
+            .windows(2)
+            .filter_map(|pair| {
+                let dx = pair[1].reference.as_secs_f64() - pair[0].reference.as_secs_f64();
+                (dx > 0.0).then(|| (pair[1].offset() - pair[0].offset()) / dx)
+            })
+            .collect();
+        rates.sort_by(|a, b| a.total_cmp(b));
+        let typical = rates.get(rates.len() / 2).copied().unwrap_or(0.0);
+
+        let mut steps = Vec::new();
+        let mut correction = 0.0;
+        let mut points = vec![(0.0, 0.0)];
+        for pair in samples.windows(2) {
+            let dx = pair[1].reference.as_secs_f64() - pair[0].reference.as_secs_f64();
+            let jump = pair[1].offset() - pair[0].offset() - typical * dx;
+            if jump.abs() > step_threshold.as_secs_f64() {
+                steps.push(ClockStep { at: pair[1].reference, seconds: jump });
+                correction += jump;
+            }
+            points.push((
+                pair[1].reference.as_secs_f64() - first.reference.as_secs_f64(),
+                pair[1].offset() - first.offset() - correction,
+            ));
+        }
+
+        Self {
+            samples: samples.len(),
+            drift_ppm: slope(&points) * 1e6,
+            end_offset: last.offset() - first.offset(),
+            steps,
+            span: last.reference.saturating_sub(first.reference),
+        }
+    }
+
+    pub fn appendix(&self) -> ReportAppendix {
+        ReportAppendix::new("Clock drift", &self.to_string())
+    }
+}
+
+/// Least-squares slope through `points`
+fn slope(points: &[(f64, f64)]) -> f64 {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let variance: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+impl fmt::Display for ClockDriftReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} samples over {:?}", self.samples, self.span)?;
+        writeln!(f, "drift: {:+.3} ppm", self.drift_ppm)?;
+        writeln!(f, "offset at end: {:+.6} s", self.end_offset)?;
+        writeln!(f, "steps: {}", self.steps.len())?;
+        for step in &self.steps {
+            writeln!(f, "  {:+.6} s at {:?}", step.seconds, step.at)?;
+        }
+        Ok(())
+    }
+}
+
+/// Soak check sampling a device clock against a reference clock
+///
+/// Both clocks are sampled every `interval` for `duration`. The device clock
+/// is read through the interface lock; the reference defaults to the time
+/// since the run started, which stands in for the OBC clock.
+pub struct ClockDriftTest<T> {
+    read_device: DeviceClockFn<T>,
+    read_reference: Option<ReferenceClockFn>,
+    duration: Duration,
+    interval: Duration,
+    max_drift_ppm: f64,
+    max_end_offset: Option<Duration>,
+    step_threshold: Duration,
+    allow_steps: bool,
+    timeout: Option<TestTimeout>,
+}
+
+impl<T: HardwareInterface + 'static> ClockDriftTest<T> {
+    pub fn new<F>(read_device: F, duration: Duration, interval: Duration) -> Self
+    where
+        F: Fn(Arc<DiagMutex<T>>) -> ClockFuture + Send + Sync + 'static,
+    {
+        Self {
+            read_device: Box::new(read_device),
+            read_reference: None,
+            duration,
+            interval,
+            max_drift_ppm: 100.0,
+            max_end_offset: None,
+            step_threshold: Duration::from_millis(10),
+            allow_steps: false,
+            timeout: None,
+        }
+    }
+
+    pub fn with_reference<F>(mut self, read_reference: F) -> Self
+    where
+        F: Fn() -> Duration + Send + Sync + 'static,
+    {
+        self.read_reference = Some(Box::new(read_reference));
+        self
+    }
+
+    pub fn with_max_drift_ppm(mut self, ppm: f64) -> Self {
+        self.max_drift_ppm = ppm;
+        self
+    }
+
+    pub fn with_max_end_offset(mut self, offset: Duration) -> Self {
+        self.max_end_offset = Some(offset);
+        self
+    }
+
+    /// Smallest jump between samples reported as a step
+    pub fn with_step_threshold(mut self, threshold: Duration) -> Self {
+        self.step_threshold = threshold;
+        self
+    }
+
+    /// Report steps without failing, e.g. for clocks disciplined by GPS
+    pub fn with_steps_allowed(mut self, allowed: bool) -> Self {
+        self.allow_steps = allowed;
+        self
+    }
+
+    /// Timeout of the generated test, by default the sampling duration plus
+    /// one interval rather than the runner's per-test timeout
+    pub fn with_timeout(mut self, timeout: TestTimeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sample both clocks for the configured duration and analyze the result
+    pub async fn measure(&self, interface: Arc<DiagMutex<T>>) -> HardwareResult<ClockDriftReport> {
+        if self.interval.is_zero() {
+            return Err(HardwareError::InvalidParameter("clock sample interval must be non-zero".to_string()));
+        }
+        let start = Instant::now();
+        let mut samples = Vec::new();
+        let mut next = start;
+        while next.duration_since(start) <= self.duration {
+            sleep_until(next).await;
+            let reference = match &self.read_reference {
+                Some(read) => read(),
+                None => start.elapsed(),
+            };
+            let device = (self.read_device)(interface.clone()).await?;
+            samples.push(ClockSample { reference, device });
+            next += self.interval;
+        }
+        Ok(ClockDriftReport::analyze(&samples, self.step_threshold))
+    }
+
+    /// Threshold violations in `report`, if any
+    pub fn check(&self, report: &ClockDriftReport) -> HardwareResult<()> {
+        let mut problems = Vec::new();
+        if report.drift_ppm.abs() > self.max_drift_ppm {
+            problems.push(format!("drift {:+.3} ppm exceeds {} ppm", report.drift_ppm, self.max_drift_ppm));
+        }
+        if let Some(max) = self.max_end_offset {
+            if report.end_offset.abs() > max.as_secs_f64() {
+                problems.push(format!("offset {:+.6} s exceeds {:?}", report.end_offset, max));
+            }
+        }
+        if !self.allow_steps && !report.steps.is_empty() {
+            problems.push(format!("{} clock steps", report.steps.len()));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(HardwareError::OperationFailed(problems.join("; ")))
+        }
+    }
+
+    /// Generated test named `<base>::clock_drift`; the report is kept in
+    /// the returned slot whether or not the thresholds hold
+    pub fn case(self, base_name: &str) -> ((String, TestFn<T>), Arc<StdMutex<Option<ClockDriftReport>>>) {
+        let slot = Arc::new(StdMutex::new(None));
+        let report_slot = slot.clone();
+        let timeout = self.timeout.unwrap_or(TestTimeout::After(self.duration + self.interval));
+        let test: TestFn<T> = Box::new(move |interface| {
+            Box::pin(async move {
+                declare_test_timeout(timeout);
+                let report = self.measure(interface).await?;
+                let result = self.check(&report);
+                *report_slot.lock().unwrap() = Some(report);
+                result
+            })
+        });
+        ((format!("{}::clock_drift", base_name), test), slot)
+    }
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run a clock drift check as a single-test suite with the drift report
+    /// in an appendix
+    pub async fn run_clock_drift(&self, name: &str, test: ClockDriftTest<T>) -> TestSuiteResult {
+        let ((case_name, test_fn), report) = test.case(name);
+        let mut suite = self.run_test_suite(name, vec![(case_name.as_str(), test_fn)]).await;
+        if let Some(report) = report.lock().unwrap().as_ref() {
+            suite.add_appendix(report.appendix());
+        }
+        suite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InterfaceStatus, TestStatus};
+    use async_trait::async_trait;
+
+    /// RTC running `ppm` fast that jumps by `step` once `step_at` has passed
+    struct FakeRtc {
+        start: Instant,
+        ppm: f64,
+        step: Option<(Duration, f64)>,
+    }
+
+    impl FakeRtc {
+        fn now(&self) -> Duration {
+            let elapsed = self.start.elapsed();
+            let mut seconds = elapsed.as_secs_f64() * (1.0 + self.ppm * 1e-6);
+            if let Some((at, jump)) = self.step {
+                if elapsed >= at {
+                    seconds += jump;
+                }
+            }
+            Duration::from_secs_f64(1000.0 + seconds)
+        }
+    }
+
+    #[async_trait]
+    impl HardwareInterface for FakeRtc {
+        async fn initialize(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        async fn deinitialize(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        fn is_initialized(&self) -> bool {
+            true
+        }
+
+        async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+            Ok(InterfaceStatus {
+                initialized: true,
+                error_count: 0,
+                last_error: None,
+                uptime: Duration::from_secs(0),
+            })
+        }
+    }
+
+    fn rtc_test() -> ClockDriftTest<FakeRtc> {
+        let read = |rtc: Arc<DiagMutex<FakeRtc>>| -> ClockFuture { Box::pin(async move { Ok(rtc.lock().await.now()) }) };
+        ClockDriftTest::new(read, Duration::from_secs(3600), Duration::from_secs(60))
+    }
+
+    fn runner(ppm: f64, step: Option<(Duration, f64)>) -> TestRunner<FakeRtc> {
+        let rtc = FakeRtc { start: Instant::now(), ppm, step };
+        TestRunner::new(rtc, Duration::from_secs(1), 0, Duration::ZERO)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fits_drift_rate() {
+        let suite = runner(25.0, None).run_clock_drift("rtc", rtc_test().with_max_drift_ppm(30.0)).await;
+
+        assert_eq!(suite.results[0].name, "rtc::clock_drift");
+        assert_eq!(suite.passed_tests, 1);
+        let body = &suite.appendices[0].body;
+        assert!(body.starts_with("61 samples over 3600s\ndrift: +25.000 ppm\noffset at end: +0.090000 s\nsteps: 0\n"), "{}", body);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_detects_step_and_fails_thresholds() {
+        let step = Some((Duration::from_secs(1830), -0.5));
+        let suite = runner(-40.0, step).run_clock_drift("rtc", rtc_test().with_max_drift_ppm(30.0)).await;
+
+        assert!(matches!(&suite.results[0].status,
+            TestStatus::Error(msg) if msg.contains("drift -40.000 ppm exceeds 30 ppm") && msg.contains("1 clock steps")));
+        assert!(suite.appendices[0].body.contains("-0.500000 s at 1860s"));
+    }
+
+    #[test]
+    fn test_analyze_excludes_steps_from_fit() {
+        // 50 ppm fast with a +2 s jump before the fourth sample
+        let samples: Vec<ClockSample> = (0..8u64)
+            .map(|i| {
+                let reference = i as f64 * 100.0;
+                let jump = if i >= 3 { 2.0 } else { 0.0 };
+                ClockSample {
+                    reference: Duration::from_secs_f64(reference),
+                    device: Duration::from_secs_f64(reference * 1.00005 + jump),
+                }
+            })
+            .collect();
+
+        let report = ClockDriftReport::analyze(&samples, Duration::from_millis(10));
+
+        assert!((report.drift_ppm - 50.0).abs() < 1e-3, "{}", report.drift_ppm);
+        assert_eq!(report.steps.len(), 1);
+        assert_eq!(report.steps[0].at, Duration::from_secs(300));
+        assert!((report.steps[0].seconds - 2.0).abs() < 1e-6);
+        assert!((report.end_offset - 2.035).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_analyze_without_samples() {
+        let report = ClockDriftReport::analyze(&[], Duration::from_millis(10));
+        assert_eq!(report.samples, 0);
+        assert_eq!(report.drift_ppm, 0.0);
+    }
+}
@@ -0,0 +1,543 @@
+/*
+ * Live Console Reporter for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use crate::{RunnerEvent, TestObserver, TestStatus, TestSuiteResult};
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Environment variables that disable colored output
+pub const NO_COLOR_VARS: [&str; 2] = ["NO_COLOR", "HWTEST_NO_COLOR"];
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const RESET: &str = "\x1b[0m";
+
+/// Number of lines in the live summary block
+const LIVE_LINES: usize = 3;
+
+/// Redraw interval of the live summary, keeping the elapsed time current
+/// while a long test runs
+const LIVE_TICK: Duration = Duration::from_millis(200);
+
+/// Terminal width assumed when `COLUMNS` is not set
+const DEFAULT_WIDTH: usize = 80;
+
+/// Observer rendering suite progress on a console
+///
+/// On a terminal the summary is redrawn in place with ANSI cursor control,
+/// on every event and every `LIVE_TICK` in between; otherwise one line is
+/// printed per finished test.
+pub struct ConsoleReporter {
+    state: Arc<Mutex<ConsoleState>>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+struct ConsoleState {
+    out: Box<dyn Write + Send>,
+    live: bool,
+    color: bool,
+    /// Live lines are cut to this many columns so none wraps, which would
+    /// throw off the cursor-up redraw
+    width: usize,
+    drawn: bool,
+    suite_start: Option<Instant>,
+    current: Option<String>,
+    total: usize,
+    finished: usize,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    errors: usize,
+    last_failure: Option<String>,
+}
+
+impl ConsoleReporter {
+    pub fn new(out: Box<dyn Write + Send>, live: bool, color: bool) -> Self {
+        let width = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_WIDTH);
+        let state = ConsoleState {
+            out,
+            live,
+            color,
+            width,
+            drawn: false,
+            suite_start: None,
+            current: None,
+            total: 0,
+            finished: 0,
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+            errors: 0,
+            last_failure: None,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            ticker: None,
+        }
+    }
+
+    /// Reporter on stdout, live when stdout is a terminal, colored unless
+    /// one of `NO_COLOR_VARS` is set
+    pub fn stdout() -> Self {
+        let live = io::stdout().is_terminal();
+        let color = live && !NO_COLOR_VARS.iter().any(|v| std::env::var_os(v).is_some());
+        Self::new(Box::new(io::stdout()), live, color)
+    }
+
+    pub fn with_color(self, color: bool) -> Self {
+        self.state.lock().unwrap().color = color;
+        self
+    }
+
+    /// Terminal width in columns, instead of `COLUMNS`
+    pub fn with_width(self, width: usize) -> Self {
+        self.state.lock().unwrap().width = width;
+        self
+    }
+
+    /// Final result table with aligned columns and a summary line
+    pub fn render_table(&self, result: &TestSuiteResult) -> String {
+        self.state.lock().unwrap().render_table(result)
+    }
+
+    /// Redraw the live summary every `LIVE_TICK` until the suite finishes;
+    /// needs a tokio runtime, without one the summary only moves on events
+    fn start_ticker(&mut self) {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let state = self.state.clone();
+        self.stop_ticker();
+        self.ticker = Some(runtime.spawn(async move {
+            let mut interval = tokio::time::interval(LIVE_TICK);
+            // The first tick completes at once, and the event just drew
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.lock().unwrap().draw_live() {
+                    log::warn!("Console reporter output failed: {}", e);
+                    return;
+                }
+            }
+        }));
+    }
+
+    fn stop_ticker(&mut self) {
+        if let Some(ticker) = self.ticker.take() {
+            ticker.abort();
+        }
+    }
+
+    fn handle(&mut self, event: &RunnerEvent) -> io::Result<()> {
+        if let RunnerEvent::SuiteFinished(_) = event {
+            self.stop_ticker();
+        }
+        let live = {
+            let mut state = self.state.lock().unwrap();
+            state.handle(event)?;
+            state.live
+        };
+        if let (RunnerEvent::SuiteStarted { .. }, true) = (event, live) {
+            self.start_ticker();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleReporter {
+    fn drop(&mut self) {
+        self.stop_ticker();
+    }
+}
+
+impl ConsoleState {
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn status_label(&self, status: &TestStatus) -> String {
+        match status {
+            TestStatus::Passed => self.paint("PASS", GREEN),
+            TestStatus::Failed(_) => self.paint("FAIL", RED),
+            TestStatus::Skipped(_) => self.paint("SKIP", YELLOW),
+            TestStatus::Error(_) => self.paint("ERROR", MAGENTA),
+        }
+    }
+
+    fn draw_live(&mut self) -> io::Result<()> {
+        if self.drawn {
+            write!(self.out, "\x1b[{}A", LIVE_LINES)?;
+        }
+        let elapsed = self.suite_start.map(|s| s.elapsed()).unwrap_or_default();
+        let lines = [
+            format!(
+                "Running: {} [{}/{}] {:.1?}",
+                self.current.as_deref().unwrap_or("-"),
+                self.finished,
+                self.total,
+                elapsed
+            ),
+            format!(
+                "Passed: {}  Failed: {}  Skipped: {}  Errors: {}",
+                self.paint(&self.passed.to_string(), GREEN),
+                self.paint(&self.failed.to_string(), RED),
+                self.paint(&self.skipped.to_string(), YELLOW),
+                self.paint(&self.errors.to_string(), MAGENTA)
+            ),
+            format!("Last failure: {}", self.last_failure.as_deref().unwrap_or("-")),
+        ];
+        // The last column stays free, as some terminals wrap on filling it
+        let width = self.width.saturating_sub(1);
+        for line in &lines {
+            write!(self.out, "\r\x1b[2K{}\n", truncate_visible(line, width))?;
+        }
+        self.drawn = true;
+        self.out.flush()
+    }
+
+    fn handle(&mut self, event: &RunnerEvent) -> io::Result<()> {
+        match event {
+            RunnerEvent::SuiteStarted { name, total_tests } => {
+                // A reused reporter starts over below the previous suite's table
+                self.suite_start = Some(Instant::now());
+                self.current = None;
+                self.total = *total_tests;
+                self.drawn = false;
+                self.finished = 0;
+                self.passed = 0;
+                self.failed = 0;
+                self.skipped = 0;
+                self.errors = 0;
+                self.last_failure = None;
+                writeln!(self.out, "Running suite {} ({} tests)", name, total_tests)?;
+            }
+            RunnerEvent::TestStarted { name } => {
+                self.current = Some(name.clone());
+            }
+            RunnerEvent::TestFinished(result) => {
+                self.finished += 1;
+                match &result.status {
+                    TestStatus::Passed => self.passed += 1,
+                    TestStatus::Failed(msg) => {
+                        self.failed += 1;
+                        self.last_failure = Some(format!("{}: {}", result.name, msg));
+                    }
+                    TestStatus::Error(msg) => {
+                        self.errors += 1;
+                        self.last_failure = Some(format!("{}: {}", result.name, msg));
+                    }
+                    TestStatus::Skipped(_) => self.skipped += 1,
+                }
+                if !self.live {
+                    let width = self.total.to_string().len();
+                    writeln!(
+                        self.out,
+                        "[{:>width$}/{}] {} {}{} ({:?})",
+                        self.finished,
+                        self.total,
+                        self.status_label(&result.status),
+                        result.name,
+                        if result.manual { " (manual)" } else { "" },
+                        result.duration,
+                        width = width
+                    )?;
+                    if result.manual {
+                        for note in result.operator_notes() {
+                            writeln!(self.out, "{:>indent$}operator: {}", "", note, indent = 2 * width + 4)?;
+                        }
+                    }
+                }
+            }
+            RunnerEvent::SuiteFinished(result) => {
+                self.current = None;
+                if self.live {
+                    self.draw_live()?;
+                }
+                let table = self.render_table(result);
+                write!(self.out, "{}", table)?;
+                return self.out.flush();
+            }
+        }
+        if self.live {
+            self.draw_live()?;
+        }
+        Ok(())
+    }
+
+    fn render_table(&self, result: &TestSuiteResult) -> String {
+        let name_width = result
+            .results
+            .iter()
+            .map(|r| r.name.len())
+            .chain(std::iter::once("Test".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut table = format!("\n{:<name_width$}  {:<6}  {:>12}  Details\n", "Test", "Status", "Duration", name_width = name_width);
+        table.push_str(&format!("{}\n", "-".repeat(name_width + 36)));
+        for r in &result.results {
+            let label = match &r.status {
+                TestStatus::Passed => "PASS",
+                TestStatus::Failed(_) => "FAIL",
+                TestStatus::Skipped(_) => "SKIP",
+                TestStatus::Error(_) => "ERROR",
+            };
+            // Pad before painting so escape codes don't break alignment
+            let padded = format!("{:<6}", label);
+            let painted = match &r.status {
+                TestStatus::Passed => self.paint(&padded, GREEN),
+                TestStatus::Failed(_) => self.paint(&padded, RED),
+                TestStatus::Skipped(_) => self.paint(&padded, YELLOW),
+                TestStatus::Error(_) => self.paint(&padded, MAGENTA),
+            };
+            let mut details = match &r.status {
+                TestStatus::Passed => String::new(),
+                TestStatus::Failed(msg) | TestStatus::Skipped(msg) | TestStatus::Error(msg) => msg.clone(),
+            };
+            if r.manual {
+                let mut parts = vec!["[manual]".to_string()];
+                parts.extend((!details.is_empty()).then_some(details));
+                parts.extend(r.operator_notes().map(|note| format!("operator: {}", note)));
+                details = parts.join(" ");
+            }
+            if r.quarantined {
+                details = format!("[quarantined] {}", details).trim_end().to_string();
+            }
+            table.push_str(
+                format!(
+                    "{:<name_width$}  {}  {:>12}  {}",
+                    r.name,
+                    painted,
+                    format!("{:?}", r.duration),
+                    details,
+                    name_width = name_width
+                )
+                .trim_end(),
+            );
+            table.push('\n');
+        }
+        table.push_str(&format!(
+            "\n{}: {} passed, {} failed, {} skipped, {} errors in {:?}\n",
+            result.name,
+            result.passed_tests,
+            result.failed_tests,
+            result.skipped_tests,
+            result.error_tests,
+            result.total_duration
+        ));
+        if result.quarantined_failures > 0 {
+            table.push_str(&format!("{} quarantined failures\n", result.quarantined_failures));
+        }
+        table
+    }
+}
+
+/// `line` cut to `width` visible characters, not counting ANSI escape
+/// sequences; a cut line ends with a color reset in case it was colored
+fn truncate_visible(line: &str, width: usize) -> String {
+    let mut cut = String::new();
+    let mut visible = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            cut.push(c);
+            for c in chars.by_ref() {
+                cut.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        if visible == width {
+            if cut.contains('\x1b') {
+                cut.push_str(RESET);
+            }
+            return cut;
+        }
+        cut.push(c);
+        visible += 1;
+    }
+    cut
+}
+
+impl TestObserver for ConsoleReporter {
+    fn on_event(&mut self, event: &RunnerEvent) {
+        if let Err(e) = self.handle(event) {
+            log::warn!("Console reporter output failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestResult;
+
+    /// Cloneable writer capturing output for assertions
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn suite_result() -> TestSuiteResult {
+        let results = vec![
+            TestResult::new("init", TestStatus::Passed, Duration::from_millis(12)),
+            TestResult::new("read_status", TestStatus::Failed("2 errors reported".to_string()), Duration::from_millis(3)),
+            TestResult::new("burn", TestStatus::Skipped("requires operator".to_string()), Duration::ZERO),
+        ];
+        TestSuiteResult::from_results("eeprom", results, Duration::from_millis(15))
+    }
+
+    #[test]
+    fn test_plain_progress_lines() {
+        let buffer = SharedBuffer::default();
+        let mut reporter = ConsoleReporter::new(Box::new(buffer.clone()), false, false);
+        let suite = suite_result();
+
+        reporter.on_event(&RunnerEvent::SuiteStarted { name: "eeprom".to_string(), total_tests: 3 });
+        for result in &suite.results {
+            reporter.on_event(&RunnerEvent::TestStarted { name: result.name.clone() });
+            reporter.on_event(&RunnerEvent::TestFinished(result.clone()));
+        }
+
+        let output = buffer.contents();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "Running suite eeprom (3 tests)");
+        assert_eq!(lines[1], "[1/3] PASS init (12ms)");
+        assert_eq!(lines[2], "[2/3] FAIL read_status (3ms)");
+        assert_eq!(lines[3], "[3/3] SKIP burn (0ns)");
+        assert!(!output.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_final_table_alignment() {
+        let reporter = ConsoleReporter::new(Box::new(io::sink()), false, false);
+        let table = reporter.render_table(&suite_result());
+        let lines: Vec<_> = table.lines().collect();
+
+        assert_eq!(lines[1], "Test         Status      Duration  Details");
+        assert_eq!(lines[3], "init         PASS            12ms");
+        assert_eq!(lines[4], "read_status  FAIL             3ms  2 errors reported");
+        assert_eq!(lines[5], "burn         SKIP             0ns  requires operator");
+        assert_eq!(lines[7], "eeprom: 1 passed, 1 failed, 1 skipped, 0 errors in 15ms");
+    }
+
+    #[test]
+    fn test_final_table_colors() {
+        let reporter = ConsoleReporter::new(Box::new(io::sink()), false, true);
+        let table = reporter.render_table(&suite_result());
+
+        assert!(table.contains("\x1b[32mPASS  \x1b[0m"));
+        assert!(table.contains("\x1b[31mFAIL  \x1b[0m"));
+    }
+
+    /// Live lines drawn so far, without the cursor control
+    fn live_lines(output: &str) -> Vec<&str> {
+        output
+            .split("\r\x1b[2K")
+            .skip(1)
+            .map(|line| line.split('\n').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_live_lines_fit_terminal() {
+        let buffer = SharedBuffer::default();
+        let mut reporter = ConsoleReporter::new(Box::new(buffer.clone()), true, true).with_width(24);
+        let failed = TestResult::new(
+            "conformance::burst_read",
+            TestStatus::Error("Test failed: TimeoutError after 3 attempts".to_string()),
+            Duration::ZERO,
+        );
+
+        reporter.on_event(&RunnerEvent::SuiteStarted { name: "eeprom".to_string(), total_tests: 1 });
+        reporter.on_event(&RunnerEvent::TestStarted { name: failed.name.clone() });
+        reporter.on_event(&RunnerEvent::TestFinished(failed));
+
+        let output = buffer.contents();
+        let lines = live_lines(&output);
+        assert_eq!(lines[lines.len() - 3], "Running: conformance::b");
+        // Escape codes take no columns, and the cut color is reset
+        assert_eq!(
+            lines[lines.len() - 2],
+            "Passed: \x1b[32m0\x1b[0m  Failed: \x1b[31m0\x1b[0m  S\x1b[0m"
+        );
+        assert_eq!(lines[lines.len() - 1], "Last failure: conforman");
+        assert_eq!(truncate_visible("\x1b[32m12345\x1b[0m", 3), "\x1b[32m123\x1b[0m");
+        assert_eq!(truncate_visible("\x1b[32m12\x1b[0m", 3), "\x1b[32m12\x1b[0m");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_live_elapsed_ticks_between_events() {
+        let buffer = SharedBuffer::default();
+        let mut reporter = ConsoleReporter::new(Box::new(buffer.clone()), true, false).with_width(80);
+
+        reporter.on_event(&RunnerEvent::SuiteStarted { name: "soak".to_string(), total_tests: 1 });
+        reporter.on_event(&RunnerEvent::TestStarted { name: "thermal".to_string() });
+        tokio::time::sleep(Duration::from_millis(900)).await;
+
+        let output = buffer.contents();
+        assert!(output.contains("Running: thermal [0/1] 800.0ms\n"), "{}", output);
+
+        let mut result = suite_result();
+        result.results.truncate(1);
+        reporter.on_event(&RunnerEvent::SuiteFinished(result));
+        let finished = buffer.contents().len();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(buffer.contents().len(), finished);
+    }
+
+    #[test]
+    fn test_reused_for_next_suite() {
+        let buffer = SharedBuffer::default();
+        let mut reporter = ConsoleReporter::new(Box::new(buffer.clone()), true, false);
+        let suite = suite_result();
+
+        for _ in 0..2 {
+            reporter.on_event(&RunnerEvent::SuiteStarted { name: "eeprom".to_string(), total_tests: 3 });
+            for result in &suite.results {
+                reporter.on_event(&RunnerEvent::TestFinished(result.clone()));
+            }
+            reporter.on_event(&RunnerEvent::SuiteFinished(suite.clone()));
+        }
+
+        let output = buffer.contents();
+        let second = &output[output.rfind("Running suite eeprom").unwrap()..];
+        // The first redraw of the second suite must not climb into the table
+        assert!(second.starts_with("Running suite eeprom (3 tests)\n\r\x1b[2K"));
+        assert!(second.contains("[1/3]"));
+        assert!(second.contains("Passed: 1  Failed: 1  Skipped: 1  Errors: 0"));
+        assert!(!second.contains("[4/3]"));
+    }
+}
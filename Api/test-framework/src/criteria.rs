@@ -0,0 +1,591 @@
+/*
+ * Pass Criteria Expressions
+ * Copyright (C) 2024
+ */
+
+use crate::{DiagMutex, HardwareError, HardwareResult, TestFn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Named values a criteria expression is evaluated against
+pub type CriteriaValues = HashMap<String, f64>;
+
+/// Boxed future returned by a criteria sampling closure
+pub type ValuesFuture = Pin<Box<dyn Future<Output = HardwareResult<CriteriaValues>> + Send>>;
+
+type SampleFn<T> = Box<dyn FnOnce(Arc<DiagMutex<T>>) -> ValuesFuture + Send>;
+
+const SYMBOLS: [&str; 12] = ["<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "(", ")"];
+const COMPARISONS: [&str; 6] = ["<=", ">=", "==", "!=", "<", ">"];
+const KEYWORDS: [&str; 4] = ["and", "or", "not", "between"];
+
+/// Parse or evaluation error at a byte position of the expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct CriteriaError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl CriteriaError {
+    fn new(position: usize, message: String) -> Self {
+        Self { position, message }
+    }
+}
+
+impl fmt::Display for CriteriaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl Error for CriteriaError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Lexed {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Lexed>, CriteriaError> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let token = if bytes[i].is_ascii_digit() || bytes[i] == b'.' {
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            let literal = &text[start..i];
+            let value = literal
+                .parse()
+                .map_err(|_| CriteriaError::new(start, format!("invalid number '{}'", literal)))?;
+            Token::Number(value)
+        } else if bytes[i].is_ascii_alphabetic() || bytes[i] == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Ident(text[start..i].to_string())
+        } else {
+            let symbol = SYMBOLS.iter().find(|s| text[i..].starts_with(**s)).ok_or_else(|| {
+                let c = text[i..].chars().next().unwrap_or_default();
+                CriteriaError::new(start, format!("unexpected character '{}'", c))
+            })?;
+            i += symbol.len();
+            Token::Symbol(symbol)
+        };
+        tokens.push(Lexed { token, start, end: i });
+    }
+    tokens.push(Lexed {
+        token: Token::End,
+        start: text.len(),
+        end: text.len(),
+    });
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Variable { name: String, position: usize },
+    Neg(Box<Expr>),
+    Arith(&'static str, Box<Expr>, Box<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn is_condition(&self) -> bool {
+        matches!(
+            self,
+            Expr::Compare(..) | Expr::Between(..) | Expr::Not(_) | Expr::And(..) | Expr::Or(..)
+        )
+    }
+
+    fn number(&self, values: &CriteriaValues) -> Result<f64, CriteriaError> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Variable { name, position } => *values
+                .get(name)
+                .ok_or_else(|| CriteriaError::new(*position, format!("unknown variable '{}'", name)))?,
+            Expr::Neg(operand) => -operand.number(values)?,
+            Expr::Arith(op, left, right) => {
+                let (left, right) = (left.number(values)?, right.number(values)?);
+                match *op {
+                    "+" => left + right,
+                    "-" => left - right,
+                    "*" => left * right,
+                    _ => left / right,
+                }
+            }
+            _ => unreachable!("conditions are rejected as numeric operands when parsing"),
+        })
+    }
+
+    fn holds(&self, values: &CriteriaValues) -> Result<bool, CriteriaError> {
+        Ok(match self {
+            Expr::Compare(op, left, right) => {
+                let (left, right) = (left.number(values)?, right.number(values)?);
+                match *op {
+                    "<=" => left <= right,
+                    ">=" => left >= right,
+                    "==" => left == right,
+                    "!=" => left != right,
+                    "<" => left < right,
+                    _ => left > right,
+                }
+            }
+            Expr::Between(value, low, high) => {
+                let value = value.number(values)?;
+                value >= low.number(values)? && value <= high.number(values)?
+            }
+            Expr::Not(operand) => !operand.holds(values)?,
+            Expr::And(left, right) => left.holds(values)? && right.holds(values)?,
+            Expr::Or(left, right) => left.holds(values)? || right.holds(values)?,
+            _ => unreachable!("numbers are rejected as conditions when parsing"),
+        })
+    }
+
+    /// Every variable reference with its position, in source order
+    fn variables<'a>(&'a self, found: &mut Vec<(&'a str, usize)>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable { name, position } => found.push((name, *position)),
+            Expr::Neg(operand) | Expr::Not(operand) => operand.variables(found),
+            Expr::Arith(_, left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right) => {
+                left.variables(found);
+                right.variables(found);
+            }
+            Expr::Between(value, low, high) => {
+                value.variables(found);
+                low.variables(found);
+                high.variables(found);
+            }
+        }
+    }
+}
+
+/// Recursive descent parser; precedence from loosest to tightest is `or`,
+/// `and`, `not`, comparisons and `between`, `+ -`, `* /`, unary minus
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Lexed>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Lexed {
+        &self.tokens[self.next]
+    }
+
+    fn advance(&mut self) -> Lexed {
+        let lexed = self.tokens[self.next].clone();
+        if self.next + 1 < self.tokens.len() {
+            self.next += 1;
+        }
+        lexed
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = matches!(&self.peek().token, Token::Ident(ident) if ident == word);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn symbol(&mut self, options: &[&'static str]) -> Option<&'static str> {
+        match self.peek().token {
+            Token::Symbol(symbol) if options.contains(&symbol) => {
+                self.advance();
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> CriteriaError {
+        let lexed = self.peek();
+        let found = match lexed.token {
+            Token::End => "end of expression".to_string(),
+            _ => format!("'{}'", &self.text[lexed.start..lexed.end]),
+        };
+        CriteriaError::new(lexed.start, format!("expected {}, found {}", expected, found))
+    }
+
+    fn condition(&mut self, parse: fn(&mut Self) -> Result<Expr, CriteriaError>) -> Result<Box<Expr>, CriteriaError> {
+        let start = self.peek().start;
+        let expr = parse(self)?;
+        if !expr.is_condition() {
+            return Err(CriteriaError::new(start, "expected a comparison, found a number".to_string()));
+        }
+        Ok(Box::new(expr))
+    }
+
+    fn numeric(&mut self, parse: fn(&mut Self) -> Result<Expr, CriteriaError>) -> Result<Box<Expr>, CriteriaError> {
+        let start = self.peek().start;
+        let expr = parse(self)?;
+        if expr.is_condition() {
+            return Err(CriteriaError::new(start, "expected a number, found a comparison".to_string()));
+        }
+        Ok(Box::new(expr))
+    }
+
+    fn or(&mut self) -> Result<Expr, CriteriaError> {
+        let start = self.peek().start;
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            if !left.is_condition() {
+                return Err(CriteriaError::new(start, "expected a comparison, found a number".to_string()));
+            }
+            left = Expr::Or(Box::new(left), self.condition(Self::and)?);
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, CriteriaError> {
+        let start = self.peek().start;
+        let mut left = self.not()?;
+        while self.keyword("and") {
+            if !left.is_condition() {
+                return Err(CriteriaError::new(start, "expected a comparison, found a number".to_string()));
+            }
+            left = Expr::And(Box::new(left), self.condition(Self::not)?);
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, CriteriaError> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(self.condition(Self::not)?));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, CriteriaError> {
+        let start = self.peek().start;
+        let left = self.sum()?;
+        let comparing = matches!(self.peek().token, Token::Symbol(s) if COMPARISONS.contains(&s))
+            || matches!(&self.peek().token, Token::Ident(w) if w == "between");
+        if comparing && left.is_condition() {
+            return Err(CriteriaError::new(start, "expected a number, found a comparison".to_string()));
+        }
+        if let Some(op) = self.symbol(&COMPARISONS) {
+            return Ok(Expr::Compare(op, Box::new(left), self.numeric(Self::sum)?));
+        }
+        if self.keyword("between") {
+            let low = self.numeric(Self::sum)?;
+            if !self.keyword("and") {
+                return Err(self.unexpected("'and'"));
+            }
+            return Ok(Expr::Between(Box::new(left), low, self.numeric(Self::sum)?));
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expr, CriteriaError> {
+        self.arithmetic(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, CriteriaError> {
+        self.arithmetic(&["*", "/"], Self::unary)
+    }
+
+    fn arithmetic(
+        &mut self,
+        operators: &[&'static str],
+        operand: fn(&mut Self) -> Result<Expr, CriteriaError>,
+    ) -> Result<Expr, CriteriaError> {
+        let start = self.peek().start;
+        let mut left = operand(self)?;
+        while let Some(op) = self.symbol(operators) {
+            if left.is_condition() {
+                return Err(CriteriaError::new(start, "expected a number, found a comparison".to_string()));
+            }
+            left = Expr::Arith(op, Box::new(left), self.numeric(operand)?);
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CriteriaError> {
+        if self.symbol(&["-"]).is_some() {
+            return Ok(Expr::Neg(self.numeric(Self::unary)?));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CriteriaError> {
+        let lexed = self.peek().clone();
+        match lexed.token {
+            Token::Number(value) => {
+                self.advance();
+                Ok(Expr::Number(value))
+            }
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                self.advance();
+                Ok(Expr::Variable {
+                    name,
+                    position: lexed.start,
+                })
+            }
+            Token::Symbol("(") => {
+                self.advance();
+                let inner = self.or()?;
+                if self.symbol(&[")"]).is_none() {
+                    return Err(self.unexpected("')'"));
+                }
+                // Parenthesised arithmetic still compares correctly, so the
+                // operand check is left to the enclosing expression
+                Ok(inner)
+            }
+            _ => Err(self.unexpected("a number, variable or '('")),
+        }
+    }
+}
+
+/// Pass criteria such as `cap_volt between 7.5 and 8.2 and sys_current < 0.4`
+/// over named f64 values.
+///
+/// Supports `+ - * /`, the comparisons `< <= > >= == !=`,
+/// `x between low and high` (inclusive), `and`, `or`, `not` and parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Criteria {
+    text: String,
+    expr: Expr,
+}
+
+impl Criteria {
+    pub fn parse(text: &str) -> Result<Self, CriteriaError> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            next: 0,
+        };
+        let expr = parser.or()?;
+        if parser.peek().token != Token::End {
+            return Err(parser.unexpected("'and', 'or' or end of expression"));
+        }
+        if !expr.is_condition() {
+            return Err(CriteriaError::new(0, "criteria must be a comparison, e.g. 'x < 1'".to_string()));
+        }
+        Ok(Self {
+            text: text.to_string(),
+            expr,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Names of the variables used, without duplicates, in source order
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _) in self.references() {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Reject variables missing from `known`, so typos surface when a suite
+    /// is loaded rather than when the test runs
+    pub fn check_variables(&self, known: &[&str]) -> Result<(), CriteriaError> {
+        match self.references().into_iter().find(|(name, _)| !known.contains(name)) {
+            Some((name, position)) => Err(CriteriaError::new(position, format!("unknown variable '{}'", name))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn evaluate(&self, values: &CriteriaValues) -> Result<bool, CriteriaError> {
+        self.expr.holds(values)
+    }
+
+    /// The expression with every variable followed by its value, e.g.
+    /// `cap_volt=7.1 between 7.5 and 8.2`
+    pub fn substitute(&self, values: &CriteriaValues) -> String {
+        let mut text = String::new();
+        let mut copied = 0;
+        for (name, position) in self.references() {
+            let end = position + name.len();
+            text.push_str(&self.text[copied..end]);
+            if let Some(value) = values.get(name) {
+                text.push_str(&format!("={}", value));
+            }
+            copied = end;
+        }
+        text.push_str(&self.text[copied..]);
+        text
+    }
+
+    /// Evaluate as a test outcome; failures carry the substituted expression
+    pub fn check(&self, values: &CriteriaValues) -> HardwareResult<()> {
+        match self.evaluate(values) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(HardwareError::OperationFailed(format!(
+                "criteria not met: {}",
+                self.substitute(values)
+            ))),
+            Err(e) => Err(HardwareError::InvalidParameter(format!("criteria '{}': {}", self.text, e))),
+        }
+    }
+
+    fn references(&self) -> Vec<(&str, usize)> {
+        let mut found = Vec::new();
+        self.expr.variables(&mut found);
+        found.sort_by_key(|(_, position)| *position);
+        found
+    }
+}
+
+/// Test case passing when its criteria hold for values sampled from the
+/// device, e.g. power rail readings
+pub struct CriteriaTest<T> {
+    criteria: Criteria,
+    sample: SampleFn<T>,
+}
+
+impl<T: 'static> CriteriaTest<T> {
+    /// Parse `expression` and check it only uses `variables`, the names the
+    /// `sample` closure provides
+    pub fn new<F>(expression: &str, variables: &[&str], sample: F) -> Result<Self, CriteriaError>
+    where
+        F: FnOnce(Arc<DiagMutex<T>>) -> ValuesFuture + Send + 'static,
+    {
+        let criteria = Criteria::parse(expression)?;
+        criteria.check_variables(variables)?;
+        Ok(Self {
+            criteria,
+            sample: Box::new(sample),
+        })
+    }
+
+    pub fn criteria(&self) -> &Criteria {
+        &self.criteria
+    }
+
+    pub fn case(self, name: &str) -> (String, TestFn<T>) {
+        let test: TestFn<T> = Box::new(move |interface| {
+            Box::pin(async move {
+                let values = (self.sample)(interface).await?;
+                self.criteria.check(&values)
+            })
+        });
+        (name.to_string(), test)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{TestRunner, TestStatus};
+    use std::time::Duration;
+
+    fn values(pairs: &[(&str, f64)]) -> CriteriaValues {
+        pairs.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+    }
+
+    fn holds(text: &str, pairs: &[(&str, f64)]) -> bool {
+        Criteria::parse(text).unwrap().evaluate(&values(pairs)).unwrap()
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert!(holds("1 + 2 * 3 == 7", &[]));
+        assert!(holds("(1 + 2) * 3 == 9", &[]));
+        assert!(holds("-x * 2 == -4", &[("x", 2.0)]));
+        assert!(holds("10 - 4 - 3 == 3", &[]));
+        // `and` binds tighter than `or`, `not` tighter than `and`
+        assert!(holds("1 > 2 and 1 > 2 or 1 < 2", &[]));
+        assert!(!holds("1 > 2 and (1 > 2 or 1 < 2)", &[]));
+        assert!(holds("not 1 > 2 and 2 > 1", &[]));
+        assert!(holds("v between 7.5 and 8.2 and i < 0.4", &[("v", 8.2), ("i", 0.3)]));
+        assert!(!holds("v between 7.5 and 8.2 and i < 0.4", &[("v", 8.3), ("i", 0.3)]));
+    }
+
+    #[test]
+    fn test_parse_errors_have_positions() {
+        let error = |text: &str| Criteria::parse(text).unwrap_err().to_string();
+        assert_eq!(error("v < "), "column 5: expected a number, variable or '(', found end of expression");
+        assert_eq!(error("v between 1 or 2"), "column 13: expected 'and', found 'or'");
+        assert_eq!(error("v < 1 $ 2"), "column 7: unexpected character '$'");
+        assert_eq!(error("(v < 1) + 2 > 0"), "column 1: expected a number, found a comparison");
+        assert_eq!(error("v + 1"), "column 1: criteria must be a comparison, e.g. 'x < 1'");
+        assert_eq!(error("v < 1 w"), "column 7: expected 'and', 'or' or end of expression, found 'w'");
+    }
+
+    #[test]
+    fn test_unknown_variables() {
+        let criteria = Criteria::parse("cap_volt > 7.5 and sys_curent < 0.4").unwrap();
+        assert_eq!(criteria.variables(), vec!["cap_volt", "sys_curent"]);
+        assert_eq!(
+            criteria.check_variables(&["cap_volt", "sys_current"]).unwrap_err().to_string(),
+            "column 20: unknown variable 'sys_curent'"
+        );
+        assert_eq!(
+            criteria.evaluate(&values(&[("cap_volt", 8.0)])).unwrap_err(),
+            CriteriaError::new(19, "unknown variable 'sys_curent'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failure_message_substitutes_values() {
+        let criteria = Criteria::parse("cap_volt between 7.5 and 8.2 and sys_current < 0.4").unwrap();
+        assert_eq!(
+            criteria.check(&values(&[("cap_volt", 7.1), ("sys_current", 0.3)])),
+            Err(HardwareError::OperationFailed(
+                "criteria not met: cap_volt=7.1 between 7.5 and 8.2 and sys_current=0.3 < 0.4".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_criteria_test_case() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let rails = |volts: f64| {
+            CriteriaTest::<MockHardwareInterface>::new("cap_volt >= 7.5", &["cap_volt"], move |_| {
+                Box::pin(async move { Ok(values(&[("cap_volt", volts)])) })
+            })
+            .unwrap()
+        };
+        assert!(CriteriaTest::<MockHardwareInterface>::new("volts > 1", &["cap_volt"], |_| {
+            Box::pin(async { Ok(CriteriaValues::new()) })
+        })
+        .is_err());
+
+        let (good, good_fn) = rails(7.9).case("rails_ok");
+        let (low, low_fn) = rails(7.0).case("rails_low");
+        let suite = runner.run_test_suite("power", vec![(good.as_str(), good_fn), (low.as_str(), low_fn)]).await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert_eq!(
+            suite.results[1].status,
+            TestStatus::Error("Test failed: OperationFailed(\"criteria not met: cap_volt=7 >= 7.5\")".to_string())
+        );
+    }
+}
@@ -0,0 +1,304 @@
+/*
+ * Test Deadline Propagation to Operation Timeouts
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareResult};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+tokio::task_local! {
+    static TEST_DEADLINE: TestDeadline;
+}
+
+/// Stand-in for "never" when neither the test nor a budget sets a deadline
+const FAR_FUTURE: Duration = Duration::from_secs(30 * 365 * 24 * 3600);
+
+/// How long a running test may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestTimeout {
+    /// Error the test once it has run this long
+    After(Duration),
+    /// Let the test run to completion, e.g. soak tests or manual steps
+    /// waiting on an operator; a budget wall time still applies
+    Unlimited,
+}
+
+/// Point by which the running test must finish, set by `TestRunner` from
+/// its per-test timeout, or the one the test declared, and the remaining
+/// budget wall time
+#[derive(Debug, Clone)]
+pub struct TestDeadline {
+    start: Instant,
+    timeout: Arc<StdMutex<TestTimeout>>,
+    /// End of the budget wall time, which no declared timeout extends
+    cap: Option<Instant>,
+    /// An operation timed out because its timeout was cut to the deadline
+    hit: Arc<AtomicBool>,
+}
+
+impl TestDeadline {
+    /// Deadline of the running test, `None` outside a runner test
+    pub fn current() -> Option<TestDeadline> {
+        TEST_DEADLINE.try_with(|deadline| deadline.clone()).ok()
+    }
+
+    pub fn at(&self) -> Instant {
+        let own = match self.timeout() {
+            TestTimeout::After(timeout) => self.start + timeout,
+            TestTimeout::Unlimited => self.start + FAR_FUTURE,
+        };
+        match self.cap {
+            Some(cap) => own.min(cap),
+            None => own,
+        }
+    }
+
+    pub fn timeout(&self) -> TestTimeout {
+        *self.timeout.lock().unwrap()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.at().saturating_duration_since(Instant::now())
+    }
+
+    /// `configured` cut to the time left, and whether it was cut
+    pub fn clamp(&self, configured: Duration) -> (Duration, bool) {
+        let remaining = self.remaining();
+        if remaining < configured {
+            (remaining, true)
+        } else {
+            (configured, false)
+        }
+    }
+}
+
+/// Replace the runner's per-test timeout for the running test, counted
+/// from the test's start; does nothing outside a runner test
+///
+/// ```ignore
+/// declare_test_timeout(TestTimeout::After(Duration::from_secs(3700)));
+/// ```
+pub fn declare_test_timeout(timeout: TestTimeout) {
+    let _ = TEST_DEADLINE.try_with(|deadline| *deadline.timeout.lock().unwrap() = timeout);
+}
+
+/// `configured` cut to the running test's remaining time, unchanged
+/// outside a runner test
+pub fn deadline_timeout(configured: Duration) -> Duration {
+    match TestDeadline::current() {
+        Some(deadline) => deadline.clamp(configured).0,
+        None => configured,
+    }
+}
+
+/// Run `operation` with `configured` cut to the running test's deadline
+///
+/// A `TimeoutError` after the timeout was cut is blamed on the test
+/// deadline rather than the device: the runner reports the test as
+/// "TimeoutError (test deadline)".
+///
+/// ```ignore
+/// let count = with_deadline(config.timeout, |timeout| uart.read(&mut buffer, timeout)).await?;
+/// ```
+pub async fn with_deadline<T, F, Fut>(configured: Duration, operation: F) -> HardwareResult<T>
+where
+    F: FnOnce(Duration) -> Fut,
+    Fut: Future<Output = HardwareResult<T>>,
+{
+    let deadline = match TestDeadline::current() {
+        Some(deadline) => deadline,
+        None => return operation(configured).await,
+    };
+    let (timeout, clamped) = deadline.clamp(configured);
+    let result = operation(timeout).await;
+    if clamped && matches!(&result, Err(e) if matches!(e.root(), HardwareError::TimeoutError)) {
+        log::warn!(
+            "Operation timed out after the test deadline cut its timeout from {:?} to {:?}",
+            configured,
+            timeout
+        );
+        deadline.hit.store(true, Ordering::SeqCst);
+    }
+    result
+}
+
+/// Test run under a `TestDeadline`
+pub(crate) struct DeadlineRun<O> {
+    /// The timeout the test was cut off at if it did not finish
+    pub output: Result<O, Duration>,
+    /// A timeout was caused by the deadline
+    pub hit: bool,
+    /// Time from the start to the deadline
+    pub limit: Duration,
+}
+
+/// Run `future` as a test cut off after `timeout` unless it declares its
+/// own, with operation timeouts also cut to `cap`
+pub(crate) async fn run_with_deadline<F: Future>(timeout: Duration, cap: Option<Instant>, future: F) -> DeadlineRun<F::Output> {
+    let deadline = TestDeadline {
+        start: Instant::now(),
+        timeout: Arc::new(StdMutex::new(TestTimeout::After(timeout))),
+        cap,
+        hit: Arc::new(AtomicBool::new(false)),
+    };
+    let future = TEST_DEADLINE.scope(deadline.clone(), future);
+    tokio::pin!(future);
+
+    // The test may declare another timeout while it runs, so the cut-off
+    // is re-read whenever the current one passes
+    let output = loop {
+        let due = match deadline.timeout() {
+            TestTimeout::After(timeout) => deadline.start + timeout,
+            TestTimeout::Unlimited => break Ok((&mut future).await),
+        };
+        if let Ok(output) = timeout_at(due, &mut future).await {
+            break Ok(output);
+        }
+        if let TestTimeout::After(timeout) = deadline.timeout() {
+            if deadline.start + timeout <= Instant::now() {
+                break Err(timeout);
+            }
+        }
+    };
+    DeadlineRun {
+        output,
+        hit: deadline.hit.load(Ordering::SeqCst),
+        limit: deadline.at().saturating_duration_since(deadline.start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::test_utils::run_with_retries_and_timeout;
+    use crate::{Budget, Readable, TestFn, TestRunner, TestStatus};
+    use async_trait::async_trait;
+    use tokio::time::sleep;
+
+    /// Device answering every read after `latency`
+    struct SlowDevice {
+        latency: Duration,
+        timeouts: Arc<StdMutex<Vec<Duration>>>,
+    }
+
+    #[async_trait]
+    impl Readable for SlowDevice {
+        async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+            self.timeouts.lock().unwrap().push(timeout);
+            if timeout < self.latency {
+                sleep(timeout).await;
+                return Err(HardwareError::TimeoutError);
+            }
+            sleep(self.latency).await;
+            Ok(buffer.len())
+        }
+
+        async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+            self.read(buffer, timeout).await.map(|_| ())
+        }
+    }
+
+    fn slow_read(latency_ms: u64, configured_ms: u64, timeouts: Arc<StdMutex<Vec<Duration>>>) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                sleep(Duration::from_millis(100)).await;
+                let mut device = SlowDevice {
+                    latency: Duration::from_millis(latency_ms),
+                    timeouts,
+                };
+                let mut buffer = [0u8; 4];
+                with_deadline(Duration::from_millis(configured_ms), |timeout| device.read(&mut buffer, timeout)).await?;
+                Ok(())
+            })
+        })
+    }
+
+    fn runner(timeout_ms: u64) -> TestRunner<MockHardwareInterface> {
+        TestRunner::new(create_mock_interface_with_defaults(), Duration::from_millis(timeout_ms), 0, Duration::ZERO)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_deadline_outside_runner() {
+        assert!(TestDeadline::current().is_none());
+        assert_eq!(deadline_timeout(Duration::from_secs(2)), Duration::from_secs(2));
+        let timeout = with_deadline(Duration::from_secs(2), |timeout| async move { Ok(timeout) }).await;
+        assert_eq!(timeout, Ok(Duration::from_secs(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_clamps_and_annotates() {
+        let timeouts = Arc::new(StdMutex::new(Vec::new()));
+        let suite = runner(500)
+            .run_test_suite(
+                "deadline",
+                vec![
+                    ("clamped", slow_read(1000, 2000, timeouts.clone())),
+                    ("device slow", slow_read(1000, 200, timeouts.clone())),
+                    ("in time", slow_read(300, 2000, timeouts.clone())),
+                ],
+            )
+            .await;
+
+        assert_eq!(
+            *timeouts.lock().unwrap(),
+            vec![Duration::from_millis(400), Duration::from_millis(200), Duration::from_millis(400)]
+        );
+        let clamped = &suite.results[0];
+        assert_eq!(clamped.status, TestStatus::Error("Test failed: TimeoutError (test deadline)".to_string()));
+        assert_eq!(clamped.duration, Duration::from_millis(500));
+        assert_eq!(clamped.notes, vec!["operation timeout cut short by the 500ms test deadline"]);
+        assert_eq!(suite.results[1].status, TestStatus::Error("Test failed: TimeoutError".to_string()));
+        assert!(suite.results[1].notes.is_empty());
+        assert_eq!(suite.results[2].status, TestStatus::Passed);
+        assert_eq!((suite.total_tests, suite.error_tests, suite.passed_tests), (3, 2, 1));
+        assert_eq!(suite.total_duration, Duration::from_millis(1200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_wall_time_tightens_deadline() {
+        let timeouts = Arc::new(StdMutex::new(Vec::new()));
+        let runner = runner(5000).with_budget(Budget::new().max_wall_time(Duration::from_millis(300)));
+
+        let suite = runner
+            .run_test_suite("deadline", vec![("clamped", slow_read(1000, 2000, timeouts.clone()))])
+            .await;
+
+        assert_eq!(*timeouts.lock().unwrap(), vec![Duration::from_millis(200)]);
+        assert_eq!(
+            suite.results[0].status,
+            TestStatus::Error("Test failed: TimeoutError (test deadline)".to_string())
+        );
+        assert!(suite.budget_exceeded.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_wrapper_clamped() {
+        let test: TestFn<MockHardwareInterface> = Box::new(|_| {
+            Box::pin(async {
+                run_with_retries_and_timeout(
+                    || async {
+                        sleep(Duration::from_secs(1)).await;
+                        Ok(())
+                    },
+                    3,
+                    Duration::ZERO,
+                    Duration::from_secs(10),
+                )
+                .await
+            })
+        });
+
+        let suite = runner(250).run_test_suite("retry", vec![("slow retries", test)]).await;
+
+        assert_eq!(
+            suite.results[0].status,
+            TestStatus::Error("Test failed: TimeoutError (test deadline)".to_string())
+        );
+        assert_eq!(suite.results[0].duration, Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * Instrumented Interface Lock
+ * Copyright (C) 2024
+ */
+
+use crate::ReportAppendix;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::Instant;
+
+/// Number of longest holds kept for reporting
+pub const MAX_TRACKED_HOLDS: usize = 16;
+
+/// Holder name used when no task-local holder is set
+pub const UNNAMED_HOLDER: &str = "<unnamed>";
+
+tokio::task_local! {
+    static LOCK_HOLDER: String;
+}
+
+/// Run `future` with `name` recorded as the holder of any `DiagMutex` it locks
+pub async fn with_lock_holder<F: Future>(name: &str, future: F) -> F::Output {
+    LOCK_HOLDER.scope(name.to_string(), future).await
+}
+
+fn current_holder() -> String {
+    LOCK_HOLDER
+        .try_with(|name| name.clone())
+        .unwrap_or_else(|_| UNNAMED_HOLDER.to_string())
+}
+
+/// Lock statistics of one holder
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HolderStats {
+    pub acquisitions: usize,
+    pub total_wait: Duration,
+    pub total_hold: Duration,
+    pub max_hold: Duration,
+}
+
+/// A single completed hold of the lock
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockHold {
+    pub holder: String,
+    pub wait: Duration,
+    pub hold: Duration,
+}
+
+/// Snapshot of the statistics recorded by a `DiagMutex`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LockReport {
+    pub acquisitions: usize,
+    /// Time spent by all holders waiting for the lock
+    pub total_contention: Duration,
+    pub holders: BTreeMap<String, HolderStats>,
+    /// Longest holds, longest first
+    pub longest_holds: Vec<LockHold>,
+}
+
+impl LockReport {
+    /// Report appendix listing at most `top_n` of the longest holds
+    pub fn appendix(&self, top_n: usize) -> ReportAppendix {
+        let mut body = format!(
+            "{} acquisitions, {:?} total contention\n",
+            self.acquisitions, self.total_contention
+        );
+        body.push_str("\nHolder  Acquisitions  Wait  Hold  Max hold\n");
+        for (holder, stats) in &self.holders {
+            body.push_str(&format!(
+                "{}  {}  {:?}  {:?}  {:?}\n",
+                holder, stats.acquisitions, stats.total_wait, stats.total_hold, stats.max_hold
+            ));
+        }
+        body.push_str("\nLongest holds:\n");
+        for hold in self.longest_holds.iter().take(top_n) {
+            body.push_str(&format!("{}  {:?} (waited {:?})\n", hold.holder, hold.hold, hold.wait));
+        }
+        ReportAppendix::new("Interface lock", &body)
+    }
+}
+
+#[derive(Default)]
+struct DiagState {
+    report: LockReport,
+    hold_warning: Option<Duration>,
+}
+
+/// Async mutex recording wait and hold times per holder
+///
+/// The holder is taken from the task-local set by `with_lock_holder`, which
+/// `TestRunner` sets to the running test's name.
+pub struct DiagMutex<T> {
+    inner: Mutex<T>,
+    state: StdMutex<DiagState>,
+}
+
+impl<T> DiagMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+            state: StdMutex::new(DiagState::default()),
+        }
+    }
+
+    /// Log a warning whenever a hold lasts longer than `threshold`
+    pub fn set_hold_warning(&self, threshold: Option<Duration>) {
+        self.state.lock().unwrap().hold_warning = threshold;
+    }
+
+    pub async fn lock(&self) -> DiagMutexGuard<'_, T> {
+        self.lock_as(&current_holder()).await
+    }
+
+    /// Lock with an explicit holder name, ignoring the task-local one
+    pub async fn lock_as(&self, holder: &str) -> DiagMutexGuard<'_, T> {
+        let requested = Instant::now();
+        let guard = self.inner.lock().await;
+        DiagMutexGuard {
+            guard,
+            mutex: self,
+            holder: holder.to_string(),
+            wait: requested.elapsed(),
+            acquired: Instant::now(),
+        }
+    }
+
+    pub fn report(&self) -> LockReport {
+        self.state.lock().unwrap().report.clone()
+    }
+
+    pub fn reset_report(&self) {
+        self.state.lock().unwrap().report = LockReport::default();
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    fn record(&self, holder: String, wait: Duration, hold: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(threshold) = state.hold_warning {
+            if hold > threshold {
+                log::warn!("{} held the interface lock for {:?} (threshold {:?})", holder, hold, threshold);
+            }
+        }
+
+        let report = &mut state.report;
+        report.acquisitions += 1;
+        report.total_contention += wait;
+
+        let stats = report.holders.entry(holder.clone()).or_default();
+        stats.acquisitions += 1;
+        stats.total_wait += wait;
+        stats.total_hold += hold;
+        stats.max_hold = stats.max_hold.max(hold);
+
+        let index = report.longest_holds.partition_point(|h| h.hold >= hold);
+        if index < MAX_TRACKED_HOLDS {
+            report.longest_holds.insert(index, LockHold { holder, wait, hold });
+            report.longest_holds.truncate(MAX_TRACKED_HOLDS);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DiagMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiagMutex").field("inner", &self.inner).finish()
+    }
+}
+
+/// Guard returned by `DiagMutex::lock`, recording the hold when dropped
+pub struct DiagMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    mutex: &'a DiagMutex<T>,
+    holder: String,
+    wait: Duration,
+    acquired: Instant,
+}
+
+impl<T> Deref for DiagMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for DiagMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for DiagMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let holder = std::mem::take(&mut self.holder);
+        self.mutex.record(holder, self.wait, self.acquired.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::sleep;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_contention_statistics() {
+        let mutex = Arc::new(DiagMutex::new(0u32));
+
+        let slow = {
+            let mutex = mutex.clone();
+            tokio::spawn(with_lock_holder("slow_transfer", async move {
+                let mut value = mutex.lock().await;
+                sleep(Duration::from_millis(100)).await;
+                *value += 1;
+            }))
+        };
+        let poller = {
+            let mutex = mutex.clone();
+            tokio::spawn(with_lock_holder("poller", async move {
+                sleep(Duration::from_millis(10)).await;
+                let mut value = mutex.lock().await;
+                sleep(Duration::from_millis(20)).await;
+                *value += 1;
+            }))
+        };
+        slow.await.unwrap();
+        poller.await.unwrap();
+
+        let report = mutex.report();
+        let tolerance = Duration::from_millis(2);
+        assert_eq!(report.acquisitions, 2);
+        assert!(report.total_contention.abs_diff(Duration::from_millis(90)) <= tolerance);
+        assert!(report.holders["slow_transfer"].total_hold.abs_diff(Duration::from_millis(100)) <= tolerance);
+        assert!(report.holders["poller"].total_wait.abs_diff(Duration::from_millis(90)) <= tolerance);
+        assert!(report.holders["poller"].max_hold.abs_diff(Duration::from_millis(20)) <= tolerance);
+        assert_eq!(report.longest_holds[0].holder, "slow_transfer");
+        assert_eq!(Arc::try_unwrap(mutex).unwrap().into_inner(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unnamed_and_explicit_holders() {
+        let mutex = DiagMutex::new(());
+        drop(mutex.lock().await);
+        drop(mutex.lock_as("telemetry").await);
+
+        let report = mutex.report();
+        assert_eq!(report.holders[UNNAMED_HOLDER].acquisitions, 1);
+        assert_eq!(report.holders["telemetry"].acquisitions, 1);
+
+        mutex.reset_report();
+        assert_eq!(mutex.report(), LockReport::default());
+    }
+
+    #[test]
+    fn test_longest_holds_are_bounded() {
+        let mutex = DiagMutex::new(());
+        for ms in 0..(MAX_TRACKED_HOLDS as u64 + 4) {
+            mutex.record(format!("t{}", ms), Duration::ZERO, Duration::from_millis(ms));
+        }
+
+        let report = mutex.report();
+        assert_eq!(report.longest_holds.len(), MAX_TRACKED_HOLDS);
+        assert_eq!(report.longest_holds[0].hold, Duration::from_millis(MAX_TRACKED_HOLDS as u64 + 3));
+        assert!(report.appendix(3).body.contains("t19"));
+    }
+}
@@ -0,0 +1,8 @@
+/*
+ * Reference Device Drivers
+ * Copyright (C) 2024
+ */
+
+pub mod spi_flash;
+
+pub use spi_flash::{FlashPhase, FlashProgress, JedecId, SpiFlash, SpiFlashConfig, SpiFlashError, SpiFlashResult};
@@ -0,0 +1,414 @@
+/*
+ * JEDEC SPI Flash Reference Driver
+ * Copyright (C) 2024
+ */
+
+use crate::{wait_for, Bidirectional, HardwareError, HardwareResult, SPI_DEFAULT_MAX_TRANSFER};
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const CMD_WRITE_ENABLE: u8 = 0x06;
+pub const CMD_READ_STATUS: u8 = 0x05;
+pub const CMD_READ_DATA: u8 = 0x03;
+pub const CMD_PAGE_PROGRAM: u8 = 0x02;
+pub const CMD_SECTOR_ERASE: u8 = 0x20;
+pub const CMD_READ_JEDEC_ID: u8 = 0x9F;
+
+pub const STATUS_WIP: u8 = 0x01;
+pub const STATUS_WEL: u8 = 0x02;
+
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Bytes read back per transfer while verifying
+const VERIFY_CHUNK: usize = 256;
+
+/// Largest read payload that keeps a read transfer within the default SPI limit
+const READ_CHUNK: usize = SPI_DEFAULT_MAX_TRANSFER - 4;
+
+/// SPI flash error types
+#[derive(Debug, PartialEq)]
+pub enum SpiFlashError {
+    Hardware(HardwareError),
+    BusyTimeout { waited: Duration },
+    VerifyMismatch { offset: u32, expected: u8, actual: u8 },
+}
+
+impl fmt::Display for SpiFlashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpiFlashError::Hardware(e) => write!(f, "{}", e),
+            SpiFlashError::BusyTimeout { waited } => {
+                write!(f, "Flash still busy after {:?}", waited)
+            }
+            SpiFlashError::VerifyMismatch { offset, expected, actual } => write!(
+                f,
+                "Verify mismatch at 0x{:06X}: expected 0x{:02X}, read 0x{:02X}",
+                offset, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for SpiFlashError {}
+
+impl From<HardwareError> for SpiFlashError {
+    fn from(e: HardwareError) -> Self {
+        SpiFlashError::Hardware(e)
+    }
+}
+
+/// Result type for SPI flash operations
+pub type SpiFlashResult<T> = Result<T, SpiFlashError>;
+
+/// Manufacturer, memory type and capacity bytes returned by 0x9F
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+/// Phase reported by `erase_program_verify`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlashPhase {
+    Erase,
+    Program,
+    Verify,
+}
+
+/// Progress update for long-running flash operations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlashProgress {
+    pub phase: FlashPhase,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// SPI flash timing configuration
+#[derive(Debug, Clone)]
+pub struct SpiFlashConfig {
+    pub transfer_timeout: Duration,
+    pub poll_interval: Duration,
+    pub program_timeout: Duration,
+    pub erase_timeout: Duration,
+}
+
+impl Default for SpiFlashConfig {
+    fn default() -> Self {
+        Self {
+            transfer_timeout: Duration::from_millis(100),
+            poll_interval: Duration::from_millis(1),
+            program_timeout: Duration::from_millis(10),
+            erase_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Reference driver for 24-bit addressed JEDEC SPI NOR flash
+pub struct SpiFlash<T: Bidirectional> {
+    spi: T,
+    config: SpiFlashConfig,
+    progress: Option<Box<dyn FnMut(FlashProgress) + Send>>,
+}
+
+impl<T: Bidirectional + Send> SpiFlash<T> {
+    pub fn new(spi: T) -> Self {
+        Self::with_config(spi, SpiFlashConfig::default())
+    }
+
+    pub fn with_config(spi: T, config: SpiFlashConfig) -> Self {
+        Self {
+            spi,
+            config,
+            progress: None,
+        }
+    }
+
+    /// Register a callback receiving progress from `erase_program_verify`
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(FlashProgress) + Send + 'static,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.spi
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.spi
+    }
+
+    pub fn into_inner(self) -> T {
+        self.spi
+    }
+
+    pub async fn read_jedec_id(&mut self) -> SpiFlashResult<JedecId> {
+        let id = self.command(&[CMD_READ_JEDEC_ID], 3).await?;
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    pub async fn read_status(&mut self) -> SpiFlashResult<u8> {
+        Ok(read_status_register(&mut self.spi, self.config.transfer_timeout).await?)
+    }
+
+    pub async fn write_enable(&mut self) -> SpiFlashResult<()> {
+        self.command(&[CMD_WRITE_ENABLE], 0).await?;
+        Ok(())
+    }
+
+    /// Poll the status register until write-in-progress clears
+    pub async fn wait_ready(&mut self, timeout: Duration) -> SpiFlashResult<()> {
+        let transfer_timeout = self.config.transfer_timeout;
+        let start = Instant::now();
+        wait_for(
+            &mut self.spi,
+            |spi| Box::pin(async move {
+                Ok((read_status_register(spi, transfer_timeout).await? & STATUS_WIP) == 0)
+            }),
+            self.config.poll_interval,
+            timeout,
+        )
+        .await
+        .map_err(|e| match e {
+            HardwareError::TimeoutError => SpiFlashError::BusyTimeout { waited: start.elapsed() },
+            e => SpiFlashError::Hardware(e),
+        })
+    }
+
+    /// Erase the 4 KiB sector containing `addr`
+    pub async fn erase_sector(&mut self, addr: u32) -> SpiFlashResult<()> {
+        self.write_enable().await?;
+        self.command(&address_command(CMD_SECTOR_ERASE, addr), 0).await?;
+        self.wait_ready(self.config.erase_timeout).await
+    }
+
+    /// Program `data` starting at `addr`, splitting at 256-byte page boundaries
+    pub async fn program(&mut self, addr: u32, data: &[u8]) -> SpiFlashResult<()> {
+        for (page_addr, chunk) in page_chunks(addr, data) {
+            self.program_page(page_addr, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Program a single page; `data` must not cross a page boundary
+    pub async fn program_page(&mut self, addr: u32, data: &[u8]) -> SpiFlashResult<()> {
+        if addr as usize % PAGE_SIZE + data.len() > PAGE_SIZE {
+            return Err(HardwareError::InvalidParameter(format!(
+                "{} bytes at 0x{:06X} cross a {} byte page boundary", data.len(), addr, PAGE_SIZE
+            )).into());
+        }
+
+        self.write_enable().await?;
+        let mut tx = address_command(CMD_PAGE_PROGRAM, addr).to_vec();
+        tx.extend_from_slice(data);
+        let mut rx = vec![0u8; tx.len()];
+        self.spi.transfer(&tx, &mut rx, self.config.transfer_timeout).await?;
+        self.wait_ready(self.config.program_timeout).await
+    }
+
+    /// Read into `buffer`, split into transfers within the SPI size limit
+    pub async fn read(&mut self, addr: u32, buffer: &mut [u8]) -> SpiFlashResult<()> {
+        let mut chunk_addr = addr;
+        for chunk in buffer.chunks_mut(READ_CHUNK) {
+            let data = self.command(&address_command(CMD_READ_DATA, chunk_addr), chunk.len()).await?;
+            chunk.copy_from_slice(&data);
+            chunk_addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Read back `data` from `addr` and report the first differing byte
+    pub async fn verify(&mut self, addr: u32, data: &[u8]) -> SpiFlashResult<()> {
+        let mut done = 0;
+        let mut buffer = vec![0u8; VERIFY_CHUNK];
+        for chunk in data.chunks(VERIFY_CHUNK) {
+            let chunk_addr = addr + done as u32;
+            let actual = &mut buffer[..chunk.len()];
+            self.read(chunk_addr, actual).await?;
+            if let Some(i) = chunk.iter().zip(actual.iter()).position(|(e, a)| e != a) {
+                return Err(SpiFlashError::VerifyMismatch {
+                    offset: chunk_addr + i as u32,
+                    expected: chunk[i],
+                    actual: actual[i],
+                });
+            }
+            done += chunk.len();
+            self.report(FlashPhase::Verify, done, data.len());
+        }
+        Ok(())
+    }
+
+    /// Erase every sector touched by `data`, program it and read it back.
+    /// Other data sharing those sectors is erased too.
+    pub async fn erase_program_verify(&mut self, addr: u32, data: &[u8]) -> SpiFlashResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let first_sector = addr as usize / SECTOR_SIZE;
+        let last_sector = (addr as usize + data.len() - 1) / SECTOR_SIZE;
+        let sectors = last_sector - first_sector + 1;
+        for (i, sector) in (first_sector..=last_sector).enumerate() {
+            self.erase_sector((sector * SECTOR_SIZE) as u32).await?;
+            self.report(FlashPhase::Erase, i + 1, sectors);
+        }
+
+        let mut done = 0;
+        for (page_addr, chunk) in page_chunks(addr, data) {
+            self.program_page(page_addr, chunk).await?;
+            done += chunk.len();
+            self.report(FlashPhase::Program, done, data.len());
+        }
+
+        self.verify(addr, data).await
+    }
+
+    async fn command(&mut self, tx: &[u8], rx_len: usize) -> HardwareResult<Vec<u8>> {
+        let mut tx_buffer = tx.to_vec();
+        tx_buffer.resize(tx.len() + rx_len, 0);
+        let mut rx_buffer = vec![0u8; tx_buffer.len()];
+        self.spi.transfer(&tx_buffer, &mut rx_buffer, self.config.transfer_timeout).await?;
+        Ok(rx_buffer.split_off(tx.len()))
+    }
+
+    fn report(&mut self, phase: FlashPhase, done: usize, total: usize) {
+        log::debug!("SPI flash {:?}: {}/{}", phase, done, total);
+        if let Some(progress) = self.progress.as_mut() {
+            progress(FlashProgress { phase, done, total });
+        }
+    }
+}
+
+async fn read_status_register<T: Bidirectional>(spi: &mut T, timeout: Duration) -> HardwareResult<u8> {
+    let mut rx = [0u8; 2];
+    spi.transfer(&[CMD_READ_STATUS, 0], &mut rx, timeout).await?;
+    Ok(rx[1])
+}
+
+fn address_command(opcode: u8, addr: u32) -> [u8; 4] {
+    [opcode, (addr >> 16) as u8, (addr >> 8) as u8, addr as u8]
+}
+
+/// Split `data` at `addr` into chunks that each stay within one page
+fn page_chunks(addr: u32, data: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let chunk_addr = addr + offset as u32;
+        let room = PAGE_SIZE - chunk_addr as usize % PAGE_SIZE;
+        let len = room.min(data.len() - offset);
+        chunks.push((chunk_addr, &data[offset..offset + len]));
+        offset += len;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeSpiFlash, HardwareInterface};
+    use std::sync::{Arc, Mutex};
+
+    async fn flash(fake: FakeSpiFlash) -> SpiFlash<FakeSpiFlash> {
+        let mut fake = fake;
+        fake.initialize().await.unwrap();
+        SpiFlash::new(fake)
+    }
+
+    #[tokio::test]
+    async fn test_read_jedec_id() {
+        let mut flash = flash(FakeSpiFlash::new(SECTOR_SIZE).with_jedec_id([0xC2, 0x20, 0x16])).await;
+        let id = flash.read_jedec_id().await.unwrap();
+        assert_eq!(id, JedecId { manufacturer: 0xC2, memory_type: 0x20, capacity: 0x16 });
+    }
+
+    #[test]
+    fn test_page_chunks() {
+        let data = vec![0u8; 0x120];
+        let chunks: Vec<_> = page_chunks(0xF0, &data).into_iter().map(|(a, c)| (a, c.len())).collect();
+        assert_eq!(chunks, vec![(0xF0, 0x10), (0x100, 0x100), (0x200, 0x10)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_program_splits_pages() {
+        let mut flash = flash(FakeSpiFlash::new(2 * SECTOR_SIZE)).await;
+        let data: Vec<u8> = (0..0x120).map(|i| i as u8).collect();
+
+        flash.erase_program_verify(0xF0, &data).await.unwrap();
+
+        let fake = flash.into_inner();
+        assert_eq!(fake.page_programs(), &[(0xF0, 0x10), (0x100, 0x100), (0x200, 0x10)]);
+        assert_eq!(&fake.memory()[0xF0..0x210], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn test_large_read_stays_within_transfer_limit() {
+        let mut fake = FakeSpiFlash::new(2 * SECTOR_SIZE);
+        fake.initialize().await.unwrap();
+        let mut flash = SpiFlash::new(fake);
+
+        let mut buffer = vec![0u8; 2 * SECTOR_SIZE];
+        flash.read(0, &mut buffer).await.unwrap();
+
+        assert!(buffer.iter().all(|&b| b == 0xFF));
+        assert_eq!(flash.inner().commands(), &[CMD_READ_DATA, CMD_READ_DATA, CMD_READ_DATA]);
+    }
+
+    #[tokio::test]
+    async fn test_program_page_rejects_boundary_crossing() {
+        let mut flash = flash(FakeSpiFlash::new(SECTOR_SIZE)).await;
+        assert!(matches!(
+            flash.program_page(0xFF, &[1, 2]).await,
+            Err(SpiFlashError::Hardware(HardwareError::InvalidParameter(_)))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_busy_timeout() {
+        let mut flash = flash(FakeSpiFlash::new(SECTOR_SIZE).with_stuck_busy()).await;
+        match flash.erase_sector(0).await {
+            Err(SpiFlashError::BusyTimeout { waited }) => {
+                assert!(waited >= SpiFlashConfig::default().erase_timeout)
+            }
+            other => panic!("expected busy timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_verify_mismatch_reports_offset() {
+        let mut flash = flash(FakeSpiFlash::new(SECTOR_SIZE).with_stuck_bits(0x42, 0x01)).await;
+        let data = vec![0x00u8; 0x80];
+
+        assert_eq!(
+            flash.erase_program_verify(0, &data).await,
+            Err(SpiFlashError::VerifyMismatch { offset: 0x42, expected: 0x00, actual: 0x01 })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_progress_reporting() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = updates.clone();
+        let mut flash = flash(FakeSpiFlash::new(SECTOR_SIZE)).await
+            .with_progress(move |p| sink.lock().unwrap().push(p));
+
+        flash.erase_program_verify(0x80, &[0xA5; 0x100]).await.unwrap();
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates[0], FlashProgress { phase: FlashPhase::Erase, done: 1, total: 1 });
+        assert_eq!(updates[1], FlashProgress { phase: FlashPhase::Program, done: 0x80, total: 0x100 });
+        assert_eq!(updates[2], FlashProgress { phase: FlashPhase::Program, done: 0x100, total: 0x100 });
+        assert_eq!(updates[3], FlashProgress { phase: FlashPhase::Verify, done: 0x100, total: 0x100 });
+    }
+}
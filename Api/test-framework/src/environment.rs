@@ -0,0 +1,100 @@
+/*
+ * Test Environment Annotation
+ * Copyright (C) 2024
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Conditions a suite ran under, e.g. thermal chamber set point
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestEnvironmentInfo {
+    pub temperature_c: Option<f64>,
+    pub supply_voltage: Option<f64>,
+    pub chamber_profile: Option<String>,
+    pub operator: Option<String>,
+    pub extra: BTreeMap<String, String>,
+}
+
+impl TestEnvironmentInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn temperature_c(mut self, temperature: f64) -> Self {
+        self.temperature_c = Some(temperature);
+        self
+    }
+
+    pub fn supply_voltage(mut self, volts: f64) -> Self {
+        self.supply_voltage = Some(volts);
+        self
+    }
+
+    pub fn chamber_profile(mut self, profile: &str) -> Self {
+        self.chamber_profile = Some(profile.to_string());
+        self
+    }
+
+    pub fn operator(mut self, operator: &str) -> Self {
+        self.operator = Some(operator.to_string());
+        self
+    }
+
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.extra.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Key identifying the environment for grouping. The operator is
+    /// deliberately left out since it doesn't change the conditions.
+    pub fn group_key(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(t) = self.temperature_c {
+            parts.push(format!("temp={}C", t));
+        }
+        if let Some(v) = self.supply_voltage {
+            parts.push(format!("supply={}V", v));
+        }
+        if let Some(profile) = &self.chamber_profile {
+            parts.push(format!("profile={}", profile));
+        }
+        for (key, value) in &self.extra {
+            parts.push(format!("{}={}", key, value));
+        }
+        if parts.is_empty() {
+            "unspecified".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+impl fmt::Display for TestEnvironmentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.group_key())?;
+        if let Some(operator) = &self.operator {
+            write!(f, " (operator: {})", operator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_key() {
+        let env = TestEnvironmentInfo::new()
+            .temperature_c(-20.0)
+            .chamber_profile("cold-soak")
+            .operator("bench-2")
+            .with("board", "fm1");
+
+        assert_eq!(env.group_key(), "temp=-20C profile=cold-soak board=fm1");
+        assert_eq!(env.to_string(), "temp=-20C profile=cold-soak board=fm1 (operator: bench-2)");
+        assert_eq!(TestEnvironmentInfo::new().group_key(), "unspecified");
+    }
+}
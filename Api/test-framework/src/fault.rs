@@ -0,0 +1,296 @@
+/*
+ * Fault Injection for Chaos Testing
+ * Copyright (C) 2024
+ */
+
+use crate::state_machine::XorShift;
+use crate::{
+    Bidirectional, CapabilitySet, HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable,
+    Writable,
+};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const DEFAULT_SEED: u64 = 0x5EED;
+
+/// Interface wrapper making any device flake out on demand
+///
+/// Every read, write and transfer is one call: it waits out the injected
+/// latency, then may fail with `HardwareError::CommunicationError` instead
+/// of reaching the inner interface. Reads that do go through can have
+/// random bits of their data flipped. Random choices come from a seeded
+/// generator, so a failing run replays with the same seed.
+///
+/// ```ignore
+/// let flaky = FaultInjector::new(uart).fail_every(3).with_latency(Duration::from_millis(20));
+/// ```
+pub struct FaultInjector<T> {
+    inner: T,
+    fail_every: Option<u64>,
+    failure_probability: f64,
+    latency: Duration,
+    bit_flips: u32,
+    rng: XorShift,
+    total_calls: u64,
+    injected_failures: u64,
+}
+
+impl<T> FaultInjector<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            fail_every: None,
+            failure_probability: 0.0,
+            latency: Duration::ZERO,
+            bit_flips: 0,
+            rng: XorShift::new(DEFAULT_SEED),
+            total_calls: 0,
+            injected_failures: 0,
+        }
+    }
+
+    /// Fail every `n`th call, counting from 1; 0 disables it
+    pub fn fail_every(mut self, n: u64) -> Self {
+        self.fail_every = (n > 0).then_some(n);
+        self
+    }
+
+    /// Fail each call with `probability`, clamped to 0..=1
+    pub fn with_failure_probability(mut self, probability: f64) -> Self {
+        self.failure_probability = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Wait `latency` before each call
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Flip `bits` random bits in the data of each successful read
+    pub fn with_bit_flips(mut self, bits: u32) -> Self {
+        self.bit_flips = bits;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift::new(seed);
+        self
+    }
+
+    /// Calls seen so far, failed or not
+    pub fn total_calls(&self) -> u64 {
+        self.total_calls
+    }
+
+    /// Calls failed by the injector rather than the inner interface
+    pub fn injected_failures(&self) -> u64 {
+        self.injected_failures
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Start a call, failing it if a fault is due
+    async fn begin_call(&mut self) -> HardwareResult<()> {
+        if !self.latency.is_zero() {
+            sleep(self.latency).await;
+        }
+        self.total_calls += 1;
+
+        let nth = self.fail_every.is_some_and(|n| self.total_calls % n == 0);
+        let random = self.failure_probability > 0.0 && self.next_unit() < self.failure_probability;
+        if nth || random {
+            self.injected_failures += 1;
+            return Err(HardwareError::CommunicationError(format!(
+                "injected fault on call {}",
+                self.total_calls
+            )));
+        }
+        Ok(())
+    }
+
+    /// Uniform sample in [0, 1)
+    fn next_unit(&mut self) -> f64 {
+        (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn corrupt(&mut self, data: &mut [u8]) {
+        if data.is_empty() {
+            return;
+        }
+        for _ in 0..self.bit_flips {
+            let bit = (self.rng.next_u64() % (data.len() as u64 * 8)) as usize;
+            data[bit / 8] ^= 1 << (bit % 8);
+        }
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Send + Sync> HardwareInterface for FaultInjector<T> {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.inner.initialize().await
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.inner.deinitialize().await
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.inner.is_initialized()
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        self.inner.get_status().await
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.inner.capabilities()
+    }
+}
+
+#[async_trait]
+impl<T: Readable + Send> Readable for FaultInjector<T> {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        self.begin_call().await?;
+        let count = self.inner.read(buffer, timeout).await?;
+        self.corrupt(&mut buffer[..count]);
+        Ok(count)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        self.begin_call().await?;
+        self.inner.read_exact(buffer, timeout).await?;
+        self.corrupt(buffer);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Writable + Send> Writable for FaultInjector<T> {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        self.begin_call().await?;
+        self.inner.write(data).await
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.begin_call().await?;
+        self.inner.write_all(data).await
+    }
+}
+
+#[async_trait]
+impl<T: Bidirectional + Send> Bidirectional for FaultInjector<T> {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        self.begin_call().await?;
+        let count = self.inner.transfer(tx_data, rx_data, timeout).await?;
+        self.corrupt(&mut rx_data[..count.min(rx_data.len())]);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{FakeLoopback, FakeRegisterMap};
+    use tokio::time::Instant;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    async fn loopback() -> FakeLoopback {
+        let mut loopback = FakeLoopback::new();
+        loopback.initialize().await.unwrap();
+        loopback
+    }
+
+    #[tokio::test]
+    async fn test_fails_every_nth_call() {
+        let mut flaky = FaultInjector::new(loopback().await).fail_every(3);
+
+        let mut failed = Vec::new();
+        for call in 1..=7 {
+            if flaky.write(&[call]).await.is_err() {
+                failed.push(call);
+            }
+        }
+
+        assert_eq!(failed, vec![3, 6]);
+        assert_eq!((flaky.total_calls(), flaky.injected_failures()), (7, 2));
+        // Failed writes never reached the link
+        assert_eq!(flaky.inner().pending(), 5);
+        flaky.write(&[0]).await.unwrap();
+        assert_eq!(
+            flaky.write(&[0]).await.unwrap_err(),
+            HardwareError::CommunicationError("injected fault on call 9".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probability_is_reproducible() {
+        async fn pattern(seed: u64) -> Vec<bool> {
+            let mut flaky = FaultInjector::new(loopback().await).with_failure_probability(0.5).with_seed(seed);
+            let mut pattern = Vec::new();
+            for _ in 0..64 {
+                pattern.push(flaky.write(&[0]).await.is_err());
+            }
+            pattern
+        }
+
+        let first = pattern(7).await;
+        assert_eq!(first, pattern(7).await);
+        assert_ne!(first, pattern(8).await);
+        let failures = first.iter().filter(|failed| **failed).count();
+        assert!((16..48).contains(&failures), "{} failures", failures);
+
+        let mut never = FaultInjector::new(loopback().await).with_failure_probability(0.0);
+        let mut always = FaultInjector::new(loopback().await).with_failure_probability(1.0);
+        for _ in 0..16 {
+            assert!(never.write(&[0]).await.is_ok());
+            assert!(always.write(&[0]).await.is_err());
+        }
+        assert_eq!((never.injected_failures(), always.injected_failures()), (0, 16));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency_before_each_call() {
+        let mut slow = FaultInjector::new(loopback().await).with_latency(Duration::from_millis(20));
+        let start = Instant::now();
+
+        slow.write_all(b"ping").await.unwrap();
+        let mut echo = [0u8; 4];
+        slow.read_exact(&mut echo, TIMEOUT).await.unwrap();
+
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+        assert_eq!(&echo, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_corrupts_reads() {
+        let mut noisy = FaultInjector::new(loopback().await).with_bit_flips(1);
+        let sent = [0x5Au8; 16];
+        noisy.write_all(&sent).await.unwrap();
+
+        let mut received = [0u8; 16];
+        noisy.read_exact(&mut received, TIMEOUT).await.unwrap();
+        let flipped: u32 = sent.iter().zip(&received).map(|(a, b)| (a ^ b).count_ones()).sum();
+        assert_eq!(flipped, 1);
+        assert_eq!(noisy.injected_failures(), 0);
+
+        let mut registers = FaultInjector::new(FakeRegisterMap::new()).with_bit_flips(1);
+        assert_eq!(registers.capabilities(), FakeRegisterMap::new().capabilities());
+        registers.initialize().await.unwrap();
+        let mut rx = [0u8; 2];
+        assert_eq!(registers.transfer(&[0x00], &mut rx, TIMEOUT).await, Ok(2));
+        assert_eq!(rx.iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+    }
+}
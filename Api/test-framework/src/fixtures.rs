@@ -0,0 +1,223 @@
+/*
+ * Suite-Level Fixtures Shared Across Tests
+ * Copyright (C) 2024
+ */
+
+use crate::runner::TestTags;
+use crate::{DiagMutex, HardwareError, HardwareInterface, HardwareResult, TestFn, TestFuture, TestRunner, TestSuiteResult};
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+
+/// Test function receiving the suite's fixtures alongside the interface
+pub type FixtureTestFn<T> = Box<dyn FnOnce(Arc<DiagMutex<T>>, Arc<FixtureMap>) -> TestFuture + Send>;
+
+type Teardown = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FixtureKey {
+    Type(TypeId),
+    Name(String),
+}
+
+/// Typed store of expensive suite setup, e.g. a parsed calibration table or
+/// an authenticated session
+///
+/// Values are keyed by their type, or by name when a suite needs several of
+/// the same type. Teardowns run in reverse insertion order once the suite
+/// has finished.
+#[derive(Default)]
+pub struct FixtureMap {
+    values: HashMap<FixtureKey, Arc<dyn Any + Send + Sync>>,
+    teardowns: StdMutex<Vec<Teardown>>,
+}
+
+impl FixtureMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn put<V: Send + Sync + 'static>(&mut self, key: FixtureKey, value: V) -> Arc<V> {
+        let value = Arc::new(value);
+        self.values.insert(key, value.clone());
+        value
+    }
+
+    fn push_teardown<V, F>(&mut self, value: Arc<V>, teardown: F)
+    where
+        V: Send + Sync + 'static,
+        F: FnOnce(&V) + Send + 'static,
+    {
+        self.teardowns.get_mut().unwrap().push(Box::new(move || teardown(&value)));
+    }
+
+    pub fn insert<V: Send + Sync + 'static>(&mut self, value: V) {
+        self.put(FixtureKey::Type(TypeId::of::<V>()), value);
+    }
+
+    pub fn insert_with_teardown<V, F>(&mut self, value: V, teardown: F)
+    where
+        V: Send + Sync + 'static,
+        F: FnOnce(&V) + Send + 'static,
+    {
+        let value = self.put(FixtureKey::Type(TypeId::of::<V>()), value);
+        self.push_teardown(value, teardown);
+    }
+
+    pub fn insert_named<V: Send + Sync + 'static>(&mut self, name: &str, value: V) {
+        self.put(FixtureKey::Name(name.to_string()), value);
+    }
+
+    pub fn insert_named_with_teardown<V, F>(&mut self, name: &str, value: V, teardown: F)
+    where
+        V: Send + Sync + 'static,
+        F: FnOnce(&V) + Send + 'static,
+    {
+        let value = self.put(FixtureKey::Name(name.to_string()), value);
+        self.push_teardown(value, teardown);
+    }
+
+    pub fn get<V: Send + Sync + 'static>(&self) -> Option<Arc<V>> {
+        self.values.get(&FixtureKey::Type(TypeId::of::<V>()))?.clone().downcast().ok()
+    }
+
+    /// Named fixture, `None` if absent or of another type
+    pub fn get_named<V: Send + Sync + 'static>(&self, name: &str) -> Option<Arc<V>> {
+        self.values.get(&FixtureKey::Name(name.to_string()))?.clone().downcast().ok()
+    }
+
+    /// Like `get`, failing with the requested type's name if absent
+    pub fn require<V: Send + Sync + 'static>(&self) -> HardwareResult<Arc<V>> {
+        self.get()
+            .ok_or_else(|| HardwareError::OperationFailed(format!("missing fixture of type {}", type_name::<V>())))
+    }
+
+    /// Like `get_named`, failing with the fixture name and requested type
+    pub fn require_named<V: Send + Sync + 'static>(&self, name: &str) -> HardwareResult<Arc<V>> {
+        self.get_named(name).ok_or_else(|| {
+            HardwareError::OperationFailed(format!("missing fixture {:?} of type {}", name, type_name::<V>()))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Run the registered teardowns, latest first. Later calls do nothing.
+    pub fn teardown(&self) {
+        let teardowns = std::mem::take(&mut *self.teardowns.lock().unwrap());
+        for teardown in teardowns.into_iter().rev() {
+            teardown();
+        }
+    }
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run tests sharing `fixtures`, tearing them down after the last test
+    pub async fn run_with_fixtures(
+        &self,
+        name: &str,
+        fixtures: FixtureMap,
+        tests: Vec<(&str, FixtureTestFn<T>)>,
+    ) -> TestSuiteResult {
+        self.run_tagged_with_fixtures(name, fixtures, tests, &TestTags::new()).await
+    }
+
+    pub(crate) async fn run_tagged_with_fixtures(
+        &self,
+        name: &str,
+        fixtures: FixtureMap,
+        tests: Vec<(&str, FixtureTestFn<T>)>,
+        tags: &TestTags,
+    ) -> TestSuiteResult {
+        let fixtures = Arc::new(fixtures);
+        let tests: Vec<(&str, TestFn<T>)> = tests
+            .into_iter()
+            .map(|(test_name, test)| {
+                let fixtures = fixtures.clone();
+                let test: TestFn<T> = Box::new(move |interface| test(interface, fixtures));
+                (test_name, test)
+            })
+            .collect();
+
+        let suite = self.run_tagged_suite(name, tests, tags).await;
+        fixtures.teardown();
+        suite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::TestStatus;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    struct CalibrationTable(Vec<f64>);
+
+    fn count_test() -> FixtureTestFn<MockHardwareInterface> {
+        Box::new(|_, fixtures| {
+            Box::pin(async move {
+                fixtures.require::<AtomicU32>()?.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_shared_fixtures_and_teardown_order() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let order = Arc::new(StdMutex::new(Vec::new()));
+        let mut fixtures = FixtureMap::new();
+        let log = order.clone();
+        fixtures.insert_with_teardown(AtomicU32::new(0), move |counter| {
+            log.lock().unwrap().push(format!("counter={}", counter.load(Ordering::SeqCst)));
+        });
+        fixtures.insert(CalibrationTable(vec![1.0, 0.5]));
+        let log = order.clone();
+        fixtures.insert_named_with_teardown("session", String::from("token"), move |_| {
+            log.lock().unwrap().push("session".to_string());
+        });
+
+        let tests = vec![("first", count_test()), ("second", count_test()), ("third", count_test())];
+        let result = runner.run_with_fixtures("shared", fixtures, tests).await;
+
+        assert_eq!(result.passed_tests, 3);
+        assert_eq!(*order.lock().unwrap(), vec!["session".to_string(), "counter=3".to_string()]);
+    }
+
+    #[test]
+    fn test_typed_and_named_access() {
+        let mut fixtures = FixtureMap::new();
+        fixtures.insert(CalibrationTable(vec![1.0, 0.5]));
+        fixtures.insert_named("stimulus", vec![0u8, 255]);
+
+        assert_eq!(fixtures.get::<CalibrationTable>().unwrap().0, vec![1.0, 0.5]);
+        assert_eq!(*fixtures.get_named::<Vec<u8>>("stimulus").unwrap(), vec![0, 255]);
+        assert!(fixtures.get_named::<String>("stimulus").is_none());
+        assert!(fixtures.get::<Vec<u8>>().is_none());
+        assert_eq!(fixtures.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_fixture_error() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let lookup: FixtureTestFn<MockHardwareInterface> = Box::new(|_, fixtures| {
+            Box::pin(async move { fixtures.require::<CalibrationTable>().map(|_| ()) })
+        });
+
+        let result = runner.run_with_fixtures("missing", FixtureMap::new(), vec![("lookup", lookup)]).await;
+
+        assert!(matches!(&result.results[0].status,
+            TestStatus::Error(msg) if msg.contains("missing fixture of type hardware_test_framework::fixtures::tests::CalibrationTable")));
+        assert_eq!(
+            FixtureMap::new().require_named::<u32>("retries").unwrap_err(),
+            HardwareError::OperationFailed("missing fixture \"retries\" of type u32".to_string())
+        );
+    }
+}
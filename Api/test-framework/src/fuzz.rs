@@ -0,0 +1,486 @@
+/*
+ * Random Operation Sequences Checked Against Invariants
+ * Copyright (C) 2024
+ */
+
+use crate::state_machine::XorShift;
+use crate::{DiagMutex, HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, ReportAppendix, TestFuture};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type TargetFn<T> = Box<dyn Fn() -> T + Send + Sync>;
+type OperationFn<T> = Box<dyn Fn(Arc<DiagMutex<T>>, Vec<u64>) -> TestFuture + Send + Sync>;
+type InvariantFn<T> = Box<dyn Fn(&T, &[Observation]) -> Result<(), String> + Send + Sync>;
+
+/// Generated operation: the name of its generator and the drawn parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuzzOp {
+    pub name: String,
+    pub params: Vec<u64>,
+}
+
+impl fmt::Display for FuzzOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+        write!(f, "{}({})", self.name, params.join(", "))
+    }
+}
+
+/// What an operation did, as seen by the invariants
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub op: FuzzOp,
+    /// Error returned by the operation; errors alone are not violations
+    pub error: Option<HardwareError>,
+    /// Interface status read after the operation, `None` if that failed
+    pub status: Option<InterfaceStatus>,
+}
+
+/// Operation sequence that can be replayed against a fresh target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FuzzScript {
+    pub seed: u64,
+    pub ops: Vec<FuzzOp>,
+}
+
+impl FuzzScript {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(path, text)
+    }
+}
+
+/// Invariant broken by a sequence, with the shrunk reproducer
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzFailure {
+    pub invariant: String,
+    pub message: String,
+    /// Length of the generated sequence up to the violation
+    pub original_len: usize,
+    /// Shortest sequence found that still breaks an invariant
+    pub script: FuzzScript,
+}
+
+impl FuzzFailure {
+    pub fn appendix(&self) -> ReportAppendix {
+        ReportAppendix::new("Fuzzer reproducer", &self.to_string())
+    }
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invariant \"{}\" violated: {}", self.invariant, self.message)?;
+        writeln!(
+            f,
+            "seed {}, shrunk from {} to {} operations:",
+            self.script.seed,
+            self.original_len,
+            self.script.ops.len()
+        )?;
+        for (i, op) in self.script.ops.iter().enumerate() {
+            writeln!(f, "  {:>3}: {}", i, op)?;
+        }
+        Ok(())
+    }
+}
+
+struct Generator<T> {
+    name: String,
+    weight: u32,
+    ranges: Vec<RangeInclusive<u64>>,
+    apply: OperationFn<T>,
+}
+
+struct Invariant<T> {
+    name: String,
+    check: InvariantFn<T>,
+}
+
+/// Violation found while executing a sequence: the invariant, its message
+/// and the number of operations executed
+type Violated = (String, String, usize);
+
+/// Fuzzer throwing seeded random sequences of valid operations at fresh
+/// targets and checking every invariant after each operation
+///
+/// On a violation the sequence is shrunk by removing operations while an
+/// invariant still breaks, and the result is reported as a replayable
+/// script.
+pub struct SequenceFuzzer<T> {
+    target: TargetFn<T>,
+    generators: Vec<Generator<T>>,
+    invariants: Vec<Invariant<T>>,
+    seed: u64,
+    sequences: usize,
+    length: usize,
+    script_path: Option<PathBuf>,
+}
+
+impl<T: HardwareInterface + 'static> SequenceFuzzer<T> {
+    /// Fuzzer building a fresh target with `target` for every sequence
+    pub fn new<F>(target: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        Self {
+            target: Box::new(target),
+            generators: Vec::new(),
+            invariants: Vec::new(),
+            seed: 1,
+            sequences: 100,
+            length: 50,
+            script_path: None,
+        }
+    }
+
+    /// Operation drawn with relative `weight`, one parameter per range
+    pub fn operation<F>(mut self, name: &str, weight: u32, ranges: &[RangeInclusive<u64>], apply: F) -> Self
+    where
+        F: Fn(Arc<DiagMutex<T>>, Vec<u64>) -> TestFuture + Send + Sync + 'static,
+    {
+        self.generators.push(Generator {
+            name: name.to_string(),
+            weight,
+            ranges: ranges.to_vec(),
+            apply: Box::new(apply),
+        });
+        self
+    }
+
+    /// Check run after every operation with the target and the observations
+    /// so far, the latest last
+    pub fn invariant<F>(mut self, name: &str, check: F) -> Self
+    where
+        F: Fn(&T, &[Observation]) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.invariants.push(Invariant {
+            name: name.to_string(),
+            check: Box::new(check),
+        });
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Number of sequences and operations per sequence
+    pub fn with_bounds(mut self, sequences: usize, length: usize) -> Self {
+        self.sequences = sequences;
+        self.length = length;
+        self
+    }
+
+    /// Save the reproducer of a violation to `path`
+    pub fn with_script_path(mut self, path: &Path) -> Self {
+        self.script_path = Some(path.to_path_buf());
+        self
+    }
+
+    fn generate(&self, rng: &mut XorShift) -> Vec<FuzzOp> {
+        let total: u64 = self.generators.iter().map(|g| g.weight as u64).sum();
+        if total == 0 {
+            return Vec::new();
+        }
+        (0..self.length)
+            .map(|_| {
+                let mut pick = rng.next_u64() % total;
+                let generator = self
+                    .generators
+                    .iter()
+                    .find(|g| {
+                        let hit = pick < g.weight as u64;
+                        pick = pick.saturating_sub(g.weight as u64);
+                        hit
+                    })
+                    .expect("pick below total weight");
+                let params = generator
+                    .ranges
+                    .iter()
+                    .map(|range| match (range.end() - range.start()).checked_add(1) {
+                        Some(span) => range.start() + rng.next_u64() % span,
+                        None => rng.next_u64(),
+                    })
+                    .collect();
+                FuzzOp { name: generator.name.clone(), params }
+            })
+            .collect()
+    }
+
+    /// Execute `ops` against a fresh target, stopping at the first violation
+    async fn execute(&self, ops: &[FuzzOp]) -> HardwareResult<Option<Violated>> {
+        let interface = Arc::new(DiagMutex::new((self.target)()));
+        let mut observations = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            let generator = self
+                .generators
+                .iter()
+                .find(|g| g.name == op.name)
+                .ok_or_else(|| HardwareError::InvalidParameter(format!("unknown fuzz operation {:?}", op.name)))?;
+            let error = (generator.apply)(interface.clone(), op.params.clone()).await.err();
+
+            let target = interface.lock().await;
+            let status = target.get_status().await.ok();
+            observations.push(Observation { op: op.clone(), error, status });
+            for invariant in &self.invariants {
+                if let Err(message) = (invariant.check)(&target, &observations) {
+                    return Ok(Some((invariant.name.clone(), message, i + 1)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove chunks of operations, halving the chunk size down to single
+    /// operations, for as long as some invariant still breaks
+    async fn shrink(&self, mut ops: Vec<FuzzOp>, mut violated: Violated) -> HardwareResult<(Vec<FuzzOp>, Violated)> {
+        let mut chunk = ops.len() / 2;
+        while chunk > 0 {
+            let mut start = 0;
+            let mut removed = false;
+            while start < ops.len() {
+                let end = (start + chunk).min(ops.len());
+                let candidate: Vec<FuzzOp> = ops[..start].iter().chain(&ops[end..]).cloned().collect();
+                match self.execute(&candidate).await? {
+                    Some(found) => {
+                        ops = candidate[..found.2].to_vec();
+                        violated = found;
+                        removed = true;
+                    }
+                    None => start += chunk,
+                }
+            }
+            if !removed {
+                chunk /= 2;
+            }
+        }
+        Ok((ops, violated))
+    }
+
+    /// Run the configured sequences, returning the shrunk reproducer of the
+    /// first violation. The reproducer is logged and, if a script path is
+    /// set, saved there.
+    pub async fn run(&self) -> HardwareResult<Option<FuzzFailure>> {
+        let mut rng = XorShift::new(self.seed);
+        for _ in 0..self.sequences {
+            let ops = self.generate(&mut rng);
+            let violated = match self.execute(&ops).await? {
+                Some(violated) => violated,
+                None => continue,
+            };
+            let original_len = violated.2;
+            let (ops, (invariant, message, _)) = self.shrink(ops[..original_len].to_vec(), violated).await?;
+            let failure = FuzzFailure {
+                invariant,
+                message,
+                original_len,
+                script: FuzzScript { seed: self.seed, ops },
+            };
+            log::error!("Fuzzer found a violation\n{}", failure);
+            if let Some(path) = &self.script_path {
+                failure
+                    .script
+                    .save(path)
+                    .map_err(|e| HardwareError::OperationFailed(format!("saving fuzz script: {}", e)))?;
+            }
+            return Ok(Some(failure));
+        }
+        Ok(None)
+    }
+
+    /// Replay a saved script against a fresh target, returning the violated
+    /// invariant and its message
+    pub async fn replay(&self, script: &FuzzScript) -> HardwareResult<Option<(String, String)>> {
+        Ok(self.execute(&script.ops).await?.map(|(invariant, message, _)| (invariant, message)))
+    }
+
+    /// `run`, failing with the reproducer if an invariant broke
+    pub async fn check(&self) -> HardwareResult<()> {
+        match self.run().await? {
+            Some(failure) => Err(HardwareError::OperationFailed(failure.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::FakeRegisterMap;
+    use crate::{Readable, Writable};
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    fn register_fuzzer() -> SequenceFuzzer<FakeRegisterMap> {
+        SequenceFuzzer::new(FakeRegisterMap::new)
+            .operation("init", 1, &[], |device, _| Box::pin(async move { device.lock().await.initialize().await }))
+            .operation("deinit", 1, &[], |device, _| Box::pin(async move { device.lock().await.deinitialize().await }))
+            .operation("write", 4, &[0..=255, 0..=255], |device, params| {
+                Box::pin(async move { device.lock().await.write_all(&[params[0] as u8, params[1] as u8]).await })
+            })
+            .operation("read", 4, &[0..=255, 1..=8], |device, params| {
+                Box::pin(async move {
+                    let mut device = device.lock().await;
+                    device.write_all(&[params[0] as u8]).await?;
+                    let mut buffer = vec![0; params[1] as usize];
+                    device.read(&mut buffer, Duration::from_millis(10)).await.map(|_| ())
+                })
+            })
+    }
+
+    #[tokio::test]
+    async fn test_register_map_upholds_invariants() {
+        let fuzzer = register_fuzzer()
+            .with_bounds(20, 40)
+            .invariant("successful writes are stored", |device, observations| {
+                let last = observations.last().unwrap();
+                if last.op.name == "write" && last.error.is_none() {
+                    let (address, value) = (last.op.params[0] as u8, last.op.params[1] as u8);
+                    if device.registers(address, 1)[0] != value {
+                        return Err(format!("register {:#04x} does not hold {:#04x}", address, value));
+                    }
+                }
+                Ok(())
+            })
+            .invariant("no access while deinitialized", |device, observations| {
+                let last = observations.last().unwrap();
+                match (device.is_initialized(), &last.error) {
+                    (false, None) if last.op.name == "write" || last.op.name == "read" => {
+                        Err(format!("{} succeeded while deinitialized", last.op))
+                    }
+                    _ => Ok(()),
+                }
+            });
+
+        assert_eq!(fuzzer.run().await.unwrap(), None);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Mode {
+        Off,
+        Idle,
+        Measuring,
+    }
+
+    /// Mode machine counting rejected commands. Deinitializing while
+    /// measuring wrongly leaves it measuring.
+    struct BuggyModeDevice {
+        mode: Mode,
+        errors: u32,
+    }
+
+    impl BuggyModeDevice {
+        fn new() -> Self {
+            Self { mode: Mode::Off, errors: 0 }
+        }
+
+        fn command(&mut self, from: Mode, to: Mode) -> HardwareResult<()> {
+            if self.mode != from {
+                self.errors += 1;
+                return Err(HardwareError::InvalidParameter(format!("{:?} -> {:?} from {:?}", from, to, self.mode)));
+            }
+            self.mode = to;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl HardwareInterface for BuggyModeDevice {
+        async fn initialize(&mut self) -> HardwareResult<()> {
+            self.command(Mode::Off, Mode::Idle)
+        }
+
+        async fn deinitialize(&mut self) -> HardwareResult<()> {
+            if self.mode != Mode::Measuring {
+                self.mode = Mode::Off;
+            }
+            Ok(())
+        }
+
+        fn is_initialized(&self) -> bool {
+            self.mode != Mode::Off
+        }
+
+        async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+            Ok(InterfaceStatus {
+                initialized: self.mode != Mode::Off,
+                error_count: self.errors,
+                last_error: None,
+                uptime: Duration::from_secs(0),
+            })
+        }
+    }
+
+    fn mode_fuzzer() -> SequenceFuzzer<BuggyModeDevice> {
+        SequenceFuzzer::new(BuggyModeDevice::new)
+            .operation("init", 1, &[], |device, _| Box::pin(async move { device.lock().await.initialize().await }))
+            .operation("start", 2, &[], |device, _| {
+                Box::pin(async move { device.lock().await.command(Mode::Idle, Mode::Measuring) })
+            })
+            .operation("stop", 2, &[], |device, _| {
+                Box::pin(async move { device.lock().await.command(Mode::Measuring, Mode::Idle) })
+            })
+            .operation("deinit", 1, &[], |device, _| Box::pin(async move { device.lock().await.deinitialize().await }))
+            .invariant("error_count only increases", |_, observations| {
+                let counts: Vec<u32> = observations.iter().filter_map(|o| o.status.as_ref()).map(|s| s.error_count).collect();
+                match counts.windows(2).find(|pair| pair[1] < pair[0]) {
+                    Some(pair) => Err(format!("error_count dropped from {} to {}", pair[0], pair[1])),
+                    None => Ok(()),
+                }
+            })
+            .invariant("not initialized after deinit", |device, observations| {
+                match observations.last() {
+                    Some(last) if last.op.name == "deinit" && device.is_initialized() => {
+                        Err(format!("still initialized in {:?}", device.mode))
+                    }
+                    _ => Ok(()),
+                }
+            })
+            .with_bounds(10, 30)
+    }
+
+    #[tokio::test]
+    async fn test_shrinks_buggy_device_to_minimal_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reproducer.json");
+        let fuzzer = mode_fuzzer().with_seed(42).with_script_path(&path);
+
+        let failure = fuzzer.run().await.unwrap().expect("violation");
+
+        assert_eq!(failure.invariant, "not initialized after deinit");
+        assert_eq!(failure.message, "still initialized in Measuring");
+        let names: Vec<&str> = failure.script.ops.iter().map(|op| op.name.as_str()).collect();
+        assert_eq!(names, vec!["init", "start", "deinit"]);
+        assert!(failure.original_len >= 3);
+        assert!(failure.to_string().contains("    2: deinit()\n"));
+
+        let saved = FuzzScript::load(&path).unwrap();
+        assert_eq!(saved, failure.script);
+        assert_eq!(
+            fuzzer.replay(&saved).await.unwrap(),
+            Some(("not initialized after deinit".to_string(), "still initialized in Measuring".to_string()))
+        );
+        assert_eq!(mode_fuzzer().with_seed(42).run().await.unwrap(), Some(failure));
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_unknown_operation() {
+        let script = FuzzScript {
+            seed: 0,
+            ops: vec![FuzzOp { name: "reset".to_string(), params: vec![] }],
+        };
+
+        assert!(matches!(mode_fuzzer().replay(&script).await, Err(HardwareError::InvalidParameter(_))));
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * Suite Result History
+ * Copyright (C) 2024
+ */
+
+use crate::{status_parts, TestStatus, TestSuiteResult, TimingRegression};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Outcome tally for one test across several runs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutcomeCounts {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub errors: usize,
+}
+
+impl OutcomeCounts {
+    fn record(&mut self, status: &TestStatus) {
+        match status {
+            TestStatus::Passed => self.passed += 1,
+            TestStatus::Failed(_) => self.failed += 1,
+            TestStatus::Skipped(_) => self.skipped += 1,
+            TestStatus::Error(_) => self.errors += 1,
+        }
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failed + self.errors > 0
+    }
+}
+
+/// Aggregated results of every run sharing one environment
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentGroup {
+    pub key: String,
+    pub runs: usize,
+    pub tests: BTreeMap<String, OutcomeCounts>,
+}
+
+/// Ordered collection of suite results from repeated runs
+#[derive(Debug, Clone, Default)]
+pub struct SuiteHistory {
+    runs: Vec<TestSuiteResult>,
+}
+
+impl SuiteHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, result: TestSuiteResult) {
+        self.runs.push(result);
+    }
+
+    pub fn runs(&self) -> &[TestSuiteResult] {
+        &self.runs
+    }
+
+    /// Aggregate per-test outcomes for each environment, in order of first appearance
+    pub fn group_by_environment(&self) -> Vec<EnvironmentGroup> {
+        let mut groups: Vec<EnvironmentGroup> = Vec::new();
+        for run in &self.runs {
+            let key = run
+                .environment
+                .as_ref()
+                .map(|env| env.group_key())
+                .unwrap_or_else(|| "unspecified".to_string());
+
+            let index = match groups.iter().position(|g| g.key == key) {
+                Some(index) => index,
+                None => {
+                    groups.push(EnvironmentGroup { key, runs: 0, tests: BTreeMap::new() });
+                    groups.len() - 1
+                }
+            };
+
+            let group = &mut groups[index];
+            group.runs += 1;
+            for result in &run.results {
+                group.tests.entry(result.name.clone()).or_default().record(&result.status);
+            }
+        }
+        groups
+    }
+
+    /// Tests that fail in at least one environment and pass cleanly in another
+    pub fn environment_sensitive_tests(&self) -> Vec<String> {
+        let groups = self.group_by_environment();
+        let mut names: Vec<String> = Vec::new();
+        for group in &groups {
+            for (name, counts) in &group.tests {
+                let clean_elsewhere = groups.iter().any(|other| {
+                    other.tests.get(name).map_or(false, |c| c.passed > 0 && !c.has_failures())
+                });
+                if counts.has_failures() && clean_elsewhere && !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names.sort();
+        names
+    }
+}
+
+/// Status change of one test between two runs; `None` means absent
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestChange {
+    pub name: String,
+    pub before: Option<TestStatus>,
+    pub after: Option<TestStatus>,
+}
+
+/// Per-test differences between two runs of a suite
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuiteDiff {
+    pub suite: String,
+    pub changes: Vec<TestChange>,
+    pub unchanged: usize,
+    /// Timing regressions flagged on the current run
+    pub timing_regressions: Vec<TimingRegression>,
+}
+
+impl SuiteDiff {
+    /// Compare by test name and status keyword; a changed failure message
+    /// alone is not a change
+    pub fn between(previous: &TestSuiteResult, current: &TestSuiteResult) -> Self {
+        let mut changes = Vec::new();
+        let mut unchanged = 0;
+        for result in &current.results {
+            let before = previous.results.iter().find(|r| r.name == result.name).map(|r| r.status.clone());
+            let same = before
+                .as_ref()
+                .map_or(false, |b| status_parts(b).0 == status_parts(&result.status).0);
+            if same {
+                unchanged += 1;
+            } else {
+                changes.push(TestChange {
+                    name: result.name.clone(),
+                    before,
+                    after: Some(result.status.clone()),
+                });
+            }
+        }
+        for result in &previous.results {
+            if !current.results.iter().any(|r| r.name == result.name) {
+                changes.push(TestChange {
+                    name: result.name.clone(),
+                    before: Some(result.status.clone()),
+                    after: None,
+                });
+            }
+        }
+        Self {
+            suite: current.name.clone(),
+            changes,
+            unchanged,
+            timing_regressions: current.timing_regressions.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.timing_regressions.is_empty()
+    }
+}
+
+fn describe(status: &Option<TestStatus>) -> String {
+    match status.as_ref().map(status_parts) {
+        None => "absent".to_string(),
+        Some((keyword, None)) => keyword.to_string(),
+        Some((keyword, Some(msg))) => format!("{} ({})", keyword, msg),
+    }
+}
+
+impl fmt::Display for SuiteDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {} changed, {} unchanged", self.suite, self.changes.len(), self.unchanged)?;
+        for change in &self.changes {
+            writeln!(f, "  {}: {} -> {}", change.name, describe(&change.before), describe(&change.after))?;
+        }
+        for regression in &self.timing_regressions {
+            writeln!(f, "  timing: {}", regression)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestEnvironmentInfo, TestResult};
+    use std::time::Duration;
+
+    fn run(temperature: f64, read_status: TestStatus) -> TestSuiteResult {
+        let results = vec![
+            TestResult::new("init", TestStatus::Passed, Duration::ZERO),
+            TestResult::new("read", read_status, Duration::ZERO),
+        ];
+        let mut suite = TestSuiteResult::from_results("eps", results, Duration::ZERO);
+        suite.environment = Some(TestEnvironmentInfo::new().temperature_c(temperature));
+        suite
+    }
+
+    #[test]
+    fn test_group_by_environment() {
+        let mut history = SuiteHistory::new();
+        history.push(run(25.0, TestStatus::Passed));
+        history.push(run(60.0, TestStatus::Failed("crc".to_string())));
+        history.push(run(25.0, TestStatus::Passed));
+        history.push(run(60.0, TestStatus::Passed));
+
+        let groups = history.group_by_environment();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].key, "temp=25C");
+        assert_eq!(groups[0].runs, 2);
+        assert_eq!(groups[0].tests["read"], OutcomeCounts { passed: 2, ..Default::default() });
+        assert_eq!(groups[1].key, "temp=60C");
+        assert_eq!(groups[1].tests["read"], OutcomeCounts { passed: 1, failed: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_environment_sensitive_tests() {
+        let mut history = SuiteHistory::new();
+        history.push(run(25.0, TestStatus::Passed));
+        history.push(run(60.0, TestStatus::Error("timeout".to_string())));
+        history.push(run(-20.0, TestStatus::Passed));
+
+        assert_eq!(history.environment_sensitive_tests(), vec!["read".to_string()]);
+    }
+
+    #[test]
+    fn test_suite_diff() {
+        let previous = run(25.0, TestStatus::Passed);
+        let mut current = run(25.0, TestStatus::Failed("crc".to_string()));
+        current.results[0].name = "probe".to_string();
+
+        let diff = SuiteDiff::between(&previous, &current);
+
+        assert_eq!(diff.unchanged, 0);
+        assert_eq!(
+            diff.to_string(),
+            "eps: 3 changed, 0 unchanged\n  probe: absent -> passed\n  read: passed -> failed (crc)\n  init: passed -> absent\n"
+        );
+        assert!(SuiteDiff::between(&previous, &previous).is_empty());
+    }
+}
@@ -0,0 +1,463 @@
+/*
+ * Data Integrity Soak Stream with End-to-End Sequence Numbers
+ * Copyright (C) 2024
+ */
+
+use crate::{crc16_ccitt, HardwareError, HardwareResult, Readable, ReportAppendix, Writable};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Marker starting every frame. Payload bytes never contain it.
+const SYNC: [u8; 2] = [0xA5, 0x5A];
+
+/// Sync, sequence number and payload length
+const HEADER_LEN: usize = 8;
+
+const CRC_LEN: usize = 2;
+
+/// Bytes shown either side of the first differing byte of a corrupted frame
+const HEX_CONTEXT: usize = 8;
+
+/// Frame layout: sync, seq (u32 LE), payload length (u16 LE), payload,
+/// CRC-16/CCITT (LE) over everything between the sync and the CRC
+pub(crate) fn encode_frame(seq: u32, payload_len: u16) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload_len as usize + CRC_LEN);
+    frame.extend_from_slice(&SYNC);
+    frame.extend_from_slice(&seq.to_le_bytes());
+    frame.extend_from_slice(&payload_len.to_le_bytes());
+    // Consecutive values modulo a prime never form the sync marker
+    frame.extend((0..payload_len as usize).map(|i| ((seq as usize + i) % 251) as u8));
+    let crc = crc16_ccitt(&frame[SYNC.len()..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// When an integrity stream stops sending
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamLimit {
+    Duration(Duration),
+    Bytes(u64),
+}
+
+impl StreamLimit {
+    pub(crate) fn reached(&self, elapsed: Duration, bytes_sent: u64) -> bool {
+        match *self {
+            StreamLimit::Duration(limit) => elapsed >= limit,
+            StreamLimit::Bytes(limit) => bytes_sent >= limit,
+        }
+    }
+}
+
+/// Frame that arrived with a bad CRC or length
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corruption {
+    /// Offset of the frame within the received byte stream
+    pub offset: u64,
+    /// Sequence number from the header, if it names a frame that was sent
+    pub seq: Option<u32>,
+    pub received: Vec<u8>,
+    /// Frame that was sent with `seq`
+    pub expected: Option<Vec<u8>>,
+}
+
+impl Corruption {
+    /// Index of the first byte differing from the expected frame
+    pub fn first_difference(&self) -> Option<usize> {
+        let expected = self.expected.as_ref()?;
+        self.received.iter().zip(expected).position(|(got, want)| got != want)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {}", self.offset)?;
+        match self.seq {
+            Some(seq) => write!(f, " (seq {})", seq)?,
+            None => write!(f, " (seq unknown)")?,
+        }
+        match (self.first_difference(), &self.expected) {
+            (Some(index), Some(expected)) => {
+                let start = index.saturating_sub(HEX_CONTEXT);
+                let end = (index + HEX_CONTEXT + 1).min(self.received.len());
+                write!(
+                    f,
+                    ": byte {} got {:02x} expected {:02x}\n    got      {}\n    expected {}",
+                    index,
+                    self.received[index],
+                    expected[index],
+                    hex(&self.received[start..end]),
+                    hex(&expected[start..end.min(expected.len())]),
+                )
+            }
+            _ => write!(f, "\n    got      {}", hex(&self.received[..self.received.len().min(2 * HEX_CONTEXT)])),
+        }
+    }
+}
+
+/// Outcome of an integrity stream run
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityReport {
+    pub frames_sent: u32,
+    pub bytes_sent: u64,
+    /// Frames that arrived intact, duplicates included
+    pub frames_received: u32,
+    pub bytes_received: u64,
+    /// Sequence numbers that never arrived intact
+    pub lost: Vec<u32>,
+    pub duplicated: Vec<u32>,
+    /// Sequence numbers that arrived after a later frame
+    pub reordered: Vec<u32>,
+    pub corruptions: Vec<Corruption>,
+    /// Bytes discarded while searching for a frame start
+    pub skipped_bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.lost.is_empty()
+            && self.duplicated.is_empty()
+            && self.reordered.is_empty()
+            && self.corruptions.is_empty()
+            && self.skipped_bytes == 0
+    }
+
+    /// Fails with a one-line summary unless the stream was clean
+    pub fn check(&self) -> HardwareResult<()> {
+        if self.is_clean() {
+            return Ok(());
+        }
+        Err(HardwareError::OperationFailed(format!(
+            "data integrity: {} lost, {} duplicated, {} reordered, {} corrupted, {} bytes skipped",
+            self.lost.len(),
+            self.duplicated.len(),
+            self.reordered.len(),
+            self.corruptions.len(),
+            self.skipped_bytes
+        )))
+    }
+
+    pub fn appendix(&self) -> ReportAppendix {
+        ReportAppendix::new("Data integrity", &self.to_string())
+    }
+}
+
+fn write_seqs(f: &mut fmt::Formatter<'_>, label: &str, seqs: &[u32]) -> fmt::Result {
+    let list: Vec<String> = seqs.iter().map(|s| s.to_string()).collect();
+    writeln!(f, "{}: {} [{}]", label, seqs.len(), list.join(", "))
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames / {} bytes sent, {} frames / {} bytes received in {:?}",
+            self.frames_sent, self.bytes_sent, self.frames_received, self.bytes_received, self.elapsed
+        )?;
+        write_seqs(f, "lost", &self.lost)?;
+        write_seqs(f, "duplicated", &self.duplicated)?;
+        write_seqs(f, "reordered", &self.reordered)?;
+        writeln!(f, "corrupted: {}", self.corruptions.len())?;
+        for corruption in &self.corruptions {
+            writeln!(f, "  {}", corruption)?;
+        }
+        writeln!(f, "skipped: {} bytes", self.skipped_bytes)
+    }
+}
+
+/// Streaming receiver reassembling frames from arbitrary read chunks
+pub(crate) struct Checker {
+    payload_len: u16,
+    buffer: Vec<u8>,
+    /// Stream offset of `buffer[0]`
+    offset: u64,
+    next_seq: u32,
+    missing: BTreeSet<u32>,
+    frames_received: u32,
+    duplicated: Vec<u32>,
+    reordered: Vec<u32>,
+    corruptions: Vec<Corruption>,
+    skipped_bytes: u64,
+}
+
+impl Checker {
+    pub(crate) fn new(payload_len: u16) -> Self {
+        Self {
+            payload_len,
+            buffer: Vec::new(),
+            offset: 0,
+            next_seq: 0,
+            missing: BTreeSet::new(),
+            frames_received: 0,
+            duplicated: Vec::new(),
+            reordered: Vec::new(),
+            corruptions: Vec::new(),
+            skipped_bytes: 0,
+        }
+    }
+
+    pub(crate) fn frame_len(&self) -> usize {
+        HEADER_LEN + self.payload_len as usize + CRC_LEN
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.buffer.drain(..count);
+        self.offset += count as u64;
+    }
+
+    pub(crate) fn errors(&self) -> usize {
+        self.missing.len() + self.duplicated.len() + self.reordered.len() + self.corruptions.len()
+    }
+
+    /// Feed received bytes; `frames_sent` bounds which header sequence
+    /// numbers are trusted when a frame is corrupted
+    pub(crate) fn push(&mut self, data: &[u8], frames_sent: u32) {
+        self.buffer.extend_from_slice(data);
+        let frame_len = self.frame_len();
+        loop {
+            match self.buffer.windows(SYNC.len()).position(|w| w == SYNC) {
+                Some(0) => {}
+                Some(start) => {
+                    self.skipped_bytes += start as u64;
+                    self.consume(start);
+                }
+                None => {
+                    // Keep a trailing first sync byte for the next chunk
+                    let keep = usize::from(self.buffer.last() == Some(&SYNC[0]));
+                    let skip = self.buffer.len() - keep;
+                    self.skipped_bytes += skip as u64;
+                    self.consume(skip);
+                    return;
+                }
+            }
+            if self.buffer.len() < frame_len {
+                return;
+            }
+
+            let frame = &self.buffer[..frame_len];
+            let seq = u32::from_le_bytes([frame[2], frame[3], frame[4], frame[5]]);
+            let len = u16::from_le_bytes([frame[6], frame[7]]);
+            let crc = u16::from_le_bytes([frame[frame_len - 2], frame[frame_len - 1]]);
+            if len == self.payload_len && crc == crc16_ccitt(&frame[SYNC.len()..frame_len - CRC_LEN]) {
+                self.accept(seq);
+                self.consume(frame_len);
+                continue;
+            }
+
+            let trusted = (seq < frames_sent).then_some(seq);
+            self.corruptions.push(Corruption {
+                offset: self.offset,
+                seq: trusted,
+                received: frame.to_vec(),
+                expected: trusted.map(|seq| encode_frame(seq, self.payload_len)),
+            });
+            // A frame cut short by lost bytes runs into the next sync marker
+            let next = frame[SYNC.len()..]
+                .windows(SYNC.len())
+                .position(|w| w == SYNC)
+                .map_or(frame_len, |p| p + SYNC.len());
+            self.consume(next);
+        }
+    }
+
+    fn accept(&mut self, seq: u32) {
+        self.frames_received += 1;
+        if seq >= self.next_seq {
+            self.missing.extend(self.next_seq..seq);
+            self.next_seq = seq + 1;
+        } else if self.missing.remove(&seq) {
+            self.reordered.push(seq);
+        } else {
+            self.duplicated.push(seq);
+        }
+    }
+
+    pub(crate) fn finish(mut self, frames_sent: u32, bytes_sent: u64, bytes_received: u64, elapsed: Duration) -> IntegrityReport {
+        self.missing.extend(self.next_seq..frames_sent);
+        // Corrupted frames are reported as such, not additionally as lost
+        for corruption in &self.corruptions {
+            if let Some(seq) = corruption.seq {
+                self.missing.remove(&seq);
+            }
+        }
+        self.skipped_bytes += self.buffer.len() as u64;
+        IntegrityReport {
+            frames_sent,
+            bytes_sent,
+            frames_received: self.frames_received,
+            bytes_received,
+            lost: self.missing.into_iter().collect(),
+            duplicated: self.duplicated,
+            reordered: self.reordered,
+            corruptions: self.corruptions,
+            skipped_bytes: self.skipped_bytes,
+            elapsed,
+        }
+    }
+}
+
+/// Soak test pushing sequence-numbered, CRC-protected frames through a
+/// link and checking what comes back
+///
+/// The port is expected to loop writes back to its reader, e.g. a UART
+/// with TX wired to RX or a peer echoing every frame.
+pub struct IntegrityStream {
+    payload_len: u16,
+    limit: StreamLimit,
+    read_timeout: Duration,
+    progress_interval: Option<Duration>,
+}
+
+impl IntegrityStream {
+    pub fn new(payload_len: u16, limit: StreamLimit) -> Self {
+        Self {
+            payload_len,
+            limit,
+            read_timeout: Duration::from_millis(100),
+            progress_interval: Some(Duration::from_secs(10)),
+        }
+    }
+
+    /// Timeout for each read; timeouts are treated as nothing received
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Interval between progress log lines, `None` to disable
+    pub fn with_progress_interval(mut self, interval: Option<Duration>) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    async fn read_some<T: Readable>(&self, port: &mut T, buffer: &mut [u8]) -> HardwareResult<usize> {
+        match port.read(buffer, self.read_timeout).await {
+            Err(e) if matches!(e.root(), HardwareError::TimeoutError) => Ok(0),
+            other => other,
+        }
+    }
+
+    /// Stream until the limit, then drain the link until a read comes back
+    /// empty. Only interface errors other than read timeouts abort the run.
+    pub async fn run<T: Readable + Writable + Send>(&self, port: &mut T) -> HardwareResult<IntegrityReport> {
+        let mut checker = Checker::new(self.payload_len);
+        let mut buffer = vec![0u8; checker.frame_len()];
+        let start = Instant::now();
+        let mut last_progress = start;
+        let mut frames_sent = 0u32;
+        let mut bytes_sent = 0u64;
+        let mut bytes_received = 0u64;
+
+        while !self.limit.reached(start.elapsed(), bytes_sent) {
+            let frame = encode_frame(frames_sent, self.payload_len);
+            port.write_all(&frame).await?;
+            frames_sent += 1;
+            bytes_sent += frame.len() as u64;
+
+            let count = self.read_some(port, &mut buffer).await?;
+            bytes_received += count as u64;
+            checker.push(&buffer[..count], frames_sent);
+
+            if let Some(interval) = self.progress_interval {
+                if last_progress.elapsed() >= interval {
+                    last_progress = Instant::now();
+                    log::info!(
+                        "Integrity stream: {} frames / {} bytes sent, {} received, {} errors",
+                        frames_sent,
+                        bytes_sent,
+                        bytes_received,
+                        checker.errors()
+                    );
+                }
+            }
+        }
+
+        loop {
+            let count = self.read_some(port, &mut buffer).await?;
+            if count == 0 {
+                break;
+            }
+            bytes_received += count as u64;
+            checker.push(&buffer[..count], frames_sent);
+        }
+
+        Ok(checker.finish(frames_sent, bytes_sent, bytes_received, start.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeLoopback, HardwareInterface, WriteFault};
+
+    const PAYLOAD_LEN: u16 = 32;
+    const FRAME_LEN: u64 = (HEADER_LEN + PAYLOAD_LEN as usize + CRC_LEN) as u64;
+
+    #[tokio::test]
+    async fn test_clean_stream() {
+        let mut link = FakeLoopback::new();
+        link.initialize().await.unwrap();
+        let stream = IntegrityStream::new(PAYLOAD_LEN, StreamLimit::Bytes(20 * FRAME_LEN));
+
+        let report = stream.run(&mut link).await.unwrap();
+
+        assert!(report.is_clean(), "{}", report);
+        assert!(report.check().is_ok());
+        assert_eq!(report.frames_sent, 20);
+        assert_eq!(report.frames_received, 20);
+        assert_eq!(report.bytes_received, report.bytes_sent);
+    }
+
+    #[tokio::test]
+    async fn test_classifies_injected_faults() {
+        let mut link = FakeLoopback::new()
+            .with_write_fault(3, WriteFault::FlipBits { offset: 12, mask: 0x01 })
+            .with_write_fault(5, WriteFault::Drop)
+            .with_write_fault(7, WriteFault::Duplicate)
+            .with_write_fault(9, WriteFault::Hold);
+        link.initialize().await.unwrap();
+        let stream = IntegrityStream::new(PAYLOAD_LEN, StreamLimit::Bytes(12 * FRAME_LEN));
+
+        let report = stream.run(&mut link).await.unwrap();
+
+        assert_eq!(report.frames_sent, 12);
+        assert_eq!(report.frames_received, 11);
+        assert_eq!(report.lost, vec![5]);
+        assert_eq!(report.duplicated, vec![7]);
+        assert_eq!(report.reordered, vec![9]);
+        assert_eq!(report.skipped_bytes, 0);
+        assert_eq!(report.corruptions.len(), 1);
+        let corruption = &report.corruptions[0];
+        assert_eq!(corruption.offset, 3 * FRAME_LEN);
+        assert_eq!(corruption.seq, Some(3));
+        assert_eq!(corruption.first_difference(), Some(12));
+        assert!(corruption.to_string().starts_with("offset 126 (seq 3): byte 12 got 06 expected 07"));
+        assert!(matches!(report.check(), Err(HardwareError::OperationFailed(_))));
+        assert_eq!(report.appendix().title, "Data integrity");
+    }
+
+    #[test]
+    fn test_checker_resyncs_after_noise() {
+        let mut checker = Checker::new(4);
+        let mut data = vec![0x00, 0xA5, 0x13];
+        data.extend(encode_frame(0, 4));
+        let frame = encode_frame(1, 4);
+
+        checker.push(&data, 2);
+        // Split mid-sync to exercise reassembly across reads
+        checker.push(&[0x77, frame[0]], 2);
+        checker.push(&frame[1..], 2);
+        let report = checker.finish(2, 0, 0, Duration::ZERO);
+
+        assert_eq!(report.frames_received, 2);
+        assert_eq!(report.skipped_bytes, 4);
+        assert!(report.lost.is_empty());
+        assert!(report.corruptions.is_empty());
+    }
+}
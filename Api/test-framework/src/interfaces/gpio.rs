@@ -0,0 +1,328 @@
+/*
+ * GPIO Interface Implementation
+ * Copyright (C) 2024
+ */
+
+use super::{InterfaceParams, InterfaceState};
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus};
+use async_trait::async_trait;
+use std::ops::Not;
+use std::time::Duration;
+
+/// Direction a GPIO line is requested in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GPIODirection {
+    Input,
+    Output,
+}
+
+/// Logical level of a line, after `active_low` inversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+impl Level {
+    pub fn is_high(self) -> bool {
+        self == Level::High
+    }
+}
+
+impl Not for Level {
+    type Output = Level;
+
+    fn not(self) -> Level {
+        match self {
+            Level::Low => Level::High,
+            Level::High => Level::Low,
+        }
+    }
+}
+
+impl From<bool> for Level {
+    fn from(high: bool) -> Self {
+        if high {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+}
+
+/// Level change to wait for; `Both` resolves to the edge that occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Edge {
+    /// Edge of a change from `from` to `to`, `None` if the level held
+    pub fn between(from: Level, to: Level) -> Option<Edge> {
+        match (from, to) {
+            (Level::Low, Level::High) => Some(Edge::Rising),
+            (Level::High, Level::Low) => Some(Edge::Falling),
+            _ => None,
+        }
+    }
+
+    /// `occurred` satisfies a wait for this edge
+    pub fn matches(self, occurred: Edge) -> bool {
+        self == Edge::Both || self == occurred
+    }
+}
+
+/// Line that can be sampled, e.g. an interrupt pin
+#[async_trait]
+pub trait DigitalRead {
+    async fn read_level(&mut self) -> HardwareResult<Level>;
+
+    /// Wait up to `timeout` for `edge`, returning the edge that occurred
+    async fn wait_for_edge(&mut self, edge: Edge, timeout: Duration) -> HardwareResult<Edge>;
+}
+
+/// Line that can be driven, e.g. a reset line
+#[async_trait]
+pub trait DigitalWrite {
+    async fn set_level(&mut self, level: Level) -> HardwareResult<()>;
+}
+
+/// GPIO line configuration
+#[derive(Debug, Clone)]
+pub struct GPIOConfig {
+    /// Character device name, e.g. "gpiochip0"
+    pub chip: String,
+    pub line: u32,
+    pub direction: GPIODirection,
+    /// Line is asserted when driven low, as most reset lines are
+    pub active_low: bool,
+    pub params: InterfaceParams,
+}
+
+impl Default for GPIOConfig {
+    fn default() -> Self {
+        Self {
+            chip: "gpiochip0".to_string(),
+            line: 0,
+            direction: GPIODirection::Input,
+            active_low: false,
+            params: InterfaceParams::default(),
+        }
+    }
+}
+
+/// GPIO interface implementation for a single line
+pub struct GPIOInterface {
+    config: GPIOConfig,
+    state: InterfaceState,
+    handle: Option<i32>,
+    /// Physical level of the line
+    raw_level: Level,
+}
+
+impl GPIOInterface {
+    pub fn new(config: GPIOConfig) -> Self {
+        Self {
+            config,
+            state: InterfaceState::new(),
+            handle: None,
+            raw_level: Level::Low,
+        }
+    }
+
+    pub fn with_default_config() -> Self {
+        Self::new(GPIOConfig::default())
+    }
+
+    pub fn direction(&self) -> GPIODirection {
+        self.config.direction
+    }
+
+    fn get_device_path(&self) -> String {
+        format!("/dev/{}", self.config.chip)
+    }
+
+    /// Logical level for a physical one and back, the same inversion
+    fn logical(&self, level: Level) -> Level {
+        if self.config.active_low {
+            !level
+        } else {
+            level
+        }
+    }
+
+    fn check_initialized(&self) -> HardwareResult<()> {
+        if !self.state.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        Ok(())
+    }
+
+    async fn open_device(&mut self) -> HardwareResult<()> {
+        // In a real implementation, this would request the line from the GPIO chip
+        // For testing, we'll just simulate success
+        self.handle = Some(1);
+        Ok(())
+    }
+
+    async fn close_device(&mut self) -> HardwareResult<()> {
+        // In a real implementation, this would release the line
+        // For testing, we'll just simulate success
+        self.handle = None;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for GPIOInterface {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        if self.state.initialized {
+            return Ok(());
+        }
+
+        match self.open_device().await {
+            Ok(_) => {
+                self.state.initialized = true;
+                Ok(())
+            }
+            Err(e) => {
+                self.state.record_error(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        if !self.state.initialized {
+            return Ok(());
+        }
+
+        match self.close_device().await {
+            Ok(_) => {
+                self.state.initialized = false;
+                Ok(())
+            }
+            Err(e) => {
+                self.state.record_error(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.state.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(self.state.to_status())
+    }
+}
+
+#[async_trait]
+impl DigitalRead for GPIOInterface {
+    async fn read_level(&mut self) -> HardwareResult<Level> {
+        self.check_initialized()?;
+
+        // In a real implementation, this would read the line value
+        // For testing, we'll return the last level driven
+        Ok(self.logical(self.raw_level))
+    }
+
+    async fn wait_for_edge(&mut self, _edge: Edge, timeout: Duration) -> HardwareResult<Edge> {
+        self.check_initialized()?;
+        if self.config.direction != GPIODirection::Input {
+            return Err(HardwareError::InvalidParameter(format!(
+                "line {} of {} is an output; edge events need an input",
+                self.config.line,
+                self.get_device_path()
+            )));
+        }
+
+        // In a real implementation, this would poll the line's event file
+        // For testing, nothing drives the line, so the wait times out
+        tokio::time::sleep(timeout).await;
+        Err(HardwareError::TimeoutError)
+    }
+}
+
+#[async_trait]
+impl DigitalWrite for GPIOInterface {
+    async fn set_level(&mut self, level: Level) -> HardwareResult<()> {
+        self.check_initialized()?;
+        if self.config.direction != GPIODirection::Output {
+            return Err(HardwareError::InvalidParameter(format!(
+                "line {} of {} is an input",
+                self.config.line,
+                self.get_device_path()
+            )));
+        }
+
+        self.raw_level = self.logical(level);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(active_low: bool) -> GPIOInterface {
+        GPIOInterface::new(GPIOConfig {
+            line: 17,
+            direction: GPIODirection::Output,
+            active_low,
+            ..GPIOConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_uninitialized_access() {
+        let mut gpio = output(false);
+        assert_eq!(gpio.read_level().await, Err(HardwareError::NotInitialized));
+        assert_eq!(gpio.set_level(Level::High).await, Err(HardwareError::NotInitialized));
+        assert_eq!(
+            gpio.wait_for_edge(Edge::Rising, Duration::ZERO).await,
+            Err(HardwareError::NotInitialized)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_active_low_output() {
+        let mut gpio = output(true);
+        gpio.initialize().await.unwrap();
+        assert_eq!(gpio.read_level().await, Ok(Level::High));
+
+        gpio.set_level(Level::High).await.unwrap();
+        assert_eq!(gpio.raw_level, Level::Low);
+        assert_eq!(gpio.read_level().await, Ok(Level::High));
+        assert!(matches!(
+            gpio.wait_for_edge(Edge::Both, Duration::ZERO).await,
+            Err(HardwareError::InvalidParameter(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_input_line() {
+        let mut gpio = GPIOInterface::with_default_config();
+        gpio.initialize().await.unwrap();
+
+        assert!(matches!(gpio.set_level(Level::High).await, Err(HardwareError::InvalidParameter(_))));
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            gpio.wait_for_edge(Edge::Falling, Duration::from_millis(50)).await,
+            Err(HardwareError::TimeoutError)
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_edges() {
+        assert_eq!(Edge::between(Level::Low, Level::High), Some(Edge::Rising));
+        assert_eq!(Edge::between(Level::High, Level::High), None);
+        assert!(Edge::Both.matches(Edge::Falling));
+        assert!(!Edge::Rising.matches(Edge::Falling));
+        assert_eq!(Level::from(true), Level::High);
+    }
+}
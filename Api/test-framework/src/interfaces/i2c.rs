@@ -3,8 +3,8 @@
  * Copyright (C) 2024
  */
 
-use super::{InterfaceParams, InterfaceState};
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, Bidirectional};
+use super::{check_buffer_size, check_transfer_buffers, InterfaceParams, InterfaceState, I2C_DEFAULT_MAX_TRANSFER};
+use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, Bidirectional, InterfaceKind, Operation, capabilities, CapabilitySet};
 use async_trait::async_trait;
 use std::time::Duration;
 
@@ -14,6 +14,8 @@ pub struct I2CConfig {
     pub bus_number: u8,
     pub device_address: u16,
     pub clock_speed: u32,
+    /// Largest single read, write or transfer; `None` means unlimited
+    pub max_transfer_size: Option<usize>,
     pub params: InterfaceParams,
 }
 
@@ -23,6 +25,7 @@ impl Default for I2CConfig {
             bus_number: 1,
             device_address: 0x50,
             clock_speed: 100_000,
+            max_transfer_size: Some(I2C_DEFAULT_MAX_TRANSFER),
             params: InterfaceParams::default(),
         }
     }
@@ -48,6 +51,10 @@ impl I2CInterface {
         Self::new(I2CConfig::default())
     }
     
+    pub fn max_transfer_size(&self) -> Option<usize> {
+        self.config.max_transfer_size
+    }
+    
     fn get_device_path(&self) -> String {
         format!("/dev/i2c-{}", self.config.bus_number)
     }
@@ -80,6 +87,7 @@ impl HardwareInterface for I2CInterface {
                 Ok(())
             }
             Err(e) => {
+                let e = e.with_context(InterfaceKind::I2c, Operation::Init);
                 self.state.record_error(e.to_string());
                 Err(e)
             }
@@ -110,6 +118,10 @@ impl HardwareInterface for I2CInterface {
     async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
         Ok(self.state.to_status())
     }
+    
+    fn capabilities(&self) -> CapabilitySet {
+        capabilities![ReadExact, Transfer, ChunkedIo]
+    }
 }
 
 #[async_trait]
@@ -119,6 +131,11 @@ impl Readable for I2CInterface {
             return Err(crate::HardwareError::NotInitialized);
         }
         
+        check_buffer_size(buffer.len(), self.config.max_transfer_size)?;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        
         // In a real implementation, this would read from the I2C device
         // For testing, we'll just simulate success
         Ok(buffer.len())
@@ -129,7 +146,7 @@ impl Readable for I2CInterface {
         if bytes_read != buffer.len() {
             return Err(crate::HardwareError::CommunicationError(
                 "Failed to read exact number of bytes".to_string()
-            ));
+            ).with_context(InterfaceKind::I2c, Operation::Read));
         }
         Ok(())
     }
@@ -142,6 +159,11 @@ impl Writable for I2CInterface {
             return Err(crate::HardwareError::NotInitialized);
         }
         
+        check_buffer_size(data.len(), self.config.max_transfer_size)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        
         // In a real implementation, this would write to the I2C device
         // For testing, we'll just simulate success
         Ok(data.len())
@@ -152,7 +174,7 @@ impl Writable for I2CInterface {
         if bytes_written != data.len() {
             return Err(crate::HardwareError::CommunicationError(
                 "Failed to write all bytes".to_string()
-            ));
+            ).with_context(InterfaceKind::I2c, Operation::Write));
         }
         Ok(())
     }
@@ -165,6 +187,8 @@ impl Bidirectional for I2CInterface {
             return Err(crate::HardwareError::NotInitialized);
         }
         
+        check_transfer_buffers(tx_data.len(), rx_data.len(), self.config.max_transfer_size)?;
+        
         // In a real implementation, this would perform an I2C transfer
         // For testing, we'll just simulate success
         Ok(rx_data.len())
@@ -232,4 +256,29 @@ mod tests {
             Err(crate::HardwareError::NotInitialized)
         ));
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_i2c_buffer_size_policy() {
+        let mut interface = I2CInterface::with_default_config();
+        assert!(interface.initialize().await.is_ok());
+        let timeout = Duration::from_millis(100);
+        
+        assert_eq!(interface.read(&mut [], timeout).await.unwrap(), 0);
+        assert_eq!(interface.write(&[]).await.unwrap(), 0);
+        assert!(matches!(
+            interface.transfer(&[], &mut [0u8; 2], timeout).await,
+            Err(crate::HardwareError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            interface.transfer(&[0x10], &mut [], timeout).await,
+            Err(crate::HardwareError::InvalidParameter(_))
+        ));
+        
+        let oversized = vec![0u8; I2C_DEFAULT_MAX_TRANSFER + 1];
+        match interface.write(&oversized).await {
+            Err(crate::HardwareError::InvalidParameter(msg)) => assert!(msg.contains("8192")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(interface.write(&oversized[1..]).await.is_ok());
+    }
+}
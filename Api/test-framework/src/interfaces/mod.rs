@@ -3,18 +3,52 @@
  * Copyright (C) 2024
  */
 
+mod gpio;
 mod i2c;
 mod uart;
 mod spi;
 
-pub use i2c::I2CInterface;
-pub use uart::UARTInterface;
-pub use spi::SPIInterface;
+pub use gpio::{DigitalRead, DigitalWrite, Edge, GPIOConfig, GPIODirection, GPIOInterface, Level};
+pub use i2c::{I2CConfig, I2CInterface};
+pub use uart::{LineAction, LineControl, LineEvent, ModemStatus, UARTConfig, UARTInterface};
+pub use spi::{SPIConfig, SPIInterface};
 
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus};
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus};
 use std::time::Duration;
 use async_trait::async_trait;
 
+/// Default `max_transfer_size` of an I2C bus
+pub const I2C_DEFAULT_MAX_TRANSFER: usize = 8192;
+
+/// Default `max_transfer_size` of an SPI bus
+pub const SPI_DEFAULT_MAX_TRANSFER: usize = 4096;
+
+/// Buffer size policy shared by every interface and fake
+///
+/// Zero-length reads and writes are no-ops returning `Ok(0)`, while a
+/// transfer needs data in both directions. Anything above the configured
+/// maximum fails before touching the bus; larger payloads go through
+/// `write_chunked` and `read_chunked`.
+pub fn check_buffer_size(len: usize, max_transfer_size: Option<usize>) -> HardwareResult<()> {
+    match max_transfer_size {
+        Some(max) if len > max => Err(HardwareError::InvalidParameter(format!(
+            "{} byte buffer exceeds max_transfer_size of {} bytes", len, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Transfer half of the buffer size policy, see `check_buffer_size`
+pub fn check_transfer_buffers(tx_len: usize, rx_len: usize, max_transfer_size: Option<usize>) -> HardwareResult<()> {
+    if tx_len == 0 || rx_len == 0 {
+        return Err(HardwareError::InvalidParameter(format!(
+            "Transfer needs non-empty TX and RX buffers, got {} and {} bytes", tx_len, rx_len
+        )));
+    }
+    check_buffer_size(tx_len, max_transfer_size)?;
+    check_buffer_size(rx_len, max_transfer_size)
+}
+
 /// Common interface parameters
 #[derive(Debug, Clone)]
 pub struct InterfaceParams {
@@ -3,8 +3,8 @@
  * Copyright (C) 2024
  */
 
-use super::{InterfaceParams, InterfaceState};
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Bidirectional};
+use super::{check_transfer_buffers, InterfaceParams, InterfaceState, SPI_DEFAULT_MAX_TRANSFER};
+use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Bidirectional, InterfaceKind, Operation, capabilities, CapabilitySet};
 use async_trait::async_trait;
 use std::time::Duration;
 
@@ -15,6 +15,8 @@ pub struct SPIConfig {
     pub mode: u8,
     pub speed: u32,
     pub bits_per_word: u8,
+    /// Largest single transfer; `None` means unlimited
+    pub max_transfer_size: Option<usize>,
     pub params: InterfaceParams,
 }
 
@@ -25,6 +27,7 @@ impl Default for SPIConfig {
             mode: 0,
             speed: 1_000_000,
             bits_per_word: 8,
+            max_transfer_size: Some(SPI_DEFAULT_MAX_TRANSFER),
             params: InterfaceParams::default(),
         }
     }
@@ -79,6 +82,10 @@ impl SPIInterface {
     pub fn set_mode(&mut self, mode: u8) {
         self.config.mode = mode;
     }
+    
+    pub fn max_transfer_size(&self) -> Option<usize> {
+        self.config.max_transfer_size
+    }
 }
 
 #[async_trait]
@@ -94,6 +101,7 @@ impl HardwareInterface for SPIInterface {
                 Ok(())
             }
             Err(e) => {
+                let e = e.with_context(InterfaceKind::Spi, Operation::Init);
                 self.state.record_error(e.to_string());
                 Err(e)
             }
@@ -124,6 +132,10 @@ impl HardwareInterface for SPIInterface {
     async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
         Ok(self.state.to_status())
     }
+    
+    fn capabilities(&self) -> CapabilitySet {
+        capabilities![Transfer]
+    }
 }
 
 #[async_trait]
@@ -139,6 +151,8 @@ impl Bidirectional for SPIInterface {
             ));
         }
         
+        check_transfer_buffers(tx_data.len(), rx_data.len(), self.config.max_transfer_size)?;
+        
         // In a real implementation, this would perform an SPI transfer
         // For testing, we'll just simulate success
         Ok(rx_data.len())
@@ -207,4 +221,24 @@ mod tests {
             Err(crate::HardwareError::InvalidParameter(_))
         ));
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_spi_buffer_size_policy() {
+        let mut interface = SPIInterface::with_default_config();
+        assert!(interface.initialize().await.is_ok());
+        let timeout = Duration::from_millis(100);
+        
+        assert!(matches!(
+            interface.transfer(&[], &mut [], timeout).await,
+            Err(crate::HardwareError::InvalidParameter(_))
+        ));
+        
+        let tx = vec![0u8; SPI_DEFAULT_MAX_TRANSFER + 1];
+        let mut rx = vec![0u8; SPI_DEFAULT_MAX_TRANSFER + 1];
+        assert!(matches!(
+            interface.transfer(&tx, &mut rx, timeout).await,
+            Err(crate::HardwareError::InvalidParameter(_))
+        ));
+        assert!(interface.transfer(&tx[1..], &mut rx[1..], timeout).await.is_ok());
+    }
+}
@@ -3,10 +3,11 @@
  * Copyright (C) 2024
  */
 
-use super::{InterfaceParams, InterfaceState};
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable};
+use super::{check_buffer_size, InterfaceParams, InterfaceState};
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, InterfaceKind, Operation, capabilities, CapabilitySet};
 use async_trait::async_trait;
 use std::time::Duration;
+use tokio::time::{sleep, Instant};
 
 /// UART interface configuration
 #[derive(Debug, Clone)]
@@ -17,6 +18,8 @@ pub struct UARTConfig {
     pub stop_bits: u8,
     pub parity: Parity,
     pub flow_control: FlowControl,
+    /// Largest single read or write; `None` means unlimited
+    pub max_transfer_size: Option<usize>,
     pub params: InterfaceParams,
 }
 
@@ -43,16 +46,71 @@ impl Default for UARTConfig {
             stop_bits: 1,
             parity: Parity::None,
             flow_control: FlowControl::None,
+            max_transfer_size: None,
             params: InterfaceParams::default(),
         }
     }
 }
 
+/// Modem status input lines
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModemStatus {
+    pub cts: bool,
+    pub dsr: bool,
+    pub dcd: bool,
+    pub ri: bool,
+}
+
+/// One step of a scripted line control sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineAction {
+    SetRts(bool),
+    SetDtr(bool),
+    Break(Duration),
+    Wait(Duration),
+}
+
+/// A line control action performed at `at`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineEvent {
+    pub at: Instant,
+    pub action: LineAction,
+}
+
+/// Break, RTS/DTR and modem status control of a serial port
+#[async_trait]
+pub trait LineControl: Send {
+    /// Hold the TX line in break for `duration`
+    async fn send_break(&mut self, duration: Duration) -> HardwareResult<()>;
+
+    async fn set_rts(&mut self, asserted: bool) -> HardwareResult<()>;
+
+    async fn set_dtr(&mut self, asserted: bool) -> HardwareResult<()>;
+
+    async fn read_modem_status(&mut self) -> HardwareResult<ModemStatus>;
+
+    /// Execute a scripted pulse pattern, e.g. the DTR/RTS reset dance many
+    /// MCUs use to enter their bootloader
+    async fn enter_bootloader(&mut self, sequence: &[LineAction]) -> HardwareResult<()> {
+        for action in sequence {
+            match *action {
+                LineAction::SetRts(asserted) => self.set_rts(asserted).await?,
+                LineAction::SetDtr(asserted) => self.set_dtr(asserted).await?,
+                LineAction::Break(duration) => self.send_break(duration).await?,
+                LineAction::Wait(duration) => sleep(duration).await,
+            }
+        }
+        Ok(())
+    }
+}
+
 /// UART interface implementation
 pub struct UARTInterface {
     config: UARTConfig,
     state: InterfaceState,
     handle: Option<i32>,
+    modem_status: ModemStatus,
+    line_events: Vec<LineEvent>,
 }
 
 impl UARTInterface {
@@ -61,6 +119,8 @@ impl UARTInterface {
             config,
             state: InterfaceState::new(),
             handle: None,
+            modem_status: ModemStatus::default(),
+            line_events: Vec::new(),
         }
     }
     
@@ -89,6 +149,28 @@ impl UARTInterface {
     pub fn set_baud_rate(&mut self, baud_rate: u32) {
         self.config.baud_rate = baud_rate;
     }
+    
+    pub fn max_transfer_size(&self) -> Option<usize> {
+        self.config.max_transfer_size
+    }
+
+    /// Line control actions performed so far, oldest first
+    pub fn line_events(&self) -> &[LineEvent] {
+        &self.line_events
+    }
+
+    /// Modem status reported by the simulated device
+    pub fn set_simulated_modem_status(&mut self, status: ModemStatus) {
+        self.modem_status = status;
+    }
+
+    fn record_line_action(&mut self, action: LineAction) -> HardwareResult<()> {
+        if !self.state.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        self.line_events.push(LineEvent { at: Instant::now(), action });
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -104,6 +186,7 @@ impl HardwareInterface for UARTInterface {
                 Ok(())
             }
             Err(e) => {
+                let e = e.with_context(InterfaceKind::Uart, Operation::Init);
                 self.state.record_error(e.to_string());
                 Err(e)
             }
@@ -134,6 +217,10 @@ impl HardwareInterface for UARTInterface {
     async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
         Ok(self.state.to_status())
     }
+    
+    fn capabilities(&self) -> CapabilitySet {
+        capabilities![ReadExact, LineControl, ChunkedIo]
+    }
 }
 
 #[async_trait]
@@ -143,6 +230,11 @@ impl Readable for UARTInterface {
             return Err(crate::HardwareError::NotInitialized);
         }
         
+        check_buffer_size(buffer.len(), self.config.max_transfer_size)?;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        
         // In a real implementation, this would read from the UART device
         // For testing, we'll just simulate success
         Ok(buffer.len())
@@ -153,7 +245,7 @@ impl Readable for UARTInterface {
         if bytes_read != buffer.len() {
             return Err(crate::HardwareError::CommunicationError(
                 "Failed to read exact number of bytes".to_string()
-            ));
+            ).with_context(InterfaceKind::Uart, Operation::Read));
         }
         Ok(())
     }
@@ -166,6 +258,11 @@ impl Writable for UARTInterface {
             return Err(crate::HardwareError::NotInitialized);
         }
         
+        check_buffer_size(data.len(), self.config.max_transfer_size)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        
         // In a real implementation, this would write to the UART device
         // For testing, we'll just simulate success
         Ok(data.len())
@@ -176,10 +273,42 @@ impl Writable for UARTInterface {
         if bytes_written != data.len() {
             return Err(crate::HardwareError::CommunicationError(
                 "Failed to write all bytes".to_string()
-            ));
+            ).with_context(InterfaceKind::Uart, Operation::Write));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LineControl for UARTInterface {
+    async fn send_break(&mut self, duration: Duration) -> HardwareResult<()> {
+        if duration.is_zero() {
+            return Err(HardwareError::InvalidParameter("Break duration must be non-zero".to_string()));
         }
+        // In a real implementation, this would bracket the sleep with the
+        // TIOCSBRK and TIOCCBRK ioctls, since tcsendbreak() ignores the duration
+        self.record_line_action(LineAction::Break(duration))?;
+        sleep(duration).await;
         Ok(())
     }
+
+    async fn set_rts(&mut self, asserted: bool) -> HardwareResult<()> {
+        // In a real implementation, this would issue TIOCMBIS/TIOCMBIC with TIOCM_RTS
+        self.record_line_action(LineAction::SetRts(asserted))
+    }
+
+    async fn set_dtr(&mut self, asserted: bool) -> HardwareResult<()> {
+        // In a real implementation, this would issue TIOCMBIS/TIOCMBIC with TIOCM_DTR
+        self.record_line_action(LineAction::SetDtr(asserted))
+    }
+
+    async fn read_modem_status(&mut self) -> HardwareResult<ModemStatus> {
+        if !self.state.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        // In a real implementation, this would decode TIOCMGET
+        Ok(self.modem_status)
+    }
 }
 
 #[cfg(test)]
@@ -236,4 +365,67 @@ mod tests {
             Err(crate::HardwareError::NotInitialized)
         ));
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_uart_buffer_size_policy() {
+        let mut interface = UARTInterface::with_default_config();
+        assert!(interface.initialize().await.is_ok());
+        
+        assert_eq!(interface.max_transfer_size(), None);
+        assert_eq!(interface.read(&mut [], Duration::from_millis(100)).await.unwrap(), 0);
+        assert_eq!(interface.write(&[]).await.unwrap(), 0);
+        assert_eq!(interface.write(&vec![0u8; 1 << 20]).await.unwrap(), 1 << 20);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_uart_bootloader_sequence() {
+        let mut interface = UARTInterface::with_default_config();
+        assert!(interface.initialize().await.is_ok());
+        
+        let sequence = [
+            LineAction::SetDtr(false),
+            LineAction::SetRts(true),
+            LineAction::Wait(Duration::from_millis(100)),
+            LineAction::SetDtr(true),
+            LineAction::SetRts(false),
+            LineAction::Wait(Duration::from_millis(50)),
+            LineAction::SetDtr(false),
+        ];
+        interface.enter_bootloader(&sequence).await.unwrap();
+        
+        let events = interface.line_events();
+        let actions: Vec<LineAction> = events.iter().map(|e| e.action).collect();
+        assert_eq!(actions, vec![
+            LineAction::SetDtr(false),
+            LineAction::SetRts(true),
+            LineAction::SetDtr(true),
+            LineAction::SetRts(false),
+            LineAction::SetDtr(false),
+        ]);
+        assert_eq!(events[2].at - events[1].at, Duration::from_millis(100));
+        assert_eq!(events[4].at - events[0].at, Duration::from_millis(150));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_uart_break_and_modem_status() {
+        let mut interface = UARTInterface::with_default_config();
+        assert!(matches!(
+            interface.send_break(Duration::from_millis(10)).await,
+            Err(crate::HardwareError::NotInitialized)
+        ));
+        assert!(interface.initialize().await.is_ok());
+        
+        assert!(matches!(
+            interface.send_break(Duration::ZERO).await,
+            Err(crate::HardwareError::InvalidParameter(_))
+        ));
+        let start = Instant::now();
+        interface.send_break(Duration::from_millis(250)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+        assert_eq!(interface.line_events()[0].action, LineAction::Break(Duration::from_millis(250)));
+        
+        let status = ModemStatus { cts: true, dsr: true, ..Default::default() };
+        interface.set_simulated_modem_status(status);
+        assert_eq!(interface.read_modem_status().await.unwrap(), status);
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Cross-Test Invariants over a Whole Suite Run
+ * Copyright (C) 2024
+ */
+
+use crate::{InterfaceStatus, StatsSnapshot, TestResult};
+use std::fmt;
+use std::time::Duration;
+
+/// Slack between device uptime and wall time before a shortfall counts as
+/// a reset, covering coarse uptime counters
+const UPTIME_TOLERANCE: Duration = Duration::from_secs(1);
+
+type InvariantFn = Box<dyn Fn(&SuiteRun) -> Vec<String> + Send + Sync>;
+
+/// What a suite invariant gets to look at once every test has run. A
+/// status is `None` if the interface could not report one.
+pub struct SuiteRun<'a> {
+    pub results: &'a [TestResult],
+    pub start_status: Option<InterfaceStatus>,
+    pub end_status: Option<InterfaceStatus>,
+    pub start_stats: StatsSnapshot,
+    pub end_stats: StatsSnapshot,
+    /// Wall time between the start and end status reads
+    pub elapsed: Duration,
+}
+
+/// Violation of a suite invariant, reported apart from test failures
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantViolation {
+    pub invariant: String,
+    pub message: String,
+    /// The violation fails the suite even if every test passed
+    pub fails_suite: bool,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.invariant, self.message)
+    }
+}
+
+/// Property that must hold across all tests of a suite, e.g. the device
+/// never rebooting. Registered with `TestRunner::with_invariant`.
+pub struct SuiteInvariant {
+    name: String,
+    check: InvariantFn,
+    fails_suite: bool,
+}
+
+impl SuiteInvariant {
+    /// `check` returns one message per violation, nothing if it holds
+    pub fn new<F>(name: &str, check: F) -> Self
+    where
+        F: Fn(&SuiteRun) -> Vec<String> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            check: Box::new(check),
+            fails_suite: false,
+        }
+    }
+
+    /// Fail the suite on a violation instead of only reporting it
+    pub fn failing_suite(mut self) -> Self {
+        self.fails_suite = true;
+        self
+    }
+
+    /// The interface error count may grow by at most `limit` over the suite
+    pub fn max_error_growth(limit: u32) -> Self {
+        Self::new("max_error_growth", move |run| match (&run.start_status, &run.end_status) {
+            (Some(start), Some(end)) if end.error_count > start.error_count.saturating_add(limit) => vec![format!(
+                "interface error count grew from {} to {}, more than {}",
+                start.error_count, end.error_count, limit
+            )],
+            _ => Vec::new(),
+        })
+    }
+
+    /// Device uptime must not go backwards or fall behind the wall time of
+    /// the suite, either of which means it rebooted during the suite, even
+    /// if its uptime has since passed the start value. Devices reporting
+    /// no uptime at all are not checked.
+    pub fn no_unexpected_reset() -> Self {
+        Self::new("no_unexpected_reset", |run| match (&run.start_status, &run.end_status) {
+            (Some(start), Some(end)) if end.uptime < start.uptime => vec![format!(
+                "uptime went back from {:?} to {:?}; the device reset during the suite",
+                start.uptime, end.uptime
+            )],
+            (Some(start), Some(end)) if !end.uptime.is_zero() => {
+                let gained = end.uptime - start.uptime;
+                if gained + UPTIME_TOLERANCE < run.elapsed {
+                    vec![format!(
+                        "uptime grew by {:?} over {:?} of suite; the device reset during the suite",
+                        gained, run.elapsed
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        })
+    }
+
+    /// Failed bus operations counted over the suite must stay within `limit`
+    pub fn max_bus_errors(limit: u32) -> Self {
+        Self::new("max_bus_errors", move |run| {
+            let errors = run.end_stats.errors.saturating_sub(run.start_stats.errors);
+            if errors > limit {
+                vec![format!("{} bus errors over the suite, more than {}", errors, limit)]
+            } else {
+                Vec::new()
+            }
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn evaluate(&self, run: &SuiteRun) -> Vec<InvariantViolation> {
+        (self.check)(run)
+            .into_iter()
+            .map(|message| InvariantViolation {
+                invariant: self.name.clone(),
+                message,
+                fails_suite: self.fails_suite,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockHardwareInterface;
+    use crate::{exit_code, AdviceRegistry, TestFn, TestRunner, TestStatus, TestSuiteResult};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    fn status(error_count: u32, uptime_secs: u64) -> InterfaceStatus {
+        InterfaceStatus {
+            initialized: true,
+            error_count,
+            last_error: None,
+            uptime: Duration::from_secs(uptime_secs),
+        }
+    }
+
+    fn run(start: InterfaceStatus, end: InterfaceStatus, bus_errors: u32) -> SuiteRun<'static> {
+        SuiteRun {
+            results: &[],
+            start_status: Some(start),
+            end_status: Some(end),
+            start_stats: StatsSnapshot { operations: 10, errors: 1 },
+            end_stats: StatsSnapshot {
+                operations: 50,
+                errors: 1 + bus_errors,
+            },
+            elapsed: Duration::from_secs(4),
+        }
+    }
+
+    fn messages(invariant: &SuiteInvariant, run: &SuiteRun) -> Vec<String> {
+        invariant.evaluate(run).iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_builtins() {
+        let errors = SuiteInvariant::max_error_growth(2);
+        assert!(messages(&errors, &run(status(1, 5), status(3, 9), 0)).is_empty());
+        assert_eq!(
+            messages(&errors, &run(status(1, 5), status(4, 9), 0)),
+            vec!["max_error_growth: interface error count grew from 1 to 4, more than 2"]
+        );
+
+        let reset = SuiteInvariant::no_unexpected_reset();
+        assert!(messages(&reset, &run(status(0, 5), status(0, 9), 0)).is_empty());
+        assert_eq!(
+            messages(&reset, &run(status(0, 500), status(0, 3), 0)),
+            vec!["no_unexpected_reset: uptime went back from 500s to 3s; the device reset during the suite"]
+        );
+        // Rebooted early in a long suite, uptime already past the start value
+        let recovered = SuiteRun {
+            elapsed: Duration::from_secs(600),
+            ..run(status(0, 5), status(0, 300), 0)
+        };
+        assert_eq!(
+            messages(&reset, &recovered),
+            vec!["no_unexpected_reset: uptime grew by 295s over 600s of suite; the device reset during the suite"]
+        );
+        let untracked = SuiteRun {
+            elapsed: Duration::from_secs(600),
+            ..run(status(0, 0), status(0, 0), 0)
+        };
+        assert!(messages(&reset, &untracked).is_empty());
+
+        assert!(messages(&SuiteInvariant::max_error_growth(u32::MAX), &run(status(5, 5), status(9, 9), 0)).is_empty());
+
+        let bus = SuiteInvariant::max_bus_errors(3);
+        assert!(messages(&bus, &run(status(0, 5), status(0, 9), 3)).is_empty());
+        assert_eq!(
+            messages(&bus, &run(status(0, 5), status(0, 9), 4)),
+            vec!["max_bus_errors: 4 bus errors over the suite, more than 3"]
+        );
+
+        let unknown = SuiteRun {
+            start_status: None,
+            ..run(status(0, 500), status(9, 3), 0)
+        };
+        assert!(messages(&reset, &unknown).is_empty());
+        assert!(messages(&errors, &unknown).is_empty());
+    }
+
+    /// Uptime drops after the first status read, as after a reboot
+    fn rebooting_device() -> MockHardwareInterface {
+        let reads = AtomicU32::new(0);
+        let mut mock = MockHardwareInterface::new();
+        mock.expect_get_status().returning(move || {
+            let uptime = if reads.fetch_add(1, Ordering::SeqCst) == 0 { 600 } else { 2 };
+            Ok(status(0, uptime))
+        });
+        mock
+    }
+
+    async fn run_suite(invariant: SuiteInvariant) -> TestSuiteResult {
+        let runner = TestRunner::new(rebooting_device(), Duration::from_secs(1), 0, Duration::ZERO)
+            .with_invariant(invariant);
+        let pass: TestFn<MockHardwareInterface> = Box::new(|_| Box::pin(async { Ok(()) }));
+        runner.run_test_suite("soak", vec![("noop", pass)]).await
+    }
+
+    #[tokio::test]
+    async fn test_violation_flips_suite_outcome() {
+        let reported = run_suite(SuiteInvariant::no_unexpected_reset()).await;
+        assert_eq!(reported.results[0].status, TestStatus::Passed);
+        assert_eq!(reported.invariant_violations.len(), 1);
+        assert!(reported.succeeded());
+        assert!(reported.to_markdown(&AdviceRegistry::new()).contains(
+            "## Suite invariants\n\n- no_unexpected_reset: uptime went back from 600s to 2s; the device reset during the suite\n"
+        ));
+
+        let failed = run_suite(SuiteInvariant::no_unexpected_reset().failing_suite()).await;
+        assert_eq!(failed.passed_tests, 1);
+        assert!(!failed.succeeded());
+        assert_eq!(failed.to_json()["invariant_violations"][0]["fails_suite"], true);
+        assert_eq!(exit_code(&[failed]), 1);
+    }
+}
@@ -15,24 +15,229 @@
  * limitations under the License.
  */
 
+mod advice;
+mod archive;
+mod artifacts;
+mod budget;
+mod campaign;
+mod capabilities;
+mod clock_drift;
+mod console;
+mod criteria;
+mod deadline;
+mod diag_mutex;
+mod drivers;
+mod environment;
+mod fault;
+mod fixtures;
+mod fuzz;
+mod history;
+mod integrity;
 mod interfaces;
+mod invariants;
+mod link;
+mod manifest;
+mod manual;
+mod measurement;
 mod mocks;
+mod observer;
+mod power;
+mod profiling;
+mod quarantine;
+mod redundant;
+mod registers;
+mod replay;
+mod report;
 mod runner;
+mod safe_state;
+mod settle;
+mod snapshot;
+mod state_machine;
+mod stats;
+mod stream_verify;
+mod streaming;
+mod suite_macro;
+mod suites;
+mod sweep;
+mod timed_scope;
+mod timeline;
+mod timing;
 mod utils;
+mod watch;
+mod wire;
 
+pub use advice::*;
+pub use archive::*;
+pub use artifacts::*;
+pub use budget::*;
+pub use campaign::*;
+pub use capabilities::*;
+pub use clock_drift::*;
+pub use console::*;
+pub use criteria::*;
+pub use deadline::*;
+pub use diag_mutex::*;
+pub use drivers::*;
+pub use environment::*;
+pub use fault::*;
+pub use fixtures::*;
+pub use fuzz::*;
+pub use history::*;
+pub use integrity::*;
 pub use interfaces::*;
+pub use invariants::*;
+pub use link::*;
+pub use manifest::*;
+pub use manual::*;
+pub use measurement::*;
 pub use mocks::*;
+pub use observer::*;
+pub use power::*;
+pub use profiling::*;
+pub use quarantine::*;
+pub use redundant::*;
+pub use registers::*;
+pub use replay::*;
+pub use report::*;
 pub use runner::*;
+pub use safe_state::*;
+pub use settle::*;
+pub use snapshot::*;
+pub use state_machine::*;
+pub use stats::*;
+pub use stream_verify::*;
+pub use streaming::*;
+pub use suite_macro::*;
+pub use suites::*;
+pub use sweep::*;
+pub use timed_scope::*;
+pub use timeline::*;
+pub use timing::*;
 pub use utils::*;
+pub use watch::*;
+pub use wire::*;
 
 use std::fmt;
 use std::time::Duration;
 use async_trait::async_trait;
 use thiserror::Error;
 use std::error::Error;
+use serde::Deserialize;
+
+/// Interface type of a manifest target or an `ErrorContext`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceKind {
+    I2c,
+    Spi,
+    Uart,
+}
+
+impl fmt::Display for InterfaceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterfaceKind::I2c => write!(f, "I2C"),
+            InterfaceKind::Spi => write!(f, "SPI"),
+            InterfaceKind::Uart => write!(f, "UART"),
+        }
+    }
+}
+
+/// Operation an interface was performing when an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Init,
+    Read,
+    Write,
+    Transfer,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Init => write!(f, "init"),
+            Operation::Read => write!(f, "read"),
+            Operation::Write => write!(f, "write"),
+            Operation::Transfer => write!(f, "transfer"),
+        }
+    }
+}
+
+/// Where and how a hardware error happened, for telling e.g. a NACK on
+/// an I2C write from a UART read timeout without matching on messages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorContext {
+    pub interface: Option<InterfaceKind>,
+    pub operation: Option<Operation>,
+    /// OS error number of the underlying I/O error
+    pub errno: Option<i32>,
+    /// Retry attempt the error ended, counted from 1
+    pub attempt: Option<u32>,
+}
+
+/// Contexts compare without the attempt, which depends on retry timing
+impl PartialEq for ErrorContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.interface == other.interface && self.operation == other.operation && self.errno == other.errno
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        match (self.interface, self.operation) {
+            (Some(interface), Some(operation)) => parts.push(format!("{} {}", interface, operation)),
+            (Some(interface), None) => parts.push(interface.to_string()),
+            (None, Some(operation)) => parts.push(operation.to_string()),
+            (None, None) => {}
+        }
+        if let Some(errno) = self.errno {
+            parts.push(format!("errno {}", errno));
+        }
+        if let Some(attempt) = self.attempt {
+            parts.push(format!("attempt {}", attempt));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Which `HardwareError` variant an error is, ignoring its payload and
+/// context, for matching errors without parsing their messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HardwareErrorKind {
+    CommunicationError,
+    TimeoutError,
+    InvalidParameter,
+    DeviceNotFound,
+    PermissionDenied,
+    NotInitialized,
+    AlreadyInitialized,
+    OperationFailed,
+    ConfigurationError,
+}
+
+impl HardwareErrorKind {
+    pub const ALL: [HardwareErrorKind; 9] = [
+        HardwareErrorKind::CommunicationError,
+        HardwareErrorKind::TimeoutError,
+        HardwareErrorKind::InvalidParameter,
+        HardwareErrorKind::DeviceNotFound,
+        HardwareErrorKind::PermissionDenied,
+        HardwareErrorKind::NotInitialized,
+        HardwareErrorKind::AlreadyInitialized,
+        HardwareErrorKind::OperationFailed,
+        HardwareErrorKind::ConfigurationError,
+    ];
+}
+
+impl fmt::Display for HardwareErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
 
 /// Hardware interface error types
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum HardwareError {
     CommunicationError(String),
     TimeoutError,
@@ -42,6 +247,123 @@ pub enum HardwareError {
     NotInitialized,
     AlreadyInitialized,
     OperationFailed(String),
+    /// Test or suite set up incorrectly, e.g. an invalid criteria expression
+    ConfigurationError(String),
+    /// `error` with the context it happened in; built with `with_context`
+    /// and `from_io`. Use `root` to match on the underlying variant.
+    Contextual {
+        error: Box<HardwareError>,
+        context: ErrorContext,
+        source: Option<std::io::Error>,
+    },
+}
+
+impl HardwareError {
+    /// Error from an I/O failure, keeping its errno and chaining to it as
+    /// the `source`
+    pub fn from_io(error: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        let root = match error.kind() {
+            ErrorKind::NotFound => HardwareError::DeviceNotFound,
+            ErrorKind::PermissionDenied => HardwareError::PermissionDenied,
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => HardwareError::TimeoutError,
+            ErrorKind::InvalidInput => HardwareError::InvalidParameter(error.to_string()),
+            _ => HardwareError::CommunicationError(error.to_string()),
+        };
+        HardwareError::Contextual {
+            error: Box::new(root),
+            context: ErrorContext {
+                errno: error.raw_os_error(),
+                ..ErrorContext::default()
+            },
+            source: Some(error),
+        }
+    }
+
+    /// Record the interface and operation the error happened in
+    pub fn with_context(self, interface: InterfaceKind, operation: Operation) -> Self {
+        self.map_context(|context| {
+            context.interface = Some(interface);
+            context.operation = Some(operation);
+        })
+    }
+
+    /// Record the retry attempt the error ended
+    pub fn with_attempt(self, attempt: u32) -> Self {
+        self.map_context(|context| context.attempt = Some(attempt))
+    }
+
+    fn map_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        let mut contextual = match self {
+            contextual @ HardwareError::Contextual { .. } => contextual,
+            error => HardwareError::Contextual {
+                error: Box::new(error),
+                context: ErrorContext::default(),
+                source: None,
+            },
+        };
+        if let HardwareError::Contextual { context, .. } = &mut contextual {
+            update(context);
+        }
+        contextual
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            HardwareError::Contextual { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context
+    pub fn root(&self) -> &HardwareError {
+        match self {
+            HardwareError::Contextual { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// Variant of the error without its context
+    pub fn kind(&self) -> HardwareErrorKind {
+        match self {
+            HardwareError::CommunicationError(_) => HardwareErrorKind::CommunicationError,
+            HardwareError::TimeoutError => HardwareErrorKind::TimeoutError,
+            HardwareError::InvalidParameter(_) => HardwareErrorKind::InvalidParameter,
+            HardwareError::DeviceNotFound => HardwareErrorKind::DeviceNotFound,
+            HardwareError::PermissionDenied => HardwareErrorKind::PermissionDenied,
+            HardwareError::NotInitialized => HardwareErrorKind::NotInitialized,
+            HardwareError::AlreadyInitialized => HardwareErrorKind::AlreadyInitialized,
+            HardwareError::OperationFailed(_) => HardwareErrorKind::OperationFailed,
+            HardwareError::ConfigurationError(_) => HardwareErrorKind::ConfigurationError,
+            HardwareError::Contextual { error, .. } => error.kind(),
+        }
+    }
+
+    /// Both errors have the same root, whatever context either carries,
+    /// e.g. to assert a bare variant against an error from an interface
+    pub fn same_kind(&self, other: &HardwareError) -> bool {
+        self.root() == other.root()
+    }
+}
+
+/// Errors compare structurally: a contextual error equals another with
+/// the same root and context only. Use `same_kind` to match regardless of
+/// context.
+impl PartialEq for HardwareError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                HardwareError::Contextual { error: a, context: context_a, .. },
+                HardwareError::Contextual { error: b, context: context_b, .. },
+            ) => context_a == context_b && a == b,
+            (HardwareError::CommunicationError(a), HardwareError::CommunicationError(b)) => a == b,
+            (HardwareError::InvalidParameter(a), HardwareError::InvalidParameter(b)) => a == b,
+            (HardwareError::OperationFailed(a), HardwareError::OperationFailed(b)) => a == b,
+            (HardwareError::ConfigurationError(a), HardwareError::ConfigurationError(b)) => a == b,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
 }
 
 impl fmt::Display for HardwareError {
@@ -55,11 +377,23 @@ impl fmt::Display for HardwareError {
             HardwareError::NotInitialized => write!(f, "Device not initialized"),
             HardwareError::AlreadyInitialized => write!(f, "Device already initialized"),
             HardwareError::OperationFailed(msg) => write!(f, "Operation failed: {}", msg),
+            HardwareError::ConfigurationError(msg) => write!(f, "Configuration error: {}", msg),
+            HardwareError::Contextual { error, context, .. } => match context.to_string() {
+                context if context.is_empty() => write!(f, "{}", error),
+                context => write!(f, "{} ({})", error, context),
+            },
         }
     }
 }
 
-impl Error for HardwareError {}
+impl Error for HardwareError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HardwareError::Contextual { source: Some(source), .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
 /// Result type for hardware operations
 pub type HardwareResult<T> = Result<T, HardwareError>;
@@ -104,6 +438,11 @@ pub trait HardwareInterface {
     
     /// Get the interface status
     fn get_status(&self) -> InterfaceStatus;
+    
+    /// Optional features the interface supports, for suites that adapt to them
+    fn capabilities(&self) -> CapabilitySet {
+        CapabilitySet::EMPTY
+    }
 }
 
 /// Readable interface trait
@@ -148,19 +487,44 @@ impl Default for InterfaceConfig {
 pub struct TestContext<T: HardwareInterface> {
     pub interface: T,
     pub config: InterfaceConfig,
+    /// Rails powering the device, switched by `setup` and `teardown`
+    rails: Vec<Box<dyn PowerRail>>,
 }
 
 impl<T: HardwareInterface> TestContext<T> {
     pub fn new(interface: T, config: InterfaceConfig) -> Self {
-        Self { interface, config }
+        Self {
+            interface,
+            config,
+            rails: Vec::new(),
+        }
+    }
+    
+    /// Enable `rail` in `setup` before the interface is initialized, and
+    /// disable it in `teardown` after the interface is deinitialized
+    pub fn with_power_rail<R: PowerRail + 'static>(mut self, rail: R) -> Self {
+        self.rails.push(Box::new(rail));
+        self
     }
     
     pub async fn setup(&mut self) -> HardwareResult<()> {
+        for rail in &mut self.rails {
+            rail.enable().await?;
+        }
         self.interface.initialize().await
     }
     
+    /// Every rail is disabled even if deinitializing or disabling another
+    /// rail fails; the first such error is reported
     pub async fn teardown(&mut self) -> HardwareResult<()> {
-        self.interface.deinitialize().await
+        let mut result = self.interface.deinitialize().await;
+        for rail in self.rails.iter_mut().rev() {
+            let disabled = rail.disable().await;
+            if result.is_ok() {
+                result = disabled;
+            }
+        }
+        result
     }
 }
 
@@ -192,7 +556,11 @@ pub mod test_utils {
                 Err(e) => {
                     attempts += 1;
                     if attempts >= retry_count {
-                        return Err(e);
+                        // Bus errors carry context; record the attempt that ended them
+                        return Err(match e.context() {
+                            Some(_) => e.with_attempt(attempts),
+                            None => e,
+                        });
                     }
                     tokio::time::sleep(retry_delay).await;
                 }
@@ -200,15 +568,18 @@ pub mod test_utils {
         }
     }
 
-    // Helper function to run test with timeout
+    // Helper function to run test with timeout, cut to the test deadline
     pub async fn run_with_timeout<F, Fut>(f: F, timeout: Duration) -> HardwareResult<()>
     where
         F: Fn() -> Fut,
         Fut: std::future::Future<Output = HardwareResult<()>>,
     {
-        tokio::time::timeout(timeout, f())
-            .await
-            .map_err(|_| HardwareError::TimeoutError)?
+        with_deadline(timeout, |timeout| async move {
+            tokio::time::timeout(timeout, f())
+                .await
+                .map_err(|_| HardwareError::TimeoutError)?
+        })
+        .await
     }
 
     // Helper function to run test with retries and timeout
@@ -254,4 +625,109 @@ mod tests {
     fn test_verify_test_data(#[case] data: Vec<u8>, #[case] expected_size: usize, #[case] expected: bool) {
         assert_eq!(verify_test_data(&data, expected_size), expected);
     }
+
+    #[test]
+    fn test_error_context() {
+        let nack = HardwareError::CommunicationError("NACK".to_string())
+            .with_context(InterfaceKind::I2c, Operation::Write)
+            .with_attempt(2);
+        assert_eq!(nack.to_string(), "Communication error: NACK (I2C write, attempt 2)");
+        assert_eq!(nack.context().unwrap().operation, Some(Operation::Write));
+        assert!(matches!(nack.root(), HardwareError::CommunicationError(_)));
+
+        // Attempts are ignored; a bare variant only matches by kind
+        let retried = HardwareError::CommunicationError("NACK".to_string())
+            .with_context(InterfaceKind::I2c, Operation::Write)
+            .with_attempt(3);
+        let bare = HardwareError::CommunicationError("NACK".to_string());
+        assert_eq!(nack, retried);
+        assert_ne!(nack, bare);
+        assert!(nack.same_kind(&bare) && bare.same_kind(&retried));
+        assert_ne!(
+            nack,
+            HardwareError::CommunicationError("NACK".to_string()).with_context(InterfaceKind::Uart, Operation::Write)
+        );
+        assert_ne!(nack, HardwareError::TimeoutError);
+        assert!(!nack.same_kind(&HardwareError::TimeoutError));
+        assert_eq!(nack.kind(), HardwareErrorKind::CommunicationError);
+        assert_eq!(nack.kind().to_string(), "CommunicationError");
+    }
+
+    #[test]
+    fn test_error_from_io() {
+        let error = HardwareError::from_io(std::io::Error::from_raw_os_error(121))
+            .with_context(InterfaceKind::I2c, Operation::Transfer);
+        assert_eq!(error.context().unwrap().errno, Some(121));
+        assert!(error.to_string().ends_with("(I2C transfer, errno 121)"));
+        let source = error.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(source.raw_os_error(), Some(121));
+
+        let missing = HardwareError::from_io(std::io::Error::new(std::io::ErrorKind::NotFound, "no /dev/i2c-9"));
+        assert!(missing.same_kind(&HardwareError::DeviceNotFound));
+        assert_eq!(missing.to_string(), "Device not found");
+        assert!(HardwareError::TimeoutError.source().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retries_record_attempt() {
+        let result = test_utils::run_with_retries(
+            || async { Err(HardwareError::TimeoutError.with_context(InterfaceKind::Uart, Operation::Read)) },
+            3,
+            Duration::ZERO,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().context().unwrap().attempt, Some(3));
+
+        let bare = test_utils::run_with_retries(|| async { Err(HardwareError::TimeoutError) }, 2, Duration::ZERO).await;
+        assert!(matches!(bare, Err(HardwareError::TimeoutError)));
+    }
+
+    #[tokio::test]
+    async fn test_context_switches_power_rails() {
+        let rail = mocks::MockPowerRail::new();
+        let mut context = TestContext::new(mocks::create_mock_interface_with_defaults(), InterfaceConfig::default())
+            .with_power_rail(rail.clone());
+
+        context.setup().await.unwrap();
+        assert!(rail.is_enabled());
+        context.teardown().await.unwrap();
+
+        assert_eq!(rail.events(), vec![mocks::PowerEvent::Enabled, mocks::PowerEvent::Disabled]);
+    }
+
+    /// Rail whose relay is stuck, so it can never be switched off
+    struct StuckRail;
+
+    #[async_trait]
+    impl PowerRail for StuckRail {
+        async fn enable(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        async fn disable(&mut self) -> HardwareResult<()> {
+            Err(HardwareError::OperationFailed("relay stuck".to_string()))
+        }
+
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        async fn measure(&mut self) -> HardwareResult<Option<RailMeasurement>> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_context_teardown_disables_every_rail() {
+        let rail = mocks::MockPowerRail::new();
+        let mut context = TestContext::new(mocks::create_mock_interface_with_defaults(), InterfaceConfig::default())
+            .with_power_rail(rail.clone())
+            .with_power_rail(StuckRail);
+
+        context.setup().await.unwrap();
+        let result = context.teardown().await;
+
+        assert_eq!(result, Err(HardwareError::OperationFailed("relay stuck".to_string())));
+        assert!(!rail.is_enabled());
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,375 @@
+/*
+ * Two-Ended Link Test for Cables and Harnesses
+ * Copyright (C) 2024
+ */
+
+use crate::integrity::{encode_frame, Checker};
+use crate::{
+    DiagMutex, HardwareError, HardwareInterface, HardwareResult, IntegrityReport, Readable, ReportAppendix,
+    StreamLimit, TestFn, TestRunner, TestSuiteResult, Writable,
+};
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Traffic in one direction of a link test
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkDirection {
+    /// e.g. "A -> B"
+    pub name: String,
+    pub payload_len: u16,
+    pub integrity: IntegrityReport,
+}
+
+impl LinkDirection {
+    /// Bytes received per second
+    pub fn throughput(&self) -> f64 {
+        self.integrity.bytes_received as f64 / self.integrity.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Lost, duplicated, reordered and corrupted frames per frame sent
+    pub fn error_rate(&self) -> f64 {
+        let report = &self.integrity;
+        let errors = report.lost.len() + report.duplicated.len() + report.reordered.len() + report.corruptions.len();
+        errors as f64 / f64::from(report.frames_sent.max(1))
+    }
+}
+
+impl fmt::Display for LinkDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} byte payloads): {:.1} KiB/s, error rate {:.4}",
+            self.name,
+            self.payload_len,
+            self.throughput() / 1024.0,
+            self.error_rate()
+        )?;
+        for line in self.integrity.to_string().lines() {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a link test, one entry per direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkTestReport {
+    pub a_to_b: LinkDirection,
+    pub b_to_a: LinkDirection,
+}
+
+impl LinkTestReport {
+    pub fn is_clean(&self) -> bool {
+        self.a_to_b.integrity.is_clean() && self.b_to_a.integrity.is_clean()
+    }
+
+    /// Fails naming each direction that was not clean
+    pub fn check(&self) -> HardwareResult<()> {
+        let problems: Vec<String> = [&self.a_to_b, &self.b_to_a]
+            .iter()
+            .filter_map(|direction| direction.integrity.check().err().map(|e| (direction, e)))
+            .map(|(direction, e)| match e {
+                HardwareError::OperationFailed(message) => format!("{}: {}", direction.name, message),
+                other => format!("{}: {:?}", direction.name, other),
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(HardwareError::OperationFailed(format!("link test: {}", problems.join("; "))))
+        }
+    }
+
+    pub fn appendix(&self) -> ReportAppendix {
+        ReportAppendix::new("Link test", &self.to_string())
+    }
+}
+
+impl fmt::Display for LinkTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.a_to_b, self.b_to_a)
+    }
+}
+
+/// Settings shared by both direction tasks
+#[derive(Clone, Copy)]
+struct DirectionPlan {
+    payload_len: u16,
+    limit: StreamLimit,
+    read_timeout: Duration,
+}
+
+/// Cable check driving two ports wired to each other, e.g. UART A to
+/// UART B on the same bench computer
+///
+/// Both directions stream the sequence-numbered frames of
+/// `IntegrityStream` at the same time, each from its own task, and are
+/// checked independently. The ports are shared through `DiagMutex`es so
+/// a direction only holds a port for one read or write.
+pub struct LinkTest<A, B> {
+    a: Arc<DiagMutex<A>>,
+    b: Arc<DiagMutex<B>>,
+    names: (String, String),
+    a_to_b_payload: u16,
+    b_to_a_payload: u16,
+    limit: StreamLimit,
+    read_timeout: Duration,
+}
+
+impl<A, B> LinkTest<A, B>
+where
+    A: Readable + Writable + Send + 'static,
+    B: Readable + Writable + Send + 'static,
+{
+    /// Both directions send `payload_len` byte frames until `limit`
+    pub fn new(a: A, b: B, payload_len: u16, limit: StreamLimit) -> Self {
+        Self {
+            a: Arc::new(DiagMutex::new(a)),
+            b: Arc::new(DiagMutex::new(b)),
+            names: ("A".to_string(), "B".to_string()),
+            a_to_b_payload: payload_len,
+            b_to_a_payload: payload_len,
+            limit,
+            read_timeout: Duration::from_millis(10),
+        }
+    }
+
+    /// Port names used in the report, e.g. "uart1" and "uart2"
+    pub fn with_names(mut self, a: &str, b: &str) -> Self {
+        self.names = (a.to_string(), b.to_string());
+        self
+    }
+
+    /// Different frame sizes per direction, e.g. small commands one way and
+    /// large telemetry frames the other
+    pub fn with_payload_lens(mut self, a_to_b: u16, b_to_a: u16) -> Self {
+        self.a_to_b_payload = a_to_b;
+        self.b_to_a_payload = b_to_a;
+        self
+    }
+
+    /// Timeout for each read; timeouts are treated as nothing received.
+    /// The port stays locked for the read, so keep this short.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    fn plan(&self, payload_len: u16) -> DirectionPlan {
+        DirectionPlan {
+            payload_len,
+            limit: self.limit,
+            read_timeout: self.read_timeout,
+        }
+    }
+
+    /// Stream both directions until the limit and drain them. Only
+    /// interface errors other than read timeouts abort the run.
+    pub async fn run(&self) -> HardwareResult<LinkTestReport> {
+        let (a_name, b_name) = &self.names;
+        let a_to_b_name = format!("{} -> {}", a_name, b_name);
+        let b_to_a_name = format!("{} -> {}", b_name, a_name);
+
+        let a_to_b = tokio::spawn(stream_direction(
+            self.a.clone(),
+            self.b.clone(),
+            self.plan(self.a_to_b_payload),
+            a_to_b_name.clone(),
+        ));
+        let b_to_a = tokio::spawn(stream_direction(
+            self.b.clone(),
+            self.a.clone(),
+            self.plan(self.b_to_a_payload),
+            b_to_a_name.clone(),
+        ));
+        let (a_to_b, b_to_a) = tokio::join!(a_to_b, b_to_a);
+        let join = |result: Result<HardwareResult<IntegrityReport>, tokio::task::JoinError>, name: &str| {
+            result.map_err(|e| HardwareError::OperationFailed(format!("link direction {} panicked: {}", name, e)))?
+        };
+
+        Ok(LinkTestReport {
+            a_to_b: LinkDirection {
+                integrity: join(a_to_b, &a_to_b_name)?,
+                name: a_to_b_name,
+                payload_len: self.a_to_b_payload,
+            },
+            b_to_a: LinkDirection {
+                integrity: join(b_to_a, &b_to_a_name)?,
+                name: b_to_a_name,
+                payload_len: self.b_to_a_payload,
+            },
+        })
+    }
+
+    /// Generated test named `<base>::link`; the report is kept in the
+    /// returned slot whether or not the link was clean. The runner's own
+    /// interface is not used.
+    pub fn case<T: 'static>(self, base_name: &str) -> ((String, TestFn<T>), Arc<StdMutex<Option<LinkTestReport>>>) {
+        let slot = Arc::new(StdMutex::new(None));
+        let report_slot = slot.clone();
+        let test: TestFn<T> = Box::new(move |_| {
+            Box::pin(async move {
+                let report = self.run().await?;
+                let result = report.check();
+                *report_slot.lock().unwrap() = Some(report);
+                result
+            })
+        });
+        ((format!("{}::link", base_name), test), slot)
+    }
+}
+
+async fn read_some<T: Readable>(port: &mut T, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+    match port.read(buffer, timeout).await {
+        Err(e) if matches!(e.root(), HardwareError::TimeoutError) => Ok(0),
+        other => other,
+    }
+}
+
+/// Send frames from `tx` and check what arrives at `rx`
+async fn stream_direction<TX, RX>(
+    tx: Arc<DiagMutex<TX>>,
+    rx: Arc<DiagMutex<RX>>,
+    plan: DirectionPlan,
+    name: String,
+) -> HardwareResult<IntegrityReport>
+where
+    TX: Writable + Send,
+    RX: Readable + Send,
+{
+    let mut checker = Checker::new(plan.payload_len);
+    let mut buffer = vec![0u8; checker.frame_len()];
+    let start = Instant::now();
+    let mut frames_sent = 0u32;
+    let mut bytes_sent = 0u64;
+    let mut bytes_received = 0u64;
+    let tx_holder = format!("link {} tx", name);
+    let rx_holder = format!("link {} rx", name);
+
+    while !plan.limit.reached(start.elapsed(), bytes_sent) {
+        let frame = encode_frame(frames_sent, plan.payload_len);
+        tx.lock_as(&tx_holder).await.write_all(&frame).await?;
+        frames_sent += 1;
+        bytes_sent += frame.len() as u64;
+
+        let count = read_some(&mut *rx.lock_as(&rx_holder).await, &mut buffer, plan.read_timeout).await?;
+        bytes_received += count as u64;
+        checker.push(&buffer[..count], frames_sent);
+        // Let the other direction at the ports between frames
+        tokio::task::yield_now().await;
+    }
+
+    loop {
+        let count = read_some(&mut *rx.lock_as(&rx_holder).await, &mut buffer, plan.read_timeout).await?;
+        if count == 0 {
+            break;
+        }
+        bytes_received += count as u64;
+        checker.push(&buffer[..count], frames_sent);
+    }
+
+    log::info!("Link {}: {} frames sent, {} errors", name, frames_sent, checker.errors());
+    Ok(checker.finish(frames_sent, bytes_sent, bytes_received, start.elapsed()))
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run a link test as a single-test suite with the link report in an
+    /// appendix
+    pub async fn run_link_test<A, B>(&self, name: &str, test: LinkTest<A, B>) -> TestSuiteResult
+    where
+        A: Readable + Writable + Send + 'static,
+        B: Readable + Writable + Send + 'static,
+    {
+        let ((case_name, test_fn), report) = test.case(name);
+        let mut suite = self.run_test_suite(name, vec![(case_name.as_str(), test_fn)]).await;
+        if let Some(report) = report.lock().unwrap().as_ref() {
+            suite.add_appendix(report.appendix());
+        }
+        suite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, FakeDuplex};
+    use crate::{TestStatus, WriteFault};
+
+    async fn cable() -> (FakeDuplex, FakeDuplex) {
+        let (mut a, mut b) = FakeDuplex::pair();
+        a.initialize().await.unwrap();
+        b.initialize().await.unwrap();
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn test_clean_asymmetric_link() {
+        let (a, b) = cable().await;
+        // 30 frames of 40 bytes one way, 12 of 100 the other
+        let test = LinkTest::new(a, b, 30, StreamLimit::Bytes(1200))
+            .with_payload_lens(30, 90)
+            .with_names("uart1", "uart2");
+
+        let report = test.run().await.unwrap();
+
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.a_to_b.name, "uart1 -> uart2");
+        assert_eq!(report.a_to_b.integrity.frames_sent, 30);
+        assert_eq!(report.a_to_b.integrity.frames_received, 30);
+        assert_eq!(report.b_to_a.name, "uart2 -> uart1");
+        assert_eq!(report.b_to_a.integrity.frames_sent, 12);
+        assert_eq!(report.b_to_a.integrity.bytes_received, 1200);
+        assert_eq!(report.b_to_a.error_rate(), 0.0);
+        assert!(report.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_faults_counted_per_direction() {
+        let (a, b) = cable().await;
+        let a = a.with_write_fault(2, WriteFault::Drop).with_write_fault(4, WriteFault::Duplicate);
+        let b = b.with_write_fault(1, WriteFault::FlipBits { offset: 9, mask: 0x80 });
+        let test = LinkTest::new(a, b, 30, StreamLimit::Bytes(400));
+
+        let report = test.run().await.unwrap();
+
+        let a_to_b = &report.a_to_b.integrity;
+        assert_eq!(a_to_b.lost, vec![2]);
+        assert_eq!(a_to_b.duplicated, vec![4]);
+        assert!(a_to_b.corruptions.is_empty());
+        assert_eq!(report.a_to_b.error_rate(), 0.2);
+
+        let b_to_a = &report.b_to_a.integrity;
+        assert!(b_to_a.lost.is_empty() && b_to_a.duplicated.is_empty());
+        assert_eq!(b_to_a.corruptions.len(), 1);
+        assert_eq!(b_to_a.corruptions[0].seq, Some(1));
+
+        assert_eq!(
+            report.check(),
+            Err(HardwareError::OperationFailed(
+                "link test: A -> B: data integrity: 1 lost, 1 duplicated, 0 reordered, 0 corrupted, 0 bytes skipped; \
+                 B -> A: data integrity: 0 lost, 0 duplicated, 0 reordered, 1 corrupted, 0 bytes skipped"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runner_reports_link() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(5), 0, Duration::ZERO);
+        let (a, b) = cable().await;
+        let a = a.with_write_fault(0, WriteFault::Drop);
+
+        let suite = runner
+            .run_link_test("harness J3", LinkTest::new(a, b, 16, StreamLimit::Bytes(260)))
+            .await;
+
+        assert_eq!(suite.results[0].name, "harness J3::link");
+        assert!(matches!(suite.results[0].status, TestStatus::Error(_)));
+        let appendix = suite.appendices.iter().find(|a| a.title == "Link test").unwrap();
+        assert!(appendix.body.starts_with("A -> B (16 byte payloads): "));
+        assert!(appendix.body.contains("  lost: 1 [0]\n"));
+        assert!(appendix.body.contains("B -> A (16 byte payloads): "));
+    }
+}
@@ -0,0 +1,716 @@
+/*
+ * Suite Manifests for the hwtest Binary
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    builtin_suite, AdviceRegistry, Budget, BuiltinTarget, ConsoleReporter, Criteria, CriteriaError, CriteriaTest,
+    CriteriaValues, DiagMutex, HardwareError, HardwareInterface, HardwareResult, I2CConfig, I2CInterface, InterfaceKind, Quarantine, SPIConfig, SPIInterface, TestFn, TestRunner,
+    TestSuiteResult, UARTConfig, UARTInterface, BUILTIN_SUITES,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Report formats a manifest can request
+pub const REPORT_FORMATS: [&str; 3] = ["json", "markdown", "junit"];
+
+/// Variables available to manifest criteria, read from the target's
+/// interface status after its suites ran
+pub const CRITERIA_VARIABLES: [&str; 2] = ["error_count", "warning_count"];
+
+/// Per-test timeout of targets without `timeout_ms`
+pub const DEFAULT_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Manifest loading and validation errors
+#[derive(Debug)]
+pub enum ManifestError {
+    Read { path: PathBuf, source: io::Error },
+    Parse(String),
+    NoTargets,
+    DuplicateTarget(String),
+    /// Two target names map to the same report file name
+    ReportNameClash { first: String, second: String },
+    UnknownSuite(String),
+    UnknownReportFormat(String),
+    Criteria { name: String, source: CriteriaError },
+    ReportWrite { path: PathBuf, source: io::Error },
+    /// A target's tests could not be set up, e.g. for a manifest built in
+    /// code rather than loaded
+    Target { name: String, source: HardwareError },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Read { path, source } => write!(f, "cannot read manifest {}: {}", path.display(), source),
+            ManifestError::Parse(msg) => write!(f, "invalid manifest: {}", msg),
+            ManifestError::NoTargets => write!(f, "manifest defines no targets; add at least one [[target]] table"),
+            ManifestError::DuplicateTarget(name) => write!(f, "target name '{}' is used more than once", name),
+            ManifestError::ReportNameClash { first, second } => write!(
+                f,
+                "targets '{}' and '{}' would write the same report file '{}'; rename one of them",
+                first,
+                second,
+                report_name(second)
+            ),
+            ManifestError::UnknownSuite(name) => write!(
+                f,
+                "unknown suite '{}'; available suites: {}",
+                name,
+                BUILTIN_SUITES.join(", ")
+            ),
+            ManifestError::UnknownReportFormat(format) => write!(
+                f,
+                "unknown report format '{}'; available formats: {}",
+                format,
+                REPORT_FORMATS.join(", ")
+            ),
+            ManifestError::Criteria { name, source } => write!(
+                f,
+                "invalid criteria '{}': {} (available variables: {})",
+                name,
+                source,
+                CRITERIA_VARIABLES.join(", ")
+            ),
+            ManifestError::ReportWrite { path, source } => {
+                write!(f, "cannot write report {}: {}", path.display(), source)
+            }
+            ManifestError::Target { name, source } => write!(f, "cannot set up target '{}': {}", name, source),
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+/// One `[[target]]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetSpec {
+    pub name: String,
+    pub interface: InterfaceKind,
+    pub device: Option<String>,
+    pub bus: Option<u8>,
+    pub address: Option<u16>,
+    pub baud: Option<u32>,
+    pub max_transfer_size: Option<usize>,
+    /// Timeout of each test run on this target
+    pub timeout_ms: Option<u64>,
+}
+
+impl TargetSpec {
+    /// `timeout_ms`, or `DEFAULT_TEST_TIMEOUT` if unset
+    pub fn test_timeout(&self) -> Duration {
+        self.timeout_ms.map_or(DEFAULT_TEST_TIMEOUT, Duration::from_millis)
+    }
+}
+
+/// The `[budget]` table, applied to each target's suite run
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetSpec {
+    pub max_wall_time_secs: Option<f64>,
+    pub max_operations: Option<u64>,
+    pub max_errors: Option<u32>,
+}
+
+/// One `[[criteria]]` table, run as test `criteria::<name>` on every target
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CriteriaSpec {
+    pub name: String,
+    pub expression: String,
+}
+
+/// The `[reports]` table; the directory is relative to the manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportSpec {
+    pub directory: Option<PathBuf>,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<String>,
+}
+
+fn default_formats() -> Vec<String> {
+    REPORT_FORMATS.iter().map(|f| f.to_string()).collect()
+}
+
+impl Default for ReportSpec {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            formats: default_formats(),
+        }
+    }
+}
+
+/// Suite manifest run by `hwtest`
+///
+/// ```toml
+/// suites = ["lifecycle", "conformance"]
+/// filter = "transfer"
+///
+/// [[target]]
+/// name = "eeprom"
+/// interface = "i2c"
+/// bus = 1
+/// address = 0x50
+/// timeout_ms = 500
+///
+/// [[criteria]]
+/// name = "clean_bus"
+/// expression = "error_count == 0 and warning_count <= 2"
+///
+/// [quarantine]
+/// tests = ["conformance::burst_read"]
+///
+/// [budget]
+/// max_wall_time_secs = 60
+///
+/// [reports]
+/// directory = "reports"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuiteManifest {
+    pub suites: Vec<String>,
+    #[serde(rename = "target", default)]
+    pub targets: Vec<TargetSpec>,
+    /// Only run tests whose name contains this text
+    pub filter: Option<String>,
+    /// Names of tests to leave out
+    #[serde(default)]
+    pub skip: Vec<String>,
+    #[serde(default)]
+    pub criteria: Vec<CriteriaSpec>,
+    /// Tests run as usual whose failures do not fail the target
+    #[serde(default)]
+    pub quarantine: Quarantine,
+    pub budget: Option<BudgetSpec>,
+    #[serde(default)]
+    pub reports: ReportSpec,
+}
+
+impl SuiteManifest {
+    /// Parse and validate a manifest
+    pub fn from_toml(text: &str) -> Result<Self, ManifestError> {
+        let manifest: SuiteManifest = toml::from_str(text).map_err(|e| ManifestError::Parse(e.to_string()))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let text = fs::read_to_string(path).map_err(|source| ManifestError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&text)
+    }
+
+    fn validate(&self) -> Result<(), ManifestError> {
+        if self.targets.is_empty() {
+            return Err(ManifestError::NoTargets);
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            if self.targets[..i].iter().any(|t| t.name == target.name) {
+                return Err(ManifestError::DuplicateTarget(target.name.clone()));
+            }
+            if let Some(other) = self.targets[..i].iter().find(|t| report_name(&t.name) == report_name(&target.name)) {
+                return Err(ManifestError::ReportNameClash {
+                    first: other.name.clone(),
+                    second: target.name.clone(),
+                });
+            }
+        }
+        if let Some(suite) = self.suites.iter().find(|s| !BUILTIN_SUITES.contains(&s.as_str())) {
+            return Err(ManifestError::UnknownSuite(suite.clone()));
+        }
+        if let Some(format) = self.reports.formats.iter().find(|f| !REPORT_FORMATS.contains(&f.as_str())) {
+            return Err(ManifestError::UnknownReportFormat(format.clone()));
+        }
+        for spec in &self.criteria {
+            Criteria::parse(&spec.expression)
+                .and_then(|criteria| criteria.check_variables(&CRITERIA_VARIABLES))
+                .map_err(|source| ManifestError::Criteria {
+                    name: spec.name.clone(),
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn budget(&self) -> Option<Budget> {
+        let spec = self.budget.as_ref()?;
+        let mut budget = Budget::new();
+        if let Some(secs) = spec.max_wall_time_secs {
+            budget = budget.max_wall_time(Duration::from_secs_f64(secs));
+        }
+        if let Some(ops) = spec.max_operations {
+            budget = budget.max_operations(ops);
+        }
+        if let Some(errors) = spec.max_errors {
+            budget = budget.max_errors(errors);
+        }
+        Some(budget)
+    }
+
+    fn selected(&self, name: &str) -> bool {
+        self.filter.as_ref().map_or(true, |f| name.contains(f.as_str())) && !self.skip.iter().any(|s| s == name)
+    }
+
+    /// Run every target, one suite result per target, and write the
+    /// configured reports relative to `base_dir`. The target interfaces
+    /// are deinitialized afterwards.
+    pub async fn run(&self, base_dir: &Path, console: bool) -> Result<Vec<TestSuiteResult>, ManifestError> {
+        let mut interfaces = TargetInterfaces::new();
+        let results = self.run_with(base_dir, console, &mut interfaces).await;
+        if let Err(e) = interfaces.deinitialize().await {
+            log::warn!("Failed to deinitialize a target after the run: {}", e);
+        }
+        results
+    }
+
+    /// Run like `run` on the interfaces kept in `interfaces`, opening the
+    /// ones missing; they stay initialized for the next run
+    pub async fn run_with(
+        &self,
+        base_dir: &Path,
+        console: bool,
+        interfaces: &mut TargetInterfaces,
+    ) -> Result<Vec<TestSuiteResult>, ManifestError> {
+        interfaces.retain_targets(&self.targets).await;
+        let mut results = Vec::new();
+        for target in &self.targets {
+            let result = match interfaces.open(target) {
+                TargetInterface::I2c(interface) => self.run_target(target, interface.clone(), console).await,
+                TargetInterface::Spi(interface) => self.run_target(target, interface.clone(), console).await,
+                TargetInterface::Uart(interface) => self.run_target(target, interface.clone(), console).await,
+            };
+            let result = result.map_err(|source| ManifestError::Target {
+                name: target.name.clone(),
+                source,
+            })?;
+            self.write_reports(base_dir, &result)?;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    async fn run_target<T: BuiltinTarget>(
+        &self,
+        target: &TargetSpec,
+        interface: Arc<DiagMutex<T>>,
+        console: bool,
+    ) -> HardwareResult<TestSuiteResult> {
+        let mut runner = TestRunner::from_shared(interface, target.test_timeout(), 0, Duration::ZERO)
+            .with_quarantine(self.quarantine.clone());
+        if let Some(budget) = self.budget() {
+            runner = runner.with_budget(budget);
+        }
+        if console {
+            runner = runner.with_observer(ConsoleReporter::stdout());
+        }
+
+        let mut tests: Vec<(String, TestFn<T>)> = Vec::new();
+        for suite in &self.suites {
+            let suite_tests = builtin_suite::<T>(suite).ok_or_else(|| {
+                HardwareError::ConfigurationError(ManifestError::UnknownSuite(suite.clone()).to_string())
+            })?;
+            tests.extend(suite_tests);
+        }
+        for spec in &self.criteria {
+            tests.push(criteria_case::<T>(spec)?);
+        }
+        tests.retain(|(test, _)| self.selected(test));
+        let names: Vec<String> = tests.iter().map(|(test, _)| test.clone()).collect();
+        let tests = names.iter().map(String::as_str).zip(tests.into_iter().map(|(_, f)| f)).collect();
+        Ok(runner.run_test_suite(&target.name, tests).await)
+    }
+
+    fn write_reports(&self, base_dir: &Path, result: &TestSuiteResult) -> Result<(), ManifestError> {
+        let directory = match &self.reports.directory {
+            Some(directory) => base_dir.join(directory),
+            None => return Ok(()),
+        };
+        let write_error = |path: PathBuf| move |source: io::Error| ManifestError::ReportWrite { path, source };
+        fs::create_dir_all(&directory).map_err(write_error(directory.clone()))?;
+        let name = report_name(&result.name);
+        for format in &self.reports.formats {
+            let path = match format.as_str() {
+                "json" => directory.join(format!("{}.json", name)),
+                "junit" => directory.join(format!("{}.xml", name)),
+                _ => directory.join(format!("{}.md", name)),
+            };
+            let written = match format.as_str() {
+                "json" => result.write_json(&path),
+                "junit" => result.write_junit_xml(&path),
+                _ => result.write_markdown(&path, &AdviceRegistry::new()),
+            };
+            written.map_err(write_error(path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Interface of one manifest target
+enum TargetInterface {
+    I2c(Arc<DiagMutex<I2CInterface>>),
+    Spi(Arc<DiagMutex<SPIInterface>>),
+    Uart(Arc<DiagMutex<UARTInterface>>),
+}
+
+impl TargetInterface {
+    fn new(target: &TargetSpec) -> Self {
+        match target.interface {
+            InterfaceKind::I2c => {
+                let mut config = I2CConfig::default();
+                config.bus_number = target.bus.unwrap_or(config.bus_number);
+                config.device_address = target.address.unwrap_or(config.device_address);
+                config.max_transfer_size = target.max_transfer_size.or(config.max_transfer_size);
+                TargetInterface::I2c(Arc::new(DiagMutex::new(I2CInterface::new(config))))
+            }
+            InterfaceKind::Spi => {
+                let mut config = SPIConfig::default();
+                config.device_path = target.device.clone().unwrap_or(config.device_path);
+                config.max_transfer_size = target.max_transfer_size.or(config.max_transfer_size);
+                TargetInterface::Spi(Arc::new(DiagMutex::new(SPIInterface::new(config))))
+            }
+            InterfaceKind::Uart => {
+                let mut config = UARTConfig::default();
+                config.device_path = target.device.clone().unwrap_or(config.device_path);
+                config.baud_rate = target.baud.unwrap_or(config.baud_rate);
+                config.max_transfer_size = target.max_transfer_size.or(config.max_transfer_size);
+                TargetInterface::Uart(Arc::new(DiagMutex::new(UARTInterface::new(config))))
+            }
+        }
+    }
+
+    async fn deinitialize(&self) -> HardwareResult<()> {
+        match self {
+            TargetInterface::I2c(interface) => interface.lock().await.deinitialize().await,
+            TargetInterface::Spi(interface) => interface.lock().await.deinitialize().await,
+            TargetInterface::Uart(interface) => interface.lock().await.deinitialize().await,
+        }
+    }
+}
+
+/// Interfaces of a manifest's targets kept between runs, so the hardware
+/// stays initialized, e.g. for `hwtest --watch --keep-initialized`
+///
+/// A target whose table changed, or that left the manifest, has its old
+/// interface deinitialized before the next run.
+#[derive(Default)]
+pub struct TargetInterfaces {
+    targets: BTreeMap<String, (TargetSpec, TargetInterface)>,
+}
+
+impl TargetInterfaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names of the targets with an open interface
+    pub fn targets(&self) -> Vec<&str> {
+        self.targets.keys().map(String::as_str).collect()
+    }
+
+    /// Deinitialize and drop every interface. All of them are
+    /// deinitialized even if one fails, whose error is returned.
+    pub async fn deinitialize(&mut self) -> HardwareResult<()> {
+        let mut outcome = Ok(());
+        for (_, (_, interface)) in std::mem::take(&mut self.targets) {
+            let deinitialized = interface.deinitialize().await;
+            if outcome.is_ok() {
+                outcome = deinitialized;
+            }
+        }
+        outcome
+    }
+
+    async fn retain_targets(&mut self, targets: &[TargetSpec]) {
+        let stale: Vec<String> = self
+            .targets
+            .iter()
+            .filter(|(name, (spec, _))| !targets.iter().any(|t| &t.name == *name && t == spec))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in stale {
+            if let Some((_, interface)) = self.targets.remove(&name) {
+                if let Err(e) = interface.deinitialize().await {
+                    log::warn!("Failed to deinitialize target '{}' before reopening it: {}", name, e);
+                }
+            }
+        }
+    }
+
+    fn open(&mut self, target: &TargetSpec) -> &TargetInterface {
+        &self
+            .targets
+            .entry(target.name.clone())
+            .or_insert_with(|| (target.clone(), TargetInterface::new(target)))
+            .1
+    }
+}
+
+/// Report file name for a target, without extension; anything but ASCII
+/// letters, digits, `-` and `_` becomes `_` so a target name cannot point
+/// outside the report directory
+fn report_name(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Manifest criteria case evaluated against the target's interface status.
+/// Loaded manifests have their expressions validated already; one built in
+/// code may not, so an invalid expression is a `ConfigurationError`.
+fn criteria_case<T: HardwareInterface + 'static>(spec: &CriteriaSpec) -> HardwareResult<(String, TestFn<T>)> {
+    let test = CriteriaTest::new(&spec.expression, &CRITERIA_VARIABLES, |interface| {
+        Box::pin(async move {
+            let status = interface.lock().await.get_status().await?;
+            let values: CriteriaValues = [
+                ("error_count".to_string(), status.error_count as f64),
+                ("warning_count".to_string(), status.warning_count as f64),
+            ]
+            .into_iter()
+            .collect();
+            Ok(values)
+        })
+    })
+    .map_err(|source| {
+        HardwareError::ConfigurationError(
+            ManifestError::Criteria {
+                name: spec.name.clone(),
+                source,
+            }
+            .to_string(),
+        )
+    })?;
+    Ok(test.case(&format!("criteria::{}", spec.name)))
+}
+
+/// Process exit code for a set of suite results: 0 when everything
+/// passed or was skipped, 1 when any test failed or errored or a suite
+/// invariant failed its suite
+pub fn exit_code(results: &[TestSuiteResult]) -> u8 {
+    if results.iter().any(|r| !r.succeeded()) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Load the manifest at `path` and run it, resolving report paths
+/// relative to the manifest's directory
+pub async fn run_manifest_file(path: &Path, console: bool) -> Result<Vec<TestSuiteResult>, ManifestError> {
+    let manifest = SuiteManifest::load(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    manifest.run(base_dir, console).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        suites = ["lifecycle", "conformance"]
+        skip = ["conformance::write_read"]
+
+        [[target]]
+        name = "eeprom"
+        interface = "i2c"
+        address = 0x51
+
+        [[target]]
+        name = "flash"
+        interface = "spi"
+        timeout_ms = 250
+
+        [reports]
+        directory = "reports"
+    "#;
+
+    #[tokio::test]
+    async fn test_run_manifest_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bench.toml");
+        fs::write(&path, MANIFEST).unwrap();
+
+        let results = run_manifest_file(&path, false).await.unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "eeprom");
+        assert_eq!(results[0].total_tests, 6);
+        assert!(!results[0].results.iter().any(|r| r.name == "conformance::write_read"));
+        assert_eq!(results[1].total_tests, 5);
+        assert_eq!(exit_code(&results), 0);
+        assert!(dir.path().join("reports/eeprom.json").exists());
+        assert!(dir.path().join("reports/flash.md").exists());
+        assert!(dir.path().join("reports/flash.xml").exists());
+    }
+
+    #[test]
+    fn test_target_timeout() {
+        let manifest = SuiteManifest::from_toml(MANIFEST).unwrap();
+
+        assert_eq!(manifest.targets[0].test_timeout(), DEFAULT_TEST_TIMEOUT);
+        assert_eq!(manifest.targets[1].test_timeout(), Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_target_interfaces_kept_between_runs() {
+        let manifest = SuiteManifest::from_toml(
+            "suites = [\"lifecycle\"]\n[[target]]\nname = \"console\"\ninterface = \"uart\"",
+        )
+        .unwrap();
+        let mut interfaces = TargetInterfaces::new();
+        let uart = |interfaces: &TargetInterfaces| match &interfaces.targets["console"].1 {
+            TargetInterface::Uart(interface) => interface.clone(),
+            _ => panic!("console is a UART target"),
+        };
+
+        manifest.run_with(Path::new("."), false, &mut interfaces).await.unwrap();
+        let first = uart(&interfaces);
+        assert!(first.lock().await.is_initialized());
+        manifest.run_with(Path::new("."), false, &mut interfaces).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &uart(&interfaces)));
+
+        // A changed target table gets a new interface
+        let mut changed = manifest.clone();
+        changed.targets[0].baud = Some(9600);
+        changed.run_with(Path::new("."), false, &mut interfaces).await.unwrap();
+        assert!(!first.lock().await.is_initialized());
+        let second = uart(&interfaces);
+        assert!(second.lock().await.is_initialized());
+
+        interfaces.deinitialize().await.unwrap();
+        assert!(interfaces.targets().is_empty());
+        assert!(!second.lock().await.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_failing_target_exit_code() {
+        // A limit of zero makes every non-empty I2C write fail the policy check
+        let manifest = SuiteManifest::from_toml(
+            r#"
+            suites = ["conformance"]
+            filter = "write_read"
+
+            [[target]]
+            name = "tiny"
+            interface = "i2c"
+            max_transfer_size = 0
+            "#,
+        )
+        .unwrap();
+
+        let results = manifest.run(Path::new("."), false).await.unwrap();
+
+        assert_eq!(results[0].total_tests, 1);
+        assert_eq!(exit_code(&results), 1);
+    }
+
+    #[tokio::test]
+    async fn test_inline_criteria() {
+        let manifest = SuiteManifest::from_toml(
+            r#"
+            suites = []
+
+            [[target]]
+            name = "console"
+            interface = "uart"
+
+            [[criteria]]
+            name = "clean_bus"
+            expression = "error_count == 0 and warning_count <= 2"
+            "#,
+        )
+        .unwrap();
+
+        let results = manifest.run(Path::new("."), false).await.unwrap();
+
+        assert_eq!(results[0].results[0].name, "criteria::clean_bus");
+        assert_eq!(exit_code(&results), 0);
+    }
+
+    #[test]
+    fn test_actionable_errors() {
+        let unknown_suite = SuiteManifest::from_toml("suites = [\"memtest\"]\n[[target]]\nname = \"a\"\ninterface = \"uart\"");
+        assert_eq!(
+            unknown_suite.unwrap_err().to_string(),
+            "unknown suite 'memtest'; available suites: lifecycle, conformance"
+        );
+
+        let typo = SuiteManifest::from_toml("suites = []\n[[target]]\nname = \"a\"\ninterface = \"uart\"\nbaudrate = 9600");
+        let message = typo.unwrap_err().to_string();
+        assert!(message.starts_with("invalid manifest:"));
+        assert!(message.contains("baudrate"));
+
+        let no_targets = SuiteManifest::from_toml("suites = [\"lifecycle\"]");
+        assert!(matches!(no_targets, Err(ManifestError::NoTargets)));
+
+        let criteria = SuiteManifest::from_toml(
+            "suites = []\n[[target]]\nname = \"a\"\ninterface = \"uart\"\n\
+             [[criteria]]\nname = \"bus\"\nexpression = \"error_count == 0 and warnings < 2\"",
+        );
+        assert_eq!(
+            criteria.unwrap_err().to_string(),
+            "invalid criteria 'bus': column 22: unknown variable 'warnings' \
+             (available variables: error_count, warning_count)"
+        );
+
+        let missing = SuiteManifest::load(Path::new("/nonexistent/bench.toml"));
+        assert!(missing.unwrap_err().to_string().starts_with("cannot read manifest /nonexistent/bench.toml"));
+    }
+
+    #[tokio::test]
+    async fn test_unvalidated_manifest_errors() {
+        let base = SuiteManifest::from_toml("suites = []\n[[target]]\nname = \"a\"\ninterface = \"uart\"").unwrap();
+
+        let mut unknown_suite = base.clone();
+        unknown_suite.suites.push("memtest".to_string());
+        let error = unknown_suite.run(Path::new("."), false).await.unwrap_err();
+        assert!(matches!(
+            &error,
+            ManifestError::Target { name, source: HardwareError::ConfigurationError(_) } if name == "a"
+        ));
+        assert!(error.to_string().contains("unknown suite 'memtest'"));
+
+        let mut criteria = base;
+        criteria.criteria.push(CriteriaSpec {
+            name: "bus".to_string(),
+            expression: "warnings < 2".to_string(),
+        });
+        let error = criteria.run(Path::new("."), false).await.unwrap_err();
+        assert!(error.to_string().contains("invalid criteria 'bus'"));
+    }
+
+    #[tokio::test]
+    async fn test_report_names_sanitized() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = SuiteManifest::from_toml(
+            "suites = []\n[[target]]\nname = \"../bench/eeprom\"\ninterface = \"uart\"\n[reports]\ndirectory = \"reports\"",
+        )
+        .unwrap();
+
+        manifest.run(dir.path(), false).await.unwrap();
+
+        assert!(dir.path().join("reports/___bench_eeprom.json").exists());
+        assert!(!dir.path().join("bench").exists());
+
+        let clash = SuiteManifest::from_toml(
+            "suites = []\n[[target]]\nname = \"a/b\"\ninterface = \"uart\"\n[[target]]\nname = \"a_b\"\ninterface = \"uart\"",
+        );
+        assert_eq!(
+            clash.unwrap_err().to_string(),
+            "targets 'a/b' and 'a_b' would write the same report file 'a_b'; rename one of them"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Manual Test Steps Carried Out by an Operator
+ * Copyright (C) 2024
+ */
+
+use crate::{TestResult, TestStatus};
+use std::io::{self, Write};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::time::{timeout_at, Instant};
+
+/// Skip message of manual steps run without an operator
+pub const REQUIRES_OPERATOR: &str = "requires operator";
+
+/// Prefix of the notes an operator attached to a test result
+const OPERATOR_NOTE: &str = "operator: ";
+
+/// Acceptance step that cannot be automated, e.g. "verify LED D3 is blinking"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManualStep {
+    pub instruction: String,
+    pub timeout: Option<Duration>,
+}
+
+impl ManualStep {
+    pub fn new(instruction: &str) -> Self {
+        Self {
+            instruction: instruction.to_string(),
+            timeout: None,
+        }
+    }
+
+    /// Skip the step if the operator has not answered within `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// Outcome of a manual step as recorded on its test result
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ManualRecord {
+    pub status: TestStatus,
+    pub note: Option<String>,
+}
+
+impl ManualRecord {
+    pub fn skipped(reason: &str) -> Self {
+        Self {
+            status: TestStatus::Skipped(reason.to_string()),
+            note: None,
+        }
+    }
+
+    /// Put this outcome on the result of the test that asked for it
+    pub fn apply(self, result: &mut TestResult) {
+        result.manual = true;
+        result.status = self.status;
+        if let Some(note) = self.note {
+            result.notes.push(format!("{}{}", OPERATOR_NOTE, note));
+        }
+    }
+}
+
+impl TestResult {
+    /// Notes the operator gave for a manual step
+    pub fn operator_notes(&self) -> impl Iterator<Item = &str> {
+        self.notes.iter().filter_map(|note| note.strip_prefix(OPERATOR_NOTE))
+    }
+}
+
+/// Parse `pass`, `fail` or `skip` (or their first letter), optionally
+/// followed by a note
+fn parse_answer(line: &str) -> Option<ManualRecord> {
+    let line = line.trim();
+    let (verdict, note) = match line.split_once(char::is_whitespace) {
+        Some((verdict, note)) => (verdict, Some(note.trim().to_string())),
+        None => (line, None),
+    };
+    let status = match verdict.to_lowercase().as_str() {
+        "p" | "pass" => TestStatus::Passed,
+        "f" | "fail" => TestStatus::Failed("operator reported failure".to_string()),
+        "s" | "skip" => TestStatus::Skipped("skipped by operator".to_string()),
+        _ => return None,
+    };
+    Some(ManualRecord { status, note: note.filter(|n| !n.is_empty()) })
+}
+
+/// Operator console asking for the outcome of manual steps
+pub struct OperatorPrompt {
+    input: Box<dyn AsyncBufRead + Unpin + Send>,
+    output: Box<dyn Write + Send>,
+}
+
+impl OperatorPrompt {
+    pub fn new(input: Box<dyn AsyncBufRead + Unpin + Send>, output: Box<dyn Write + Send>) -> Self {
+        Self { input, output }
+    }
+
+    /// Prompt on stdout, answers from stdin
+    pub fn stdio() -> Self {
+        Self::new(Box::new(BufReader::new(tokio::io::stdin())), Box::new(io::stdout()))
+    }
+
+    fn show(&mut self, step: &ManualStep) -> io::Result<()> {
+        writeln!(self.output, "\nMANUAL STEP: {}", step.instruction)?;
+        if let Some(timeout) = step.timeout {
+            writeln!(self.output, "(skipped if unanswered within {:?})", timeout)?;
+        }
+        write!(self.output, "[p]ass / [f]ail / [s]kip, optionally followed by a note: ")?;
+        self.output.flush()
+    }
+
+    /// Show the instruction and wait for a valid answer; unrecognized
+    /// answers are asked again
+    pub(crate) async fn ask(&mut self, step: &ManualStep) -> io::Result<ManualRecord> {
+        let deadline = step.timeout.map(|t| Instant::now() + t);
+        self.show(step)?;
+        loop {
+            let mut line = String::new();
+            let read = self.input.read_line(&mut line);
+            let count = match deadline {
+                Some(deadline) => match timeout_at(deadline, read).await {
+                    Ok(count) => count?,
+                    Err(_) => {
+                        writeln!(self.output, "\nno answer, skipping")?;
+                        let timeout = step.timeout.unwrap_or_default();
+                        return Ok(ManualRecord::skipped(&format!("no operator response within {:?}", timeout)));
+                    }
+                },
+                None => read.await?,
+            };
+            if count == 0 {
+                return Ok(ManualRecord::skipped("operator input closed"));
+            }
+            match parse_answer(&line) {
+                Some(record) => return Ok(record),
+                None => {
+                    write!(self.output, "unrecognized answer {:?}, expected pass, fail or skip: ", line.trim())?;
+                    self.output.flush()?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{AdviceRegistry, ConsoleReporter, TestFn, TestRunner};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn prompt(input: &'static [u8], output: &SharedBuffer) -> OperatorPrompt {
+        OperatorPrompt::new(Box::new(input), Box::new(output.clone()))
+    }
+
+    fn automated() -> TestFn<MockHardwareInterface> {
+        Box::new(|_| Box::pin(async { Ok(()) }))
+    }
+
+    fn runner() -> TestRunner<MockHardwareInterface> {
+        TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+    }
+
+    #[tokio::test]
+    async fn test_pass_and_fail_with_note() {
+        let output = SharedBuffer::default();
+        let runner = runner().with_operator(prompt(b"maybe\np\nfail LED D3 stays off\n", &output));
+
+        let tests = vec![
+            ("init", automated()),
+            ("led_blinking", runner.manual_step(ManualStep::new("Verify LED D3 is blinking"))),
+            ("burn_wire", runner.manual_step(ManualStep::new("Confirm the burn wire is disconnected"))),
+        ];
+        let suite = runner.run_test_suite("acceptance", tests).await;
+
+        assert_eq!(suite.passed_tests, 2);
+        assert!(!suite.results[0].manual);
+        assert!(suite.results[1].manual);
+        assert_eq!(suite.results[2].status, TestStatus::Failed("operator reported failure".to_string()));
+        assert_eq!(suite.results[2].notes, vec!["operator: LED D3 stays off".to_string()]);
+        let shown = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(shown.starts_with("\nMANUAL STEP: Verify LED D3 is blinking\n"));
+        assert!(shown.contains("unrecognized answer \"maybe\""));
+
+        let md = suite.to_markdown(&AdviceRegistry::new());
+        assert!(md.contains("| led_blinking | passed (manual) |"));
+        assert!(md.contains("## Manual steps\n\n- led_blinking: passed\n- burn_wire: failed\n  - operator: LED D3 stays off\n"));
+        assert_eq!(suite.to_json()["results"][2]["manual"], true);
+        let table = ConsoleReporter::new(Box::new(io::sink()), false, false).render_table(&suite);
+        assert!(table.contains("[manual] operator reported failure operator: LED D3 stays off\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_skips() {
+        let output = SharedBuffer::default();
+        let (_writer, reader) = tokio::io::duplex(64);
+        let runner = runner().with_operator(OperatorPrompt::new(Box::new(BufReader::new(reader)), Box::new(output)));
+        let step = ManualStep::new("Verify LED D3 is blinking").with_timeout(Duration::from_secs(30));
+
+        let suite = runner.run_test_suite("acceptance", vec![("led", runner.manual_step(step))]).await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Skipped("no operator response within 30s".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_non_interactive_skip() {
+        let runner = runner();
+
+        let suite = runner.run_test_suite("acceptance", vec![("led", runner.manual_step(ManualStep::new("Verify LED")))]).await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Skipped(REQUIRES_OPERATOR.to_string()));
+        assert!(suite.results[0].manual);
+        assert_eq!(suite.skipped_tests, 1);
+    }
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(parse_answer("PASS\n").unwrap().status, TestStatus::Passed);
+        assert_eq!(parse_answer("s  not fitted ").unwrap().note.as_deref(), Some("not fitted"));
+        assert!(parse_answer("\n").is_none());
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Measurements with Propagated Uncertainty
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareResult};
+use std::fmt;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Measured value with its standard uncertainty (one sigma)
+///
+/// Arithmetic treats operands as independent and propagates uncertainty to
+/// first order, summing contributions in quadrature. Combining a measurement
+/// with itself therefore overstates the uncertainty of the result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub value: f64,
+    pub uncertainty: f64,
+}
+
+impl Measurement {
+    pub fn new(value: f64, uncertainty: f64) -> Self {
+        Self { value, uncertainty: uncertainty.abs() }
+    }
+
+    /// Nominal value without uncertainty, e.g. a datasheet setpoint
+    pub fn exact(value: f64) -> Self {
+        Self::new(value, 0.0)
+    }
+
+    /// Value with a symmetric tolerance band treated as uniformly
+    /// distributed, e.g. a DUT rail specified as 3.3 V ±5 %
+    pub fn with_tolerance(value: f64, half_width: f64) -> Self {
+        Self::new(value, half_width / 3f64.sqrt())
+    }
+
+    /// Convert a raw ADC reading using the channel's calibration
+    pub fn from_adc(counts: i64, calibration: &ChannelCalibration) -> Self {
+        let value = counts as f64 * calibration.scale + calibration.offset;
+        let gain = value * calibration.gain_error;
+        // Rounding to the nearest count is uniform over one LSB
+        let quantization = calibration.scale / 12f64.sqrt();
+        Self::new(value, quadrature(&[gain, calibration.offset_error, quantization]))
+    }
+
+    /// Distance between two measurements in units of their combined
+    /// uncertainty; infinite if they differ but neither has uncertainty
+    pub fn sigma_distance(&self, other: &Measurement) -> f64 {
+        let difference = (self.value - other.value).abs();
+        let combined = quadrature(&[self.uncertainty, other.uncertainty]);
+        if difference == 0.0 {
+            0.0
+        } else {
+            difference / combined
+        }
+    }
+}
+
+fn quadrature(terms: &[f64]) -> f64 {
+    terms.iter().map(|t| t * t).sum::<f64>().sqrt()
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*} ± {:.*}", precision, self.value, precision, self.uncertainty),
+            None => write!(f, "{} ± {}", self.value, self.uncertainty),
+        }
+    }
+}
+
+impl Add for Measurement {
+    type Output = Measurement;
+
+    fn add(self, rhs: Measurement) -> Measurement {
+        Measurement::new(self.value + rhs.value, quadrature(&[self.uncertainty, rhs.uncertainty]))
+    }
+}
+
+impl Sub for Measurement {
+    type Output = Measurement;
+
+    fn sub(self, rhs: Measurement) -> Measurement {
+        Measurement::new(self.value - rhs.value, quadrature(&[self.uncertainty, rhs.uncertainty]))
+    }
+}
+
+impl Mul for Measurement {
+    type Output = Measurement;
+
+    fn mul(self, rhs: Measurement) -> Measurement {
+        Measurement::new(
+            self.value * rhs.value,
+            quadrature(&[rhs.value * self.uncertainty, self.value * rhs.uncertainty]),
+        )
+    }
+}
+
+impl Div for Measurement {
+    type Output = Measurement;
+
+    fn div(self, rhs: Measurement) -> Measurement {
+        Measurement::new(
+            self.value / rhs.value,
+            quadrature(&[
+                self.uncertainty / rhs.value,
+                self.value * rhs.uncertainty / (rhs.value * rhs.value),
+            ]),
+        )
+    }
+}
+
+impl Mul<f64> for Measurement {
+    type Output = Measurement;
+
+    fn mul(self, rhs: f64) -> Measurement {
+        Measurement::new(self.value * rhs, self.uncertainty * rhs)
+    }
+}
+
+impl Div<f64> for Measurement {
+    type Output = Measurement;
+
+    fn div(self, rhs: f64) -> Measurement {
+        Measurement::new(self.value / rhs, self.uncertainty / rhs)
+    }
+}
+
+/// Calibration of one ADC channel and its stated accuracy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelCalibration {
+    /// Units per count
+    pub scale: f64,
+    pub offset: f64,
+    /// Relative gain uncertainty, e.g. 0.001 for 0.1 %
+    pub gain_error: f64,
+    /// Absolute offset uncertainty in units
+    pub offset_error: f64,
+}
+
+impl ChannelCalibration {
+    pub fn new(scale: f64, offset: f64) -> Self {
+        Self {
+            scale,
+            offset,
+            gain_error: 0.0,
+            offset_error: 0.0,
+        }
+    }
+
+    pub fn with_accuracy(mut self, gain_error: f64, offset_error: f64) -> Self {
+        self.gain_error = gain_error;
+        self.offset_error = offset_error;
+        self
+    }
+}
+
+/// Check that two measurements agree within `k_sigma` combined standard
+/// uncertainties. Measurements exactly `k_sigma` apart are consistent.
+pub fn assert_consistent(a: Measurement, b: Measurement, k_sigma: f64) -> HardwareResult<()> {
+    let distance = a.sigma_distance(&b);
+    if distance <= k_sigma {
+        return Ok(());
+    }
+    Err(HardwareError::OperationFailed(format!(
+        "inconsistent measurements: {} vs {}, combined uncertainty {}, {:.2} sigma apart (limit {})",
+        a,
+        b,
+        quadrature(&[a.uncertainty, b.uncertainty]),
+        distance,
+        k_sigma
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_propagation() {
+        let a = Measurement::new(3.0, 0.3);
+        let b = Measurement::new(4.0, 0.4);
+
+        let sum = a + b;
+        assert!(close(sum.value, 7.0) && close(sum.uncertainty, 0.5));
+        let difference = a - b;
+        assert!(close(difference.value, -1.0) && close(difference.uncertainty, 0.5));
+        // Relative uncertainties of 10 % each add to 10 * sqrt(2) %
+        let product = a * b;
+        assert!(close(product.value, 12.0) && close(product.uncertainty, 1.2 * 2f64.sqrt()));
+        let quotient = a / b;
+        assert!(close(quotient.value, 0.75) && close(quotient.uncertainty, 0.075 * 2f64.sqrt()));
+        let scaled = a * -2.0;
+        assert!(close(scaled.value, -6.0) && close(scaled.uncertainty, 0.6));
+    }
+
+    #[test]
+    fn test_from_adc() {
+        let calibration = ChannelCalibration::new(0.001, 0.0).with_accuracy(0.001, 0.0);
+
+        let reading = Measurement::from_adc(3300, &calibration);
+
+        assert!(close(reading.value, 3.3));
+        let expected = (0.0033f64.powi(2) + 0.001f64.powi(2) / 12.0).sqrt();
+        assert!(close(reading.uncertainty, expected));
+    }
+
+    #[test]
+    fn test_consistency_boundaries() {
+        let exact = Measurement::exact(3.3);
+        assert!(assert_consistent(exact, exact, 0.0).is_ok());
+        assert!(assert_consistent(exact, Measurement::exact(3.31), 100.0).is_err());
+
+        // 2.5 apart with combined uncertainty 1.25: exactly two sigma
+        let a = Measurement::new(1.0, 0.75);
+        let b = Measurement::new(3.5, 1.0);
+        assert_eq!(a.sigma_distance(&b), 2.0);
+        assert!(assert_consistent(a, b, 2.0).is_ok());
+        match assert_consistent(a, b, 1.9) {
+            Err(HardwareError::OperationFailed(message)) => assert_eq!(
+                message,
+                "inconsistent measurements: 1 ± 0.75 vs 3.5 ± 1, combined uncertainty 1.25, 2.00 sigma apart (limit 1.9)"
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
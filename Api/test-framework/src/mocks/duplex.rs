@@ -0,0 +1,144 @@
+/*
+ * Fake Duplex Cable Between Two In-Memory Ports
+ * Copyright (C) 2024
+ */
+
+use super::loopback::deliver;
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, WriteFault};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+/// One end of a cable crossing TX to RX between two ports, like UART A
+/// wired to UART B. Faults hit writes from this end, keyed by write index.
+/// Reads with nothing pending return `Ok(0)`.
+pub struct FakeDuplex {
+    rx: Arc<StdMutex<VecDeque<u8>>>,
+    tx: Arc<StdMutex<VecDeque<u8>>>,
+    faults: BTreeMap<usize, WriteFault>,
+    held: Option<Vec<u8>>,
+    writes: usize,
+    initialized: bool,
+}
+
+impl FakeDuplex {
+    /// Both ends of a fresh cable
+    pub fn pair() -> (Self, Self) {
+        let a_to_b = Arc::new(StdMutex::new(VecDeque::new()));
+        let b_to_a = Arc::new(StdMutex::new(VecDeque::new()));
+        (Self::end(b_to_a.clone(), a_to_b.clone()), Self::end(a_to_b, b_to_a))
+    }
+
+    fn end(rx: Arc<StdMutex<VecDeque<u8>>>, tx: Arc<StdMutex<VecDeque<u8>>>) -> Self {
+        Self {
+            rx,
+            tx,
+            faults: BTreeMap::new(),
+            held: None,
+            writes: 0,
+            initialized: false,
+        }
+    }
+
+    pub fn with_write_fault(mut self, write_index: usize, fault: WriteFault) -> Self {
+        self.faults.insert(write_index, fault);
+        self
+    }
+
+    /// Bytes sent to this end but not yet read
+    pub fn pending(&self) -> usize {
+        self.rx.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for FakeDuplex {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Readable for FakeDuplex {
+    async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        let mut rx = self.rx.lock().unwrap();
+        let count = buffer.len().min(rx.len());
+        for (slot, byte) in buffer.iter_mut().zip(rx.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        if self.pending() < buffer.len() {
+            return Err(HardwareError::TimeoutError);
+        }
+        self.read(buffer, timeout).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Writable for FakeDuplex {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        let index = self.writes;
+        self.writes += 1;
+        deliver(self.faults.get(&index), data, &mut self.tx.lock().unwrap(), &mut self.held);
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pair_crosses_over() {
+        let (a, b) = FakeDuplex::pair();
+        let mut a = a.with_write_fault(1, WriteFault::Drop);
+        let mut b = b;
+        a.initialize().await.unwrap();
+        b.initialize().await.unwrap();
+
+        a.write_all(&[1, 2]).await.unwrap();
+        a.write_all(&[3]).await.unwrap();
+        b.write_all(&[9]).await.unwrap();
+
+        let mut buffer = [0u8; 4];
+        assert_eq!(b.read(&mut buffer, Duration::ZERO).await.unwrap(), 2);
+        assert_eq!(&buffer[..2], &[1, 2]);
+        assert_eq!(a.pending(), 1);
+        assert_eq!(a.read(&mut buffer, Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(buffer[0], 9);
+        assert_eq!(a.read(&mut buffer, Duration::ZERO).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Mock GPIO Line
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    DigitalRead, DigitalWrite, Edge, GPIODirection, HardwareError, HardwareInterface, HardwareResult, InterfaceStatus,
+    Level,
+};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Edge wait seen by `MockGPIOInterface`, with the edge it returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeWait {
+    pub edge: Edge,
+    pub timeout: Duration,
+    /// `None` if the wait timed out
+    pub occurred: Option<Edge>,
+}
+
+struct LineState {
+    direction: GPIODirection,
+    initialized: bool,
+    level: Level,
+    /// Scripted changes as (offset from `initialize`, level)
+    script: Vec<(Duration, Level)>,
+    start: Option<Instant>,
+    written: Vec<Level>,
+    waits: Vec<EdgeWait>,
+}
+
+impl LineState {
+    /// Level at `offset` after `initialize`
+    fn level_at(&self, offset: Duration) -> Level {
+        self.script
+            .iter()
+            .take_while(|(at, _)| *at <= offset)
+            .fold(self.level, |_, (_, level)| *level)
+    }
+
+    fn offset(&self) -> Duration {
+        self.start.map(|start| start.elapsed()).unwrap_or_default()
+    }
+}
+
+/// GPIO line playing a scripted level sequence on tokio time, recording
+/// levels written and edge waits
+///
+/// Scripted changes are timed from `initialize`, so tests run with paused
+/// time see them at exact instants. Clones share their state, so a test
+/// can keep a handle while the runner owns the line.
+#[derive(Clone)]
+pub struct MockGPIOInterface {
+    state: Arc<Mutex<LineState>>,
+}
+
+impl MockGPIOInterface {
+    pub fn new(direction: GPIODirection, initial: Level) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LineState {
+                direction,
+                initialized: false,
+                level: initial,
+                script: Vec::new(),
+                start: None,
+                written: Vec::new(),
+                waits: Vec::new(),
+            })),
+        }
+    }
+
+    /// Input line starting at `initial`
+    pub fn input(initial: Level) -> Self {
+        Self::new(GPIODirection::Input, initial)
+    }
+
+    /// Output line starting low
+    pub fn output() -> Self {
+        Self::new(GPIODirection::Output, Level::Low)
+    }
+
+    /// Change the line to `level` `after` the previous scripted change
+    pub fn with_change(self, after: Duration, level: Level) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            let last = state.script.last().map(|(at, _)| *at).unwrap_or_default();
+            state.script.push((last + after, level));
+        }
+        self
+    }
+
+    /// Levels set through `DigitalWrite`, in order
+    pub fn levels_written(&self) -> Vec<Level> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    pub fn edge_waits(&self) -> Vec<EdgeWait> {
+        self.state.lock().unwrap().waits.clone()
+    }
+
+    fn check_initialized(state: &LineState) -> HardwareResult<()> {
+        if !state.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for MockGPIOInterface {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.initialized {
+            state.initialized = true;
+            state.start = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.state.lock().unwrap().initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.state.lock().unwrap().initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.state.lock().unwrap().initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl DigitalRead for MockGPIOInterface {
+    async fn read_level(&mut self) -> HardwareResult<Level> {
+        let state = self.state.lock().unwrap();
+        Self::check_initialized(&state)?;
+        Ok(state.level_at(state.offset()))
+    }
+
+    async fn wait_for_edge(&mut self, edge: Edge, timeout: Duration) -> HardwareResult<Edge> {
+        let next = {
+            let state = self.state.lock().unwrap();
+            Self::check_initialized(&state)?;
+            if state.direction != GPIODirection::Input {
+                return Err(HardwareError::InvalidParameter("edge events need an input line".to_string()));
+            }
+            let now = state.offset();
+            let mut level = state.level_at(now);
+            let mut next = None;
+            for (at, to) in state.script.iter().filter(|(at, _)| *at > now) {
+                if *at - now > timeout {
+                    break;
+                }
+                match Edge::between(level, *to) {
+                    Some(occurred) if edge.matches(occurred) => {
+                        next = Some((*at - now, occurred));
+                        break;
+                    }
+                    _ => level = *to,
+                }
+            }
+            next
+        };
+
+        tokio::time::sleep(next.map(|(wait, _)| wait).unwrap_or(timeout)).await;
+        let occurred = next.map(|(_, occurred)| occurred);
+        self.state.lock().unwrap().waits.push(EdgeWait { edge, timeout, occurred });
+        occurred.ok_or(HardwareError::TimeoutError)
+    }
+}
+
+#[async_trait]
+impl DigitalWrite for MockGPIOInterface {
+    async fn set_level(&mut self, level: Level) -> HardwareResult<()> {
+        let mut state = self.state.lock().unwrap();
+        Self::check_initialized(&state)?;
+        if state.direction != GPIODirection::Output {
+            return Err(HardwareError::InvalidParameter("cannot drive an input line".to_string()));
+        }
+        state.level = level;
+        state.written.push(level);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Interrupt pin pulsing high twice
+    fn irq() -> MockGPIOInterface {
+        MockGPIOInterface::input(Level::Low)
+            .with_change(ms(10), Level::High)
+            .with_change(ms(5), Level::Low)
+            .with_change(ms(20), Level::High)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scripted_levels() {
+        let mut pin = irq();
+        pin.initialize().await.unwrap();
+
+        let mut levels = Vec::new();
+        for _ in 0..4 {
+            levels.push(pin.read_level().await.unwrap());
+            tokio::time::sleep(ms(10)).await;
+        }
+        assert_eq!(levels, vec![Level::Low, Level::High, Level::Low, Level::Low]);
+        assert_eq!(pin.read_level().await, Ok(Level::High));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_edge_waits() {
+        let mut pin = irq();
+        let handle = pin.clone();
+        pin.initialize().await.unwrap();
+        let start = Instant::now();
+
+        assert_eq!(pin.wait_for_edge(Edge::Falling, ms(100)).await, Ok(Edge::Falling));
+        assert_eq!(start.elapsed(), ms(15));
+        assert_eq!(pin.wait_for_edge(Edge::Rising, ms(5)).await, Err(HardwareError::TimeoutError));
+        assert_eq!(start.elapsed(), ms(20));
+        assert_eq!(pin.wait_for_edge(Edge::Both, ms(100)).await, Ok(Edge::Rising));
+        assert_eq!(start.elapsed(), ms(35));
+
+        assert_eq!(
+            handle.edge_waits(),
+            vec![
+                EdgeWait { edge: Edge::Falling, timeout: ms(100), occurred: Some(Edge::Falling) },
+                EdgeWait { edge: Edge::Rising, timeout: ms(5), occurred: None },
+                EdgeWait { edge: Edge::Both, timeout: ms(100), occurred: Some(Edge::Rising) },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_and_errors() {
+        let mut reset = MockGPIOInterface::output();
+        assert_eq!(reset.set_level(Level::High).await, Err(HardwareError::NotInitialized));
+        assert_eq!(reset.read_level().await, Err(HardwareError::NotInitialized));
+        reset.initialize().await.unwrap();
+
+        reset.set_level(Level::High).await.unwrap();
+        reset.set_level(Level::Low).await.unwrap();
+        assert_eq!(reset.read_level().await, Ok(Level::Low));
+        assert_eq!(reset.levels_written(), vec![Level::High, Level::Low]);
+        assert!(matches!(
+            reset.wait_for_edge(Edge::Rising, ms(1)).await,
+            Err(HardwareError::InvalidParameter(_))
+        ));
+
+        let mut pin = irq();
+        pin.initialize().await.unwrap();
+        assert!(matches!(pin.set_level(Level::High).await, Err(HardwareError::InvalidParameter(_))));
+    }
+}
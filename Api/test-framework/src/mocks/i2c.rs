@@ -3,10 +3,12 @@
  * Copyright (C) 2024
  */
 
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, Bidirectional};
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, Bidirectional};
+use crate::{check_buffer_size, check_transfer_buffers, I2C_DEFAULT_MAX_TRANSFER};
 use crate::interfaces::i2c::I2CConfig;
 use async_trait::async_trait;
 use mockall::mock;
+use std::collections::VecDeque;
 use std::time::Duration;
 
 mock! {
@@ -61,10 +63,195 @@ impl MockI2CInterface {
                 last_error: None,
                 uptime: Duration::from_secs(0),
             }));
+        // Enforce the same buffer size policy as I2CInterface
+        mock.expect_read()
+            .returning(|buffer, _| {
+                check_buffer_size(buffer.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+                Ok(buffer.len())
+            });
+        mock.expect_read_exact()
+            .returning(|buffer, _| check_buffer_size(buffer.len(), Some(I2C_DEFAULT_MAX_TRANSFER)));
+        mock.expect_write()
+            .returning(|data| {
+                check_buffer_size(data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+                Ok(data.len())
+            });
+        mock.expect_write_all()
+            .returning(|data| check_buffer_size(data.len(), Some(I2C_DEFAULT_MAX_TRANSFER)));
+        mock.expect_transfer()
+            .returning(|tx_data, rx_data, _| {
+                check_transfer_buffers(tx_data.len(), rx_data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+                Ok(rx_data.len())
+            });
         mock
     }
 }
 
+/// Queued bus exchange: the bytes the device expects, then its reply
+#[derive(Debug, Clone)]
+struct Exchange {
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    /// `tx` arrived as a plain write; `rx` waits for the following read
+    written: bool,
+}
+
+/// I2C device replaying a queued conversation, for multi-step protocols
+/// that would need an expectation per call on `MockI2CInterface`
+///
+/// Each exchange is consumed either by one `transfer` or by a `write` of
+/// its tx bytes followed by a `read` of its rx bytes, so a register read
+/// scripted as `push_exchange(&[0x01], &[0xAA, 0xBB])` serves both styles.
+/// Calls that don't match the front of the queue fail with
+/// `CommunicationError` and leave the exchange queued.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedI2C {
+    exchanges: VecDeque<Exchange>,
+    initialized: bool,
+}
+
+impl ScriptedI2C {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the device expecting `tx` and answering with `rx`
+    pub fn push_exchange(&mut self, tx: &[u8], rx: &[u8]) {
+        self.exchanges.push_back(Exchange {
+            tx: tx.to_vec(),
+            rx: rx.to_vec(),
+            written: false,
+        });
+    }
+
+    pub fn with_exchange(mut self, tx: &[u8], rx: &[u8]) -> Self {
+        self.push_exchange(tx, rx);
+        self
+    }
+
+    /// Exchanges not yet consumed, including one whose read is still due
+    pub fn remaining_exchanges(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    fn front(&mut self, call: &str) -> HardwareResult<&mut Exchange> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        self.exchanges.front_mut().ok_or_else(|| {
+            HardwareError::CommunicationError(format!("unexpected {}: script exhausted", call))
+        })
+    }
+
+    fn check_tx(exchange: &Exchange, data: &[u8]) -> HardwareResult<()> {
+        if exchange.written {
+            return Err(HardwareError::CommunicationError(format!(
+                "expected read of {} bytes, got write of {:02X?}",
+                exchange.rx.len(),
+                data
+            )));
+        }
+        if exchange.tx != data {
+            return Err(HardwareError::CommunicationError(format!(
+                "expected tx {:02X?}, got {:02X?}",
+                exchange.tx, data
+            )));
+        }
+        Ok(())
+    }
+
+    fn reply(&mut self, buffer: &mut [u8]) -> HardwareResult<usize> {
+        check_buffer_size(buffer.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        let exchange = self.front("read")?;
+        if !exchange.written && !exchange.tx.is_empty() {
+            return Err(HardwareError::CommunicationError(format!(
+                "expected tx {:02X?} before reading",
+                exchange.tx
+            )));
+        }
+        let count = buffer.len().min(exchange.rx.len());
+        buffer[..count].copy_from_slice(&exchange.rx[..count]);
+        self.exchanges.pop_front();
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for ScriptedI2C {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Readable for ScriptedI2C {
+    async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        self.reply(buffer)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<()> {
+        let expected = self.front("read")?.rx.len();
+        if buffer.len() != expected {
+            return Err(HardwareError::CommunicationError(format!(
+                "expected read of {} bytes, got {}",
+                expected,
+                buffer.len()
+            )));
+        }
+        self.reply(buffer).map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Writable for ScriptedI2C {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        check_buffer_size(data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        let exchange = self.front("write")?;
+        Self::check_tx(exchange, data)?;
+        if exchange.rx.is_empty() {
+            self.exchanges.pop_front();
+        } else {
+            exchange.written = true;
+        }
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Bidirectional for ScriptedI2C {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        check_transfer_buffers(tx_data.len(), rx_data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        Self::check_tx(self.front("transfer")?, tx_data)?;
+        let exchange = self.exchanges.pop_front().unwrap();
+        let count = rx_data.len().min(exchange.rx.len());
+        rx_data[..count].copy_from_slice(&exchange.rx[..count]);
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,4 +329,69 @@ mod tests {
             Err(crate::HardwareError::DeviceNotFound)
         ));
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_mock_i2c_default_buffer_policy() {
+        let mut mock = MockI2CInterface::new_with_defaults();
+        let timeout = Duration::from_millis(100);
+        
+        assert_eq!(mock.write(&[]).await.unwrap(), 0);
+        assert!(mock.transfer(&[0x10], &mut [], timeout).await.is_err());
+        assert!(mock.write(&vec![0u8; I2C_DEFAULT_MAX_TRANSFER + 1]).await.is_err());
+        assert_eq!(mock.transfer(&[0x10], &mut [0u8; 2], timeout).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_register_reads() {
+        let mut device = ScriptedI2C::new();
+        device.push_exchange(&[0x01], &[0xAA, 0xBB]);
+        device.push_exchange(&[0x02], &[0xCC]);
+        device.push_exchange(&[0x10, 0x7F], &[]);
+        device.initialize().await.unwrap();
+        let timeout = Duration::from_millis(100);
+
+        let mut rx = [0u8; 2];
+        assert_eq!(device.transfer(&[0x01], &mut rx, timeout).await.unwrap(), 2);
+        assert_eq!(rx, [0xAA, 0xBB]);
+
+        device.write_all(&[0x02]).await.unwrap();
+        assert_eq!(device.remaining_exchanges(), 2);
+        let mut rx = [0u8; 1];
+        device.read_exact(&mut rx, timeout).await.unwrap();
+        assert_eq!(rx, [0xCC]);
+
+        assert_eq!(device.write(&[0x10, 0x7F]).await.unwrap(), 2);
+        assert_eq!(device.remaining_exchanges(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_mismatches() {
+        let mut device = ScriptedI2C::new().with_exchange(&[0x01], &[0xAA, 0xBB]);
+        let timeout = Duration::from_millis(100);
+        let mut rx = [0u8; 2];
+        assert_eq!(device.write(&[0x01]).await, Err(HardwareError::NotInitialized));
+        device.initialize().await.unwrap();
+
+        assert_eq!(
+            device.transfer(&[0x02], &mut rx, timeout).await,
+            Err(HardwareError::CommunicationError("expected tx [01], got [02]".to_string()))
+        );
+        assert_eq!(
+            device.read(&mut rx, timeout).await,
+            Err(HardwareError::CommunicationError("expected tx [01] before reading".to_string()))
+        );
+        device.write(&[0x01]).await.unwrap();
+        assert_eq!(
+            device.write(&[0x01]).await,
+            Err(HardwareError::CommunicationError("expected read of 2 bytes, got write of [01]".to_string()))
+        );
+        assert!(device.read_exact(&mut [0u8; 3], timeout).await.is_err());
+        assert_eq!(device.remaining_exchanges(), 1);
+
+        assert_eq!(device.read(&mut rx, timeout).await.unwrap(), 2);
+        assert_eq!(
+            device.read(&mut rx, timeout).await,
+            Err(HardwareError::CommunicationError("unexpected read: script exhausted".to_string()))
+        );
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * Fake Loopback Link with Fault Injection
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, capabilities, CapabilitySet};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Fault applied to one write passing through a `FakeLoopback`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteFault {
+    /// XOR the byte at `offset` within the write with `mask`
+    FlipBits { offset: usize, mask: u8 },
+    /// Lose the write entirely
+    Drop,
+    /// Deliver the write twice
+    Duplicate,
+    /// Deliver the write after the next one
+    Hold,
+}
+
+/// Queue `data` on `buffer` as a link would with `fault` applied, releasing
+/// any write held back by an earlier `WriteFault::Hold`
+pub(super) fn deliver(fault: Option<&WriteFault>, data: &[u8], buffer: &mut VecDeque<u8>, held: &mut Option<Vec<u8>>) {
+    let mut data = data.to_vec();
+    match fault {
+        Some(WriteFault::FlipBits { offset, mask }) => {
+            if let Some(byte) = data.get_mut(*offset) {
+                *byte ^= mask;
+            }
+            buffer.extend(&data);
+        }
+        Some(WriteFault::Drop) => {}
+        Some(WriteFault::Duplicate) => {
+            buffer.extend(&data);
+            buffer.extend(&data);
+        }
+        Some(WriteFault::Hold) => {
+            *held = Some(data);
+            return;
+        }
+        None => buffer.extend(&data),
+    }
+    if let Some(data) = held.take() {
+        buffer.extend(data);
+    }
+}
+
+/// Loopback cable echoing every write back to the reader, like a UART
+/// with TX wired to RX. Faults are keyed by the index of the write they hit.
+/// Reads of an empty link return `Ok(0)`.
+pub struct FakeLoopback {
+    buffer: VecDeque<u8>,
+    faults: BTreeMap<usize, WriteFault>,
+    held: Option<Vec<u8>>,
+    writes: usize,
+    initialized: bool,
+}
+
+impl FakeLoopback {
+    pub fn new() -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            faults: BTreeMap::new(),
+            held: None,
+            writes: 0,
+            initialized: false,
+        }
+    }
+
+    pub fn with_write_fault(mut self, write_index: usize, fault: WriteFault) -> Self {
+        self.faults.insert(write_index, fault);
+        self
+    }
+
+    /// Bytes written but not yet read
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Default for FakeLoopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for FakeLoopback {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        capabilities![ReadExact, ChunkedIo]
+    }
+}
+
+#[async_trait]
+impl Readable for FakeLoopback {
+    async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        let count = buffer.len().min(self.buffer.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.buffer.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        if self.buffer.len() < buffer.len() {
+            return Err(HardwareError::TimeoutError);
+        }
+        self.read(buffer, timeout).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Writable for FakeLoopback {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        let index = self.writes;
+        self.writes += 1;
+        deliver(self.faults.get(&index), data, &mut self.buffer, &mut self.held);
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_loopback_faults() {
+        let mut link = FakeLoopback::new()
+            .with_write_fault(0, WriteFault::Hold)
+            .with_write_fault(2, WriteFault::FlipBits { offset: 1, mask: 0xFF })
+            .with_write_fault(3, WriteFault::Drop);
+        link.initialize().await.unwrap();
+
+        for data in [[1, 1], [2, 2], [3, 3], [4, 4]] {
+            link.write_all(&data).await.unwrap();
+        }
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(link.read(&mut buffer, Duration::ZERO).await.unwrap(), 6);
+        assert_eq!(&buffer[..6], &[2, 2, 1, 1, 3, 0xFC]);
+        assert_eq!(link.pending(), 0);
+    }
+}
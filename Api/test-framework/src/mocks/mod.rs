@@ -3,13 +3,27 @@
  * Copyright (C) 2024
  */
 
+mod duplex;
+mod gpio;
 mod i2c;
+mod loopback;
 mod uart;
+mod power;
+mod registers;
+mod rs485;
 mod spi;
+mod spi_flash;
 
-pub use i2c::MockI2CInterface;
+pub use duplex::FakeDuplex;
+pub use gpio::{EdgeWait, MockGPIOInterface};
+pub use i2c::{MockI2CInterface, ScriptedI2C};
+pub use loopback::{FakeLoopback, WriteFault};
 pub use uart::MockUARTInterface;
+pub use power::{MockPowerRail, PowerEvent};
+pub use registers::FakeRegisterMap;
+pub use rs485::{BusFrame, FakeBusDevice, Rs485BusSim, Rs485Endpoint};
 pub use spi::MockSPIInterface;
+pub use spi_flash::FakeSpiFlash;
 
 use crate::{HardwareInterface, HardwareResult, InterfaceStatus};
 use async_trait::async_trait;
@@ -0,0 +1,91 @@
+/*
+ * Mock Power Rail
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareResult, PowerRail, RailMeasurement};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+/// Switch event recorded by `MockPowerRail`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerEvent {
+    Enabled,
+    Disabled,
+}
+
+#[derive(Default)]
+struct RailState {
+    enabled: bool,
+    events: Vec<PowerEvent>,
+    measurement: Option<RailMeasurement>,
+}
+
+/// Power rail recording every switch event
+///
+/// Clones share their state, so a test can keep a handle while the runner
+/// owns the rail.
+#[derive(Clone, Default)]
+pub struct MockPowerRail {
+    state: Arc<Mutex<RailState>>,
+}
+
+impl MockPowerRail {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Measurement returned by `measure`
+    pub fn with_measurement(self, measurement: RailMeasurement) -> Self {
+        self.state.lock().unwrap().measurement = Some(measurement);
+        self
+    }
+
+    pub fn events(&self) -> Vec<PowerEvent> {
+        self.state.lock().unwrap().events.clone()
+    }
+}
+
+#[async_trait]
+impl PowerRail for MockPowerRail {
+    async fn enable(&mut self) -> HardwareResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.enabled = true;
+        state.events.push(PowerEvent::Enabled);
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> HardwareResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.enabled = false;
+        state.events.push(PowerEvent::Disabled);
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+
+    async fn measure(&mut self) -> HardwareResult<Option<RailMeasurement>> {
+        Ok(self.state.lock().unwrap().measurement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_power_rail_shares_events() {
+        let measurement = RailMeasurement { volts: 3.3, amps: 0.1 };
+        let mut rail = MockPowerRail::new().with_measurement(measurement);
+        let handle = rail.clone();
+
+        rail.enable().await.unwrap();
+        rail.disable().await.unwrap();
+
+        assert!(!handle.is_enabled());
+        assert_eq!(handle.events(), vec![PowerEvent::Enabled, PowerEvent::Disabled]);
+        assert_eq!(rail.measure().await.unwrap(), Some(measurement));
+    }
+}
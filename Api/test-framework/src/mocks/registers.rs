@@ -0,0 +1,209 @@
+/*
+ * Fake Register-Mapped Device
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    check_buffer_size, check_transfer_buffers, Bidirectional, HardwareError, HardwareInterface, HardwareResult,
+    InterfaceStatus, Readable, RegisterAccess, Writable, I2C_DEFAULT_MAX_TRANSFER, capabilities, CapabilitySet,
+};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Device with 256 byte-wide registers behind an address-first protocol
+///
+/// The first written byte selects the register and any further bytes are
+/// written from there; reads continue from the selected register. The
+/// address auto-increments and wraps like common I2C sensors, and buffer
+/// sizes follow the same policy as `I2CInterface`.
+pub struct FakeRegisterMap {
+    registers: [u8; 256],
+    pointer: u8,
+    initialized: bool,
+    writes: Vec<(u8, Vec<u8>)>,
+}
+
+impl FakeRegisterMap {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 256],
+            pointer: 0,
+            initialized: false,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Preset consecutive registers starting at `address`
+    pub fn with_registers(mut self, address: u8, values: &[u8]) -> Self {
+        for (i, &value) in values.iter().enumerate() {
+            self.registers[(address as usize + i) % 256] = value;
+        }
+        self
+    }
+
+    pub fn registers(&self, address: u8, len: usize) -> &[u8] {
+        &self.registers[address as usize..address as usize + len]
+    }
+
+    /// Start address and data of every register write, in order
+    pub fn writes(&self) -> &[(u8, Vec<u8>)] {
+        &self.writes
+    }
+}
+
+impl Default for FakeRegisterMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for FakeRegisterMap {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        capabilities![ReadExact, Transfer, ChunkedIo]
+    }
+}
+
+impl FakeRegisterMap {
+    fn check_ready(&self) -> HardwareResult<()> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        Ok(())
+    }
+
+    fn select_and_write(&mut self, frame: &[u8]) {
+        let address = frame[0];
+        let data = &frame[1..];
+        for (i, &value) in data.iter().enumerate() {
+            self.registers[(address as usize + i) % 256] = value;
+        }
+        if !data.is_empty() {
+            self.writes.push((address, data.to_vec()));
+        }
+        self.pointer = address;
+    }
+
+    fn read_from_pointer(&mut self, buffer: &mut [u8]) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.registers[(self.pointer as usize + i) % 256];
+        }
+    }
+}
+
+#[async_trait]
+impl Readable for FakeRegisterMap {
+    async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        self.check_ready()?;
+        check_buffer_size(buffer.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        self.read_from_pointer(buffer);
+        Ok(buffer.len())
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        self.read(buffer, timeout).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Writable for FakeRegisterMap {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        self.check_ready()?;
+        check_buffer_size(data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        if !data.is_empty() {
+            self.select_and_write(data);
+        }
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Bidirectional for FakeRegisterMap {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        self.check_ready()?;
+        check_transfer_buffers(tx_data.len(), rx_data.len(), Some(I2C_DEFAULT_MAX_TRANSFER))?;
+        self.select_and_write(tx_data);
+        self.read_from_pointer(rx_data);
+        Ok(rx_data.len())
+    }
+}
+
+/// Direct register access, as `RegisterBus` would do over the bus
+#[async_trait]
+impl RegisterAccess for FakeRegisterMap {
+    async fn read_registers(&mut self, address: u8, buffer: &mut [u8]) -> HardwareResult<()> {
+        self.transfer(&[address], buffer, Duration::ZERO).await.map(|_| ())
+    }
+
+    async fn write_registers(&mut self, address: u8, data: &[u8]) -> HardwareResult<()> {
+        let mut frame = Vec::with_capacity(data.len() + 1);
+        frame.push(address);
+        frame.extend_from_slice(data);
+        self.write_all(&frame).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fake_register_auto_increment() {
+        let mut fake = FakeRegisterMap::new().with_registers(0xFF, &[0xAA, 0xBB]);
+        fake.initialize().await.unwrap();
+
+        let mut rx = [0u8; 2];
+        fake.transfer(&[0xFF], &mut rx, Duration::from_millis(10)).await.unwrap();
+        assert_eq!(rx, [0xAA, 0xBB]);
+
+        fake.write_all(&[0x10, 1, 2, 3]).await.unwrap();
+        assert_eq!(fake.registers(0x10, 3), &[1, 2, 3]);
+        assert_eq!(fake.writes(), &[(0x10, vec![1, 2, 3])]);
+
+        fake.read_exact(&mut rx, Duration::from_millis(10)).await.unwrap();
+        assert_eq!(rx, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_fake_register_buffer_policy() {
+        let mut fake = FakeRegisterMap::new();
+        fake.initialize().await.unwrap();
+
+        assert_eq!(fake.write(&[]).await.unwrap(), 0);
+        assert!(matches!(
+            fake.transfer(&[0x10], &mut [], Duration::from_millis(10)).await,
+            Err(HardwareError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            fake.write(&vec![0; I2C_DEFAULT_MAX_TRANSFER + 1]).await,
+            Err(HardwareError::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,457 @@
+/*
+ * Simulated Multi-Drop RS-485 Bus
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+
+type RequestHandler = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>> + Send>;
+
+/// Device sharing a `Rs485BusSim`. It sees every frame starting with its
+/// address and answers with the handler's reply, if any.
+pub struct FakeBusDevice {
+    address: u8,
+    /// Scripted `(tx, rx)` exchanges, answered before the handler is asked
+    exchanges: VecDeque<(Vec<u8>, Vec<u8>)>,
+    handler: RequestHandler,
+}
+
+impl FakeBusDevice {
+    pub fn new<F>(address: u8, handler: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+    {
+        Self {
+            address,
+            exchanges: VecDeque::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Device answering only scripted exchanges, added with `with_exchange`
+    pub fn scripted(address: u8) -> Self {
+        Self::new(address, |_| None)
+    }
+
+    /// Queue the device expecting the frame `tx` and answering with `rx`,
+    /// as `ScriptedI2C::with_exchange` does. Exchanges are answered in
+    /// order; frames that don't match the front of the queue get no reply
+    /// and leave it queued.
+    pub fn with_exchange(mut self, tx: &[u8], rx: &[u8]) -> Self {
+        self.exchanges.push_back((tx.to_vec(), rx.to_vec()));
+        self
+    }
+
+    fn reply(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        match self.exchanges.front() {
+            Some((tx, _)) if tx.as_slice() == frame => self.exchanges.pop_front().map(|(_, rx)| rx),
+            _ => (self.handler)(frame),
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+}
+
+/// A frame as it appeared on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusFrame {
+    pub bytes: Vec<u8>,
+    /// Two masters drove the bus at once and the frame is the wired-AND
+    /// of both transmissions
+    pub collided: bool,
+}
+
+/// Transmission still on the wire, only tracked with collisions enabled
+struct Transmission {
+    master: usize,
+    bytes: Vec<u8>,
+    end: Instant,
+    collided: bool,
+}
+
+struct BusState {
+    devices: Vec<FakeBusDevice>,
+    receivers: Vec<VecDeque<u8>>,
+    frames: Vec<BusFrame>,
+    on_wire: Option<Transmission>,
+}
+
+impl BusState {
+    /// Route the transmission on the wire, if any, to the addressed device
+    /// and broadcast the reply to every endpoint
+    fn finish(&mut self) {
+        if let Some(transmission) = self.on_wire.take() {
+            self.deliver(transmission.bytes, transmission.collided);
+        }
+    }
+
+    /// `finish` once the transmission's airtime has passed at `now`
+    fn finish_due(&mut self, now: Instant) {
+        if self.on_wire.as_ref().map_or(false, |transmission| transmission.end <= now) {
+            self.finish();
+        }
+    }
+
+    fn deliver(&mut self, bytes: Vec<u8>, collided: bool) {
+        let reply = match bytes.first() {
+            Some(&address) => self
+                .devices
+                .iter_mut()
+                .find(|device| device.address == address)
+                .and_then(|device| device.reply(&bytes)),
+            None => None,
+        };
+        self.frames.push(BusFrame { bytes, collided });
+        if let Some(reply) = reply {
+            for receiver in &mut self.receivers {
+                receiver.extend(&reply);
+            }
+        }
+    }
+}
+
+/// Half-duplex multi-drop bus where several fake devices share one line
+/// and answer only frames addressed to them (first byte). Frames to
+/// unknown addresses get no reply, which masters see as a read timeout.
+///
+/// Every `endpoint()` is a master implementing the same traits as
+/// `UARTInterface`. With `with_collisions`, a frame written while another
+/// master's frame is still on the wire (at the configured baud rate)
+/// corrupts both, as driver contention would.
+#[derive(Clone)]
+pub struct Rs485BusSim {
+    state: Arc<Mutex<BusState>>,
+    /// Woken on every write, so waiting masters see replies to other masters
+    activity: Arc<Notify>,
+    collisions: Option<u32>,
+}
+
+impl Rs485BusSim {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(BusState {
+                devices: Vec::new(),
+                receivers: Vec::new(),
+                frames: Vec::new(),
+                on_wire: None,
+            })),
+            activity: Arc::new(Notify::new()),
+            collisions: None,
+        }
+    }
+
+    /// Model frames as occupying the bus for 10 bit times per byte at
+    /// `baud_rate`, colliding when masters overlap
+    pub fn with_collisions(mut self, baud_rate: u32) -> Self {
+        self.collisions = Some(baud_rate);
+        self
+    }
+
+    pub fn with_device(self, device: FakeBusDevice) -> Self {
+        self.state.lock().unwrap().devices.push(device);
+        self
+    }
+
+    /// New master attached to the bus
+    pub fn endpoint(&self) -> Rs485Endpoint {
+        let mut state = self.state.lock().unwrap();
+        state.receivers.push(VecDeque::new());
+        Rs485Endpoint {
+            bus: self.clone(),
+            index: state.receivers.len() - 1,
+            initialized: false,
+        }
+    }
+
+    /// Every frame seen on the wire so far, including one still in flight,
+    /// without delivering it early
+    pub fn frames(&self) -> Vec<BusFrame> {
+        let state = self.state.lock().unwrap();
+        let mut frames = state.frames.clone();
+        if let Some(transmission) = &state.on_wire {
+            frames.push(BusFrame {
+                bytes: transmission.bytes.clone(),
+                collided: transmission.collided,
+            });
+        }
+        frames
+    }
+
+    fn transmit(&self, master: usize, data: &[u8]) {
+        self.put_on_wire(master, data);
+        self.activity.notify_waiters();
+    }
+
+    fn put_on_wire(&self, master: usize, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let baud_rate = match self.collisions {
+            Some(baud_rate) => baud_rate,
+            None => return state.deliver(data.to_vec(), false),
+        };
+        let now = Instant::now();
+        let airtime = Duration::from_secs_f64(data.len() as f64 * 10.0 / baud_rate as f64);
+        if let Some(on_wire) = state.on_wire.as_mut() {
+            if on_wire.master != master && now < on_wire.end {
+                // Dominant zero bits win where both drivers are active
+                for (byte, &other) in on_wire.bytes.iter_mut().zip(data) {
+                    *byte &= other;
+                }
+                if data.len() > on_wire.bytes.len() {
+                    on_wire.bytes.extend(&data[on_wire.bytes.len()..]);
+                }
+                on_wire.end = on_wire.end.max(now + airtime);
+                on_wire.collided = true;
+                return;
+            }
+        }
+        state.finish();
+        state.on_wire = Some(Transmission {
+            master,
+            bytes: data.to_vec(),
+            end: now + airtime,
+            collided: false,
+        });
+    }
+}
+
+impl Default for Rs485BusSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Master's view of a `Rs485BusSim`
+pub struct Rs485Endpoint {
+    bus: Rs485BusSim,
+    index: usize,
+    initialized: bool,
+}
+
+impl Rs485Endpoint {
+    /// Take up to `buffer.len()` received bytes once at least `wanted` are
+    /// available, failing after `timeout`. With collisions modelled, a
+    /// frame on the wire is only answered once its airtime has passed.
+    async fn receive(&mut self, buffer: &mut [u8], wanted: usize, timeout: Duration) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Registered before looking, so a write in between still wakes us
+            let activity = self.bus.activity.notified();
+            let in_flight = {
+                let mut state = self.bus.state.lock().unwrap();
+                state.finish_due(Instant::now());
+                let receiver = &mut state.receivers[self.index];
+                if receiver.len() >= wanted.max(1) {
+                    let count = buffer.len().min(receiver.len());
+                    for (slot, byte) in buffer.iter_mut().zip(receiver.drain(..count)) {
+                        *slot = byte;
+                    }
+                    return Ok(count);
+                }
+                state.on_wire.as_ref().map(|transmission| transmission.end)
+            };
+            if Instant::now() >= deadline {
+                return Err(HardwareError::TimeoutError);
+            }
+            let wake = in_flight.map_or(deadline, |end| end.min(deadline));
+            tokio::select! {
+                _ = activity => {}
+                _ = sleep_until(wake) => {}
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for Rs485Endpoint {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Readable for Rs485Endpoint {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        self.receive(buffer, 1, timeout).await
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        let wanted = buffer.len();
+        self.receive(buffer, wanted, timeout).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Writable for Rs485Endpoint {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+        self.bus.transmit(self.index, data);
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc16_ccitt;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn frame(address: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![address];
+        frame.extend_from_slice(payload);
+        let crc = crc16_ccitt(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    fn crc_ok(frame: &[u8]) -> bool {
+        frame.len() > 2 && crc16_ccitt(&frame[..frame.len() - 2]).to_be_bytes() == frame[frame.len() - 2..]
+    }
+
+    /// Echoes the payload back with the CRC checked, like a real slave
+    fn echo(address: u8) -> FakeBusDevice {
+        FakeBusDevice::new(address, move |request| {
+            crc_ok(request).then(|| frame(address, &request[1..request.len() - 2]))
+        })
+    }
+
+    async fn master(bus: &Rs485BusSim) -> Rs485Endpoint {
+        let mut endpoint = bus.endpoint();
+        endpoint.initialize().await.unwrap();
+        endpoint
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_routes_by_address() {
+        let scripted = FakeBusDevice::scripted(0x12).with_exchange(&[0x12, 0x01], &[0x12, 0x81, 0x2a]);
+        let bus = Rs485BusSim::new().with_device(echo(0x11)).with_device(scripted);
+        let mut uart = master(&bus).await;
+
+        uart.write(&frame(0x11, b"hi")).await.unwrap();
+        let mut reply = vec![0u8; 5];
+        uart.read_exact(&mut reply, TIMEOUT).await.unwrap();
+        assert_eq!(reply, frame(0x11, b"hi"));
+
+        uart.write(&[0x12, 0x01]).await.unwrap();
+        let mut reply = vec![0u8; 3];
+        uart.read_exact(&mut reply, TIMEOUT).await.unwrap();
+        assert_eq!(reply, vec![0x12, 0x81, 0x2a]);
+
+        // The exchange was used up
+        uart.write(&[0x12, 0x01]).await.unwrap();
+        assert_eq!(uart.read(&mut reply, TIMEOUT).await, Err(HardwareError::TimeoutError));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reply_after_airtime() {
+        let bus = Rs485BusSim::new().with_collisions(9600).with_device(echo(0x11));
+        let mut uart = master(&bus).await;
+        let request = frame(0x11, b"hi");
+        let airtime = Duration::from_secs_f64(request.len() as f64 * 10.0 / 9600.0);
+
+        uart.write(&request).await.unwrap();
+        let start = Instant::now();
+        // Looking at the wire doesn't deliver the frame early
+        assert_eq!(bus.frames(), vec![BusFrame { bytes: request.clone(), collided: false }]);
+        let mut reply = vec![0u8; 5];
+        assert_eq!(uart.read(&mut reply, Duration::from_millis(1)).await, Err(HardwareError::TimeoutError));
+        uart.read_exact(&mut reply, TIMEOUT).await.unwrap();
+
+        assert_eq!(reply, request);
+        assert_eq!(start.elapsed(), airtime);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiting_master_sees_reply_to_other_master() {
+        let bus = Rs485BusSim::new().with_device(echo(0x11));
+        let mut listener = master(&bus).await;
+        let mut talker = master(&bus).await;
+        let start = Instant::now();
+
+        let mut reply = vec![0u8; 5];
+        let (received, _) = tokio::join!(listener.read_exact(&mut reply, TIMEOUT), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            talker.write(&frame(0x11, b"hi")).await.unwrap();
+        });
+
+        received.unwrap();
+        assert_eq!(reply, frame(0x11, b"hi"));
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unknown_address_times_out() {
+        let bus = Rs485BusSim::new().with_device(echo(0x11));
+        let mut uart = master(&bus).await;
+
+        uart.write(&frame(0x42, b"hi")).await.unwrap();
+        let start = Instant::now();
+        let mut reply = vec![0u8; 5];
+
+        assert_eq!(uart.read(&mut reply, TIMEOUT).await, Err(HardwareError::TimeoutError));
+        assert_eq!(start.elapsed(), TIMEOUT);
+        assert_eq!(bus.frames(), vec![BusFrame { bytes: frame(0x42, b"hi"), collided: false }]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_collision_corrupts_frame() {
+        let bus = Rs485BusSim::new().with_collisions(9600).with_device(echo(0x11)).with_device(echo(0x12));
+        let mut first = master(&bus).await;
+        let mut second = master(&bus).await;
+
+        first.write(&frame(0x11, b"ping")).await.unwrap();
+        second.write(&frame(0x12, b"pong")).await.unwrap();
+        let mut reply = vec![0u8; 7];
+        assert_eq!(first.read(&mut reply, TIMEOUT).await, Err(HardwareError::TimeoutError));
+
+        // Once the wire is quiet again frames go through untouched
+        second.write(&frame(0x12, b"pong")).await.unwrap();
+        second.read_exact(&mut reply, TIMEOUT).await.unwrap();
+        assert_eq!(reply, frame(0x12, b"pong"));
+
+        let frames = bus.frames();
+        assert!(frames[0].collided);
+        assert_eq!(frames[0].bytes[0], 0x11 & 0x12);
+        assert!(!crc_ok(&frames[0].bytes));
+        assert!(!frames[1].collided);
+    }
+}
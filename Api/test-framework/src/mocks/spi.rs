@@ -4,6 +4,7 @@
  */
 
 use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Bidirectional};
+use crate::{check_transfer_buffers, HardwareError, SPI_DEFAULT_MAX_TRANSFER};
 use crate::interfaces::spi::SPIConfig;
 use async_trait::async_trait;
 use mockall::mock;
@@ -51,6 +52,17 @@ impl MockSPIInterface {
                 last_error: None,
                 uptime: Duration::from_secs(0),
             }));
+        // Enforce the same buffer size policy as SPIInterface
+        mock.expect_transfer()
+            .returning(|tx_data, rx_data, _| {
+                if tx_data.len() != rx_data.len() {
+                    return Err(HardwareError::InvalidParameter(
+                        "TX and RX buffers must be the same size".to_string()
+                    ));
+                }
+                check_transfer_buffers(tx_data.len(), rx_data.len(), Some(SPI_DEFAULT_MAX_TRANSFER))?;
+                Ok(rx_data.len())
+            });
         mock
     }
 }
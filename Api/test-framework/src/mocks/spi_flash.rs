@@ -0,0 +1,287 @@
+/*
+ * Fake JEDEC SPI Flash Device
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    check_transfer_buffers, Bidirectional, HardwareError, HardwareInterface, HardwareResult, InterfaceStatus,
+    SPI_DEFAULT_MAX_TRANSFER,
+};
+use crate::drivers::spi_flash::{
+    CMD_PAGE_PROGRAM, CMD_READ_DATA, CMD_READ_JEDEC_ID, CMD_READ_STATUS, CMD_SECTOR_ERASE,
+    CMD_WRITE_ENABLE, PAGE_SIZE, SECTOR_SIZE, STATUS_WEL, STATUS_WIP,
+};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Scripted SPI NOR flash modelling the status register and page semantics
+///
+/// Programming only clears bits, page programs wrap within their page like
+/// real parts do, and the device stays busy for a configurable number of
+/// status polls after every erase or program. Transfers follow the same
+/// buffer size policy as `SPIInterface`.
+pub struct FakeSpiFlash {
+    memory: Vec<u8>,
+    jedec_id: [u8; 3],
+    status: u8,
+    busy_polls: u32,
+    busy_remaining: u32,
+    stuck_busy: bool,
+    stuck_bits: Vec<(usize, u8)>,
+    initialized: bool,
+    commands: Vec<u8>,
+    page_programs: Vec<(u32, usize)>,
+}
+
+impl FakeSpiFlash {
+    pub fn new(size: usize) -> Self {
+        Self {
+            memory: vec![0xFF; size],
+            jedec_id: [0xEF, 0x40, 0x18],
+            status: 0,
+            busy_polls: 2,
+            busy_remaining: 0,
+            stuck_busy: false,
+            stuck_bits: Vec::new(),
+            initialized: false,
+            commands: Vec::new(),
+            page_programs: Vec::new(),
+        }
+    }
+
+    pub fn with_jedec_id(mut self, id: [u8; 3]) -> Self {
+        self.jedec_id = id;
+        self
+    }
+
+    /// Number of status reads that report write-in-progress after each operation
+    pub fn with_busy_polls(mut self, polls: u32) -> Self {
+        self.busy_polls = polls;
+        self
+    }
+
+    /// Never clear write-in-progress once an operation starts
+    pub fn with_stuck_busy(mut self) -> Self {
+        self.stuck_busy = true;
+        self
+    }
+
+    /// Bits in `mask` at `offset` can no longer be programmed to zero
+    pub fn with_stuck_bits(mut self, offset: usize, mask: u8) -> Self {
+        self.stuck_bits.push((offset, mask));
+        self
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Opcodes in the order they were received
+    pub fn commands(&self) -> &[u8] {
+        &self.commands
+    }
+
+    /// Start address and length of every accepted page program
+    pub fn page_programs(&self) -> &[(u32, usize)] {
+        &self.page_programs
+    }
+
+    fn is_busy(&self) -> bool {
+        self.status & STATUS_WIP != 0
+    }
+
+    fn start_busy(&mut self) {
+        self.status = (self.status | STATUS_WIP) & !STATUS_WEL;
+        self.busy_remaining = self.busy_polls;
+        if self.busy_remaining == 0 && !self.stuck_busy {
+            self.status &= !STATUS_WIP;
+        }
+    }
+
+    fn poll_status(&mut self) -> u8 {
+        let status = self.status;
+        if self.is_busy() && !self.stuck_busy {
+            self.busy_remaining = self.busy_remaining.saturating_sub(1);
+            if self.busy_remaining == 0 {
+                self.status &= !STATUS_WIP;
+            }
+        }
+        status
+    }
+
+    fn address(tx: &[u8]) -> HardwareResult<usize> {
+        if tx.len() < 4 {
+            return Err(HardwareError::InvalidParameter(
+                "Command requires a 24-bit address".to_string()
+            ));
+        }
+        Ok(((tx[1] as usize) << 16) | ((tx[2] as usize) << 8) | tx[3] as usize)
+    }
+
+    fn check_range(&self, addr: usize, len: usize) -> HardwareResult<()> {
+        if addr + len > self.memory.len() {
+            return Err(HardwareError::InvalidParameter(format!(
+                "Address 0x{:06X} outside {} byte device", addr, self.memory.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, addr: usize) -> HardwareResult<()> {
+        let base = addr - addr % SECTOR_SIZE;
+        self.check_range(base, SECTOR_SIZE)?;
+        self.memory[base..base + SECTOR_SIZE].fill(0xFF);
+        self.start_busy();
+        Ok(())
+    }
+
+    fn program_page(&mut self, addr: usize, data: &[u8]) -> HardwareResult<()> {
+        let base = addr - addr % PAGE_SIZE;
+        self.check_range(base, PAGE_SIZE)?;
+        for (i, &byte) in data.iter().enumerate() {
+            let target = base + (addr % PAGE_SIZE + i) % PAGE_SIZE;
+            let stuck = self
+                .stuck_bits
+                .iter()
+                .filter(|(offset, _)| *offset == target)
+                .fold(0u8, |mask, (_, bits)| mask | bits);
+            self.memory[target] &= byte | stuck;
+        }
+        self.page_programs.push((addr as u32, data.len()));
+        self.start_busy();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HardwareInterface for FakeSpiFlash {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: 0,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Bidirectional for FakeSpiFlash {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        if !self.initialized {
+            return Err(HardwareError::NotInitialized);
+        }
+
+        if tx_data.len() != rx_data.len() {
+            return Err(HardwareError::InvalidParameter(
+                "TX and RX buffers must be the same size".to_string()
+            ));
+        }
+
+        check_transfer_buffers(tx_data.len(), rx_data.len(), Some(SPI_DEFAULT_MAX_TRANSFER))?;
+
+        rx_data.fill(0xFF);
+        let opcode = tx_data[0];
+        self.commands.push(opcode);
+
+        // A busy part only answers status reads
+        if self.is_busy() && opcode != CMD_READ_STATUS {
+            return Ok(rx_data.len());
+        }
+
+        match opcode {
+            CMD_READ_JEDEC_ID => {
+                for (dst, src) in rx_data.iter_mut().skip(1).zip(self.jedec_id.iter()) {
+                    *dst = *src;
+                }
+            }
+            CMD_READ_STATUS => {
+                for byte in rx_data.iter_mut().skip(1) {
+                    *byte = self.poll_status();
+                }
+            }
+            CMD_WRITE_ENABLE => self.status |= STATUS_WEL,
+            CMD_SECTOR_ERASE if self.status & STATUS_WEL != 0 => {
+                self.erase_sector(Self::address(tx_data)?)?;
+            }
+            CMD_PAGE_PROGRAM if self.status & STATUS_WEL != 0 => {
+                self.program_page(Self::address(tx_data)?, &tx_data[4..])?;
+            }
+            CMD_READ_DATA => {
+                let addr = Self::address(tx_data)?;
+                let len = rx_data.len() - 4;
+                self.check_range(addr, len)?;
+                rx_data[4..].copy_from_slice(&self.memory[addr..addr + len]);
+            }
+            _ => {}
+        }
+
+        Ok(rx_data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn status(flash: &mut FakeSpiFlash) -> u8 {
+        let mut rx = [0u8; 2];
+        flash.transfer(&[CMD_READ_STATUS, 0], &mut rx, Duration::from_millis(10)).await.unwrap();
+        rx[1]
+    }
+
+    #[tokio::test]
+    async fn test_fake_flash_requires_write_enable() {
+        let mut flash = FakeSpiFlash::new(SECTOR_SIZE);
+        flash.initialize().await.unwrap();
+
+        let mut rx = [0u8; 5];
+        flash.transfer(&[CMD_PAGE_PROGRAM, 0, 0, 0, 0x00], &mut rx, Duration::from_millis(10)).await.unwrap();
+        assert_eq!(flash.memory()[0], 0xFF);
+        assert!(flash.page_programs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fake_flash_busy_polls() {
+        let mut flash = FakeSpiFlash::new(SECTOR_SIZE).with_busy_polls(2);
+        flash.initialize().await.unwrap();
+
+        let mut rx = [0u8; 5];
+        flash.transfer(&[CMD_WRITE_ENABLE], &mut rx[..1], Duration::from_millis(10)).await.unwrap();
+        assert_eq!(status(&mut flash).await, STATUS_WEL);
+        flash.transfer(&[CMD_PAGE_PROGRAM, 0, 0, 0, 0x5A], &mut rx, Duration::from_millis(10)).await.unwrap();
+
+        assert_eq!(status(&mut flash).await & STATUS_WIP, STATUS_WIP);
+        assert_eq!(status(&mut flash).await & STATUS_WIP, STATUS_WIP);
+        assert_eq!(status(&mut flash).await, 0);
+        assert_eq!(flash.memory()[0], 0x5A);
+    }
+
+    #[tokio::test]
+    async fn test_fake_flash_page_wraps() {
+        let mut flash = FakeSpiFlash::new(SECTOR_SIZE).with_busy_polls(0);
+        flash.initialize().await.unwrap();
+
+        let mut rx = [0u8; 6];
+        flash.transfer(&[CMD_WRITE_ENABLE], &mut rx[..1], Duration::from_millis(10)).await.unwrap();
+        flash.transfer(&[CMD_PAGE_PROGRAM, 0, 0, 0xFF, 0x11, 0x22], &mut rx, Duration::from_millis(10)).await.unwrap();
+
+        assert_eq!(flash.memory()[0xFF], 0x11);
+        assert_eq!(flash.memory()[0x00], 0x22);
+        assert_eq!(flash.memory()[0x100], 0xFF);
+    }
+}
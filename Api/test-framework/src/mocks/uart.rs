@@ -4,7 +4,7 @@
  */
 
 use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable};
-use crate::interfaces::uart::{UARTConfig, Parity, FlowControl};
+use crate::interfaces::uart::{UARTConfig, Parity, FlowControl, LineControl, ModemStatus};
 use async_trait::async_trait;
 use mockall::mock;
 use std::time::Duration;
@@ -40,6 +40,14 @@ mock! {
         async fn write(&mut self, data: &[u8]) -> HardwareResult<usize>;
         async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()>;
     }
+    
+    #[async_trait]
+    impl LineControl for UARTInterface {
+        async fn send_break(&mut self, duration: Duration) -> HardwareResult<()>;
+        async fn set_rts(&mut self, asserted: bool) -> HardwareResult<()>;
+        async fn set_dtr(&mut self, asserted: bool) -> HardwareResult<()>;
+        async fn read_modem_status(&mut self) -> HardwareResult<ModemStatus>;
+    }
 }
 
 impl MockUARTInterface {
@@ -58,6 +66,23 @@ impl MockUARTInterface {
                 last_error: None,
                 uptime: Duration::from_secs(0),
             }));
+        // UARTInterface has no size limit; empty buffers are no-ops
+        mock.expect_read()
+            .returning(|buffer, _| Ok(buffer.len()));
+        mock.expect_read_exact()
+            .returning(|_, _| Ok(()));
+        mock.expect_write()
+            .returning(|data| Ok(data.len()));
+        mock.expect_write_all()
+            .returning(|_| Ok(()));
+        mock.expect_send_break()
+            .returning(|_| Ok(()));
+        mock.expect_set_rts()
+            .returning(|_| Ok(()));
+        mock.expect_set_dtr()
+            .returning(|_| Ok(()));
+        mock.expect_read_modem_status()
+            .returning(|| Ok(ModemStatus::default()));
         mock
     }
 }
@@ -65,6 +90,7 @@ impl MockUARTInterface {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interfaces::uart::LineAction;
     use mockall::predicate::*;
     
     #[tokio::test]
@@ -151,4 +177,32 @@ mod tests {
             Err(crate::HardwareError::DeviceNotFound)
         ));
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_mock_uart_line_control_sequence() {
+        let mut mock = MockUARTInterface::new(UARTConfig::default());
+        let mut seq = mockall::Sequence::new();
+        
+        mock.expect_set_dtr()
+            .with(eq(false))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        mock.expect_set_rts()
+            .with(eq(true))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        mock.expect_send_break()
+            .with(eq(Duration::from_millis(20)))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+            
+        mock.enter_bootloader(&[
+            LineAction::SetDtr(false),
+            LineAction::SetRts(true),
+            LineAction::Break(Duration::from_millis(20)),
+        ]).await.unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * Test Runner Events and Observers
+ * Copyright (C) 2024
+ */
+
+use crate::{TestResult, TestSuiteResult};
+use tokio::sync::mpsc;
+
+/// Progress event emitted by the test runner
+#[derive(Debug, Clone)]
+pub enum RunnerEvent {
+    SuiteStarted { name: String, total_tests: usize },
+    TestStarted { name: String },
+    TestFinished(TestResult),
+    SuiteFinished(TestSuiteResult),
+}
+
+/// Receives runner events as a suite executes
+pub trait TestObserver: Send {
+    fn on_event(&mut self, event: &RunnerEvent);
+}
+
+/// Observer forwarding every event into a channel
+pub struct ChannelObserver {
+    sender: mpsc::UnboundedSender<RunnerEvent>,
+}
+
+impl ChannelObserver {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<RunnerEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+}
+
+impl TestObserver for ChannelObserver {
+    fn on_event(&mut self, event: &RunnerEvent) {
+        // A dropped receiver just means nobody is listening any more
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestStatus;
+    use std::time::Duration;
+
+    #[test]
+    fn test_channel_observer_forwards_events() {
+        let (mut observer, mut receiver) = ChannelObserver::new();
+        observer.on_event(&RunnerEvent::TestStarted { name: "probe".to_string() });
+        observer.on_event(&RunnerEvent::TestFinished(TestResult::new("probe", TestStatus::Passed, Duration::ZERO)));
+
+        assert!(matches!(receiver.try_recv(), Ok(RunnerEvent::TestStarted { name }) if name == "probe"));
+        assert!(matches!(receiver.try_recv(), Ok(RunnerEvent::TestFinished(r)) if r.status == TestStatus::Passed));
+    }
+}
@@ -0,0 +1,487 @@
+/*
+ * Bench Power Rail Control
+ * Copyright (C) 2024
+ */
+
+use crate::{DigitalWrite, HardwareError, HardwareResult, Level, Readable, Writable};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+
+/// Longest SCPI response line accepted before giving up
+const MAX_SCPI_RESPONSE: usize = 256;
+
+/// Largest datagram a `UdpPort` receives in one go
+const MAX_DATAGRAM: usize = 2048;
+
+/// Voltage and current measured on a rail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RailMeasurement {
+    pub volts: f64,
+    pub amps: f64,
+}
+
+/// A switchable payload supply, e.g. a relay board or programmable PSU
+#[async_trait]
+pub trait PowerRail: Send {
+    async fn enable(&mut self) -> HardwareResult<()>;
+
+    async fn disable(&mut self) -> HardwareResult<()>;
+
+    fn is_enabled(&self) -> bool;
+
+    /// Present voltage and current, or `None` if the rail cannot measure
+    async fn measure(&mut self) -> HardwareResult<Option<RailMeasurement>>;
+}
+
+/// Digital output driving a relay coil
+#[async_trait]
+pub trait OutputPin: Send {
+    async fn set_level(&mut self, high: bool) -> HardwareResult<()>;
+}
+
+/// `OutputPin` on a GPIO line, e.g. a `GPIOInterface` configured as an
+/// output, so a `RelayRail` can drive a relay board from the bench GPIO
+///
+/// ```ignore
+/// let rail = RelayRail::new(GpioPin::new(GPIOInterface::new(config))).with_active_low();
+/// ```
+pub struct GpioPin<D: DigitalWrite + Send> {
+    line: D,
+}
+
+impl<D: DigitalWrite + Send> GpioPin<D> {
+    pub fn new(line: D) -> Self {
+        Self { line }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.line
+    }
+}
+
+#[async_trait]
+impl<D: DigitalWrite + Send> OutputPin for GpioPin<D> {
+    async fn set_level(&mut self, high: bool) -> HardwareResult<()> {
+        self.line.set_level(Level::from(high)).await
+    }
+}
+
+/// Rail switched by a relay on a digital output
+pub struct RelayRail<P: OutputPin> {
+    pin: P,
+    active_low: bool,
+    enabled: bool,
+}
+
+impl<P: OutputPin> RelayRail<P> {
+    pub fn new(pin: P) -> Self {
+        Self {
+            pin,
+            active_low: false,
+            enabled: false,
+        }
+    }
+
+    /// Energize the relay by driving the pin low, as on most relay boards
+    pub fn with_active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+#[async_trait]
+impl<P: OutputPin> PowerRail for RelayRail<P> {
+    async fn enable(&mut self) -> HardwareResult<()> {
+        self.pin.set_level(!self.active_low).await?;
+        self.enabled = true;
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> HardwareResult<()> {
+        self.pin.set_level(self.active_low).await?;
+        self.enabled = false;
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    async fn measure(&mut self) -> HardwareResult<Option<RailMeasurement>> {
+        Ok(None)
+    }
+}
+
+/// Programmable supply speaking newline-terminated SCPI over a byte stream
+pub struct ScpiSupply<T: Readable + Writable + Send> {
+    port: T,
+    timeout: Duration,
+    enabled: bool,
+}
+
+impl<T: Readable + Writable + Send> ScpiSupply<T> {
+    pub fn new(port: T) -> Self {
+        Self {
+            port,
+            timeout: Duration::from_millis(500),
+            enabled: false,
+        }
+    }
+
+    /// Per-byte timeout while waiting for a query response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.port
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.port
+    }
+
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+
+    pub async fn command(&mut self, command: &str) -> HardwareResult<()> {
+        self.port.write_all(format!("{}\n", command).as_bytes()).await
+    }
+
+    /// Send a query and return its response line without the terminator
+    pub async fn query(&mut self, command: &str) -> HardwareResult<String> {
+        self.command(command).await?;
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            self.port.read_exact(&mut byte, self.timeout).await?;
+            match byte[0] {
+                b'\n' => break,
+                b => line.push(b),
+            }
+            if line.len() > MAX_SCPI_RESPONSE {
+                return Err(HardwareError::CommunicationError(format!(
+                    "SCPI response to {} exceeds {} bytes", command, MAX_SCPI_RESPONSE
+                )));
+            }
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end_matches('\r').to_string())
+    }
+
+    async fn query_number(&mut self, command: &str) -> HardwareResult<f64> {
+        let response = self.query(command).await?;
+        response.trim().parse().map_err(|_| {
+            HardwareError::CommunicationError(format!("unexpected SCPI response to {}: {:?}", command, response))
+        })
+    }
+}
+
+#[async_trait]
+impl<T: Readable + Writable + Send> PowerRail for ScpiSupply<T> {
+    async fn enable(&mut self) -> HardwareResult<()> {
+        self.command("OUTP ON").await?;
+        self.enabled = true;
+        Ok(())
+    }
+
+    async fn disable(&mut self) -> HardwareResult<()> {
+        self.command("OUTP OFF").await?;
+        self.enabled = false;
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    async fn measure(&mut self) -> HardwareResult<Option<RailMeasurement>> {
+        let volts = self.query_number("MEAS:VOLT?").await?;
+        let amps = self.query_number("MEAS:CURR?").await?;
+        Ok(Some(RailMeasurement { volts, amps }))
+    }
+}
+
+/// Byte stream over UDP for networked supplies and PDUs taking SCPI
+/// datagrams, used as the port of a `ScpiSupply`
+///
+/// Every write is sent as one datagram. Reads return bytes from received
+/// datagrams, keeping what did not fit the buffer for the next read.
+pub struct UdpPort {
+    socket: UdpSocket,
+    pending: VecDeque<u8>,
+}
+
+impl UdpPort {
+    /// Bind an ephemeral local port and send to `peer`, e.g. port 5025 of
+    /// the supply
+    pub async fn connect(peer: SocketAddr) -> HardwareResult<Self> {
+        let local: SocketAddr = match peer {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await.map_err(HardwareError::from_io)?;
+        socket.connect(peer).await.map_err(HardwareError::from_io)?;
+        Ok(Self {
+            socket,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl Readable for UdpPort {
+    /// Wait up to `timeout` for a datagram if none is buffered
+    async fn read(&mut self, buffer: &mut [u8], timeout_after: Duration) -> HardwareResult<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() {
+            let mut datagram = [0u8; MAX_DATAGRAM];
+            let received = timeout(timeout_after, self.socket.recv(&mut datagram))
+                .await
+                .map_err(|_| HardwareError::TimeoutError)?
+                .map_err(HardwareError::from_io)?;
+            self.pending.extend(&datagram[..received]);
+        }
+        let count = buffer.len().min(self.pending.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.pending.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout_after: Duration) -> HardwareResult<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            filled += self.read(&mut buffer[filled..], timeout_after).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Writable for UdpPort {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        self.socket.send(data).await.map_err(HardwareError::from_io)
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        let sent = self.write(data).await?;
+        if sent != data.len() {
+            return Err(HardwareError::CommunicationError(format!(
+                "sent {} of {} bytes in one datagram",
+                sent,
+                data.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Rail power-cycled by `TestRunner` around every test of a suite
+///
+/// The rail is enabled before the first test, switched off for `off_time`
+/// between tests and disabled once the suite finishes.
+pub struct PowerCycle {
+    rail: Mutex<Box<dyn PowerRail>>,
+    off_time: Duration,
+    settle_time: Duration,
+}
+
+impl PowerCycle {
+    pub fn new<R: PowerRail + 'static>(rail: R, off_time: Duration) -> Self {
+        Self {
+            rail: Mutex::new(Box::new(rail)),
+            off_time,
+            settle_time: Duration::ZERO,
+        }
+    }
+
+    /// Wait after every power-up before the next test starts
+    pub fn with_settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+        self
+    }
+
+    pub(crate) async fn setup(&self) -> HardwareResult<()> {
+        self.rail.lock().await.enable().await?;
+        sleep(self.settle_time).await;
+        Ok(())
+    }
+
+    pub(crate) async fn cycle(&self) -> HardwareResult<()> {
+        let mut rail = self.rail.lock().await;
+        rail.disable().await?;
+        sleep(self.off_time).await;
+        rail.enable().await?;
+        sleep(self.settle_time).await;
+        Ok(())
+    }
+
+    pub(crate) async fn teardown(&self) -> HardwareResult<()> {
+        self.rail.lock().await.disable().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockGPIOInterface;
+    use crate::HardwareInterface;
+
+    /// Byte stream recording writes and answering reads from a script
+    #[derive(Default)]
+    struct ScriptedPort {
+        written: Vec<u8>,
+        responses: VecDeque<u8>,
+    }
+
+    impl ScriptedPort {
+        fn respond(mut self, response: &str) -> Self {
+            self.responses.extend(response.bytes());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Readable for ScriptedPort {
+        async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+            let mut count = 0;
+            while count < buffer.len() {
+                match self.responses.pop_front() {
+                    Some(byte) => buffer[count] = byte,
+                    None => break,
+                }
+                count += 1;
+            }
+            Ok(count)
+        }
+
+        async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+            match self.read(buffer, timeout).await? {
+                n if n == buffer.len() => Ok(()),
+                _ => Err(HardwareError::TimeoutError),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Writable for ScriptedPort {
+        async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+            self.written.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+            self.write(data).await.map(|_| ())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scpi_command_formatting() {
+        let port = ScriptedPort::default().respond("+5.020E+00\r\n0.3100\n");
+        let mut supply = ScpiSupply::new(port);
+
+        supply.enable().await.unwrap();
+        assert!(supply.is_enabled());
+        let measurement = supply.measure().await.unwrap();
+        supply.disable().await.unwrap();
+
+        assert_eq!(measurement, Some(RailMeasurement { volts: 5.02, amps: 0.31 }));
+        assert_eq!(
+            String::from_utf8(supply.into_inner().written).unwrap(),
+            "OUTP ON\nMEAS:VOLT?\nMEAS:CURR?\nOUTP OFF\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scpi_bad_responses() {
+        let mut supply = ScpiSupply::new(ScriptedPort::default().respond("ERR\n"));
+        assert!(matches!(supply.measure().await, Err(HardwareError::CommunicationError(_))));
+
+        let mut silent = ScpiSupply::new(ScriptedPort::default().respond("5.0"));
+        assert!(matches!(silent.query("MEAS:VOLT?").await, Err(HardwareError::TimeoutError)));
+    }
+
+    #[derive(Default)]
+    struct RecordingPin(Vec<bool>);
+
+    #[async_trait]
+    impl OutputPin for RecordingPin {
+        async fn set_level(&mut self, high: bool) -> HardwareResult<()> {
+            self.0.push(high);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_active_low_relay() {
+        let mut relay = RelayRail::new(RecordingPin::default()).with_active_low();
+
+        relay.enable().await.unwrap();
+        assert!(relay.is_enabled());
+        relay.disable().await.unwrap();
+
+        assert_eq!(relay.measure().await.unwrap(), None);
+        assert_eq!(relay.into_inner().0, vec![false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_relay_on_gpio_line() {
+        let mut line = MockGPIOInterface::output();
+        line.initialize().await.unwrap();
+        let mut relay = RelayRail::new(GpioPin::new(line.clone())).with_active_low();
+
+        relay.enable().await.unwrap();
+        relay.disable().await.unwrap();
+
+        assert_eq!(line.levels_written(), vec![Level::Low, Level::High]);
+    }
+
+    #[tokio::test]
+    async fn test_scpi_over_udp() {
+        let supply_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = supply_socket.local_addr().unwrap();
+        let fake_supply = tokio::spawn(async move {
+            let mut commands = Vec::new();
+            let mut datagram = [0u8; 64];
+            while commands.len() < 4 {
+                let (len, from) = supply_socket.recv_from(&mut datagram).await.unwrap();
+                let command = String::from_utf8_lossy(&datagram[..len]).to_string();
+                match command.as_str() {
+                    "MEAS:VOLT?\n" => supply_socket.send_to(b"12.000\n", from).await.unwrap(),
+                    "MEAS:CURR?\n" => supply_socket.send_to(b"1.5\n", from).await.unwrap(),
+                    _ => 0,
+                };
+                commands.push(command);
+            }
+            commands
+        });
+
+        let mut supply = ScpiSupply::new(UdpPort::connect(address).await.unwrap());
+        supply.enable().await.unwrap();
+        let measurement = supply.measure().await.unwrap();
+        supply.disable().await.unwrap();
+
+        assert_eq!(measurement, Some(RailMeasurement { volts: 12.0, amps: 1.5 }));
+        assert_eq!(fake_supply.await.unwrap(), vec!["OUTP ON\n", "MEAS:VOLT?\n", "MEAS:CURR?\n", "OUTP OFF\n"]);
+
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut port = UdpPort::connect(silent.local_addr().unwrap()).await.unwrap();
+        assert!(matches!(
+            port.read(&mut [0u8; 4], Duration::from_millis(20)).await,
+            Err(HardwareError::TimeoutError)
+        ));
+    }
+}
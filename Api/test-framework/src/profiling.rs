@@ -0,0 +1,439 @@
+/*
+ * Latency Profiling for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use crate::{Bidirectional, HardwareInterface, HardwareResult, InterfaceStatus, ReportAppendix, Readable, Writable, CapabilitySet};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Number of power-of-two microsecond buckets; the last one is open-ended
+const BUCKETS: usize = 40;
+
+/// Operation kinds tracked by `Profiled`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OperationKind {
+    Read,
+    Write,
+    Transfer,
+}
+
+impl OperationKind {
+    pub const ALL: [OperationKind; 3] = [OperationKind::Read, OperationKind::Write, OperationKind::Transfer];
+
+    fn index(self) -> usize {
+        match self {
+            OperationKind::Read => 0,
+            OperationKind::Write => 1,
+            OperationKind::Transfer => 2,
+        }
+    }
+}
+
+/// Fixed-size latency histogram with log2 microsecond buckets
+///
+/// Bucket 0 holds sub-microsecond samples, bucket `k` holds samples in
+/// `[2^(k-1), 2^k)` microseconds. Recording never allocates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    total_us: u128,
+    min_us: u64,
+    max_us: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            total_us: 0,
+            min_us: u64::MAX,
+            max_us: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[Self::bucket_for(us)] += 1;
+        self.count += 1;
+        self.total_us += us as u128;
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    fn bucket_for(us: u64) -> usize {
+        ((64 - us.leading_zeros()) as usize).min(BUCKETS - 1)
+    }
+
+    /// Inclusive upper bound of a bucket in microseconds
+    fn bucket_upper(bucket: usize) -> u64 {
+        if bucket == BUCKETS - 1 {
+            u64::MAX
+        } else {
+            (1u64 << bucket) - 1
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn bucket_counts(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.min_us))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.max_us))
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros((self.total_us / self.count as u128) as u64))
+    }
+
+    /// Latency at percentile `p` (0.0..=100.0): the upper bound of the
+    /// bucket containing it, clamped to the observed min and max
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let us = Self::bucket_upper(bucket).clamp(self.min_us, self.max_us);
+                return Some(Duration::from_micros(us));
+            }
+        }
+        self.max()
+    }
+
+    fn summary_json(&self) -> serde_json::Value {
+        let us = |d: Option<Duration>| d.map(|d| d.as_micros() as u64);
+        serde_json::json!({
+            "count": self.count,
+            "min_us": us(self.min()),
+            "mean_us": us(self.mean()),
+            "p50_us": us(self.percentile(50.0)),
+            "p90_us": us(self.percentile(90.0)),
+            "p99_us": us(self.percentile(99.0)),
+            "max_us": us(self.max()),
+            "buckets": self.buckets.to_vec(),
+        })
+    }
+}
+
+/// Latency histograms per operation kind, shared between a `Profiled`
+/// wrapper and the runner reporting them
+#[derive(Debug, Default)]
+pub struct LatencyProfile {
+    histograms: Mutex<[Histogram; 3]>,
+}
+
+impl LatencyProfile {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record(&self, op: OperationKind, latency: Duration) {
+        self.histograms.lock().unwrap()[op.index()].record(latency);
+    }
+
+    pub fn histogram(&self, op: OperationKind) -> Histogram {
+        self.histograms.lock().unwrap()[op.index()].clone()
+    }
+
+    pub fn reset(&self) {
+        *self.histograms.lock().unwrap() = Default::default();
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+        for op in OperationKind::ALL {
+            map.insert(format!("{:?}", op).to_lowercase(), self.histogram(op).summary_json());
+        }
+        serde_json::Value::Object(map)
+    }
+
+    pub fn to_table(&self) -> String {
+        let mut table = format!(
+            "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "Operation", "Count", "Min", "p50", "p90", "p99", "Max"
+        );
+        let fmt = |d: Option<Duration>| d.map(|d| format!("{:?}", d)).unwrap_or_else(|| "-".to_string());
+        for op in OperationKind::ALL {
+            let h = self.histogram(op);
+            let _ = writeln!(
+                table,
+                "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                format!("{:?}", op),
+                h.count(),
+                fmt(h.min()),
+                fmt(h.percentile(50.0)),
+                fmt(h.percentile(90.0)),
+                fmt(h.percentile(99.0)),
+                fmt(h.max()),
+            );
+        }
+        table
+    }
+
+    /// Latency appendix for attaching to a suite result
+    pub fn appendix(&self, name: &str) -> ReportAppendix {
+        ReportAppendix::new(&format!("Latency: {}", name), &self.to_table())
+    }
+}
+
+/// Interface wrapper recording the latency of every operation
+///
+/// Pass `profile()` to `TestRunner::with_profiling` to get the latencies
+/// in every suite report.
+pub struct Profiled<T> {
+    inner: T,
+    profile: Arc<LatencyProfile>,
+}
+
+impl<T> Profiled<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            profile: LatencyProfile::new(),
+        }
+    }
+
+    pub fn profile(&self) -> Arc<LatencyProfile> {
+        self.profile.clone()
+    }
+
+    pub fn histogram(&self, op: OperationKind) -> Histogram {
+        self.profile.histogram(op)
+    }
+
+    pub fn reset(&mut self) {
+        self.profile.reset();
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&mut self, op: OperationKind, start: Instant) {
+        self.profile.record(op, start.elapsed());
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.profile.to_json()
+    }
+
+    pub fn to_table(&self) -> String {
+        self.profile.to_table()
+    }
+
+    /// Latency appendix for attaching to a suite result
+    pub fn appendix(&self, name: &str) -> ReportAppendix {
+        self.profile.appendix(name)
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Send + Sync> HardwareInterface for Profiled<T> {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.inner.initialize().await
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.inner.deinitialize().await
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.inner.is_initialized()
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        self.inner.get_status().await
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.inner.capabilities()
+    }
+}
+
+#[async_trait]
+impl<T: Readable + Send> Readable for Profiled<T> {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let start = Instant::now();
+        let result = self.inner.read(buffer, timeout).await;
+        self.record(OperationKind::Read, start);
+        result
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        let start = Instant::now();
+        let result = self.inner.read_exact(buffer, timeout).await;
+        self.record(OperationKind::Read, start);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Writable + Send> Writable for Profiled<T> {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        let start = Instant::now();
+        let result = self.inner.write(data).await;
+        self.record(OperationKind::Write, start);
+        result
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        let start = Instant::now();
+        let result = self.inner.write_all(data).await;
+        self.record(OperationKind::Write, start);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Bidirectional + Send> Bidirectional for Profiled<T> {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let start = Instant::now();
+        let result = self.inner.transfer(tx_data, rx_data, timeout).await;
+        self.record(OperationKind::Transfer, start);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{I2CInterface, TestFn, TestRunner};
+
+    /// Writable whose writes take a scripted amount of (virtual) time
+    struct Delayed {
+        delays: Vec<Duration>,
+    }
+
+    #[async_trait]
+    impl Writable for Delayed {
+        async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+            let delay = self.delays.remove(0);
+            tokio::time::sleep(delay).await;
+            Ok(data.len())
+        }
+
+        async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+            self.write(data).await.map(|_| ())
+        }
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut h = Histogram::new();
+        h.record(Duration::ZERO);
+        h.record(Duration::from_micros(1));
+        h.record(Duration::from_micros(3000));
+        h.record(Duration::from_micros(4096));
+
+        assert_eq!(h.bucket_counts()[0], 1);
+        assert_eq!(h.bucket_counts()[1], 1);
+        assert_eq!(h.bucket_counts()[12], 1);
+        assert_eq!(h.bucket_counts()[13], 1);
+        assert_eq!(h.count(), 4);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut h = Histogram::new();
+        assert_eq!(h.percentile(50.0), None);
+
+        for _ in 0..90 {
+            h.record(Duration::from_millis(3));
+        }
+        for _ in 0..10 {
+            h.record(Duration::from_millis(50));
+        }
+
+        assert_eq!(h.percentile(50.0), Some(Duration::from_micros(4095)));
+        assert_eq!(h.percentile(90.0), Some(Duration::from_micros(4095)));
+        assert_eq!(h.percentile(91.0), Some(Duration::from_millis(50)));
+        assert_eq!(h.percentile(0.0), Some(Duration::from_micros(4095)));
+        assert_eq!(h.max(), Some(Duration::from_millis(50)));
+        assert_eq!(h.mean(), Some(Duration::from_micros(7700)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_profiled_records_injected_delays() {
+        let delays = vec![Duration::from_millis(2), Duration::from_millis(2), Duration::from_millis(20)];
+        let mut profiled = Profiled::new(Delayed { delays });
+
+        for _ in 0..3 {
+            profiled.write(&[0x01]).await.unwrap();
+        }
+
+        let h = profiled.histogram(OperationKind::Write);
+        assert_eq!(h.count(), 3);
+        assert_eq!(h.bucket_counts()[11], 2);
+        assert_eq!(h.bucket_counts()[15], 1);
+        assert_eq!(h.min(), Some(Duration::from_millis(2)));
+        assert_eq!(h.max(), Some(Duration::from_millis(20)));
+        assert_eq!(profiled.histogram(OperationKind::Read).count(), 0);
+
+        let json = profiled.to_json();
+        assert_eq!(json["write"]["count"], 3);
+        assert_eq!(json["write"]["max_us"], 20_000);
+
+        let appendix = profiled.appendix("eeprom");
+        assert_eq!(appendix.title, "Latency: eeprom");
+        assert!(appendix.body.lines().nth(2).unwrap().starts_with("Write"));
+    }
+
+    #[tokio::test]
+    async fn test_runner_attaches_latency_appendix() {
+        let interface = Profiled::new(I2CInterface::with_default_config());
+        let profile = interface.profile();
+        let runner = TestRunner::new(interface, Duration::from_millis(100), 0, Duration::ZERO)
+            .with_profiling("i2c", profile.clone());
+        let writes = || -> TestFn<Profiled<I2CInterface>> {
+            Box::new(|interface| {
+                Box::pin(async move {
+                    let mut interface = interface.lock().await;
+                    interface.initialize().await?;
+                    interface.write(&[0x01]).await?;
+                    interface.write(&[0x02]).await?;
+                    Ok(())
+                })
+            })
+        };
+
+        for _ in 0..2 {
+            let suite = runner.run_test_suite("eeprom", vec![("writes", writes())]).await;
+            let appendix = suite.appendices.iter().find(|a| a.title == "Latency: i2c").unwrap();
+            // Each suite reports only its own operations
+            assert_eq!(profile.histogram(OperationKind::Write).count(), 2);
+            assert!(appendix.body.lines().nth(2).unwrap().starts_with("Write             2"));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+/*
+ * Quarantine for Known-Flaky Tests
+ * Copyright (C) 2024
+ */
+
+use crate::{SuiteHistory, TestStatus};
+use serde::Deserialize;
+
+/// Tests that still run and report their real status but whose failures
+/// are counted apart and never fail the suite, e.g. tests hit by a
+/// hardware erratum that won't be fixed
+///
+/// Matches tests by name or, for cases declared with `suite!`, by tag.
+/// Loaded from a manifest's `[quarantine]` table or set with
+/// `TestRunner::with_quarantine`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quarantine {
+    #[serde(default)]
+    pub tests: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Quarantine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_test(mut self, name: &str) -> Self {
+        self.tests.push(name.to_string());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty() && self.tags.is_empty()
+    }
+
+    pub fn contains(&self, name: &str, tags: &[&str]) -> bool {
+        self.tests.iter().any(|test| test == name) || self.tags.iter().any(|tag| tags.contains(&tag.as_str()))
+    }
+}
+
+impl SuiteHistory {
+    /// Quarantined tests of the latest run that passed in each of the last
+    /// `runs` runs, which may be ready to leave the quarantine
+    pub fn dequarantine_candidates(&self, runs: usize) -> Vec<String> {
+        let recent = match self.runs().len().checked_sub(runs) {
+            Some(start) if runs > 0 => &self.runs()[start..],
+            _ => return Vec::new(),
+        };
+        let latest = &recent[recent.len() - 1];
+        let mut names: Vec<String> = latest
+            .results
+            .iter()
+            .filter(|result| result.quarantined)
+            .filter(|result| {
+                recent.iter().all(|run| {
+                    run.results
+                        .iter()
+                        .any(|r| r.name == result.name && r.status == TestStatus::Passed)
+                })
+            })
+            .map(|result| result.name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{exit_code, suite, AdviceRegistry, HardwareError, SuiteManifest, TestResult, TestRunner, TestSuiteResult};
+    use std::time::Duration;
+
+    suite!(erratum(iface: MockHardwareInterface) {
+        "init" => { iface.initialize().await },
+        "burst read" [tags: erratum_17] => { Err(HardwareError::TimeoutError) },
+        "crc check" => { Err(HardwareError::OperationFailed("crc mismatch".to_string())) },
+        "clock stretch" => { Err(HardwareError::CommunicationError("nack".to_string())) },
+    });
+
+    fn runner(quarantine: Quarantine) -> TestRunner<MockHardwareInterface> {
+        TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+            .with_quarantine(quarantine)
+    }
+
+    #[test]
+    fn test_matching() {
+        let quarantine = Quarantine::new().with_test("crc check").with_tag("erratum_17");
+        assert!(quarantine.contains("crc check", &[]));
+        assert!(quarantine.contains("burst read", &["slow", "erratum_17"]));
+        assert!(!quarantine.contains("burst read", &["slow"]));
+        assert!(Quarantine::new().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_counted_apart() {
+        let quarantine = Quarantine::new().with_test("crc check").with_tag("erratum_17");
+        let suite = runner(quarantine).run_cases("erratum", erratum()).await;
+
+        let flags: Vec<bool> = suite.results.iter().map(|r| r.quarantined).collect();
+        assert_eq!(flags, vec![false, true, true, false]);
+        assert!(matches!(suite.results[1].status, TestStatus::Error(_)));
+        assert_eq!(suite.total_tests, 4);
+        assert_eq!(suite.passed_tests, 1);
+        assert_eq!(suite.error_tests, 1);
+        assert_eq!(suite.quarantined_failures, 2);
+        assert!(!suite.succeeded());
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_failures_keep_suite_green() {
+        let quarantine = Quarantine::new().with_test("crc check").with_test("clock stretch").with_tag("erratum_17");
+        let suite = runner(quarantine).run_cases("erratum", erratum()).await;
+
+        assert_eq!((suite.error_tests, suite.quarantined_failures), (0, 3));
+        assert!(suite.succeeded());
+        assert_eq!(exit_code(&[suite.clone()]), 0);
+
+        let json = suite.to_json();
+        assert_eq!(json["quarantined_failures"], 3);
+        assert_eq!(json["error_tests"], 0);
+        assert_eq!(json["results"][1]["quarantined"], true);
+        assert_eq!(json["results"][1]["status"], "error");
+
+        let md = suite.to_markdown(&AdviceRegistry::empty());
+        assert!(md.contains("3 quarantined failures, not failing the suite\n"));
+        assert!(md.contains("| burst read | error (quarantined) |"));
+        assert!(md.contains(
+            "## Quarantined tests\n\n\
+             - burst read: error (Test failed: TimeoutError)\n\
+             - crc check: error (Test failed: OperationFailed(\"crc mismatch\"))\n\
+             - clock stretch: error (Test failed: CommunicationError(\"nack\"))\n"
+        ));
+    }
+
+    fn run(statuses: &[(&str, TestStatus, bool)]) -> TestSuiteResult {
+        let results = statuses
+            .iter()
+            .map(|(name, status, quarantined)| {
+                let mut result = TestResult::new(name, status.clone(), Duration::ZERO);
+                result.quarantined = *quarantined;
+                result
+            })
+            .collect();
+        TestSuiteResult::from_results("nightly", results, Duration::ZERO)
+    }
+
+    #[test]
+    fn test_dequarantine_candidates() {
+        let failed = || TestStatus::Failed("timeout".to_string());
+        let mut history = SuiteHistory::new();
+        history.push(run(&[("burst read", failed(), true), ("crc check", TestStatus::Passed, true)]));
+        assert!(history.dequarantine_candidates(3).is_empty());
+
+        for _ in 0..2 {
+            history.push(run(&[
+                ("burst read", TestStatus::Passed, true),
+                ("crc check", TestStatus::Passed, true),
+                ("init", TestStatus::Passed, false),
+            ]));
+        }
+        assert_eq!(history.dequarantine_candidates(3), vec!["crc check"]);
+        assert_eq!(history.dequarantine_candidates(2), vec!["burst read", "crc check"]);
+        assert!(history.dequarantine_candidates(0).is_empty());
+
+        history.push(run(&[("burst read", TestStatus::Passed, true), ("crc check", failed(), true)]));
+        assert_eq!(history.dequarantine_candidates(3), vec!["burst read"]);
+    }
+
+    #[test]
+    fn test_manifest_table() {
+        let manifest = SuiteManifest::from_toml(
+            "suites = []\n[[target]]\nname = \"a\"\ninterface = \"uart\"\n\
+             [quarantine]\ntests = [\"burst read\"]\ntags = [\"erratum_17\"]",
+        )
+        .unwrap();
+        assert_eq!(manifest.quarantine, Quarantine::new().with_test("burst read").with_tag("erratum_17"));
+
+        let typo = SuiteManifest::from_toml("suites = []\n[[target]]\nname = \"a\"\ninterface = \"uart\"\n[quarantine]\ntest = []");
+        assert!(typo.is_err());
+    }
+}
@@ -0,0 +1,522 @@
+/*
+ * Dual-Redundant Interface with Automatic Failover
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    Bidirectional, CapabilitySet, HardwareError, HardwareErrorKind, HardwareInterface, HardwareResult, InterfaceStatus,
+    Readable, Writable,
+};
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// One half of a redundant pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Primary,
+    Secondary,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self {
+            Side::Primary => Side::Secondary,
+            Side::Secondary => Side::Primary,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Side::Primary => 0,
+            Side::Secondary => 1,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Primary => write!(f, "primary"),
+            Side::Secondary => write!(f, "secondary"),
+        }
+    }
+}
+
+/// When an error on the active side triggers failover
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailoverPolicy {
+    consecutive_errors: u32,
+    immediate: Vec<HardwareErrorKind>,
+}
+
+impl FailoverPolicy {
+    /// Fail over after `errors` consecutive failed operations
+    pub fn consecutive_errors(errors: u32) -> Self {
+        Self {
+            consecutive_errors: errors.max(1),
+            immediate: Vec::new(),
+        }
+    }
+
+    /// Also fail over on the first error of this kind, e.g.
+    /// `HardwareErrorKind::DeviceNotFound`
+    pub fn with_immediate(mut self, kind: HardwareErrorKind) -> Self {
+        self.immediate.push(kind);
+        self
+    }
+
+    /// The rule `error` fires, if any; an immediate kind wins over the
+    /// consecutive error count
+    fn triggers(&self, consecutive: u32, error: &HardwareError) -> Option<FailoverTrigger> {
+        if self.immediate.contains(&error.kind()) {
+            Some(FailoverTrigger::Immediate(error.kind()))
+        } else if consecutive >= self.consecutive_errors {
+            Some(FailoverTrigger::ConsecutiveErrors(consecutive))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self::consecutive_errors(3)
+    }
+}
+
+/// Policy rule that caused a failover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverTrigger {
+    /// This many operations in a row failed
+    ConsecutiveErrors(u32),
+    /// An error of a kind registered with `with_immediate`
+    Immediate(HardwareErrorKind),
+}
+
+impl fmt::Display for FailoverTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailoverTrigger::ConsecutiveErrors(errors) => write!(f, "{} consecutive errors", errors),
+            FailoverTrigger::Immediate(kind) => write!(f, "immediate failover on {}", kind),
+        }
+    }
+}
+
+/// A switch from one side to the other
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailoverEvent {
+    pub from: Side,
+    pub to: Side,
+    pub trigger: FailoverTrigger,
+    pub reason: String,
+}
+
+/// Status of both sides of a `Redundant` interface
+#[derive(Debug, PartialEq)]
+pub struct RedundantStatus {
+    pub active: Side,
+    pub primary: InterfaceStatus,
+    pub secondary: InterfaceStatus,
+    pub failovers: usize,
+}
+
+impl fmt::Display for RedundantStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "active={} failovers={} primary errors={} secondary errors={}",
+            self.active, self.failovers, self.primary.error_count, self.secondary.error_count
+        )
+    }
+}
+
+/// Primary and secondary interface to the same device, e.g. I2C-A and I2C-B
+///
+/// Operations go to the active side. When the failure policy triggers, the
+/// wrapper switches to the other side, retries the failed operation there
+/// once, and re-initializes the failed side in the background. A side whose
+/// re-initialization failed is not failed over to again.
+pub struct Redundant<T> {
+    sides: [Arc<Mutex<T>>; 2],
+    healthy: [Arc<AtomicBool>; 2],
+    active: Side,
+    policy: FailoverPolicy,
+    consecutive_errors: u32,
+    initialized: bool,
+    failovers: Vec<FailoverEvent>,
+    recovery: Option<JoinHandle<()>>,
+    /// What both sides support, since either may be active
+    capabilities: CapabilitySet,
+}
+
+/// Run `$op` on the active side, failing over and retrying once if needed
+macro_rules! with_failover {
+    ($self:ident, $iface:ident => $op:expr) => {{
+        let result = {
+            let mut $iface = $self.sides[$self.active.index()].lock().await;
+            $op.await
+        };
+        if $self.note_result(&result) {
+            let retry = {
+                let mut $iface = $self.sides[$self.active.index()].lock().await;
+                $op.await
+            };
+            $self.note_result(&retry);
+            retry
+        } else {
+            result
+        }
+    }};
+}
+
+impl<T: HardwareInterface + Send + Sync + 'static> Redundant<T> {
+    pub fn new(primary: T, secondary: T) -> Self {
+        let capabilities = primary.capabilities().intersection(secondary.capabilities());
+        Self {
+            sides: [Arc::new(Mutex::new(primary)), Arc::new(Mutex::new(secondary))],
+            healthy: [Arc::new(AtomicBool::new(true)), Arc::new(AtomicBool::new(true))],
+            active: Side::Primary,
+            policy: FailoverPolicy::default(),
+            consecutive_errors: 0,
+            initialized: false,
+            failovers: Vec::new(),
+            recovery: None,
+            capabilities,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: FailoverPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn active_side(&self) -> Side {
+        self.active
+    }
+
+    /// Switch sides without a recorded failover, e.g. to test the secondary
+    pub fn force_side(&mut self, side: Side) {
+        self.active = side;
+        self.consecutive_errors = 0;
+    }
+
+    pub fn failovers(&self) -> &[FailoverEvent] {
+        &self.failovers
+    }
+
+    /// Shared handle to one side, e.g. to inspect a fake
+    pub fn side(&self, side: Side) -> Arc<Mutex<T>> {
+        self.sides[side.index()].clone()
+    }
+
+    /// Whether `side` is considered usable as a failover target
+    pub fn is_healthy(&self, side: Side) -> bool {
+        self.healthy[side.index()].load(Ordering::SeqCst)
+    }
+
+    /// Wait for the background re-initialization of the last failed side
+    pub async fn wait_for_recovery(&mut self) {
+        if let Some(recovery) = self.recovery.take() {
+            let _ = recovery.await;
+        }
+    }
+
+    pub async fn redundant_status(&self) -> HardwareResult<RedundantStatus> {
+        Ok(RedundantStatus {
+            active: self.active,
+            primary: self.sides[0].lock().await.get_status().await?,
+            secondary: self.sides[1].lock().await.get_status().await?,
+            failovers: self.failovers.len(),
+        })
+    }
+
+    /// Track the outcome of an operation on the active side, returning
+    /// whether it caused a failover
+    fn note_result<R>(&mut self, result: &HardwareResult<R>) -> bool {
+        let error = match result {
+            Ok(_) => {
+                self.consecutive_errors = 0;
+                return false;
+            }
+            Err(error) => error,
+        };
+        self.consecutive_errors += 1;
+        let target = self.active.other();
+        let trigger = match self.policy.triggers(self.consecutive_errors, error) {
+            Some(trigger) if self.is_healthy(target) => trigger,
+            _ => return false,
+        };
+
+        let failed = self.active;
+        let reason = format!("{}, last: {}", trigger, error);
+        log::warn!("Redundant interface failing over from {} to {}: {}", failed, target, reason);
+        self.failovers.push(FailoverEvent {
+            from: failed,
+            to: target,
+            trigger,
+            reason,
+        });
+        self.active = target;
+        self.consecutive_errors = 0;
+        self.spawn_recovery(failed);
+        true
+    }
+
+    fn spawn_recovery(&mut self, side: Side) {
+        let interface = self.sides[side.index()].clone();
+        let healthy = self.healthy[side.index()].clone();
+        healthy.store(false, Ordering::SeqCst);
+        self.recovery = Some(tokio::spawn(async move {
+            let mut interface = interface.lock().await;
+            let _ = interface.deinitialize().await;
+            let recovered = interface.initialize().await.is_ok();
+            if !recovered {
+                log::warn!("Re-initializing the {} side failed", side);
+            }
+            healthy.store(recovered, Ordering::SeqCst);
+        }));
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Send + Sync + 'static> HardwareInterface for Redundant<T> {
+    /// Initialize both sides; a failing side is marked unhealthy, and the
+    /// secondary becomes active if only it comes up
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        let primary = self.sides[0].lock().await.initialize().await;
+        let secondary = self.sides[1].lock().await.initialize().await;
+        self.healthy[0].store(primary.is_ok(), Ordering::SeqCst);
+        self.healthy[1].store(secondary.is_ok(), Ordering::SeqCst);
+        match (primary, secondary) {
+            (Err(e), Err(_)) => return Err(e),
+            (Err(_), Ok(())) => self.active = Side::Secondary,
+            _ => {}
+        }
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.wait_for_recovery().await;
+        let primary = self.sides[0].lock().await.deinitialize().await;
+        let secondary = self.sides[1].lock().await.deinitialize().await;
+        self.initialized = false;
+        primary.and(secondary)
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Status of the active side with the error counts of both sides
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        let status = self.redundant_status().await?;
+        let (active, standby) = match status.active {
+            Side::Primary => (status.primary, status.secondary),
+            Side::Secondary => (status.secondary, status.primary),
+        };
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: active.error_count + standby.error_count,
+            last_error: active.last_error.or(standby.last_error),
+            uptime: active.uptime.max(standby.uptime),
+        })
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.capabilities
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Readable + Send + Sync + 'static> Readable for Redundant<T> {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        with_failover!(self, interface => interface.read(buffer, timeout))
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        with_failover!(self, interface => interface.read_exact(buffer, timeout))
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Writable + Send + Sync + 'static> Writable for Redundant<T> {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        with_failover!(self, interface => interface.write(data))
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        with_failover!(self, interface => interface.write_all(data))
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Bidirectional + Send + Sync + 'static> Bidirectional for Redundant<T> {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        with_failover!(self, interface => interface.transfer(tx_data, rx_data, timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    /// Bus failing a configurable number of operations, with an optional
+    /// fault that survives re-initialization
+    #[derive(Default)]
+    struct FlakyBus {
+        failures: Arc<AtomicU32>,
+        broken: Arc<AtomicBool>,
+        initializations: u32,
+        writes: u32,
+        errors: u32,
+    }
+
+    impl FlakyBus {
+        fn operation(&mut self) -> HardwareResult<()> {
+            let failing = self.broken.load(Ordering::SeqCst)
+                || self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok();
+            if failing {
+                self.errors += 1;
+                return Err(HardwareError::TimeoutError);
+            }
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl HardwareInterface for FlakyBus {
+        async fn initialize(&mut self) -> HardwareResult<()> {
+            self.initializations += 1;
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(HardwareError::DeviceNotFound);
+            }
+            Ok(())
+        }
+
+        async fn deinitialize(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        fn is_initialized(&self) -> bool {
+            true
+        }
+
+        async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+            Ok(InterfaceStatus {
+                initialized: true,
+                error_count: self.errors,
+                last_error: None,
+                uptime: Duration::from_secs(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Writable for FlakyBus {
+        async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+            self.operation().map(|_| data.len())
+        }
+
+        async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+            self.write(data).await.map(|_| ())
+        }
+    }
+
+    async fn pair() -> (Redundant<FlakyBus>, [Arc<AtomicU32>; 2]) {
+        let primary = FlakyBus::default();
+        let secondary = FlakyBus::default();
+        let faults = [primary.failures.clone(), secondary.failures.clone()];
+        let mut redundant = Redundant::new(primary, secondary).with_policy(FailoverPolicy::consecutive_errors(2));
+        redundant.initialize().await.unwrap();
+        (redundant, faults)
+    }
+
+    #[tokio::test]
+    async fn test_failover_threshold_and_retry() {
+        let (mut redundant, faults) = pair().await;
+        faults[0].store(5, Ordering::SeqCst);
+
+        assert!(redundant.write(&[1]).await.is_err());
+        assert_eq!(redundant.active_side(), Side::Primary);
+
+        // The second consecutive error fails over and is retried on the secondary
+        assert_eq!(redundant.write(&[1, 2]).await.unwrap(), 2);
+        assert_eq!(redundant.active_side(), Side::Secondary);
+        assert_eq!(redundant.failovers().len(), 1);
+        assert_eq!(redundant.failovers()[0].from, Side::Primary);
+        assert_eq!(redundant.failovers()[0].trigger, FailoverTrigger::ConsecutiveErrors(2));
+        assert_eq!(redundant.side(Side::Secondary).lock().await.writes, 1);
+
+        redundant.wait_for_recovery().await;
+        assert_eq!(redundant.side(Side::Primary).lock().await.initializations, 2);
+        assert!(redundant.is_healthy(Side::Primary));
+
+        let status = redundant.redundant_status().await.unwrap();
+        assert_eq!(status.to_string(), "active=secondary failovers=1 primary errors=2 secondary errors=0");
+        assert_eq!(redundant.get_status().await.unwrap().error_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fail_back_after_recovery() {
+        let (mut redundant, faults) = pair().await;
+        faults[0].store(2, Ordering::SeqCst);
+        let _ = redundant.write(&[1]).await;
+        redundant.write(&[1]).await.unwrap();
+        redundant.wait_for_recovery().await;
+
+        faults[1].store(2, Ordering::SeqCst);
+        let _ = redundant.write(&[1]).await;
+        redundant.write(&[1]).await.unwrap();
+
+        assert_eq!(redundant.active_side(), Side::Primary);
+        let sides: Vec<_> = redundant.failovers().iter().map(|e| (e.from, e.to)).collect();
+        assert_eq!(sides, vec![(Side::Primary, Side::Secondary), (Side::Secondary, Side::Primary)]);
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_to_unhealthy_side() {
+        let (mut redundant, _) = pair().await;
+        let secondary_broken = redundant.side(Side::Secondary).lock().await.broken.clone();
+        let primary_broken = redundant.side(Side::Primary).lock().await.broken.clone();
+
+        primary_broken.store(true, Ordering::SeqCst);
+        let _ = redundant.write(&[1]).await;
+        redundant.write(&[1]).await.unwrap();
+        redundant.wait_for_recovery().await;
+        assert!(!redundant.is_healthy(Side::Primary));
+
+        // The broken primary stays out of rotation while the secondary fails
+        secondary_broken.store(true, Ordering::SeqCst);
+        for _ in 0..4 {
+            assert!(redundant.write(&[1]).await.is_err());
+        }
+        assert_eq!(redundant.active_side(), Side::Secondary);
+        assert_eq!(redundant.failovers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_immediate_error_kind_and_force_side() {
+        let (redundant, faults) = pair().await;
+        let mut redundant = redundant.with_policy(FailoverPolicy::consecutive_errors(10).with_immediate(HardwareErrorKind::TimeoutError));
+        faults[0].store(1, Ordering::SeqCst);
+
+        redundant.write(&[1]).await.unwrap();
+        assert_eq!(redundant.active_side(), Side::Secondary);
+        let event = &redundant.failovers()[0];
+        assert_eq!(event.trigger, FailoverTrigger::Immediate(HardwareErrorKind::TimeoutError));
+        assert!(event.reason.starts_with("immediate failover on TimeoutError, last: "));
+
+        redundant.force_side(Side::Primary);
+        redundant.write(&[1]).await.unwrap();
+        assert_eq!(redundant.active_side(), Side::Primary);
+        assert_eq!(redundant.failovers().len(), 1);
+    }
+}
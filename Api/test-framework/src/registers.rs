@@ -0,0 +1,365 @@
+/*
+ * Register Map Descriptions for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use crate::{Bidirectional, HardwareResult, Writable};
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+#[doc(hidden)]
+pub use paste::paste as __register_map_paste;
+
+/// Byte-addressed register access, as offered by most I2C sensors
+#[async_trait]
+pub trait RegisterAccess: Send {
+    /// Read consecutive registers starting at `address`
+    async fn read_registers(&mut self, address: u8, buffer: &mut [u8]) -> HardwareResult<()>;
+
+    /// Write consecutive registers starting at `address`
+    async fn write_registers(&mut self, address: u8, data: &[u8]) -> HardwareResult<()>;
+}
+
+/// Register access over a bus taking the register address first: writes
+/// send address and data in one frame, reads are a write-then-read transfer
+pub struct RegisterBus<T: Bidirectional> {
+    bus: T,
+    timeout: Duration,
+}
+
+impl<T: Bidirectional> RegisterBus<T> {
+    pub fn new(bus: T) -> Self {
+        Self {
+            bus,
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.bus
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.bus
+    }
+
+    pub fn into_inner(self) -> T {
+        self.bus
+    }
+}
+
+#[async_trait]
+impl<T: Bidirectional + Send> RegisterAccess for RegisterBus<T> {
+    async fn read_registers(&mut self, address: u8, buffer: &mut [u8]) -> HardwareResult<()> {
+        self.bus.transfer(&[address], buffer, self.timeout).await?;
+        Ok(())
+    }
+
+    async fn write_registers(&mut self, address: u8, data: &[u8]) -> HardwareResult<()> {
+        let mut frame = Vec::with_capacity(data.len() + 1);
+        frame.push(address);
+        frame.extend_from_slice(data);
+        self.bus.write_all(&frame).await
+    }
+}
+
+/// One register read by a generated `dump_all`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterValue {
+    pub name: &'static str,
+    pub address: u8,
+    pub width: usize,
+    pub value: u64,
+}
+
+impl fmt::Display for RegisterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (0x{:02X}) = 0x{:0digits$X}",
+            self.name,
+            self.address,
+            self.value,
+            digits = self.width * 2
+        )
+    }
+}
+
+/// Register of a `register_map!`, as listed in the map's `REGISTERS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterDescriptor {
+    pub name: &'static str,
+    pub address: u8,
+    pub width: usize,
+    pub big_endian: bool,
+}
+
+/// Text form of a register dump, one register per line, suitable for
+/// registering with an `ArtifactCollector`
+pub fn render_register_dump(dump: &[RegisterValue]) -> Vec<u8> {
+    dump.iter().map(|r| format!("{}\n", r)).collect::<String>().into_bytes()
+}
+
+/// Describe a device register map once and generate typed accessors
+///
+/// ```ignore
+/// register_map! {
+///     pub Lis3dh {
+///         CTRL_REG1: 0x20 => CtrlReg1(u8) {
+///             odr: 4..8,
+///             lpen: 3,
+///             axes_enable: 0..3,
+///         }
+///         OUT_X: 0x28 => OutX(u16, le) {
+///             value: ro 0..16,
+///         }
+///     }
+/// }
+/// ```
+///
+/// Every register gets a value type with field getters and `set_*` setters
+/// (omitted for fields marked `ro`); single-bit fields are `bool`, ranges
+/// are half-open bit ranges of the register type. The map type gets
+/// `read_*`, `write_*` and `modify_*` per register plus `dump_all`, and
+/// lists its registers in `REGISTERS`.
+/// Multi-byte registers take `le` or `be`, defaulting to little endian.
+#[macro_export]
+macro_rules! register_map {
+    (@decode [] $ty:ty, $buf:ident) => { <$ty>::from_le_bytes($buf) };
+    (@decode [le] $ty:ty, $buf:ident) => { <$ty>::from_le_bytes($buf) };
+    (@decode [be] $ty:ty, $buf:ident) => { <$ty>::from_be_bytes($buf) };
+
+    (@big_endian []) => { false };
+    (@big_endian [le]) => { false };
+    (@big_endian [be]) => { true };
+
+    (@encode [] $value:expr) => { $value.to_le_bytes() };
+    (@encode [le] $value:expr) => { $value.to_le_bytes() };
+    (@encode [be] $value:expr) => { $value.to_be_bytes() };
+
+    (@field $ty:ty, [$($mode:ident)?], $field:ident, $bit:literal) => {
+        pub fn $field(&self) -> bool {
+            ((self.0 >> $bit) & 1) != 0
+        }
+
+        $crate::register_map!(@bit_setter [$($mode)?] $field, $bit);
+    };
+    (@bit_setter [] $field:ident, $bit:literal) => {
+        $crate::__register_map_paste! {
+            pub fn [<set_ $field>](&mut self, value: bool) -> &mut Self {
+                if value {
+                    self.0 |= 1 << $bit;
+                } else {
+                    self.0 &= !(1 << $bit);
+                }
+                self
+            }
+        }
+    };
+    (@bit_setter [ro] $field:ident, $bit:literal) => {};
+
+    (@field $ty:ty, [$($mode:ident)?], $field:ident, $lo:literal, $hi:literal) => {
+        pub fn $field(&self) -> $ty {
+            (self.0 >> $lo) & (<$ty>::MAX >> (<$ty>::BITS - ($hi - $lo)))
+        }
+
+        $crate::register_map!(@range_setter [$($mode)?] $ty, $field, $lo, $hi);
+    };
+    (@range_setter [] $ty:ty, $field:ident, $lo:literal, $hi:literal) => {
+        $crate::__register_map_paste! {
+            /// Bits of `value` beyond the field width are discarded
+            pub fn [<set_ $field>](&mut self, value: $ty) -> &mut Self {
+                let mask = (<$ty>::MAX >> (<$ty>::BITS - ($hi - $lo))) << $lo;
+                self.0 = (self.0 & !mask) | ((value << $lo) & mask);
+                self
+            }
+        }
+    };
+    (@range_setter [ro] $ty:ty, $field:ident, $lo:literal, $hi:literal) => {};
+
+    (
+        $(#[$meta:meta])*
+        $vis:vis $map:ident {
+            $(
+                $reg:ident : $addr:literal => $value:ident ( $ty:ty $(, $endian:ident)? ) {
+                    $( $field:ident : $($mode:ident)? $lo:literal $(.. $hi:literal)? ),* $(,)?
+                }
+            )*
+        }
+    ) => {
+        $(
+            #[doc = concat!("Value of the ", stringify!($reg), " register")]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+            $vis struct $value(pub $ty);
+
+            impl $value {
+                pub const ADDRESS: u8 = $addr;
+
+                pub fn bits(&self) -> $ty {
+                    self.0
+                }
+
+                $( $crate::register_map!(@field $ty, [$($mode)?], $field, $lo $(, $hi)?); )*
+            }
+        )*
+
+        $(#[$meta])*
+        $vis struct $map;
+
+        $crate::__register_map_paste! {
+            impl $map {
+                pub const REGISTERS: &'static [$crate::RegisterDescriptor] = &[
+                    $(
+                        $crate::RegisterDescriptor {
+                            name: stringify!($reg),
+                            address: $addr,
+                            width: ::std::mem::size_of::<$ty>(),
+                            big_endian: $crate::register_map!(@big_endian [$($endian)?]),
+                        },
+                    )*
+                ];
+
+                $(
+                    pub async fn [<read_ $reg:lower>](
+                        bus: &mut impl $crate::RegisterAccess,
+                    ) -> $crate::HardwareResult<$value> {
+                        let mut buf = [0u8; ::std::mem::size_of::<$ty>()];
+                        bus.read_registers($addr, &mut buf).await?;
+                        Ok($value($crate::register_map!(@decode [$($endian)?] $ty, buf)))
+                    }
+
+                    pub async fn [<write_ $reg:lower>](
+                        bus: &mut impl $crate::RegisterAccess,
+                        value: $value,
+                    ) -> $crate::HardwareResult<()> {
+                        let buf = $crate::register_map!(@encode [$($endian)?] value.0);
+                        bus.write_registers($addr, &buf).await
+                    }
+
+                    /// Read-modify-write, returning the value written
+                    pub async fn [<modify_ $reg:lower>]<F: FnOnce(&mut $value)>(
+                        bus: &mut impl $crate::RegisterAccess,
+                        update: F,
+                    ) -> $crate::HardwareResult<$value> {
+                        let mut value = Self::[<read_ $reg:lower>](bus).await?;
+                        update(&mut value);
+                        Self::[<write_ $reg:lower>](bus, value).await?;
+                        Ok(value)
+                    }
+                )*
+
+                /// Read every register in declaration order
+                pub async fn dump_all(
+                    bus: &mut impl $crate::RegisterAccess,
+                ) -> $crate::HardwareResult<Vec<$crate::RegisterValue>> {
+                    let mut dump = Vec::new();
+                    $(
+                        dump.push($crate::RegisterValue {
+                            name: stringify!($reg),
+                            address: $addr,
+                            width: ::std::mem::size_of::<$ty>(),
+                            value: Self::[<read_ $reg:lower>](bus).await?.0 as u64,
+                        });
+                    )*
+                    Ok(dump)
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeRegisterMap, HardwareInterface};
+
+    register_map! {
+        Lis3dh {
+            WHO_AM_I: 0x0F => WhoAmI(u8) {
+                id: ro 0..8,
+            }
+            CTRL_REG1: 0x20 => CtrlReg1(u8) {
+                odr: 4..8,
+                lpen: 3,
+                axes_enable: 0..3,
+            }
+            OUT_X: 0x28 => OutX(u16, le) {
+                value: ro 0..16,
+            }
+            THRESHOLD: 0x30 => Threshold(u16, be) {
+                level: 0..12,
+                latch: 15,
+            }
+        }
+    }
+
+    async fn device() -> RegisterBus<FakeRegisterMap> {
+        let mut fake = FakeRegisterMap::new()
+            .with_registers(0x0F, &[0x33])
+            .with_registers(0x20, &[0b0101_1010])
+            .with_registers(0x28, &[0x34, 0x12])
+            .with_registers(0x30, &[0x80, 0x7F]);
+        fake.initialize().await.unwrap();
+        RegisterBus::new(fake)
+    }
+
+    #[tokio::test]
+    async fn test_field_extraction() {
+        let mut bus = device().await;
+
+        let ctrl = Lis3dh::read_ctrl_reg1(&mut bus).await.unwrap();
+        assert_eq!(ctrl.odr(), 0b0101);
+        assert!(ctrl.lpen());
+        assert_eq!(ctrl.axes_enable(), 0b010);
+
+        assert_eq!(Lis3dh::read_out_x(&mut bus).await.unwrap().value(), 0x1234);
+
+        let threshold = Lis3dh::read_threshold(&mut bus).await.unwrap();
+        assert!(threshold.latch());
+        assert_eq!(threshold.level(), 0x07F);
+    }
+
+    #[tokio::test]
+    async fn test_read_modify_write() {
+        let mut bus = device().await;
+
+        let written = Lis3dh::modify_ctrl_reg1(&mut bus, |r| {
+            r.set_odr(0b1001).set_lpen(false);
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(written.bits(), 0b1001_0010);
+        assert_eq!(bus.inner().registers(0x20, 1), &[0b1001_0010]);
+
+        Lis3dh::modify_threshold(&mut bus, |r| {
+            r.set_level(0xFFFF);
+        })
+        .await
+        .unwrap();
+        assert_eq!(bus.inner().registers(0x30, 2), &[0x8F, 0xFF]);
+        assert_eq!(bus.inner().writes(), &[(0x20, vec![0b1001_0010]), (0x30, vec![0x8F, 0xFF])]);
+    }
+
+    #[tokio::test]
+    async fn test_dump_all() {
+        let mut bus = device().await;
+
+        let dump = Lis3dh::dump_all(&mut bus).await.unwrap();
+        let text = String::from_utf8(render_register_dump(&dump)).unwrap();
+
+        assert_eq!(
+            text,
+            "WHO_AM_I (0x0F) = 0x33\n\
+             CTRL_REG1 (0x20) = 0x5A\n\
+             OUT_X (0x28) = 0x1234\n\
+             THRESHOLD (0x30) = 0x807F\n"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+/*
+ * Flight Log Replay Through Mock Interfaces
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    Bidirectional, HardwareError, HardwareInterface, HardwareResult, InterfaceStatus, Readable, ReportAppendix, Writable,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Bus operation recorded in a transaction log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionOp {
+    Read,
+    Write,
+    Transfer,
+}
+
+impl fmt::Display for TransactionOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionOp::Read => write!(f, "read"),
+            TransactionOp::Write => write!(f, "write"),
+            TransactionOp::Transfer => write!(f, "transfer"),
+        }
+    }
+}
+
+/// One logged bus transaction; `tx` is what the flight driver sent and
+/// `rx` what the device answered, both as hex strings in the log
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transaction {
+    pub op: TransactionOp,
+    #[serde(default, deserialize_with = "hex_bytes")]
+    pub tx: Vec<u8>,
+    #[serde(default, deserialize_with = "hex_bytes")]
+    pub rx: Vec<u8>,
+}
+
+pub(crate) fn hex_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(serde::de::Error::custom(format!("odd number of hex digits in {:?}", text)));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| serde::de::Error::custom(format!("invalid hex byte {:?}", byte)))
+        })
+        .collect()
+}
+
+/// Transaction log dumped from orbit together with the outputs the flight
+/// software reported while it ran
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlightLog {
+    pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub reported: BTreeMap<String, serde_json::Value>,
+}
+
+impl FlightLog {
+    pub fn from_json(text: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(text)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::from_json(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Offset-prefixed hex dump, 16 bytes per line
+pub fn hexdump(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "    (empty)\n".to_string();
+    }
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("    {:04x}: {}\n", line * 16, hex.join(" "))
+        })
+        .collect()
+}
+
+/// Divergence between the replayed driver and the flight log
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayMismatch {
+    /// The driver sent different bytes than the flight driver did
+    Sent { index: usize, op: TransactionOp, expected: Vec<u8>, actual: Vec<u8> },
+    /// The driver issued a different kind of operation
+    Operation { index: usize, expected: TransactionOp, actual: TransactionOp },
+    /// The driver issued more operations than were logged
+    Exhausted { index: usize, op: TransactionOp },
+    /// Logged transactions the driver never issued
+    Unconsumed { remaining: usize },
+    /// A driver output differs from what the flight software reported
+    Output { name: String, after: usize, expected: serde_json::Value, actual: serde_json::Value },
+    /// The log holds no reported value to compare an output against
+    NotReported { name: String },
+}
+
+impl fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayMismatch::Sent { index, op, expected, actual } => write!(
+                f,
+                "transaction {} ({}): sent bytes differ\n  expected:\n{}  actual:\n{}",
+                index,
+                op,
+                hexdump(expected),
+                hexdump(actual)
+            ),
+            ReplayMismatch::Operation { index, expected, actual } => {
+                write!(f, "transaction {}: expected {}, driver issued {}", index, expected, actual)
+            }
+            ReplayMismatch::Exhausted { index, op } => write!(f, "transaction {}: {} past the end of the log", index, op),
+            ReplayMismatch::Unconsumed { remaining } => write!(f, "{} logged transactions not replayed", remaining),
+            ReplayMismatch::Output { name, after, expected, actual } => write!(
+                f,
+                "output {} after transaction {}: flight reported {}, replay produced {}",
+                name, after, expected, actual
+            ),
+            ReplayMismatch::NotReported { name } => write!(f, "output {} was not reported in the flight log", name),
+        }
+    }
+}
+
+struct ReplayState {
+    log: FlightLog,
+    position: usize,
+    mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayState {
+    /// Next logged transaction if it has the requested kind
+    fn next(&mut self, op: TransactionOp) -> HardwareResult<(usize, Transaction)> {
+        let index = self.position;
+        let logged = match self.log.transactions.get(index) {
+            Some(logged) => logged.clone(),
+            None => {
+                self.mismatches.push(ReplayMismatch::Exhausted { index, op });
+                return Err(HardwareError::CommunicationError(format!("replay log exhausted at transaction {}", index)));
+            }
+        };
+        if logged.op != op {
+            self.mismatches.push(ReplayMismatch::Operation { index, expected: logged.op, actual: op });
+            return Err(HardwareError::CommunicationError(format!(
+                "replay expected {} at transaction {}, got {}",
+                logged.op, index, op
+            )));
+        }
+        self.position += 1;
+        Ok((index, logged))
+    }
+
+    fn compare_sent(&mut self, index: usize, logged: &Transaction, sent: &[u8]) {
+        if logged.tx != sent {
+            self.mismatches.push(ReplayMismatch::Sent {
+                index,
+                op: logged.op,
+                expected: logged.tx.clone(),
+                actual: sent.to_vec(),
+            });
+        }
+    }
+}
+
+/// Replays a flight log against current driver code
+///
+/// Drivers are given a `ReplayInterface`, which answers every operation
+/// with the response logged in flight and records where the driver's
+/// traffic diverges. Driver outputs are then compared against what the
+/// flight software reported with `check_output`.
+pub struct FlightLogReplayer {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl FlightLogReplayer {
+    pub fn new(log: FlightLog) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ReplayState {
+                log,
+                position: 0,
+                mismatches: Vec::new(),
+            })),
+        }
+    }
+
+    /// Interface feeding the logged responses; all interfaces share the
+    /// replay position
+    pub fn interface(&self) -> ReplayInterface {
+        ReplayInterface {
+            state: self.state.clone(),
+            initialized: false,
+        }
+    }
+
+    /// Compare a driver output with the value reported under `name`
+    pub fn check_output<V: Serialize>(&self, name: &str, actual: &V) {
+        let actual = serde_json::to_value(actual).unwrap_or_else(|e| serde_json::Value::String(e.to_string()));
+        let mut state = self.state.lock().unwrap();
+        let after = state.position.saturating_sub(1);
+        let mismatch = match state.log.reported.get(name) {
+            Some(expected) if *expected == actual => return,
+            Some(expected) => ReplayMismatch::Output {
+                name: name.to_string(),
+                after,
+                expected: expected.clone(),
+                actual,
+            },
+            None => ReplayMismatch::NotReported { name: name.to_string() },
+        };
+        state.mismatches.push(mismatch);
+    }
+
+    /// Mismatches so far, plus any logged transactions not yet replayed
+    pub fn mismatches(&self) -> Vec<ReplayMismatch> {
+        let state = self.state.lock().unwrap();
+        let mut mismatches = state.mismatches.clone();
+        let remaining = state.log.transactions.len().saturating_sub(state.position);
+        if remaining > 0 {
+            mismatches.push(ReplayMismatch::Unconsumed { remaining });
+        }
+        mismatches
+    }
+
+    pub fn check(&self) -> HardwareResult<()> {
+        let mismatches = self.mismatches();
+        match mismatches.first() {
+            None => Ok(()),
+            Some(first) => Err(HardwareError::OperationFailed(format!(
+                "{} replay mismatches, first: {}",
+                mismatches.len(),
+                first.to_string().lines().next().unwrap_or_default()
+            ))),
+        }
+    }
+
+    pub fn appendix(&self) -> ReportAppendix {
+        let body: String = self.mismatches().iter().map(|m| format!("{}\n", m)).collect();
+        ReportAppendix::new("Flight log replay", &body)
+    }
+}
+
+/// Interface answering operations from a flight log
+pub struct ReplayInterface {
+    state: Arc<Mutex<ReplayState>>,
+    initialized: bool,
+}
+
+#[async_trait]
+impl HardwareInterface for ReplayInterface {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.initialized = false;
+        Ok(())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        Ok(InterfaceStatus {
+            initialized: self.initialized,
+            error_count: self.state.lock().unwrap().mismatches.len() as u32,
+            last_error: None,
+            uptime: Duration::from_secs(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Readable for ReplayInterface {
+    async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        let (_, logged) = self.state.lock().unwrap().next(TransactionOp::Read)?;
+        let count = buffer.len().min(logged.rx.len());
+        buffer[..count].copy_from_slice(&logged.rx[..count]);
+        Ok(count)
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        if self.read(buffer, timeout).await? < buffer.len() {
+            return Err(HardwareError::TimeoutError);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Writable for ReplayInterface {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        let (index, logged) = state.next(TransactionOp::Write)?;
+        state.compare_sent(index, &logged, data);
+        Ok(data.len())
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        self.write(data).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Bidirectional for ReplayInterface {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        let (index, logged) = state.next(TransactionOp::Transfer)?;
+        state.compare_sent(index, &logged, tx_data);
+        // Like a short read, the driver only gets the bytes that were logged
+        let count = rx_data.len().min(logged.rx.len());
+        rx_data[..count].copy_from_slice(&logged.rx[..count]);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SpiFlash;
+
+    fn flight_log(jedec_rx: &str) -> FlightLog {
+        FlightLog::from_json(&format!(
+            r#"{{
+                "transactions": [
+                    {{"op": "transfer", "tx": "9f000000", "rx": "{}"}},
+                    {{"op": "transfer", "tx": "0500", "rx": "ff03"}}
+                ],
+                "reported": {{
+                    "jedec_id": {{"manufacturer": 239, "memory_type": 64, "capacity": 24}},
+                    "status": 3
+                }}
+            }}"#,
+            jedec_rx
+        ))
+        .unwrap()
+    }
+
+    async fn replay_driver(replayer: &FlightLogReplayer) {
+        let mut flash = SpiFlash::new(replayer.interface());
+        let id = flash.read_jedec_id().await.unwrap();
+        replayer.check_output("jedec_id", &id);
+        let status = flash.read_status().await.unwrap();
+        replayer.check_output("status", &status);
+    }
+
+    #[tokio::test]
+    async fn test_replay_matches_flight() {
+        let replayer = FlightLogReplayer::new(flight_log("ffef4018"));
+
+        replay_driver(&replayer).await;
+
+        assert_eq!(replayer.mismatches(), vec![]);
+        assert!(replayer.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_altered_response_reports_output_mismatch() {
+        let replayer = FlightLogReplayer::new(flight_log("ffef4017"));
+
+        replay_driver(&replayer).await;
+
+        let mismatches = replayer.mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "output jedec_id after transaction 0: flight reported {\"capacity\":24,\"manufacturer\":239,\"memory_type\":64}, \
+             replay produced {\"capacity\":23,\"manufacturer\":239,\"memory_type\":64}"
+        );
+        assert!(replayer.check().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_diverging_traffic_is_hexdumped() {
+        let replayer = FlightLogReplayer::new(flight_log("ffef4018"));
+        let mut spi = replayer.interface();
+
+        let mut rx = [0u8; 2];
+        spi.transfer(&[0x9f, 0x00, 0x00, 0x01], &mut [0u8; 4], Duration::ZERO).await.unwrap();
+        spi.transfer(&[0x05, 0x00], &mut rx, Duration::ZERO).await.unwrap();
+        assert_eq!(rx, [0xff, 0x03]);
+        assert!(spi.write(&[0x06]).await.is_err());
+
+        let mismatches = replayer.mismatches();
+        assert_eq!(
+            mismatches[0].to_string(),
+            "transaction 0 (transfer): sent bytes differ\n  expected:\n    0000: 9f 00 00 00\n  actual:\n    0000: 9f 00 00 01\n"
+        );
+        assert_eq!(mismatches[1], ReplayMismatch::Exhausted { index: 2, op: TransactionOp::Write });
+    }
+
+    #[tokio::test]
+    async fn test_transfer_returns_logged_length() {
+        let replayer = FlightLogReplayer::new(flight_log("ffef4018"));
+        let mut spi = replayer.interface();
+
+        let mut rx = [0u8; 6];
+        let count = spi.transfer(&[0x9f, 0x00, 0x00, 0x00], &mut rx, Duration::ZERO).await.unwrap();
+
+        assert_eq!(count, 4);
+        assert_eq!(rx, [0xff, 0xef, 0x40, 0x18, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_rejects_bad_hex() {
+        let error = FlightLog::from_json(r#"{"transactions": [{"op": "read", "rx": "0g"}]}"#).unwrap_err();
+        assert!(error.to_string().contains("invalid hex byte \"0g\""));
+    }
+}
@@ -0,0 +1,534 @@
+/*
+ * Machine-Readable Suite Reports
+ * Copyright (C) 2024
+ */
+
+use crate::{AdviceRegistry, TestResult, TestStatus, TestSuiteResult};
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Version of the JSON report layout, bumped on incompatible changes
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Status keyword and message of a result, as used by every report format
+pub fn status_parts(status: &TestStatus) -> (&'static str, Option<&str>) {
+    match status {
+        TestStatus::Passed => ("passed", None),
+        TestStatus::Failed(msg) => ("failed", Some(msg)),
+        TestStatus::Skipped(msg) => ("skipped", Some(msg)),
+        TestStatus::Error(msg) => ("error", Some(msg)),
+    }
+}
+
+/// Escape text for use inside a Markdown table cell
+fn md_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Escape text for XML attribute values and character data, replacing
+/// control characters XML 1.0 cannot represent
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn result_json(result: &TestResult) -> Value {
+    let (status, message) = status_parts(&result.status);
+    json!({
+        "name": result.name,
+        "status": status,
+        "message": message,
+        "duration_secs": result.duration.as_secs_f64(),
+        "error_count": result.error_count,
+        "warning_count": result.warning_count,
+        "notes": result.notes,
+        "params": result.params,
+        "manual": result.manual,
+        "quarantined": result.quarantined,
+    })
+}
+
+impl TestSuiteResult {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "schema_version": REPORT_SCHEMA_VERSION,
+            "name": self.name,
+            "total_tests": self.total_tests,
+            "passed_tests": self.passed_tests,
+            "failed_tests": self.failed_tests,
+            "skipped_tests": self.skipped_tests,
+            "error_tests": self.error_tests,
+            "quarantined_failures": self.quarantined_failures,
+            "total_duration_secs": self.total_duration.as_secs_f64(),
+            "budget_exceeded": self.budget_exceeded.as_ref().map(|b| b.to_string()),
+            "environment": self.environment,
+            "results": self.results.iter().map(result_json).collect::<Vec<_>>(),
+            "timing_regressions": self.timing_regressions.iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+            "invariant_violations": self.invariant_violations.iter()
+                .map(|v| json!({ "invariant": v.invariant, "message": v.message, "fails_suite": v.fails_suite }))
+                .collect::<Vec<_>>(),
+            "safe_state": self.safe_state.as_ref().map(|record| json!({
+                "event": record.event.to_string(),
+                "complete": record.is_complete(),
+                "hooks": record.hooks.iter()
+                    .map(|h| json!({
+                        "name": h.name,
+                        "outcome": h.outcome.to_string(),
+                        "duration_secs": h.duration.as_secs_f64(),
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+            "appendices": self.appendices.iter()
+                .map(|a| json!({ "title": a.title, "body": a.body }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(&self.to_json())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(path, text)
+    }
+
+    /// Human-readable report with operator guidance for every failed test
+    pub fn to_markdown(&self, advice: &AdviceRegistry) -> String {
+        let mut md = format!("# {}\n\n", self.name);
+        md.push_str(&format!(
+            "{} passed, {} failed, {} skipped, {} errors in {:?}\n\n",
+            self.passed_tests, self.failed_tests, self.skipped_tests, self.error_tests, self.total_duration
+        ));
+        if self.quarantined_failures > 0 {
+            md.push_str(&format!(
+                "{} quarantined failures, not failing the suite\n\n",
+                self.quarantined_failures
+            ));
+        }
+        if let Some(environment) = &self.environment {
+            md.push_str(&format!("Environment: {}\n\n", environment));
+        }
+        if let Some(exceeded) = &self.budget_exceeded {
+            md.push_str(&format!("Budget exceeded: {}\n\n", exceeded));
+        }
+
+        md.push_str("| Test | Status | Duration | Details |\n|---|---|---|---|\n");
+        for result in &self.results {
+            let (status, message) = status_parts(&result.status);
+            md.push_str(&format!(
+                "| {} | {}{}{} | {:?} | {} |\n",
+                md_cell(&result.name),
+                status,
+                if result.manual { " (manual)" } else { "" },
+                if result.quarantined { " (quarantined)" } else { "" },
+                result.duration,
+                md_cell(message.unwrap_or(""))
+            ));
+        }
+
+        let guided: Vec<_> = self
+            .results
+            .iter()
+            .filter_map(|r| advice.advice_for_result(r).map(|a| (r, a)))
+            .collect();
+        if !guided.is_empty() {
+            md.push_str("\n## Operator guidance\n");
+            for (result, advice) in guided {
+                md.push_str(&format!("\n### {}\n\nSeverity: {}\n", result.name, advice.severity));
+                if !advice.probable_causes.is_empty() {
+                    md.push_str("\nProbable causes:\n");
+                    for cause in &advice.probable_causes {
+                        md.push_str(&format!("- {}\n", cause));
+                    }
+                }
+                if !advice.suggested_actions.is_empty() {
+                    md.push_str("\nSuggested actions:\n");
+                    for action in &advice.suggested_actions {
+                        md.push_str(&format!("- {}\n", action));
+                    }
+                }
+            }
+        }
+
+        let manual: Vec<_> = self.results.iter().filter(|r| r.manual).collect();
+        if !manual.is_empty() {
+            md.push_str("\n## Manual steps\n\n");
+            for result in manual {
+                let (status, _) = status_parts(&result.status);
+                md.push_str(&format!("- {}: {}\n", result.name, status));
+                for note in result.operator_notes() {
+                    md.push_str(&format!("  - operator: {}\n", note));
+                }
+            }
+        }
+
+        let quarantined: Vec<_> = self.results.iter().filter(|r| r.quarantined).collect();
+        if !quarantined.is_empty() {
+            md.push_str("\n## Quarantined tests\n\n");
+            for result in quarantined {
+                let (status, message) = status_parts(&result.status);
+                match message {
+                    Some(message) => md.push_str(&format!("- {}: {} ({})\n", result.name, status, message)),
+                    None => md.push_str(&format!("- {}: {}\n", result.name, status)),
+                }
+            }
+        }
+
+        if !self.timing_regressions.is_empty() {
+            md.push_str("\n## Timing regressions\n\n");
+            for regression in &self.timing_regressions {
+                md.push_str(&format!("- {}\n", regression));
+            }
+        }
+
+        if !self.invariant_violations.is_empty() {
+            md.push_str("\n## Suite invariants\n\n");
+            for violation in &self.invariant_violations {
+                let marker = if violation.fails_suite { " (fails suite)" } else { "" };
+                md.push_str(&format!("- {}{}\n", violation, marker));
+            }
+        }
+
+        if let Some(record) = &self.safe_state {
+            md.push_str("\n## Safe state\n\n");
+            if record.is_complete() {
+                md.push_str(&format!("Entered after {}\n\n", record.event));
+            } else {
+                md.push_str(&format!("**INCOMPLETE** safe-state sequence after {}\n\n", record.event));
+            }
+            for hook in &record.hooks {
+                md.push_str(&format!("- {}: {} ({:?})\n", hook.name, hook.outcome, hook.duration));
+            }
+        }
+
+        for appendix in &self.appendices {
+            md.push_str(&format!("\n## {}\n\n```\n{}\n```\n", appendix.title, appendix.body.trim_end()));
+        }
+        md
+    }
+
+    pub fn write_markdown(&self, path: &Path, advice: &AdviceRegistry) -> io::Result<()> {
+        fs::write(path, self.to_markdown(advice))
+    }
+
+    /// JUnit XML for CI test reporting such as GitLab and Jenkins
+    ///
+    /// Quarantined tests are marked with a `quarantined` property; their
+    /// failures and errors are written as `<skipped>` with the real status in
+    /// the message, so they never fail CI. Test notes go to `<system-out>`. The test
+    /// environment becomes suite `<properties>`, and suite-level failures
+    /// (invariant violations failing the suite, an incomplete safe-state
+    /// sequence) are added as failing test cases, so a CI reading only
+    /// the JUnit file fails whenever `succeeded()` is false.
+    pub fn to_junit_xml(&self) -> String {
+        let suite = xml_escape(&self.name);
+        let suite_failures = self.suite_failures();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            suite,
+            self.total_tests + suite_failures.len(),
+            self.failed_tests + suite_failures.len(),
+            self.error_tests,
+            self.skipped_tests + self.quarantined_failures,
+            self.total_duration.as_secs_f64()
+        ));
+        let properties = self.junit_properties();
+        if !properties.is_empty() {
+            xml.push_str("  <properties>\n");
+            for (name, value) in properties {
+                xml.push_str(&format!(
+                    "    <property name=\"{}\" value=\"{}\"/>\n",
+                    xml_escape(&name),
+                    xml_escape(&value)
+                ));
+            }
+            xml.push_str("  </properties>\n");
+        }
+        for result in &self.results {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&result.name),
+                suite,
+                result.duration.as_secs_f64()
+            ));
+            let message = xml_escape(status_parts(&result.status).1.unwrap_or(""));
+            let outcome = match &result.status {
+                TestStatus::Passed => None,
+                TestStatus::Failed(_) if result.quarantined => {
+                    Some(format!("<skipped message=\"quarantined failure: {}\"/>", message))
+                }
+                TestStatus::Error(_) if result.quarantined => {
+                    Some(format!("<skipped message=\"quarantined error: {}\"/>", message))
+                }
+                TestStatus::Failed(_) => Some(format!("<failure message=\"{0}\">{0}</failure>", message)),
+                TestStatus::Error(_) => Some(format!("<error message=\"{0}\">{0}</error>", message)),
+                TestStatus::Skipped(_) => Some(format!("<skipped message=\"{}\"/>", message)),
+            };
+            if outcome.is_none() && result.notes.is_empty() && !result.quarantined {
+                xml.push_str("/>\n");
+                continue;
+            }
+            xml.push_str(">\n");
+            if result.quarantined {
+                xml.push_str("    <properties>\n      <property name=\"quarantined\" value=\"true\"/>\n    </properties>\n");
+            }
+            if let Some(outcome) = outcome {
+                xml.push_str(&format!("    {}\n", outcome));
+            }
+            if !result.notes.is_empty() {
+                xml.push_str(&format!("    <system-out>{}</system-out>\n", xml_escape(&result.notes.join("\n"))));
+            }
+            xml.push_str("  </testcase>\n");
+        }
+        for (name, message, detail) in suite_failures {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"0.000\">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                xml_escape(&name),
+                suite,
+                xml_escape(&message),
+                xml_escape(&detail)
+            ));
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+
+    /// Environment of the run as JUnit property names and values
+    fn junit_properties(&self) -> Vec<(String, String)> {
+        let environment = match &self.environment {
+            Some(environment) => environment,
+            None => return Vec::new(),
+        };
+        let mut properties = Vec::new();
+        if let Some(temperature) = environment.temperature_c {
+            properties.push(("temperature_c".to_string(), temperature.to_string()));
+        }
+        if let Some(voltage) = environment.supply_voltage {
+            properties.push(("supply_voltage".to_string(), voltage.to_string()));
+        }
+        if let Some(profile) = &environment.chamber_profile {
+            properties.push(("chamber_profile".to_string(), profile.clone()));
+        }
+        if let Some(operator) = &environment.operator {
+            properties.push(("operator".to_string(), operator.clone()));
+        }
+        properties.extend(environment.extra.iter().map(|(key, value)| (key.clone(), value.clone())));
+        properties
+    }
+
+    /// Failures of the suite as a whole rather than of one test, as test
+    /// case name, message and detail
+    fn suite_failures(&self) -> Vec<(String, String, String)> {
+        let mut failures: Vec<(String, String, String)> = self
+            .invariant_violations
+            .iter()
+            .filter(|violation| violation.fails_suite)
+            .map(|violation| {
+                let name = format!("invariant::{}", violation.invariant);
+                (name, violation.message.clone(), violation.to_string())
+            })
+            .collect();
+        if let Some(record) = self.safe_state.as_ref().filter(|record| !record.is_complete()) {
+            let message = format!("incomplete safe-state sequence after {}", record.event);
+            failures.push(("safe_state".to_string(), message, record.to_string()));
+        }
+        failures
+    }
+
+    pub fn write_junit_xml(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_junit_xml())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AdviceMatcher, AdviceSeverity, ErrorAdvice, FatalEvent, HardwareErrorKind, HookOutcome, HookRecord, InvariantViolation,
+        SafeStateRecord, TestEnvironmentInfo,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_json_report() {
+        let results = vec![
+            TestResult::new("init", TestStatus::Passed, Duration::from_millis(500)),
+            TestResult::new("read", TestStatus::Error("Test failed: TimeoutError".to_string()), Duration::from_secs(1)),
+        ];
+        let mut suite = TestSuiteResult::from_results("i2c", results, Duration::from_millis(1500));
+        suite.environment = Some(TestEnvironmentInfo::new().temperature_c(60.0).operator("ana"));
+
+        let json = suite.to_json();
+
+        assert_eq!(json["schema_version"], REPORT_SCHEMA_VERSION);
+        assert_eq!(json["error_tests"], 1);
+        assert_eq!(json["results"][0]["status"], "passed");
+        assert_eq!(json["results"][0]["duration_secs"], 0.5);
+        assert_eq!(json["results"][1]["message"], "Test failed: TimeoutError");
+        assert_eq!(json["environment"]["temperature_c"], 60.0);
+        assert_eq!(json["environment"]["operator"], "ana");
+    }
+
+    #[test]
+    fn test_markdown_guidance() {
+        let mut read = TestResult::new("read", TestStatus::Error("Test failed: TimeoutError".to_string()), Duration::from_secs(1));
+        read.error_kind = Some(HardwareErrorKind::TimeoutError);
+        let results = vec![TestResult::new("init", TestStatus::Passed, Duration::from_millis(5)), read];
+        let suite = TestSuiteResult::from_results("uart", results, Duration::from_millis(1005));
+        let mut advice = AdviceRegistry::empty();
+        advice.register(
+            AdviceMatcher::Kind(HardwareErrorKind::TimeoutError),
+            ErrorAdvice::new(AdviceSeverity::Medium)
+                .cause("Device held in reset")
+                .action("Check the reset line"),
+        );
+
+        let md = suite.to_markdown(&advice);
+
+        assert!(md.starts_with("# uart\n\n1 passed, 0 failed, 0 skipped, 1 errors"));
+        assert!(md.contains("| read | error | 1s | Test failed: TimeoutError |\n"));
+        assert!(md.contains(
+            "## Operator guidance\n\n### read\n\nSeverity: medium\n\n\
+             Probable causes:\n- Device held in reset\n\n\
+             Suggested actions:\n- Check the reset line\n"
+        ));
+        assert!(!md.contains("### init"));
+    }
+
+    #[test]
+    fn test_write_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let suite = TestSuiteResult::from_results("empty", Vec::new(), Duration::ZERO);
+
+        suite.write_json(&path).unwrap();
+
+        let value: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(value["name"], "empty");
+        assert_eq!(value["environment"], Value::Null);
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let mut quarantined = TestResult::new("burst read", TestStatus::Error("Test failed: TimeoutError".to_string()), Duration::ZERO);
+        quarantined.quarantined = true;
+        let mut noted = TestResult::new("read <0x50> & 'verify'", TestStatus::Passed, Duration::from_millis(1250));
+        noted.notes.push("retried \"twice\"".to_string());
+        let results = vec![
+            TestResult::new("init", TestStatus::Passed, Duration::from_millis(5)),
+            noted,
+            TestResult::new("write", TestStatus::Failed("2 errors reported".to_string()), Duration::from_millis(20)),
+            TestResult::new(
+                "transfer",
+                TestStatus::Error("Test failed: CommunicationError(\"<nack>\")".to_string()),
+                Duration::from_millis(100),
+            ),
+            TestResult::new("flash", TestStatus::Skipped("no flash fitted".to_string()), Duration::ZERO),
+            quarantined,
+        ];
+        let suite = TestSuiteResult::from_results("i2c & spi", results, Duration::from_millis(1375));
+
+        assert_eq!(
+            suite.to_junit_xml(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"i2c &amp; spi\" tests=\"6\" failures=\"1\" errors=\"1\" skipped=\"2\" time=\"1.375\">\n\
+             \x20 <testcase name=\"init\" classname=\"i2c &amp; spi\" time=\"0.005\"/>\n\
+             \x20 <testcase name=\"read &lt;0x50&gt; &amp; &apos;verify&apos;\" classname=\"i2c &amp; spi\" time=\"1.250\">\n\
+             \x20   <system-out>retried &quot;twice&quot;</system-out>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"write\" classname=\"i2c &amp; spi\" time=\"0.020\">\n\
+             \x20   <failure message=\"2 errors reported\">2 errors reported</failure>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"transfer\" classname=\"i2c &amp; spi\" time=\"0.100\">\n\
+             \x20   <error message=\"Test failed: CommunicationError(&quot;&lt;nack&gt;&quot;)\">\
+             Test failed: CommunicationError(&quot;&lt;nack&gt;&quot;)</error>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"flash\" classname=\"i2c &amp; spi\" time=\"0.000\">\n\
+             \x20   <skipped message=\"no flash fitted\"/>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"burst read\" classname=\"i2c &amp; spi\" time=\"0.000\">\n\
+             \x20   <properties>\n\
+             \x20     <property name=\"quarantined\" value=\"true\"/>\n\
+             \x20   </properties>\n\
+             \x20   <skipped message=\"quarantined error: Test failed: TimeoutError\"/>\n\
+             \x20 </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+
+    #[test]
+    fn test_junit_properties_and_suite_failures() {
+        let results = vec![TestResult::new("init", TestStatus::Passed, Duration::from_millis(5))];
+        let mut suite = TestSuiteResult::from_results("eps", results, Duration::from_millis(5));
+        suite.environment = Some(TestEnvironmentInfo::new().temperature_c(-20.0).operator("ana").with("board", "fm<1>"));
+        suite.invariant_violations = vec![
+            InvariantViolation {
+                invariant: "no_unexpected_reset".to_string(),
+                message: "uptime went back".to_string(),
+                fails_suite: true,
+            },
+            InvariantViolation {
+                invariant: "error_budget".to_string(),
+                message: "3 new errors".to_string(),
+                fails_suite: false,
+            },
+        ];
+        suite.safe_state = Some(SafeStateRecord {
+            event: FatalEvent::Panicked("boom".to_string()),
+            hooks: vec![HookRecord {
+                name: "rails off".to_string(),
+                outcome: HookOutcome::TimedOut,
+                duration: Duration::from_secs(1),
+            }],
+        });
+        assert!(!suite.succeeded());
+
+        let xml = suite.to_junit_xml();
+
+        assert!(xml.contains("<testsuite name=\"eps\" tests=\"3\" failures=\"2\" errors=\"0\" skipped=\"0\""));
+        assert!(xml.contains(
+            "  <properties>\n\
+             \x20   <property name=\"temperature_c\" value=\"-20\"/>\n\
+             \x20   <property name=\"operator\" value=\"ana\"/>\n\
+             \x20   <property name=\"board\" value=\"fm&lt;1&gt;\"/>\n\
+             \x20 </properties>\n"
+        ));
+        assert!(xml.contains(
+            "  <testcase name=\"invariant::no_unexpected_reset\" classname=\"eps\" time=\"0.000\">\n\
+             \x20   <failure message=\"uptime went back\">no_unexpected_reset: uptime went back</failure>\n"
+        ));
+        assert!(!xml.contains("invariant::error_budget"));
+        assert!(xml.contains("<testcase name=\"safe_state\" classname=\"eps\" time=\"0.000\">\n    <failure message=\"incomplete safe-state sequence after"));
+    }
+
+    #[test]
+    fn test_xml_escape_control_characters() {
+        assert_eq!(xml_escape("bus\u{0}idle\tok\n"), "bus\u{FFFD}idle\tok\n");
+    }
+
+    #[test]
+    fn test_write_junit_xml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("junit.xml");
+        let suite = TestSuiteResult::from_results("empty", Vec::new(), Duration::ZERO);
+
+        suite.write_junit_xml(&path).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().ends_with(
+            "<testsuite name=\"empty\" tests=\"0\" failures=\"0\" errors=\"0\" skipped=\"0\" time=\"0.000\">\n</testsuite>\n"
+        ));
+    }
+}
@@ -3,11 +3,39 @@
  * Copyright (C) 2024
  */
 
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus};
-use std::time::{Duration, Instant};
+use crate::{
+    with_lock_holder, ArchivedRun, ArtifactCollector, Budget, BudgetExceeded, DeviceSnapshot, DiagMutex, HardwareError, HardwareErrorKind,
+    HardwareInterface, HardwareResult, InterfaceStatus, InvariantViolation, LatencyProfile, ManualRecord, ManualStep, OperationStats, OperatorPrompt, StatsSnapshot,
+    PowerCycle, RegisterAccess, RegisterDescriptor, RunArchive, RunnerEvent, ScopeMeasurement, SnapshotCheck,
+    Quarantine, FatalEvent, SafeState, SafeStateRecord, SettleError, Settling, SuiteInvariant, SuiteRun, TestEnvironmentInfo, TestObserver, TimingRegression, REQUIRES_OPERATOR,
+};
+use crate::capabilities::record_skip;
+use crate::deadline::{declare_test_timeout, run_with_deadline, TestTimeout};
+use crate::safe_state::{panic_message, CatchPanic, SafeStateGuard};
+use crate::timed_scope::{record_scopes, scope_appendix};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::Instant;
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Boxed future returned by a test closure
+pub type TestFuture = Pin<Box<dyn Future<Output = HardwareResult<()>> + Send>>;
+
+/// Boxed test closure, allowing suites to mix differently-typed closures
+pub type TestFn<T> = Box<dyn FnOnce(Arc<DiagMutex<T>>) -> TestFuture + Send>;
+
+/// Tags of the tests of a suite, by test name
+pub(crate) type TestTags = BTreeMap<String, &'static [&'static str]>;
+
+/// Interval at which a running test's operations and errors are checked
+/// against the runner's budget
+const BUDGET_POLL: Duration = Duration::from_millis(10);
 
 /// Test result status
 #[derive(Debug, Clone, PartialEq)]
@@ -26,6 +54,37 @@ pub struct TestResult {
     pub duration: Duration,
     pub error_count: u32,
     pub warning_count: u32,
+    pub notes: Vec<String>,
+    /// Sweep parameters the test ran with, empty for plain tests
+    pub params: BTreeMap<String, String>,
+    /// Outcome given by an operator rather than by test code
+    pub manual: bool,
+    /// `TimedScope`s the test went through, in start order
+    pub scopes: Vec<ScopeMeasurement>,
+    /// On the runner's quarantine list; a failure is counted apart and
+    /// does not fail the suite
+    pub quarantined: bool,
+    /// Kind of the error the test ended with, for matching advice without
+    /// parsing the status message
+    pub error_kind: Option<HardwareErrorKind>,
+}
+
+impl TestResult {
+    pub fn new(name: &str, status: TestStatus, duration: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            duration,
+            error_count: 0,
+            warning_count: 0,
+            notes: Vec::new(),
+            params: BTreeMap::new(),
+            manual: false,
+            scopes: Vec::new(),
+            quarantined: false,
+            error_kind: None,
+        }
+    }
 }
 
 impl fmt::Display for TestResult {
@@ -38,7 +97,21 @@ impl fmt::Display for TestResult {
             self.duration,
             self.error_count,
             self.warning_count
-        )
+        )?;
+        
+        for (name, value) in &self.params {
+            writeln!(f, "Param: {}={}", name, value)?;
+        }
+        
+        for note in &self.notes {
+            writeln!(f, "Note: {}", note)?;
+        }
+        
+        if self.quarantined {
+            writeln!(f, "Quarantined")?;
+        }
+        
+        Ok(())
     }
 }
 
@@ -52,7 +125,76 @@ pub struct TestSuiteResult {
     pub failed_tests: usize,
     pub skipped_tests: usize,
     pub error_tests: usize,
+    /// Failed or errored quarantined tests, left out of `failed_tests` and
+    /// `error_tests`
+    pub quarantined_failures: usize,
     pub total_duration: Duration,
+    pub budget_exceeded: Option<BudgetExceeded>,
+    pub appendices: Vec<ReportAppendix>,
+    pub environment: Option<TestEnvironmentInfo>,
+    /// Slowdowns against a timing baseline, reported apart from failures
+    pub timing_regressions: Vec<TimingRegression>,
+    /// Violations of the runner's suite invariants
+    pub invariant_violations: Vec<InvariantViolation>,
+    /// Safe-state hooks run after a fatal event during the suite
+    pub safe_state: Option<SafeStateRecord>,
+}
+
+impl TestSuiteResult {
+    /// Build a suite result, deriving the counts from the individual results
+    pub fn from_results(name: &str, results: Vec<TestResult>, total_duration: Duration) -> Self {
+        let count = |f: fn(&TestStatus) -> bool| results.iter().filter(|r| f(&r.status)).count();
+        let counted = |f: fn(&TestStatus) -> bool| results.iter().filter(|r| !r.quarantined && f(&r.status)).count();
+        Self {
+            name: name.to_string(),
+            total_tests: results.len(),
+            passed_tests: count(|s| matches!(s, TestStatus::Passed)),
+            failed_tests: counted(|s| matches!(s, TestStatus::Failed(_))),
+            skipped_tests: count(|s| matches!(s, TestStatus::Skipped(_))),
+            error_tests: counted(|s| matches!(s, TestStatus::Error(_))),
+            quarantined_failures: results
+                .iter()
+                .filter(|r| r.quarantined && matches!(r.status, TestStatus::Failed(_) | TestStatus::Error(_)))
+                .count(),
+            total_duration,
+            budget_exceeded: None,
+            appendices: Vec::new(),
+            environment: None,
+            timing_regressions: Vec::new(),
+            invariant_violations: Vec::new(),
+            safe_state: None,
+            results,
+        }
+    }
+    
+    /// No test outside the quarantine failed or errored, no invariant
+    /// violation fails the suite and no safe-state sequence was left
+    /// incomplete
+    pub fn succeeded(&self) -> bool {
+        self.failed_tests + self.error_tests == 0
+            && !self.invariant_violations.iter().any(|v| v.fails_suite)
+            && self.safe_state.as_ref().map_or(true, |record| record.is_complete())
+    }
+    
+    pub fn add_appendix(&mut self, appendix: ReportAppendix) {
+        self.appendices.push(appendix);
+    }
+}
+
+/// Supplementary section rendered after the test results, e.g. latency tables
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportAppendix {
+    pub title: String,
+    pub body: String,
+}
+
+impl ReportAppendix {
+    pub fn new(title: &str, body: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            body: body.to_string(),
+        }
+    }
 }
 
 impl fmt::Display for TestSuiteResult {
@@ -69,43 +211,364 @@ impl fmt::Display for TestSuiteResult {
             self.total_duration
         )?;
         
+        if self.quarantined_failures > 0 {
+            writeln!(f, "Quarantined Failures: {}\n", self.quarantined_failures)?;
+        }
+        
+        if let Some(environment) = &self.environment {
+            writeln!(f, "Environment: {}\n", environment)?;
+        }
+        
+        if let Some(exceeded) = &self.budget_exceeded {
+            writeln!(f, "Budget Exceeded: {}\n", exceeded)?;
+        }
+        
         for result in &self.results {
             write!(f, "{}", result)?;
         }
         
+        for regression in &self.timing_regressions {
+            writeln!(f, "Timing Regression: {}", regression)?;
+        }
+        
+        for violation in &self.invariant_violations {
+            writeln!(f, "Invariant Violation: {}", violation)?;
+        }
+        
+        for appendix in &self.appendices {
+            write!(f, "\n{}\n{}", appendix.title, appendix.body)?;
+        }
+        
         Ok(())
     }
 }
 
 /// Test runner
 pub struct TestRunner<T: HardwareInterface> {
-    interface: Arc<Mutex<T>>,
+    interface: Arc<DiagMutex<T>>,
     timeout: Duration,
     retry_count: u32,
     retry_delay: Duration,
+    budget: Option<Budget>,
+    stats: Arc<OperationStats>,
+    artifacts: Option<ArtifactCollector>,
+    observers: std::sync::Mutex<Vec<Box<dyn TestObserver>>>,
+    environment: Option<TestEnvironmentInfo>,
+    environment_sampler: Option<Box<dyn Fn() -> TestEnvironmentInfo + Send + Sync>>,
+    lock_report_holds: Option<usize>,
+    profiles: Vec<(String, Arc<LatencyProfile>)>,
+    power_cycle: Option<PowerCycle>,
+    operator: Option<Arc<tokio::sync::Mutex<OperatorPrompt>>>,
+    manual_outcome: Arc<std::sync::Mutex<Option<ManualRecord>>>,
+    snapshots: Option<SnapshotCheck<T>>,
+    invariants: Vec<SuiteInvariant>,
+    quarantine: Quarantine,
+    safe_state: Option<SafeState>,
+    settle_after_setup: Option<Duration>,
+    settling: BTreeMap<String, Settling>,
 }
 
 impl<T: HardwareInterface> TestRunner<T> {
     pub fn new(interface: T, timeout: Duration, retry_count: u32, retry_delay: Duration) -> Self {
+        Self::from_shared(Arc::new(DiagMutex::new(interface)), timeout, retry_count, retry_delay)
+    }
+    
+    /// Runner on an interface lock the caller keeps, e.g. to run the next
+    /// suite on an interface that is still initialized
+    pub fn from_shared(interface: Arc<DiagMutex<T>>, timeout: Duration, retry_count: u32, retry_delay: Duration) -> Self {
         Self {
-            interface: Arc::new(Mutex::new(interface)),
+            interface,
             timeout,
             retry_count,
             retry_delay,
+            budget: None,
+            stats: OperationStats::new(),
+            artifacts: None,
+            observers: std::sync::Mutex::new(Vec::new()),
+            environment: None,
+            environment_sampler: None,
+            lock_report_holds: None,
+            profiles: Vec::new(),
+            power_cycle: None,
+            operator: None,
+            manual_outcome: Arc::new(std::sync::Mutex::new(None)),
+            snapshots: None,
+            invariants: Vec::new(),
+            quarantine: Quarantine::new(),
+            safe_state: None,
+            settle_after_setup: None,
+            settling: BTreeMap::new(),
         }
     }
     
-    pub async fn run_test<F>(&self, name: &str, test_fn: F) -> TestResult
+    /// Interface lock shared with the tests, e.g. for a telemetry poller
+    /// running alongside the suite
+    pub fn interface(&self) -> Arc<DiagMutex<T>> {
+        self.interface.clone()
+    }
+    
+    /// Attach interface lock statistics with the `top_n` longest holds to
+    /// every suite result
+    pub fn with_lock_report(mut self, top_n: usize) -> Self {
+        self.lock_report_holds = Some(top_n);
+        self
+    }
+    
+    /// Attach the latencies a `Profiled` interface records during each suite
+    /// to its result, as a "Latency: `name`" appendix
+    pub fn with_profiling(mut self, name: &str, profile: Arc<LatencyProfile>) -> Self {
+        self.profiles.push((name.to_string(), profile));
+        self
+    }
+    
+    /// Log a warning whenever the interface lock is held longer than `threshold`
+    pub fn with_lock_hold_warning(self, threshold: Duration) -> Self {
+        self.interface.set_hold_warning(Some(threshold));
+        self
+    }
+    
+    /// Limit the resources a suite run may consume. Operations and errors
+    /// are those counted in `stats()`, e.g. by a `Counted` interface, and are
+    /// checked while a test runs as well as between tests.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+    
+    /// Share operation counters with a `Counted` interface wrapper
+    pub fn with_stats(mut self, stats: Arc<OperationStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+    
+    pub fn stats(&self) -> Arc<OperationStats> {
+        self.stats.clone()
+    }
+    
+    /// Notify `observer` of suite progress
+    pub fn with_observer<O: TestObserver + 'static>(self, observer: O) -> Self {
+        self.observers.lock().unwrap().push(Box::new(observer));
+        self
+    }
+    
+    fn notify(&self, event: RunnerEvent) {
+        for observer in self.observers.lock().unwrap().iter_mut() {
+            observer.on_event(&event);
+        }
+    }
+    
+    /// Record the conditions the suite runs under in its result
+    pub fn with_environment(mut self, environment: TestEnvironmentInfo) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+    
+    /// Sample the environment at the start of every suite, e.g. from a bench
+    /// temperature sensor. Takes precedence over `with_environment`.
+    pub fn with_environment_sampler<F>(mut self, sampler: F) -> Self
+    where
+        F: Fn() -> TestEnvironmentInfo + Send + Sync + 'static,
+    {
+        self.environment_sampler = Some(Box::new(sampler));
+        self
+    }
+    
+    /// Check `invariant` over every suite run, once all its tests finished
+    pub fn with_invariant(mut self, invariant: SuiteInvariant) -> Self {
+        self.invariants.push(invariant);
+        self
+    }
+    
+    /// Run the tests of `quarantine` as usual but count their failures
+    /// apart, never failing the suite
+    pub fn with_quarantine(mut self, quarantine: Quarantine) -> Self {
+        self.quarantine = quarantine;
+        self
+    }
+    
+    /// Wait `delay` once a suite is set up, after powering on the rail of
+    /// `with_power_cycle`, before its first test
+    pub fn with_settle_after_setup(mut self, delay: Duration) -> Self {
+        self.settle_after_setup = Some(delay);
+        self
+    }
+    
+    /// Settle before test `name` starts, replacing any earlier settling
+    /// for it. A `SettleCondition` that times out errors the test without
+    /// running it; the time it took to settle is recorded as a timed scope.
+    pub fn with_settling(mut self, name: &str, settling: Settling) -> Self {
+        self.settling.insert(name.to_string(), settling);
+        self
+    }
+    
+    /// Drive the bench to a safe state when a suite dies: on budget
+    /// exhaustion, a panicking test, cancellation or a watchdog `trigger`
+    /// on a clone of `safe_state`. The remaining tests are skipped.
+    pub fn with_safe_state(mut self, safe_state: SafeState) -> Self {
+        self.safe_state = Some(safe_state);
+        self
+    }
+    
+    /// Power-cycle a rail between the tests of every suite
+    pub fn with_power_cycle(mut self, power_cycle: PowerCycle) -> Self {
+        self.power_cycle = Some(power_cycle);
+        self
+    }
+    
+    /// Run manual steps interactively, asking `operator` for their outcome
+    pub fn with_operator(mut self, operator: OperatorPrompt) -> Self {
+        self.operator = Some(Arc::new(tokio::sync::Mutex::new(operator)));
+        self
+    }
+    
+    /// Snapshot the registers of `map` before and after every test, failing
+    /// tests that change any register outside `ignore`
+    pub fn with_register_snapshots(mut self, map: &'static [RegisterDescriptor], ignore: &[&str]) -> Self
+    where
+        T: RegisterAccess + 'static,
+    {
+        self.snapshots = Some(SnapshotCheck {
+            capture: Box::new(move |interface| {
+                Box::pin(async move { DeviceSnapshot::capture(&mut *interface.lock().await, map).await })
+            }),
+            ignore: ignore.iter().map(|name| name.to_string()).collect(),
+        });
+        self
+    }
+    
+    /// Test case for a step carried out by the operator, to be interleaved
+    /// with automated tests. Without `with_operator` the step is skipped as
+    /// requiring an operator. The runner's per-test timeout does not apply;
+    /// only the step's own timeout ends the wait for an answer.
+    pub fn manual_step(&self, step: ManualStep) -> TestFn<T>
     where
-        F: FnOnce(Arc<Mutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
+        T: 'static,
+    {
+        let operator = self.operator.clone();
+        let outcome = self.manual_outcome.clone();
+        Box::new(move |_| {
+            Box::pin(async move {
+                declare_test_timeout(TestTimeout::Unlimited);
+                let record = match operator {
+                    Some(operator) => operator.lock().await.ask(&step).await.map_err(|e| {
+                        HardwareError::OperationFailed(format!("operator prompt failed: {}", e))
+                    })?,
+                    None => ManualRecord::skipped(REQUIRES_OPERATOR),
+                };
+                *outcome.lock().unwrap() = Some(record);
+                Ok(())
+            })
+        })
+    }
+    
+    /// Collect an artifact bundle for every failed or errored test
+    pub fn with_artifacts(mut self, collector: ArtifactCollector) -> Self {
+        self.artifacts = Some(collector);
+        self
+    }
+    
+    /// Archive `result` with the artifact bundles this runner collected for
+    /// its failures; bundles left over from earlier runs are not archived
+    pub fn finish_and_archive(&self, result: &TestSuiteResult, archive: &RunArchive) -> io::Result<ArchivedRun> {
+        let bundles: Vec<PathBuf> = match &self.artifacts {
+            Some(collector) => {
+                let collected = collector.collected();
+                result
+                    .results
+                    .iter()
+                    .map(|r| collector.bundle_dir(&r.name))
+                    .filter(|dir| collected.contains(dir))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        archive.archive(result, &bundles)
+    }
+    
+    /// Run one test, erroring it once it runs longer than `timeout` or, if
+    /// `None`, the runner's per-test timeout, unless the test declares its
+    /// own with `declare_test_timeout`. The limit is exposed to the test as
+    /// its `TestDeadline`.
+    pub async fn run_test<F>(&self, name: &str, timeout: Option<Duration>, test_fn: F) -> TestResult
+    where
+        F: FnOnce(Arc<DiagMutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
+    {
+        self.run_test_within(name, test_fn, timeout.unwrap_or(self.timeout), None).await
+    }
+    
+    /// Run one test cut off after `timeout`, with operation timeouts also
+    /// cut to `cap`
+    async fn run_test_within<F>(&self, name: &str, test_fn: F, timeout: Duration, cap: Option<Instant>) -> TestResult
+    where
+        F: FnOnce(Arc<DiagMutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
+    {
+        self.manual_outcome.lock().unwrap().take();
+        let before = match &self.snapshots {
+            Some(snapshots) => Some(snapshots.capture(&self.interface).await),
+            None => None,
+        };
+        
+        let mut test_result = match &self.safe_state {
+            Some(safe_state) => {
+                let start = Instant::now();
+                match CatchPanic::new(Self::execute(name, test_fn, self.interface.clone(), timeout, cap)).await {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        let message = panic_message(&*panic);
+                        safe_state.trigger(FatalEvent::Panicked(message.clone())).await;
+                        let status = TestStatus::Error(format!("Test panicked: {}", message));
+                        TestResult::new(name, status, start.elapsed())
+                    }
+                }
+            }
+            None => Self::execute(name, test_fn, self.interface.clone(), timeout, cap).await,
+        };
+        
+        if let Some(record) = self.manual_outcome.lock().unwrap().take() {
+            record.apply(&mut test_result);
+        }
+        
+        if let (Some(snapshots), Some(before)) = (&self.snapshots, before) {
+            snapshots.check(&self.interface, before, &mut test_result).await;
+        }
+        
+        if matches!(test_result.status, TestStatus::Failed(_) | TestStatus::Error(_)) {
+            self.collect_artifacts(&mut test_result).await;
+        }
+        
+        test_result
+    }
+    
+    /// Run one test on `interface` cut off after `timeout` unless it
+    /// declares its own, with operation timeouts also cut to `cap`, without
+    /// the runner's per-test hooks
+    async fn execute<F>(
+        name: &str,
+        test_fn: F,
+        interface: Arc<DiagMutex<T>>,
+        timeout: Duration,
+        cap: Option<Instant>,
+    ) -> TestResult
+    where
+        F: FnOnce(Arc<DiagMutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
     {
         let start = Instant::now();
         let mut error_count = 0;
         let mut warning_count = 0;
-        
-        let result = match test_fn(self.interface.clone()).await {
-            Ok(_) => {
-                let status = self.interface.lock().await.get_status().await;
+        let test = with_lock_holder(name, test_fn(interface.clone()));
+        // A test still running at its timeout is dropped at the await point it was blocked on
+        let ((run, scopes), skipped) = record_skip(record_scopes(run_with_deadline(timeout, cap, test))).await;
+        let timed_out = run.output.as_ref().err().copied();
+        let outcome = run.output.unwrap_or(Err(HardwareError::TimeoutError));
+        let deadline_timeout = run.hit && matches!(&outcome, Err(e) if matches!(e.root(), HardwareError::TimeoutError));
+        let mut error_kind = outcome.as_ref().err().map(HardwareError::kind);
+        let result = match (outcome, skipped) {
+            (Err(_), _) if timed_out.is_some() => {
+                TestStatus::Error(format!("timed out after {:?}", timed_out.unwrap_or(timeout)))
+            }
+            (Ok(_), Some(reason)) => TestStatus::Skipped(reason),
+            (Ok(_), None) => {
+                let status = interface.lock_as(name).await.get_status().await;
                 match status {
                     Ok(status) => {
                         error_count = status.error_count;
@@ -116,62 +579,422 @@ impl<T: HardwareInterface> TestRunner<T> {
                             TestStatus::Failed(format!("{} errors reported", status.error_count))
                         }
                     }
-                    Err(e) => TestStatus::Error(format!("Failed to get status: {:?}", e)),
+                    Err(e) => {
+                        error_kind = Some(e.kind());
+                        TestStatus::Error(format!("Failed to get status: {:?}", e))
+                    }
                 }
             }
-            Err(e) => TestStatus::Error(format!("Test failed: {:?}", e)),
+            (Err(e), _) if deadline_timeout => TestStatus::Error(format!("Test failed: {:?} (test deadline)", e)),
+            (Err(e), _) => TestStatus::Error(format!("Test failed: {:?}", e)),
         };
         
-        TestResult {
+        let mut test_result = TestResult {
             name: name.to_string(),
             status: result,
             duration: start.elapsed(),
             error_count,
             warning_count,
+            notes: Vec::new(),
+            params: BTreeMap::new(),
+            manual: false,
+            scopes,
+            quarantined: false,
+            error_kind,
+        };
+        
+        if deadline_timeout {
+            test_result
+                .notes
+                .push(format!("operation timeout cut short by the {:?} test deadline", run.limit));
+        }
+        
+        test_result
+    }
+    
+    /// Collection problems are reported as notes and never change the outcome
+    async fn collect_artifacts(&self, result: &mut TestResult) {
+        let collector = match &self.artifacts {
+            Some(collector) => collector,
+            None => return,
+        };
+        
+        let status = self.interface.lock_as(&result.name).await.get_status().await.ok();
+        match collector.collect(result, status.as_ref(), &self.stats) {
+            Ok(bundle) => result.notes.push(format!("artifacts collected in {}", bundle.directory.display())),
+            Err(e) => result.notes.push(format!("artifact collection failed: {}", e)),
         }
     }
     
     pub async fn run_test_suite<F>(&self, name: &str, tests: Vec<(&str, F)>) -> TestSuiteResult
     where
-        F: FnOnce(Arc<Mutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
+        F: FnOnce(Arc<DiagMutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
+    {
+        self.run_tagged_suite(name, tests, &TestTags::new()).await
+    }
+    
+    /// Run a suite whose tests carry tags, for tag-based quarantine
+    pub(crate) async fn run_tagged_suite<F>(&self, name: &str, tests: Vec<(&str, F)>, tags: &TestTags) -> TestSuiteResult
+    where
+        F: FnOnce(Arc<DiagMutex<T>>) -> std::pin::Pin<Box<dyn std::future::Future<Output = HardwareResult<()>> + Send>>,
     {
         let start = Instant::now();
+        let guard = SafeStateGuard::new(self.safe_state.as_ref());
         let mut results = Vec::new();
-        let mut passed_tests = 0;
-        let mut failed_tests = 0;
-        let mut skipped_tests = 0;
-        let mut error_tests = 0;
+        let mut budget_exceeded = None;
+        let environment = match &self.environment_sampler {
+            Some(sampler) => Some(sampler()),
+            None => self.environment.clone(),
+        };
+        
+        if self.lock_report_holds.is_some() {
+            self.interface.reset_report();
+        }
+        for (_, profile) in &self.profiles {
+            profile.reset();
+        }
+        let invariant_start = if self.invariants.is_empty() {
+            None
+        } else {
+            Some((self.interface.lock().await.get_status().await.ok(), self.stats.snapshot(), Instant::now()))
+        };
+        // Budgets count this suite's usage only, not earlier suites on the same runner
+        let budget_start = self.budget.as_ref().map(|_| self.stats.snapshot());
+        
+        self.notify(RunnerEvent::SuiteStarted {
+            name: name.to_string(),
+            total_tests: tests.len(),
+        });
         
-        for (test_name, test_fn) in tests {
-            let result = self.run_test(test_name, test_fn).await;
+        for (index, (test_name, test_fn)) in tests.into_iter().enumerate() {
+            if let (None, Some(stats_start)) = (&budget_exceeded, &budget_start) {
+                budget_exceeded = self.check_budget(start, stats_start);
+            }
             
-            match result.status {
-                TestStatus::Passed => passed_tests += 1,
-                TestStatus::Failed(_) => failed_tests += 1,
-                TestStatus::Skipped(_) => skipped_tests += 1,
-                TestStatus::Error(_) => error_tests += 1,
+            let fatal = self.safe_state.as_ref().and_then(|safe_state| safe_state.event());
+            // Skipped tests only get a TestFinished
+            if budget_exceeded.is_none() && fatal.is_none() {
+                self.notify(RunnerEvent::TestStarted { name: test_name.to_string() });
             }
+            let power = match (&self.power_cycle, &budget_exceeded) {
+                (Some(_), None) if fatal.is_some() => Ok(()),
+                (Some(power_cycle), None) if index == 0 => power_cycle.setup().await,
+                (Some(power_cycle), None) => power_cycle.cycle().await,
+                _ => Ok(()),
+            };
+            
+            let settled = match (&budget_exceeded, &fatal, &power) {
+                (None, None, Ok(())) => self.settle(index, test_name).await,
+                _ => Ok(None),
+            };
             
+            let mut result = match (&budget_exceeded, &budget_start) {
+                (Some(exceeded), _) => TestResult::new(
+                    test_name,
+                    TestStatus::Skipped(format!("budget exceeded: {}", exceeded)),
+                    Duration::ZERO,
+                ),
+                (None, _) if fatal.is_some() => TestResult::new(
+                    test_name,
+                    TestStatus::Skipped(format!("safe state entered after {}", fatal.unwrap())),
+                    Duration::ZERO,
+                ),
+                (None, _) if power.is_err() => TestResult::new(
+                    test_name,
+                    TestStatus::Error(format!("power cycle failed: {:?}", power.unwrap_err())),
+                    Duration::ZERO,
+                ),
+                (None, _) if settled.is_err() => TestResult::new(
+                    test_name,
+                    TestStatus::Error(format!("settling failed: {}", settled.as_ref().unwrap_err())),
+                    Duration::ZERO,
+                ),
+                (None, Some(stats_start)) => {
+                    let test_start = Instant::now();
+                    let cap = self.remaining_wall_time(start).map(|remaining| test_start + remaining);
+                    tokio::select! {
+                        result = self.run_test_within(test_name, test_fn, self.timeout, cap) => result,
+                        exceeded = self.budget_tripped(start, stats_start) => {
+                            // The in-flight test is dropped at the await point it was blocked on
+                            let status = TestStatus::Error(format!("budget exceeded: {}", exceeded));
+                            budget_exceeded = Some(exceeded);
+                            TestResult::new(test_name, status, test_start.elapsed())
+                        }
+                    }
+                }
+                (None, None) => self.run_test(test_name, None, test_fn).await,
+            };
+            
+            if let Ok(Some(settle)) = settled {
+                result.scopes.insert(0, settle);
+            }
+            
+            if let (Some(safe_state), Some(exceeded)) = (&self.safe_state, &budget_exceeded) {
+                safe_state.trigger(FatalEvent::BudgetExceeded(exceeded.to_string())).await;
+            }
+            
+            result.quarantined = self.quarantine.contains(test_name, tags.get(test_name).copied().unwrap_or_default());
+            
+            self.notify(RunnerEvent::TestFinished(result.clone()));
             results.push(result);
         }
         
-        TestSuiteResult {
+        if let Some(power_cycle) = &self.power_cycle {
+            if let Err(e) = power_cycle.teardown().await {
+                log::warn!("Failed to power down after suite {}: {}", name, e);
+            }
+        }
+        
+        guard.disarm();
+        let mut suite = TestSuiteResult::from_results(name, results, start.elapsed());
+        suite.budget_exceeded = budget_exceeded;
+        suite.environment = environment;
+        suite.safe_state = self.safe_state.as_ref().and_then(|safe_state| safe_state.take_record());
+        if let Some(top_n) = self.lock_report_holds {
+            suite.add_appendix(self.interface.report().appendix(top_n));
+        }
+        for (profile_name, profile) in &self.profiles {
+            suite.add_appendix(profile.appendix(profile_name));
+        }
+        if let Some(appendix) = scope_appendix(&suite.results) {
+            suite.add_appendix(appendix);
+        }
+        if let Some((start_status, start_stats, started)) = invariant_start {
+            let run = SuiteRun {
+                results: &suite.results,
+                start_status,
+                end_status: self.interface.lock().await.get_status().await.ok(),
+                start_stats,
+                end_stats: self.stats.snapshot(),
+                elapsed: started.elapsed(),
+            };
+            let violations = self.invariants.iter().flat_map(|invariant| invariant.evaluate(&run)).collect();
+            suite.invariant_violations = violations;
+        }
+        self.notify(RunnerEvent::SuiteFinished(suite.clone()));
+        suite
+    }
+    
+    /// Wait before test `index` as configured, returning the settling
+    /// time of a `SettleCondition`
+    async fn settle(&self, index: usize, test_name: &str) -> Result<Option<ScopeMeasurement>, SettleError> {
+        if let (0, Some(delay)) = (index, self.settle_after_setup) {
+            tokio::time::sleep(delay).await;
+        }
+        match self.settling.get(test_name) {
+            Some(Settling::Delay(delay)) => {
+                tokio::time::sleep(*delay).await;
+                Ok(None)
+            }
+            Some(Settling::Until(condition)) => {
+                let elapsed = condition.wait().await?;
+                Ok(Some(condition.measurement(elapsed)))
+            }
+            None => Ok(None),
+        }
+    }
+    
+    /// Check the suite's wall time since `start`, and the operations and
+    /// errors counted in the runner's stats since `stats_start`, against the
+    /// budget
+    fn check_budget(&self, start: Instant, stats_start: &StatsSnapshot) -> Option<BudgetExceeded> {
+        self.budget.as_ref()?.check(
+            start.elapsed(),
+            self.stats.operations().saturating_sub(stats_start.operations),
+            self.stats.errors().saturating_sub(stats_start.errors),
+        )
+    }
+    
+    /// Resolve with the budget the suite exceeds, checking the stats every
+    /// `BUDGET_POLL` so a test stuck in a retry storm is cut short; never
+    /// resolves without a budget
+    async fn budget_tripped(&self, start: Instant, stats_start: &StatsSnapshot) -> BudgetExceeded {
+        let budget = match &self.budget {
+            Some(budget) => budget,
+            None => return std::future::pending().await,
+        };
+        loop {
+            if let Some(exceeded) = self.check_budget(start, stats_start) {
+                return exceeded;
+            }
+            let poll = budget.remaining_wall_time(start.elapsed()).map_or(BUDGET_POLL, |left| left.min(BUDGET_POLL));
+            tokio::time::sleep(poll).await;
+        }
+    }
+    
+    fn remaining_wall_time(&self, start: Instant) -> Option<Duration> {
+        self.budget.as_ref()?.remaining_wall_time(start.elapsed())
+    }
+}
+
+/// Progress of a test spawned by a parallel suite, by submission index
+enum ParallelEvent {
+    Started(usize),
+    Finished(usize, TestResult),
+}
+
+impl<T: HardwareInterface + Send + Sync + 'static> TestRunner<T> {
+    /// Run a suite with up to `max_concurrency` tests at once, all on the
+    /// runner's interface, so tests holding its lock still take turns
+    ///
+    /// Results are reported in submission order. Once the budget is exceeded
+    /// the running tests are cancelled and those not yet started skipped.
+    /// Power cycling, register snapshots, manual steps, artifacts and
+    /// invariants need the tests one at a time and apply to
+    /// `run_test_suite` only.
+    pub async fn run_test_suite_parallel(
+        &self,
+        name: &str,
+        tests: Vec<(&str, TestFn<T>)>,
+        max_concurrency: usize,
+    ) -> TestSuiteResult {
+        let interface = self.interface.clone();
+        self.run_parallel(name, tests, max_concurrency, move || interface.clone()).await
+    }
+    
+    /// Run a suite with up to `max_concurrency` tests at once, each on a
+    /// fresh interface from `factory`
+    pub async fn run_test_suite_parallel_with<F>(
+        &self,
+        name: &str,
+        tests: Vec<(&str, TestFn<T>)>,
+        max_concurrency: usize,
+        factory: F,
+    ) -> TestSuiteResult
+    where
+        F: Fn() -> T,
+    {
+        self.run_parallel(name, tests, max_concurrency, || Arc::new(DiagMutex::new(factory()))).await
+    }
+    
+    async fn run_parallel<F>(
+        &self,
+        name: &str,
+        tests: Vec<(&str, TestFn<T>)>,
+        max_concurrency: usize,
+        interface: F,
+    ) -> TestSuiteResult
+    where
+        F: Fn() -> Arc<DiagMutex<T>>,
+    {
+        let start = Instant::now();
+        let guard = SafeStateGuard::new(self.safe_state.as_ref());
+        let environment = match &self.environment_sampler {
+            Some(sampler) => Some(sampler()),
+            None => self.environment.clone(),
+        };
+        if self.lock_report_holds.is_some() {
+            self.interface.reset_report();
+        }
+        for (_, profile) in &self.profiles {
+            profile.reset();
+        }
+        let stats_start = self.stats.snapshot();
+        let cap = self.remaining_wall_time(start).map(|remaining| start + remaining);
+        let (stop, stopped) = watch::channel(None::<BudgetExceeded>);
+        
+        self.notify(RunnerEvent::SuiteStarted {
             name: name.to_string(),
-            results,
             total_tests: tests.len(),
-            passed_tests,
-            failed_tests,
-            skipped_tests,
-            error_tests,
-            total_duration: start.elapsed(),
+        });
+        
+        let permits = Arc::new(Semaphore::new(max_concurrency.max(1)));
+        let (events, mut received) = mpsc::unbounded_channel();
+        let names: Vec<String> = tests.iter().map(|(test_name, _)| test_name.to_string()).collect();
+        let mut handles = Vec::new();
+        for (index, (test_name, test_fn)) in tests.into_iter().enumerate() {
+            let test_name = test_name.to_string();
+            let interface = interface();
+            let permits = permits.clone();
+            let events = events.clone();
+            let mut stopped = stopped.clone();
+            let timeout = self.timeout;
+            handles.push(tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("suite semaphore closed");
+                let exceeded = stopped.borrow().clone();
+                if let Some(exceeded) = exceeded {
+                    let status = TestStatus::Skipped(format!("budget exceeded: {}", exceeded));
+                    let _ = events.send(ParallelEvent::Finished(index, TestResult::new(&test_name, status, Duration::ZERO)));
+                    return;
+                }
+                let _ = events.send(ParallelEvent::Started(index));
+                let test_start = Instant::now();
+                let exceeded = async { stopped.wait_for(Option::is_some).await.ok().and_then(|exceeded| exceeded.clone()) };
+                let result = tokio::select! {
+                    result = Self::execute(&test_name, test_fn, interface, timeout, cap) => result,
+                    // The in-flight test is dropped at the await point it was blocked on
+                    Some(exceeded) = exceeded => {
+                        let status = TestStatus::Error(format!("budget exceeded: {}", exceeded));
+                        TestResult::new(&test_name, status, test_start.elapsed())
+                    }
+                };
+                let _ = events.send(ParallelEvent::Finished(index, result));
+            }));
+        }
+        drop(events);
+        
+        let mut results: Vec<Option<TestResult>> = vec![None; names.len()];
+        let mut budget_exceeded = None;
+        loop {
+            tokio::select! {
+                event = received.recv() => match event {
+                    Some(ParallelEvent::Started(index)) => self.notify(RunnerEvent::TestStarted { name: names[index].clone() }),
+                    Some(ParallelEvent::Finished(index, mut result)) => {
+                        result.quarantined = self.quarantine.contains(&result.name, &[]);
+                        self.notify(RunnerEvent::TestFinished(result.clone()));
+                        results[index] = Some(result);
+                    }
+                    None => break,
+                },
+                exceeded = self.budget_tripped(start, &stats_start), if budget_exceeded.is_none() => {
+                    if let Some(safe_state) = &self.safe_state {
+                        safe_state.trigger(FatalEvent::BudgetExceeded(exceeded.to_string())).await;
+                    }
+                    let _ = stop.send(Some(exceeded.clone()));
+                    budget_exceeded = Some(exceeded);
+                }
+            }
         }
+        
+        // A panicking test drops its task without sending a result
+        for (index, handle) in handles.into_iter().enumerate() {
+            if let Err(e) = handle.await {
+                if let (Some(safe_state), true) = (&self.safe_state, e.is_panic()) {
+                    safe_state.trigger(FatalEvent::Panicked(e.to_string())).await;
+                }
+                let mut result = TestResult::new(&names[index], TestStatus::Error(format!("Test panicked: {}", e)), Duration::ZERO);
+                result.quarantined = self.quarantine.contains(&result.name, &[]);
+                self.notify(RunnerEvent::TestFinished(result.clone()));
+                results[index] = Some(result);
+            }
+        }
+        
+        guard.disarm();
+        let results = results.into_iter().flatten().collect();
+        let mut suite = TestSuiteResult::from_results(name, results, start.elapsed());
+        suite.budget_exceeded = budget_exceeded;
+        suite.environment = environment;
+        suite.safe_state = self.safe_state.as_ref().and_then(|safe_state| safe_state.take_record());
+        if let Some(top_n) = self.lock_report_holds {
+            suite.add_appendix(self.interface.report().appendix(top_n));
+        }
+        for (profile_name, profile) in &self.profiles {
+            suite.add_appendix(profile.appendix(profile_name));
+        }
+        if let Some(appendix) = scope_appendix(&suite.results) {
+            suite.add_appendix(appendix);
+        }
+        self.notify(RunnerEvent::SuiteFinished(suite.clone()));
+        suite
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mocks::create_mock_interface;
+    use crate::mocks::{create_mock_interface, create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{Counted, I2CInterface, Writable};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     
     #[tokio::test]
     async fn test_run_test() {
@@ -184,7 +1007,7 @@ mod tests {
         );
         
         let result = runner
-            .run_test("test_initialize", |interface| {
+            .run_test("test_initialize", None, |interface| {
                 Box::pin(async move {
                     let mut interface = interface.lock().await;
                     interface.initialize().await
@@ -207,28 +1030,12 @@ mod tests {
             Duration::from_millis(10),
         );
         
-        let tests = vec![
-            (
-                "test_initialize",
-                |interface: Arc<Mutex<_>>| {
-                    Box::pin(async move {
-                        let mut interface = interface.lock().await;
-                        interface.initialize().await
-                    })
-                },
-            ),
-            (
-                "test_deinitialize",
-                |interface: Arc<Mutex<_>>| {
-                    Box::pin(async move {
-                        let mut interface = interface.lock().await;
-                        interface.deinitialize().await
-                    })
-                },
-            ),
-        ];
+        crate::suite!(lifecycle(interface: MockHardwareInterface) {
+            "test_initialize" => { interface.initialize().await },
+            "test_deinitialize" => { interface.deinitialize().await },
+        });
         
-        let result = runner.run_test_suite("test_suite", tests).await;
+        let result = runner.run_cases("test_suite", lifecycle()).await;
         
         assert_eq!(result.total_tests, 2);
         assert_eq!(result.passed_tests, 2);
@@ -236,4 +1043,476 @@ mod tests {
         assert_eq!(result.skipped_tests, 0);
         assert_eq!(result.error_tests, 0);
     }
-} 
\ No newline at end of file
+    
+    fn write_test(bytes: usize) -> TestFn<Counted<I2CInterface>> {
+        Box::new(move |interface| {
+            Box::pin(async move {
+                let mut interface = interface.lock().await;
+                interface.initialize().await?;
+                interface.write(&vec![0u8; bytes]).await?;
+                interface.write(&vec![0u8; bytes]).await?;
+                Ok(())
+            })
+        })
+    }
+    
+    fn sleep_test(duration: Duration) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                tokio::time::sleep(duration).await;
+                Ok(())
+            })
+        })
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_hanging_test_times_out() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_millis(100), 0, Duration::ZERO);
+        let tests = vec![
+            ("hangs", sleep_test(Duration::from_secs(3600))),
+            ("quick", sleep_test(Duration::from_millis(10))),
+        ];
+        
+        let result = runner.run_test_suite("timeouts", tests).await;
+        
+        assert_eq!(result.results[0].status, TestStatus::Error("timed out after 100ms".to_string()));
+        assert_eq!(result.results[0].duration, Duration::from_millis(100));
+        assert_eq!(result.results[1].status, TestStatus::Passed);
+        assert_eq!((result.error_tests, result.passed_tests), (1, 1));
+        assert_eq!(result.total_duration, Duration::from_millis(110));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_run_test_timeout_override() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_millis(100), 0, Duration::ZERO);
+        
+        let slow_init = runner.run_test("init", Some(Duration::from_millis(300)), sleep_test(Duration::from_millis(200))).await;
+        assert_eq!(slow_init.status, TestStatus::Passed);
+        assert_eq!(slow_init.duration, Duration::from_millis(200));
+        
+        let data_path = runner.run_test("data path", None, sleep_test(Duration::from_millis(200))).await;
+        assert_eq!(data_path.status, TestStatus::Error("timed out after 100ms".to_string()));
+        assert_eq!(data_path.duration, Duration::from_millis(100));
+    }
+    
+    fn declaring_test(timeout: TestTimeout, duration: Duration) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                declare_test_timeout(timeout);
+                tokio::time::sleep(duration).await;
+                Ok(())
+            })
+        })
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_declared_timeouts_replace_runner_timeout() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_millis(100), 0, Duration::ZERO);
+        let tests = vec![
+            ("soak", declaring_test(TestTimeout::Unlimited, Duration::from_secs(3600))),
+            ("long", declaring_test(TestTimeout::After(Duration::from_secs(60)), Duration::from_secs(30))),
+            ("too long", declaring_test(TestTimeout::After(Duration::from_secs(60)), Duration::from_secs(90))),
+            ("default", sleep_test(Duration::from_secs(1))),
+        ];
+        
+        let result = runner.run_test_suite("timeouts", tests).await;
+        
+        assert_eq!(result.results[0].status, TestStatus::Passed);
+        assert_eq!(result.results[0].duration, Duration::from_secs(3600));
+        assert_eq!(result.results[1].status, TestStatus::Passed);
+        assert_eq!(result.results[2].status, TestStatus::Error("timed out after 60s".to_string()));
+        assert_eq!(result.results[2].duration, Duration::from_secs(60));
+        assert_eq!(result.results[3].status, TestStatus::Error("timed out after 100ms".to_string()));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_caps_declared_timeouts() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_millis(100), 0, Duration::ZERO)
+            .with_budget(Budget::new().max_wall_time(Duration::from_secs(10)));
+        
+        let result = runner
+            .run_test_suite("timeouts", vec![("soak", declaring_test(TestTimeout::Unlimited, Duration::from_secs(3600)))])
+            .await;
+        
+        assert!(matches!(&result.results[0].status, TestStatus::Error(msg) if msg.starts_with("budget exceeded")));
+        assert_eq!(result.results[0].duration, Duration::from_secs(10));
+    }
+    
+    #[tokio::test]
+    async fn test_budget_max_operations() {
+        let stats = OperationStats::new();
+        let interface = Counted::new(I2CInterface::with_default_config(), stats.clone());
+        let runner = TestRunner::new(interface, Duration::from_millis(100), 3, Duration::from_millis(10))
+            .with_stats(stats)
+            .with_budget(Budget::new().max_operations(3));
+        
+        let tests = vec![("first", write_test(4)), ("second", write_test(4)), ("third", write_test(4))];
+        let result = runner.run_test_suite("ops_budget", tests).await;
+        
+        assert_eq!(result.total_tests, 3);
+        assert_eq!(result.passed_tests, 2);
+        assert_eq!(result.skipped_tests, 1);
+        assert_eq!(
+            result.results[2].status,
+            TestStatus::Skipped("budget exceeded: 4 bus operations reached limit 3".to_string())
+        );
+        assert_eq!(result.budget_exceeded, Some(BudgetExceeded::Operations { limit: 3, used: 4 }));
+        
+        // A second suite on the same runner starts with a fresh budget
+        let tests = vec![("first", write_test(4)), ("second", write_test(4)), ("third", write_test(4))];
+        let again = runner.run_test_suite("ops_budget", tests).await;
+        assert_eq!((again.passed_tests, again.skipped_tests), (2, 1));
+        assert_eq!(again.budget_exceeded, Some(BudgetExceeded::Operations { limit: 3, used: 4 }));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_max_wall_time() {
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_budget(Budget::new().max_wall_time(Duration::from_millis(50)));
+        
+        let tests = vec![
+            ("first", sleep_test(Duration::from_millis(30))),
+            ("second", sleep_test(Duration::from_millis(30))),
+            ("third", sleep_test(Duration::from_millis(30))),
+        ];
+        let result = runner.run_test_suite("time_budget", tests).await;
+        
+        assert_eq!(result.passed_tests, 1);
+        assert_eq!(result.error_tests, 1);
+        assert_eq!(result.skipped_tests, 1);
+        assert!(matches!(&result.results[1].status, TestStatus::Error(msg) if msg.starts_with("budget exceeded: wall time")));
+        assert!(matches!(&result.results[2].status, TestStatus::Skipped(msg) if msg.starts_with("budget exceeded: wall time")));
+        assert!(matches!(result.budget_exceeded, Some(BudgetExceeded::WallTime { .. })));
+    }
+    
+    /// Test failing an operation every millisecond without ever giving
+    /// up, counted into `stats`
+    fn retry_storm_test(stats: Arc<OperationStats>) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                loop {
+                    stats.record::<()>(&Err(crate::HardwareError::TimeoutError));
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        })
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_max_errors() {
+        let stats = OperationStats::new();
+        // Errors from before the suite don't count against its budget
+        for _ in 0..5 {
+            stats.record::<()>(&Err(crate::HardwareError::TimeoutError));
+        }
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(10), 0, Duration::ZERO)
+            .with_stats(stats.clone())
+            .with_budget(Budget::new().max_errors(5));
+        
+        let tests = vec![("storm", retry_storm_test(stats.clone())), ("after", sleep_test(Duration::ZERO))];
+        let result = runner.run_test_suite("error_budget", tests).await;
+        
+        // The storm is cancelled at the first check past the limit, not at its timeout
+        assert!(matches!(&result.results[0].status, TestStatus::Error(msg) if msg.starts_with("budget exceeded: ")));
+        assert!(result.results[0].duration <= BUDGET_POLL * 2);
+        assert!(matches!(&result.results[1].status, TestStatus::Skipped(msg) if msg.starts_with("budget exceeded: ")));
+        assert!(matches!(result.budget_exceeded, Some(BudgetExceeded::Errors { limit: 5, used }) if used >= 5));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_parallel_budget_cancels_running_tests() {
+        let stats = OperationStats::new();
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(10), 0, Duration::ZERO)
+            .with_stats(stats.clone())
+            .with_budget(Budget::new().max_operations(20));
+        
+        let tests = vec![
+            ("storm", retry_storm_test(stats.clone())),
+            ("queued", sleep_test(Duration::ZERO)),
+        ];
+        let result = runner.run_test_suite_parallel("ops_budget", tests, 1).await;
+        
+        assert!(matches!(&result.results[0].status, TestStatus::Error(msg) if msg.starts_with("budget exceeded: ")));
+        assert!(matches!(&result.results[1].status, TestStatus::Skipped(msg) if msg.starts_with("budget exceeded: ")));
+        assert!(matches!(result.budget_exceeded, Some(BudgetExceeded::Operations { limit: 20, .. })));
+    }
+    
+    #[tokio::test]
+    async fn test_artifacts_collected_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut collector = ArtifactCollector::new(dir.path()).with_seed(7);
+        collector.register("registers", || Ok(vec![0xAB; 4]));
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_artifacts(collector);
+        
+        let result = runner
+            .run_test("broken", None, |_| Box::pin(async { Err(crate::HardwareError::TimeoutError) }))
+            .await;
+        
+        assert!(matches!(result.status, TestStatus::Error(_)));
+        let bundle = ArtifactCollector::new(dir.path()).bundle_dir("broken");
+        assert_eq!(result.notes, vec![format!("artifacts collected in {}", bundle.display())]);
+        
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(bundle.join("manifest.json")).unwrap()).unwrap();
+        let names: Vec<_> = manifest["artifacts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["result", "interface_status", "operation_stats", "registers"]);
+        assert_eq!(manifest["seed"], 7);
+    }
+    
+    #[tokio::test]
+    async fn test_artifacts_not_collected_on_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_artifacts(ArtifactCollector::new(dir.path()));
+        
+        let result = runner.run_test("fine", None, |_| Box::pin(async { Ok(()) })).await;
+        
+        assert_eq!(result.status, TestStatus::Passed);
+        assert!(result.notes.is_empty());
+        assert!(!ArtifactCollector::new(dir.path()).bundle_dir("fine").exists());
+    }
+    
+    #[tokio::test]
+    async fn test_archive_skips_stale_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundles = dir.path().join("bundles");
+        // Left behind by an earlier run in which "flaky" failed
+        let stale = ArtifactCollector::new(&bundles).bundle_dir("flaky");
+        std::fs::create_dir_all(&stale).unwrap();
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            0,
+            Duration::ZERO,
+        )
+        .with_artifacts(ArtifactCollector::new(&bundles));
+        
+        let tests: Vec<(&str, TestFn<MockHardwareInterface>)> = vec![
+            ("flaky", Box::new(|_| Box::pin(async { Ok(()) }))),
+            ("broken", Box::new(|_| Box::pin(async { Err(crate::HardwareError::TimeoutError) }))),
+        ];
+        let suite = runner.run_test_suite("bench", tests).await;
+        let run = runner.finish_and_archive(&suite, &RunArchive::new(dir.path().join("runs"))).unwrap();
+        
+        let archived: Vec<_> = std::fs::read_dir(run.directory.join("artifacts"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let broken = ArtifactCollector::new(&bundles).bundle_dir("broken");
+        assert_eq!(archived, vec![run.directory.join("artifacts").join(broken.file_name().unwrap())]);
+    }
+    
+    #[tokio::test]
+    async fn test_observer_events() {
+        let (observer, mut events) = crate::ChannelObserver::new();
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_observer(observer);
+        
+        let tests = vec![("only", sleep_test(Duration::ZERO))];
+        runner.run_test_suite("observed", tests).await;
+        
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::SuiteStarted { total_tests: 1, .. })));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::TestStarted { name }) if name == "only"));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::TestFinished(r)) if r.status == TestStatus::Passed));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::SuiteFinished(s)) if s.passed_tests == 1));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_no_start_event_for_skipped_tests() {
+        let (observer, mut events) = crate::ChannelObserver::new();
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+            .with_budget(Budget::new().max_wall_time(Duration::from_millis(50)))
+            .with_observer(observer);
+        
+        let tests = vec![("slow", sleep_test(Duration::from_millis(60))), ("never", sleep_test(Duration::ZERO))];
+        runner.run_test_suite("observed", tests).await;
+        
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::SuiteStarted { .. })));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::TestStarted { name }) if name == "slow"));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::TestFinished(r)) if r.name == "slow"));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::TestFinished(r)) if matches!(r.status, TestStatus::Skipped(_))));
+        assert!(matches!(events.try_recv(), Ok(RunnerEvent::SuiteFinished(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_environment_sampled_at_suite_start() {
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_environment(TestEnvironmentInfo::new().temperature_c(25.0))
+        .with_environment_sampler(|| TestEnvironmentInfo::new().temperature_c(59.5).chamber_profile("hot"));
+        
+        let result = runner.run_test_suite("thermal", vec![("only", sleep_test(Duration::ZERO))]).await;
+        
+        let environment = result.environment.unwrap();
+        assert_eq!(environment.temperature_c, Some(59.5));
+        assert_eq!(environment.chamber_profile.as_deref(), Some("hot"));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_lock_report_names_tests() {
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_lock_report(2);
+        
+        crate::suite!(locks(_interface: MockHardwareInterface) {
+            "long_hold" => {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok(())
+            },
+        });
+        let result = runner.run_cases("locks", locks()).await;
+        
+        let report = runner.interface().report();
+        assert_eq!(report.longest_holds[0].holder, "long_hold");
+        assert_eq!(report.longest_holds[0].hold, Duration::from_millis(30));
+        assert_eq!(result.appendices[0].title, "Interface lock");
+        assert!(result.appendices[0].body.contains("long_hold  30ms (waited 0ns)"));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_power_cycle_between_tests() {
+        use crate::mocks::{MockPowerRail, PowerEvent};
+        use PowerEvent::{Disabled, Enabled};
+        
+        let rail = MockPowerRail::new();
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        )
+        .with_power_cycle(PowerCycle::new(rail.clone(), Duration::from_millis(500)));
+        
+        let expect_events = |expected: Vec<PowerEvent>| -> TestFn<MockHardwareInterface> {
+            let rail = rail.clone();
+            Box::new(move |_| {
+                Box::pin(async move {
+                    assert_eq!(rail.events(), expected);
+                    Ok(())
+                })
+            })
+        };
+        let tests = vec![
+            ("first", expect_events(vec![Enabled])),
+            ("second", expect_events(vec![Enabled, Disabled, Enabled])),
+        ];
+        let start = Instant::now();
+        let result = runner.run_test_suite("power", tests).await;
+        
+        assert_eq!(result.passed_tests, 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        assert_eq!(rail.events(), vec![Enabled, Disabled, Enabled, Disabled]);
+    }
+    
+    fn parallel_test(duration_ms: u64, running: Arc<AtomicUsize>, peak: Arc<AtomicUsize>) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(duration_ms)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                if duration_ms == 0 {
+                    return Err(HardwareError::OperationFailed("no response".to_string()));
+                }
+                Ok(())
+            })
+        })
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_parallel_suite_with_factory() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tests: Vec<(&str, TestFn<MockHardwareInterface>)> = [300, 100, 0, 200, 100, 100]
+            .iter()
+            .zip(["a", "b", "c", "d", "e", "f"])
+            .map(|(&ms, name)| (name, parallel_test(ms, running.clone(), peak.clone())))
+            .collect();
+        
+        let suite = runner
+            .run_test_suite_parallel_with("parallel", tests, 3, create_mock_interface_with_defaults)
+            .await;
+        
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        let names: Vec<&str> = suite.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(
+            suite.results[2].status,
+            TestStatus::Error("Test failed: OperationFailed(\"no response\")".to_string())
+        );
+        assert_eq!(suite.results[3].duration, Duration::from_millis(200));
+        assert_eq!((suite.total_tests, suite.passed_tests, suite.error_tests), (6, 5, 1));
+        assert_eq!(suite.total_duration, Duration::from_millis(300));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_parallel_suite_serializes_on_shared_interface() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let hold = |ms: u64| -> TestFn<MockHardwareInterface> {
+            Box::new(move |interface| {
+                Box::pin(async move {
+                    let _guard = interface.lock().await;
+                    tokio::time::sleep(Duration::from_millis(ms)).await;
+                    Ok(())
+                })
+            })
+        };
+        
+        let suite = runner
+            .run_test_suite_parallel("shared", vec![("first", hold(100)), ("second", hold(100))], 4)
+            .await;
+        
+        assert_eq!(suite.passed_tests, 2);
+        assert_eq!(suite.total_duration, Duration::from_millis(200));
+    }
+    
+    #[tokio::test]
+    async fn test_parallel_suite_reports_panics() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let panics: TestFn<MockHardwareInterface> = Box::new(|_| Box::pin(async { panic!("bus fault") }));
+        let passes: TestFn<MockHardwareInterface> = Box::new(|_| Box::pin(async { Ok(()) }));
+        
+        let suite = runner
+            .run_test_suite_parallel("panics", vec![("panics", panics), ("passes", passes)], 2)
+            .await;
+        
+        assert!(matches!(&suite.results[0].status, TestStatus::Error(msg) if msg.starts_with("Test panicked")));
+        assert_eq!(suite.results[1].status, TestStatus::Passed);
+        assert_eq!((suite.total_tests, suite.error_tests), (2, 1));
+    }
+}
@@ -0,0 +1,473 @@
+/*
+ * Safe-State Hooks Run on Suite-Fatal Events
+ * Copyright (C) 2024
+ */
+
+use crate::HardwareResult;
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Boxed future returned by a safe-state hook
+pub type SafeStateFuture = Pin<Box<dyn Future<Output = HardwareResult<()>> + Send>>;
+
+/// What ended a suite early
+#[derive(Debug, Clone, PartialEq)]
+pub enum FatalEvent {
+    BudgetExceeded(String),
+    /// The suite future was dropped, e.g. on Ctrl-C or runtime shutdown
+    Cancelled,
+    Panicked(String),
+    Watchdog(String),
+}
+
+impl fmt::Display for FatalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FatalEvent::BudgetExceeded(reason) => write!(f, "budget exceeded: {}", reason),
+            FatalEvent::Cancelled => write!(f, "suite cancelled"),
+            FatalEvent::Panicked(message) => write!(f, "test panicked: {}", message),
+            FatalEvent::Watchdog(reason) => write!(f, "watchdog tripped: {}", reason),
+        }
+    }
+}
+
+/// Named step driving the bench to a safe state, e.g. "rails down"
+#[derive(Clone)]
+pub struct SafeStateHook {
+    pub name: String,
+    pub timeout: Duration,
+    action: Arc<dyn Fn() -> SafeStateFuture + Send + Sync>,
+}
+
+impl SafeStateHook {
+    pub fn new<F, Fut>(name: &str, timeout: Duration, action: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HardwareResult<()>> + Send + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            timeout,
+            action: Arc::new(move || Box::pin(action())),
+        }
+    }
+}
+
+impl fmt::Debug for SafeStateHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SafeStateHook")
+            .field("name", &self.name)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookOutcome {
+    Ran,
+    Failed(String),
+    TimedOut,
+}
+
+impl fmt::Display for HookOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookOutcome::Ran => write!(f, "ran"),
+            HookOutcome::Failed(reason) => write!(f, "failed: {}", reason),
+            HookOutcome::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookRecord {
+    pub name: String,
+    pub outcome: HookOutcome,
+    pub duration: Duration,
+}
+
+/// Safe-state sequence run for a fatal event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafeStateRecord {
+    pub event: FatalEvent,
+    pub hooks: Vec<HookRecord>,
+}
+
+impl SafeStateRecord {
+    /// Every hook ran to completion
+    pub fn is_complete(&self) -> bool {
+        self.hooks.iter().all(|hook| hook.outcome == HookOutcome::Ran)
+    }
+}
+
+impl fmt::Display for SafeStateRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.is_complete() { "complete" } else { "INCOMPLETE" };
+        writeln!(f, "Safe state after {}: {}", self.event, state)?;
+        for hook in &self.hooks {
+            writeln!(f, "  {}: {} ({:?})", hook.name, hook.outcome, hook.duration)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Fired {
+    event: Option<FatalEvent>,
+    record: Option<SafeStateRecord>,
+}
+
+/// Ordered hooks driving the bench hardware to a defined safe state
+/// (outputs off, actuators parked, rails down) whenever a suite dies
+///
+/// The hooks run at most once until `reset`, however many fatal paths fire:
+/// clones share that state, so a watchdog holding a clone and the runner
+/// never both run them. On budget exhaustion, a panicking test and a
+/// `trigger` from a watchdog they run on the suite's runtime. When the
+/// suite future is dropped instead, e.g. on cancellation or while the tokio
+/// runtime shuts down, the old runtime can no longer be relied on, so
+/// `trigger_blocking` runs them on a dedicated thread with its own
+/// current-thread runtime and waits for them.
+#[derive(Clone, Default)]
+pub struct SafeState {
+    hooks: Vec<SafeStateHook>,
+    fired: Arc<StdMutex<Fired>>,
+}
+
+impl SafeState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a hook; hooks run in the order they were added
+    pub fn with_hook(mut self, hook: SafeStateHook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Event the hooks ran for, `None` while armed
+    pub fn event(&self) -> Option<FatalEvent> {
+        self.fired.lock().unwrap().event.clone()
+    }
+
+    /// Take the record of the hooks' run, leaving the hooks disarmed
+    pub fn take_record(&self) -> Option<SafeStateRecord> {
+        self.fired.lock().unwrap().record.take()
+    }
+
+    /// Re-arm the hooks, e.g. once the bench has been inspected
+    pub fn reset(&self) {
+        *self.fired.lock().unwrap() = Fired::default();
+    }
+
+    fn claim(&self, event: &FatalEvent) -> bool {
+        let mut fired = self.fired.lock().unwrap();
+        if fired.event.is_some() {
+            return false;
+        }
+        fired.event = Some(event.clone());
+        true
+    }
+
+    fn store(&self, record: SafeStateRecord) -> SafeStateRecord {
+        if !record.is_complete() {
+            log::error!("{}", record.to_string().trim_end());
+        }
+        self.fired.lock().unwrap().record = Some(record.clone());
+        record
+    }
+
+    /// Run the hooks for `event`, returning their record unless they
+    /// already ran for an earlier event
+    pub async fn trigger(&self, event: FatalEvent) -> Option<SafeStateRecord> {
+        if !self.claim(&event) {
+            return None;
+        }
+        let record = run_hooks(&self.hooks, event).await;
+        Some(self.store(record))
+    }
+
+    /// `trigger` for synchronous contexts such as `Drop`, running the hooks
+    /// on a dedicated thread and runtime
+    pub fn trigger_blocking(&self, event: FatalEvent) -> Option<SafeStateRecord> {
+        if !self.claim(&event) {
+            return None;
+        }
+        let hooks = self.hooks.clone();
+        let thread_event = event.clone();
+        let record = std::thread::spawn(move || {
+            match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime.block_on(run_hooks(&hooks, thread_event)),
+                Err(e) => unrun(&hooks, thread_event, &format!("cannot start runtime: {}", e)),
+            }
+        })
+        .join()
+        .unwrap_or_else(|_| unrun(&self.hooks, event, "safe-state thread panicked"));
+        Some(self.store(record))
+    }
+}
+
+async fn run_hooks(hooks: &[SafeStateHook], event: FatalEvent) -> SafeStateRecord {
+    log::warn!("Entering safe state after {}", event);
+    let mut records = Vec::new();
+    for hook in hooks {
+        let start = Instant::now();
+        let outcome = match tokio::time::timeout(hook.timeout, CatchPanic::new((hook.action)())).await {
+            Ok(Ok(Ok(()))) => HookOutcome::Ran,
+            Ok(Ok(Err(e))) => HookOutcome::Failed(format!("{:?}", e)),
+            Ok(Err(panic)) => HookOutcome::Failed(format!("panicked: {}", panic_message(&*panic))),
+            Err(_) => HookOutcome::TimedOut,
+        };
+        records.push(HookRecord {
+            name: hook.name.clone(),
+            outcome,
+            duration: start.elapsed(),
+        });
+    }
+    SafeStateRecord { event, hooks: records }
+}
+
+fn unrun(hooks: &[SafeStateHook], event: FatalEvent, reason: &str) -> SafeStateRecord {
+    let hooks = hooks
+        .iter()
+        .map(|hook| HookRecord {
+            name: hook.name.clone(),
+            outcome: HookOutcome::Failed(reason.to_string()),
+            duration: Duration::ZERO,
+        })
+        .collect();
+    SafeStateRecord { event, hooks }
+}
+
+/// Future resolving to `Err` with the payload if polling `F` panicked
+pub(crate) struct CatchPanic<F: Future>(Pin<Box<F>>);
+
+impl<F: Future> CatchPanic<F> {
+    pub(crate) fn new(future: F) -> Self {
+        Self(Box::pin(future))
+    }
+}
+
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.0;
+        match catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "non-string panic payload".to_string(),
+    }
+}
+
+/// Runs the safe-state hooks if a suite is torn down before it finishes,
+/// i.e. its future was dropped mid-test
+pub(crate) struct SafeStateGuard<'a> {
+    safe_state: Option<&'a SafeState>,
+}
+
+impl<'a> SafeStateGuard<'a> {
+    pub(crate) fn new(safe_state: Option<&'a SafeState>) -> Self {
+        Self { safe_state }
+    }
+
+    /// The suite finished; its fatal events were handled on the way
+    pub(crate) fn disarm(mut self) {
+        self.safe_state = None;
+    }
+}
+
+impl Drop for SafeStateGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(safe_state) = self.safe_state {
+            let event = if std::thread::panicking() {
+                FatalEvent::Panicked("suite unwound".to_string())
+            } else {
+                FatalEvent::Cancelled
+            };
+            safe_state.trigger_blocking(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{AdviceRegistry, Budget, HardwareError, TestFn, TestRunner, TestStatus};
+    use tokio::time::sleep;
+
+    type Log = Arc<StdMutex<Vec<String>>>;
+
+    fn hook(name: &'static str, log: &Log, work: Duration, result: fn() -> HardwareResult<()>) -> SafeStateHook {
+        let log = log.clone();
+        SafeStateHook::new(name, Duration::from_millis(100), move || {
+            let log = log.clone();
+            async move {
+                sleep(work).await;
+                log.lock().unwrap().push(name.to_string());
+                result()
+            }
+        })
+    }
+
+    fn bench(log: &Log) -> SafeState {
+        SafeState::new()
+            .with_hook(hook("outputs off", log, Duration::ZERO, || Ok(())))
+            .with_hook(hook("rails down", log, Duration::ZERO, || Ok(())))
+    }
+
+    fn sleeper(ms: u64) -> TestFn<MockHardwareInterface> {
+        Box::new(move |_| {
+            Box::pin(async move {
+                sleep(Duration::from_millis(ms)).await;
+                Ok(())
+            })
+        })
+    }
+
+    fn runner(safe_state: &SafeState) -> TestRunner<MockHardwareInterface> {
+        TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(5), 0, Duration::ZERO)
+            .with_safe_state(safe_state.clone())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hooks_run_in_order_once() {
+        let log = Log::default();
+        let safe_state = SafeState::new()
+            .with_hook(hook("outputs off", &log, Duration::ZERO, || Ok(())))
+            .with_hook(hook("actuators parked", &log, Duration::from_secs(1), || Ok(())))
+            .with_hook(hook("rails down", &log, Duration::ZERO, || Err(HardwareError::DeviceNotFound)));
+
+        let record = safe_state.trigger(FatalEvent::Watchdog("over temperature".to_string())).await.unwrap();
+        let outcomes: Vec<_> = record.hooks.iter().map(|h| (h.name.as_str(), h.outcome.clone())).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("outputs off", HookOutcome::Ran),
+                ("actuators parked", HookOutcome::TimedOut),
+                ("rails down", HookOutcome::Failed("DeviceNotFound".to_string())),
+            ]
+        );
+        assert_eq!(record.hooks[1].duration, Duration::from_millis(100));
+        assert!(!record.is_complete());
+        assert_eq!(*log.lock().unwrap(), vec!["outputs off", "rails down"]);
+
+        assert!(safe_state.clone().trigger(FatalEvent::Cancelled).await.is_none());
+        assert!(safe_state.trigger_blocking(FatalEvent::Cancelled).is_none());
+        assert_eq!(log.lock().unwrap().len(), 2);
+        assert_eq!(safe_state.event(), Some(FatalEvent::Watchdog("over temperature".to_string())));
+
+        safe_state.reset();
+        assert!(safe_state.trigger(FatalEvent::Cancelled).await.is_some());
+        assert_eq!(log.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_exhaustion() {
+        let log = Log::default();
+        let safe_state = bench(&log);
+        let runner = runner(&safe_state).with_budget(Budget::new().max_wall_time(Duration::from_millis(150)));
+
+        let suite = runner
+            .run_test_suite("budget", vec![("a", sleeper(100)), ("b", sleeper(100)), ("c", sleeper(100))])
+            .await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert!(matches!(&suite.results[1].status, TestStatus::Error(msg) if msg.starts_with("budget exceeded")));
+        assert!(matches!(&suite.results[2].status, TestStatus::Skipped(msg) if msg.starts_with("budget exceeded")));
+        let record = suite.safe_state.as_ref().unwrap();
+        assert!(matches!(record.event, FatalEvent::BudgetExceeded(_)));
+        assert!(record.is_complete());
+        assert_eq!(*log.lock().unwrap(), vec!["outputs off", "rails down"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_trip_reported() {
+        let log = Log::default();
+        let safe_state = bench(&log).with_hook(hook("relay open", &log, Duration::ZERO, || Err(HardwareError::TimeoutError)));
+        let watchdog = safe_state.clone();
+        let trips: TestFn<MockHardwareInterface> = Box::new(move |_| {
+            Box::pin(async move {
+                watchdog.trigger(FatalEvent::Watchdog("over temperature".to_string())).await;
+                Ok(())
+            })
+        });
+
+        let suite = runner(&safe_state)
+            .run_test_suite("watchdog", vec![("trips", trips), ("after", sleeper(10))])
+            .await;
+
+        assert_eq!(
+            suite.results[1].status,
+            TestStatus::Skipped("safe state entered after watchdog tripped: over temperature".to_string())
+        );
+        assert_eq!(log.lock().unwrap().len(), 3);
+        assert!(!suite.succeeded());
+        assert!(safe_state.take_record().is_none());
+
+        let md = suite.to_markdown(&AdviceRegistry::empty());
+        assert!(md.contains(
+            "## Safe state\n\n**INCOMPLETE** safe-state sequence after watchdog tripped: over temperature\n\n\
+             - outputs off: ran (0ns)\n- rails down: ran (0ns)\n- relay open: failed: TimeoutError (0ns)\n"
+        ));
+        let json = suite.to_json();
+        assert_eq!(json["safe_state"]["complete"], false);
+        assert_eq!(json["safe_state"]["hooks"][2]["outcome"], "failed: TimeoutError");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicking_test() {
+        let log = Log::default();
+        let safe_state = bench(&log);
+        let panics: TestFn<MockHardwareInterface> = Box::new(|_| Box::pin(async { panic!("bus fault") }));
+
+        let suite = runner(&safe_state)
+            .run_test_suite("panic", vec![("panics", panics), ("after", sleeper(10))])
+            .await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Error("Test panicked: bus fault".to_string()));
+        assert!(matches!(suite.results[1].status, TestStatus::Skipped(_)));
+        assert_eq!(suite.safe_state.unwrap().event, FatalEvent::Panicked("bus fault".to_string()));
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_suite() {
+        let log = Log::default();
+        let safe_state = bench(&log);
+        let runner = runner(&safe_state);
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(20),
+            runner.run_test_suite("cancel", vec![("hangs", sleeper(60_000))]),
+        )
+        .await;
+
+        assert!(cancelled.is_err());
+        assert_eq!(*log.lock().unwrap(), vec!["outputs off", "rails down"]);
+        let record = safe_state.take_record().unwrap();
+        assert_eq!(record.event, FatalEvent::Cancelled);
+        assert!(record.is_complete());
+
+        let suite = runner.run_test_suite("after cancel", vec![("quick", sleeper(1))]).await;
+        assert!(matches!(suite.results[0].status, TestStatus::Skipped(_)));
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,245 @@
+/*
+ * Settling Before Tests on Analog Hardware
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareResult, ScopeMeasurement};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{sleep, timeout_at, Instant};
+
+/// Name of the timed scope recording how long a `SettleCondition` took
+pub const SETTLE_SCOPE: &str = "settle";
+
+const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Future sampling the value a `SettleCondition` watches
+pub type SampleFuture = Pin<Box<dyn Future<Output = HardwareResult<f64>> + Send>>;
+
+/// Why a `SettleCondition` was not met
+#[derive(Debug, PartialEq)]
+pub enum SettleError {
+    /// The value was still moving at the timeout
+    Timeout { timeout: Duration, last_samples: Vec<f64> },
+    /// Taking a sample failed
+    Sample(HardwareError),
+}
+
+impl fmt::Display for SettleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettleError::Timeout { timeout, last_samples } => {
+                write!(f, "value did not settle within {:?}; last samples: {:?}", timeout, last_samples)
+            }
+            SettleError::Sample(e) => write!(f, "sampling failed: {:?}", e),
+        }
+    }
+}
+
+impl Error for SettleError {}
+
+/// Gate on a sampled value staying within `tolerance` for
+/// `stable_samples` consecutive samples, e.g. a rail voltage after a
+/// relay switch
+///
+/// Samples are taken every `interval` (1 ms unless set) on tokio time, so
+/// paused-time tests settle deterministically.
+pub struct SettleCondition {
+    sample: Box<dyn Fn() -> SampleFuture + Send + Sync>,
+    tolerance: f64,
+    stable_samples: usize,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl SettleCondition {
+    pub fn new<F>(sample: F, tolerance: f64, stable_samples: usize, timeout: Duration) -> Self
+    where
+        F: Fn() -> SampleFuture + Send + Sync + 'static,
+    {
+        Self {
+            sample: Box::new(sample),
+            tolerance,
+            stable_samples: stable_samples.max(1),
+            interval: DEFAULT_SAMPLE_INTERVAL,
+            timeout,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sample until the value is stable, returning the time it took
+    pub async fn wait(&self) -> Result<Duration, SettleError> {
+        let start = Instant::now();
+        let deadline = start + self.timeout;
+        let mut window = VecDeque::with_capacity(self.stable_samples);
+        let timed_out = |window: VecDeque<f64>| SettleError::Timeout {
+            timeout: self.timeout,
+            last_samples: window.into(),
+        };
+
+        loop {
+            let value = match timeout_at(deadline, (self.sample)()).await {
+                Ok(value) => value.map_err(SettleError::Sample)?,
+                Err(_) => return Err(timed_out(window)),
+            };
+            if window.len() == self.stable_samples {
+                window.pop_front();
+            }
+            window.push_back(value);
+
+            if window.len() == self.stable_samples && self.spread(&window) <= self.tolerance {
+                return Ok(start.elapsed());
+            }
+            if Instant::now() + self.interval > deadline {
+                return Err(timed_out(window));
+            }
+            sleep(self.interval).await;
+        }
+    }
+
+    fn spread(&self, window: &VecDeque<f64>) -> f64 {
+        let min = window.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = window.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        max - min
+    }
+
+    /// Settling time as a timed scope budgeted at the timeout
+    pub(crate) fn measurement(&self, elapsed: Duration) -> ScopeMeasurement {
+        ScopeMeasurement {
+            name: SETTLE_SCOPE.to_string(),
+            parent: None,
+            depth: 0,
+            budget: self.timeout,
+            elapsed,
+        }
+    }
+}
+
+/// How a test waits before it starts
+pub enum Settling {
+    /// Fixed delay
+    Delay(Duration),
+    /// Wait for a sampled value to settle
+    Until(SettleCondition),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::create_mock_interface_with_defaults;
+    use crate::{TestFn, TestRunner, TestStatus};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Rail readings ringing after a relay switch, then flat at 3.3 V
+    fn ringing(settled_after: usize) -> SettleCondition {
+        let count = Arc::new(AtomicUsize::new(0));
+        SettleCondition::new(
+            move || {
+                let n = count.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move {
+                    Ok(if n < settled_after {
+                        3.3 + if n % 2 == 0 { 0.4 } else { -0.4 }
+                    } else {
+                        3.3 + 0.001 * (n % 3) as f64
+                    })
+                })
+            },
+            0.01,
+            3,
+            Duration::from_millis(50),
+        )
+    }
+
+    fn passing() -> TestFn<crate::mocks::MockHardwareInterface> {
+        Box::new(|_| Box::pin(async { Ok(()) }))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_settles_after_noise() {
+        // Samples 0-9 ring, 10-12 are the first stable window
+        assert_eq!(ringing(10).wait().await, Ok(Duration::from_millis(12)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_keeps_last_samples() {
+        let error = ringing(usize::MAX).wait().await.unwrap_err();
+        match &error {
+            SettleError::Timeout { timeout, last_samples } => {
+                assert_eq!(*timeout, Duration::from_millis(50));
+                assert_eq!(last_samples.len(), 3);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(error.to_string().starts_with("value did not settle within 50ms; last samples: [3."));
+
+        let failing = SettleCondition::new(
+            || Box::pin(async { Err(HardwareError::TimeoutError) }),
+            0.1,
+            2,
+            Duration::from_millis(5),
+        );
+        assert_eq!(failing.wait().await, Err(SettleError::Sample(HardwareError::TimeoutError)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runner_fixed_delays() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+            .with_settle_after_setup(Duration::from_millis(20))
+            .with_settling("measure", Settling::Delay(Duration::from_millis(5)));
+
+        let start = Instant::now();
+        let suite = runner
+            .run_test_suite("pacing", vec![("switch", passing()), ("measure", passing()), ("after", passing())])
+            .await;
+
+        assert_eq!(suite.passed_tests, 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(25));
+        assert!(suite.results.iter().all(|r| r.scopes.is_empty()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runner_settle_condition() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+            .with_settling("settled", Settling::Until(ringing(4)))
+            .with_settling("ringing", Settling::Until(ringing(usize::MAX)));
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counted = |ran: Arc<AtomicUsize>| -> TestFn<crate::mocks::MockHardwareInterface> {
+            Box::new(move |_| {
+                Box::pin(async move {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            })
+        };
+        let suite = runner
+            .run_test_suite("pacing", vec![("settled", counted(ran.clone())), ("ringing", counted(ran.clone()))])
+            .await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert_eq!(suite.results[0].scopes, vec![ringing(4).measurement(Duration::from_millis(6))]);
+        match &suite.results[1].status {
+            TestStatus::Error(message) => {
+                assert!(message.starts_with("settling failed: value did not settle within 50ms; last samples: ["))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+
+        let appendix = suite.appendices.iter().find(|a| a.title == "Timed scopes").unwrap();
+        assert_eq!(appendix.body, "settled\n  settle: 6ms of 50ms\n");
+    }
+}
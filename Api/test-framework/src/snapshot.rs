@@ -0,0 +1,266 @@
+/*
+ * Device Register Snapshots and Diffs
+ * Copyright (C) 2024
+ */
+
+use crate::{DiagMutex, HardwareError, HardwareResult, RegisterAccess, RegisterDescriptor, TestResult, TestStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Boxed future returned by a snapshot capture closure
+pub type SnapshotFuture = Pin<Box<dyn Future<Output = HardwareResult<DeviceSnapshot>> + Send>>;
+
+pub(crate) type CaptureFn<T> = Box<dyn Fn(Arc<DiagMutex<T>>) -> SnapshotFuture + Send + Sync>;
+
+/// One register of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRegister {
+    pub name: String,
+    pub address: u8,
+    pub width: usize,
+    pub value: u64,
+}
+
+/// Values of every register of a map at one point, e.g. a golden
+/// configuration kept across runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    pub registers: Vec<SnapshotRegister>,
+}
+
+impl DeviceSnapshot {
+    /// Read every register of `map`, as listed in a `register_map!`'s
+    /// `REGISTERS`
+    pub async fn capture<B: RegisterAccess>(bus: &mut B, map: &[RegisterDescriptor]) -> HardwareResult<Self> {
+        let mut registers = Vec::with_capacity(map.len());
+        for register in map {
+            let mut buf = vec![0u8; register.width];
+            bus.read_registers(register.address, &mut buf).await?;
+            if !register.big_endian {
+                buf.reverse();
+            }
+            registers.push(SnapshotRegister {
+                name: register.name.to_string(),
+                address: register.address,
+                width: register.width,
+                value: buf.iter().fold(0u64, |value, &byte| (value << 8) | byte as u64),
+            });
+        }
+        Ok(Self { registers })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SnapshotRegister> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    /// Registers whose value differs from this snapshot in `after`,
+    /// including registers present in only one of them
+    pub fn diff(&self, after: &DeviceSnapshot) -> Vec<RegisterDelta> {
+        let changed = self.registers.iter().filter_map(|before| {
+            let value = after.get(&before.name).map(|r| r.value);
+            (value != Some(before.value)).then(|| RegisterDelta::new(before, Some(before.value), value))
+        });
+        let added = after
+            .registers
+            .iter()
+            .filter(|r| self.get(&r.name).is_none())
+            .map(|r| RegisterDelta::new(r, None, Some(r.value)));
+        changed.chain(added).collect()
+    }
+
+    /// Fail with the deltas if `after` differs outside the `ignore`d
+    /// registers, e.g. counters and status bits
+    pub fn assert_unchanged(&self, after: &DeviceSnapshot, ignore: &[&str]) -> HardwareResult<()> {
+        let deltas: Vec<String> = self
+            .diff(after)
+            .iter()
+            .filter(|d| !ignore.contains(&d.name.as_str()))
+            .map(|d| d.to_string())
+            .collect();
+        if deltas.is_empty() {
+            Ok(())
+        } else {
+            Err(HardwareError::OperationFailed(format!("register state changed: {}", deltas.join("; "))))
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(path, text)
+    }
+}
+
+/// Register whose value changed between two snapshots; `None` where the
+/// register is missing from a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDelta {
+    pub name: String,
+    pub address: u8,
+    pub width: usize,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+}
+
+impl RegisterDelta {
+    fn new(register: &SnapshotRegister, before: Option<u64>, after: Option<u64>) -> Self {
+        Self {
+            name: register.name.clone(),
+            address: register.address,
+            width: register.width,
+            before,
+            after,
+        }
+    }
+}
+
+impl fmt::Display for RegisterDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |v: Option<u64>| match v {
+            Some(v) => format!("0x{:0digits$X}", v, digits = self.width * 2),
+            None => "-".to_string(),
+        };
+        write!(f, "{} (0x{:02X}): {} -> {}", self.name, self.address, value(self.before), value(self.after))
+    }
+}
+
+/// Before/after snapshots taken around every test by the runner
+pub(crate) struct SnapshotCheck<T> {
+    pub capture: CaptureFn<T>,
+    pub ignore: Vec<String>,
+}
+
+impl<T> SnapshotCheck<T> {
+    pub async fn capture(&self, interface: &Arc<DiagMutex<T>>) -> HardwareResult<DeviceSnapshot> {
+        (self.capture)(interface.clone()).await
+    }
+
+    /// Note every changed register on `result`, failing it if it passed.
+    /// A failed capture is noted and leaves the outcome alone.
+    pub async fn check(
+        &self,
+        interface: &Arc<DiagMutex<T>>,
+        before: HardwareResult<DeviceSnapshot>,
+        result: &mut TestResult,
+    ) {
+        let (before, after) = match (before, self.capture(interface).await) {
+            (Ok(before), Ok(after)) => (before, after),
+            (Err(e), _) | (_, Err(e)) => {
+                result.notes.push(format!("register snapshot failed: {:?}", e));
+                return;
+            }
+        };
+        let deltas: Vec<RegisterDelta> = before
+            .diff(&after)
+            .into_iter()
+            .filter(|d| !self.ignore.contains(&d.name))
+            .collect();
+        if deltas.is_empty() {
+            return;
+        }
+        for delta in &deltas {
+            result.notes.push(format!("register changed: {}", delta));
+        }
+        if result.status == TestStatus::Passed {
+            result.status = TestStatus::Failed(format!("{} registers changed", deltas.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::FakeRegisterMap;
+    use crate::{register_map, HardwareInterface, TestFn, TestRunner};
+    use std::time::Duration;
+
+    register_map! {
+        Sensor {
+            CTRL: 0x20 => Ctrl(u8) {
+                enable: 0,
+            }
+            SAMPLE_COUNT: 0x27 => SampleCount(u8) {
+                count: ro 0..8,
+            }
+            THRESHOLD: 0x30 => Threshold(u16, be) {
+                level: 0..12,
+            }
+        }
+    }
+
+    async fn device() -> FakeRegisterMap {
+        let mut fake = FakeRegisterMap::new()
+            .with_registers(0x20, &[0x01])
+            .with_registers(0x30, &[0x01, 0x80]);
+        fake.initialize().await.unwrap();
+        fake
+    }
+
+    #[tokio::test]
+    async fn test_diff_and_ignore_list() {
+        let mut fake = device().await;
+        let before = DeviceSnapshot::capture(&mut fake, Sensor::REGISTERS).await.unwrap();
+        assert_eq!(before.get("THRESHOLD").unwrap().value, 0x0180);
+
+        Sensor::write_ctrl(&mut fake, Ctrl(0x00)).await.unwrap();
+        fake.write_registers(0x27, &[5]).await.unwrap();
+        let after = DeviceSnapshot::capture(&mut fake, Sensor::REGISTERS).await.unwrap();
+
+        let deltas = before.diff(&after);
+        assert_eq!(deltas[0], RegisterDelta {
+            name: "CTRL".to_string(),
+            address: 0x20,
+            width: 1,
+            before: Some(0x01),
+            after: Some(0x00),
+        });
+        assert_eq!(deltas[1].to_string(), "SAMPLE_COUNT (0x27): 0x00 -> 0x05");
+        assert!(before.assert_unchanged(&after, &["SAMPLE_COUNT", "CTRL"]).is_ok());
+        assert_eq!(
+            before.assert_unchanged(&after, &["SAMPLE_COUNT"]),
+            Err(HardwareError::OperationFailed("register state changed: CTRL (0x20): 0x01 -> 0x00".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_golden_snapshot_round_trip() {
+        let mut fake = device().await;
+        let golden = DeviceSnapshot::capture(&mut fake, Sensor::REGISTERS).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("golden.json");
+        golden.save(&path).unwrap();
+
+        let mut partial = DeviceSnapshot::load(&path).unwrap();
+        assert_eq!(partial, golden);
+        partial.registers.pop();
+        assert_eq!(golden.diff(&partial)[0].to_string(), "THRESHOLD (0x30): 0x0180 -> -");
+    }
+
+    #[tokio::test]
+    async fn test_runner_snapshots_every_test() {
+        let runner = TestRunner::new(device().await, Duration::from_secs(1), 0, Duration::ZERO)
+            .with_register_snapshots(Sensor::REGISTERS, &["SAMPLE_COUNT"]);
+        let disable: TestFn<FakeRegisterMap> = Box::new(|device| {
+            Box::pin(async move { Sensor::write_ctrl(&mut *device.lock().await, Ctrl(0x00)).await })
+        });
+        let sample: TestFn<FakeRegisterMap> = Box::new(|device| {
+            Box::pin(async move { device.lock().await.write_registers(0x27, &[1]).await })
+        });
+
+        let suite = runner.run_test_suite("config", vec![("sample", sample), ("disable", disable)]).await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert_eq!(suite.results[1].status, TestStatus::Failed("1 registers changed".to_string()));
+        assert_eq!(suite.results[1].notes, vec!["register changed: CTRL (0x20): 0x01 -> 0x00".to_string()]);
+    }
+}
@@ -0,0 +1,520 @@
+/*
+ * Polling-Driven Device State Machine Tester
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    DiagMutex, HardwareError, HardwareInterface, HardwareResult, ReportAppendix, TestFn, TestFuture, TestRunner,
+    TestSuiteResult,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// Boxed future returned by a `read_state` closure
+pub type StateFuture<S> = Pin<Box<dyn Future<Output = HardwareResult<S>> + Send>>;
+
+type ReadStateFn<T, S> = Box<dyn Fn(Arc<DiagMutex<T>>) -> StateFuture<S> + Send + Sync>;
+type CommandFn<T> = Box<dyn Fn(Arc<DiagMutex<T>>) -> TestFuture + Send + Sync>;
+
+/// How the tester walks the declared transitions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exploration {
+    /// Every declared transition once, in declaration order
+    EveryEdge,
+    /// `steps` transitions chosen by a seeded pseudo-random walk from the
+    /// initial state, reproducible for a given seed
+    RandomWalk { seed: u64, steps: usize },
+}
+
+/// A transition rule broken by the device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation<S> {
+    /// The device settled in a state other than the transition's target
+    WrongDestination { from: S, expected: S, actual: S },
+    /// The device stayed in `from` past the transition's time bound
+    Timeout { from: S, to: S, waited: Duration },
+    /// The state changed between steps without a command
+    Spontaneous { expected: S, observed: S },
+    /// A transition declared forbidden was observed
+    Forbidden { from: S, to: S },
+    /// No declared path leads from the observed state to the next step
+    Unreachable { from: S, to: S },
+}
+
+impl<S: fmt::Debug> fmt::Display for Violation<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::WrongDestination { from, expected, actual } => {
+                write!(f, "{:?} -> {:?} ended in {:?}", from, expected, actual)
+            }
+            Violation::Timeout { from, to, waited } => {
+                write!(f, "{:?} -> {:?} not reached within {:?}", from, to, waited)
+            }
+            Violation::Spontaneous { expected, observed } => {
+                write!(f, "spontaneous transition {:?} -> {:?} between steps", expected, observed)
+            }
+            Violation::Forbidden { from, to } => write!(f, "forbidden transition {:?} -> {:?} observed", from, to),
+            Violation::Unreachable { from, to } => write!(f, "no declared path from {:?} to {:?}", from, to),
+        }
+    }
+}
+
+struct Transition<T, S> {
+    from: S,
+    to: S,
+    max_time: Duration,
+    command: CommandFn<T>,
+}
+
+struct Model<T, S> {
+    states: Vec<S>,
+    transitions: Vec<Transition<T, S>>,
+    forbidden: Vec<(S, S)>,
+    read_state: ReadStateFn<T, S>,
+    poll_interval: Duration,
+}
+
+/// Tester for device mode machines such as OFF -> BOOT -> IDLE -> MEASURING
+///
+/// Each step issues the command of one declared transition and polls
+/// `read_state` until the target state appears or the transition's time
+/// bound passes. Before a step the device is routed to the transition's
+/// source state along declared transitions, so a failed step does not stop
+/// the exploration.
+pub struct StateMachineTester<T, S> {
+    model: Model<T, S>,
+    initial: Option<S>,
+    exploration: Exploration,
+}
+
+impl<T, S> StateMachineTester<T, S>
+where
+    T: HardwareInterface + 'static,
+    S: Copy + PartialEq + fmt::Debug + Send + Sync + 'static,
+{
+    pub fn new<F>(read_state: F) -> Self
+    where
+        F: Fn(Arc<DiagMutex<T>>) -> StateFuture<S> + Send + Sync + 'static,
+    {
+        Self {
+            model: Model {
+                states: Vec::new(),
+                transitions: Vec::new(),
+                forbidden: Vec::new(),
+                read_state: Box::new(read_state),
+                poll_interval: Duration::from_millis(10),
+            },
+            initial: None,
+            exploration: Exploration::EveryEdge,
+        }
+    }
+
+    /// Declare a state; the first declared state is the initial one unless
+    /// `with_initial` says otherwise
+    pub fn state(mut self, state: S) -> Self {
+        if !self.model.states.contains(&state) {
+            self.model.states.push(state);
+        }
+        self
+    }
+
+    /// Allow `from -> to`, triggered by `command` and completing within `max_time`
+    pub fn transition<F>(mut self, from: S, to: S, max_time: Duration, command: F) -> Self
+    where
+        F: Fn(Arc<DiagMutex<T>>) -> TestFuture + Send + Sync + 'static,
+    {
+        self = self.state(from).state(to);
+        self.model.transitions.push(Transition {
+            from,
+            to,
+            max_time,
+            command: Box::new(command),
+        });
+        self
+    }
+
+    /// Transition that must never be observed
+    pub fn forbid(mut self, from: S, to: S) -> Self {
+        self.model.forbidden.push((from, to));
+        self
+    }
+
+    pub fn with_initial(mut self, state: S) -> Self {
+        self.initial = Some(state);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.model.poll_interval = interval;
+        self
+    }
+
+    pub fn with_exploration(mut self, exploration: Exploration) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    /// Transition indices visited by the configured exploration
+    fn plan(&self) -> Vec<usize> {
+        match self.exploration {
+            Exploration::EveryEdge => (0..self.model.transitions.len()).collect(),
+            Exploration::RandomWalk { seed, steps } => {
+                let mut rng = XorShift::new(seed);
+                let mut current = match self.initial.or_else(|| self.model.states.first().copied()) {
+                    Some(state) => state,
+                    None => return Vec::new(),
+                };
+                let mut plan = Vec::new();
+                for _ in 0..steps {
+                    let outgoing: Vec<usize> = (0..self.model.transitions.len())
+                        .filter(|&i| self.model.transitions[i].from == current)
+                        .collect();
+                    if outgoing.is_empty() {
+                        break;
+                    }
+                    let index = outgoing[rng.next_u64() as usize % outgoing.len()];
+                    plan.push(index);
+                    current = self.model.transitions[index].to;
+                }
+                plan
+            }
+        }
+    }
+
+    /// Generated tests, one per planned step, named `<base>::<n>:<FROM>-><TO>`.
+    /// Violations are also collected in the returned log.
+    pub fn cases(self, base_name: &str) -> (Vec<(String, TestFn<T>)>, Arc<StdMutex<Vec<Violation<S>>>>) {
+        let plan = self.plan();
+        let model = Arc::new(self.model);
+        let violations = Arc::new(StdMutex::new(Vec::new()));
+        let last_seen: Arc<StdMutex<Option<S>>> = Arc::new(StdMutex::new(None));
+
+        let cases = plan
+            .into_iter()
+            .enumerate()
+            .map(|(step, index)| {
+                let transition = &model.transitions[index];
+                let name = format!("{}::{}:{:?}->{:?}", base_name, step, transition.from, transition.to);
+                let model = model.clone();
+                let violations = violations.clone();
+                let last_seen = last_seen.clone();
+                let test: TestFn<T> = Box::new(move |interface| {
+                    Box::pin(async move {
+                        let found = model.step(interface, index, &last_seen).await?;
+                        if found.is_empty() {
+                            return Ok(());
+                        }
+                        let message = found.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("; ");
+                        violations.lock().unwrap().extend(found);
+                        Err(HardwareError::OperationFailed(message))
+                    })
+                });
+                (name, test)
+            })
+            .collect();
+        (cases, violations)
+    }
+}
+
+impl<T: HardwareInterface + 'static, S: Copy + PartialEq + fmt::Debug + Send + Sync + 'static> Model<T, S> {
+    async fn read(&self, interface: &Arc<DiagMutex<T>>) -> HardwareResult<S> {
+        (self.read_state)(interface.clone()).await
+    }
+
+    fn is_forbidden(&self, from: S, to: S) -> bool {
+        self.forbidden.contains(&(from, to))
+    }
+
+    /// Run one planned transition, returning the violations it exposed
+    async fn step(
+        &self,
+        interface: Arc<DiagMutex<T>>,
+        index: usize,
+        last_seen: &StdMutex<Option<S>>,
+    ) -> HardwareResult<Vec<Violation<S>>> {
+        let mut found = Vec::new();
+        let transition = &self.transitions[index];
+
+        let mut current = self.read(&interface).await?;
+        let expected = *last_seen.lock().unwrap();
+        if let Some(expected) = expected {
+            if current != expected {
+                found.push(Violation::Spontaneous { expected, observed: current });
+                if self.is_forbidden(expected, current) {
+                    found.push(Violation::Forbidden { from: expected, to: current });
+                }
+            }
+        }
+
+        if current != transition.from {
+            let path = match self.path(current, transition.from) {
+                Some(path) => path,
+                None => {
+                    found.push(Violation::Unreachable { from: current, to: transition.from });
+                    *last_seen.lock().unwrap() = Some(current);
+                    return Ok(found);
+                }
+            };
+            for hop in path {
+                let (reached, _) = self.fire(&interface, hop).await?;
+                current = reached;
+                if current != self.transitions[hop].to {
+                    found.push(Violation::Unreachable { from: current, to: transition.from });
+                    *last_seen.lock().unwrap() = Some(current);
+                    return Ok(found);
+                }
+            }
+        }
+
+        let (reached, observed) = self.fire(&interface, index).await?;
+        *last_seen.lock().unwrap() = Some(reached);
+        for state in observed {
+            if state != transition.from && self.is_forbidden(transition.from, state) {
+                let violation = Violation::Forbidden { from: transition.from, to: state };
+                if !found.contains(&violation) {
+                    found.push(violation);
+                }
+            }
+        }
+        if reached == transition.from {
+            found.push(Violation::Timeout {
+                from: transition.from,
+                to: transition.to,
+                waited: transition.max_time,
+            });
+        } else if reached != transition.to {
+            found.push(Violation::WrongDestination {
+                from: transition.from,
+                expected: transition.to,
+                actual: reached,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Issue a transition's command and poll until its target appears or
+    /// the time bound passes, returning the last and all observed states
+    async fn fire(&self, interface: &Arc<DiagMutex<T>>, index: usize) -> HardwareResult<(S, Vec<S>)> {
+        let transition = &self.transitions[index];
+        (transition.command)(interface.clone()).await?;
+
+        let deadline = Instant::now() + transition.max_time;
+        let mut observed = Vec::new();
+        loop {
+            let state = self.read(interface).await?;
+            if !observed.contains(&state) {
+                observed.push(state);
+            }
+            if state == transition.to || Instant::now() >= deadline {
+                return Ok((state, observed));
+            }
+            sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Shortest chain of declared transitions from `from` to `to`
+    fn path(&self, from: S, to: S) -> Option<Vec<usize>> {
+        let mut queue = VecDeque::from([(from, Vec::new())]);
+        let mut visited = vec![from];
+        while let Some((state, path)) = queue.pop_front() {
+            if state == to {
+                return Some(path);
+            }
+            for (i, transition) in self.transitions.iter().enumerate() {
+                if transition.from == state && !visited.contains(&transition.to) {
+                    visited.push(transition.to);
+                    let mut next = path.clone();
+                    next.push(i);
+                    queue.push_back((transition.to, next));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Small deterministic generator for reproducible random walks
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run a state machine exploration as a suite, one test per step, with
+    /// the violations listed in a report appendix
+    pub async fn run_state_machine<S>(&self, name: &str, tester: StateMachineTester<T, S>) -> TestSuiteResult
+    where
+        S: Copy + PartialEq + fmt::Debug + Send + Sync + 'static,
+    {
+        let (cases, violations) = tester.cases(name);
+        let names: Vec<String> = cases.iter().map(|(name, _)| name.clone()).collect();
+        let tests = names.iter().map(String::as_str).zip(cases.into_iter().map(|(_, f)| f)).collect();
+
+        let mut suite = self.run_test_suite(name, tests).await;
+        let violations = violations.lock().unwrap();
+        if !violations.is_empty() {
+            let body: String = violations.iter().map(|v| format!("{}\n", v)).collect();
+            suite.add_appendix(ReportAppendix::new("State machine violations", &body));
+        }
+        suite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InterfaceStatus, TestStatus};
+    use async_trait::async_trait;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Mode {
+        Off,
+        Idle,
+        Measuring,
+    }
+
+    /// Device settling into a commanded mode after 20ms. Stopping a
+    /// measurement wrongly powers it off.
+    struct FakeModeDevice {
+        mode: Mode,
+        pending: Option<(Mode, Instant)>,
+    }
+
+    impl FakeModeDevice {
+        fn command(&mut self, target: Mode) {
+            let target = if self.mode == Mode::Measuring && target == Mode::Idle { Mode::Off } else { target };
+            self.pending = Some((target, Instant::now() + Duration::from_millis(20)));
+        }
+
+        fn mode(&mut self) -> Mode {
+            if let Some((mode, at)) = self.pending {
+                if Instant::now() >= at {
+                    self.mode = mode;
+                    self.pending = None;
+                }
+            }
+            self.mode
+        }
+    }
+
+    #[async_trait]
+    impl HardwareInterface for FakeModeDevice {
+        async fn initialize(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        async fn deinitialize(&mut self) -> HardwareResult<()> {
+            Ok(())
+        }
+
+        fn is_initialized(&self) -> bool {
+            true
+        }
+
+        async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+            Ok(InterfaceStatus {
+                initialized: true,
+                error_count: 0,
+                last_error: None,
+                uptime: Duration::from_secs(0),
+            })
+        }
+    }
+
+    fn command(target: Mode) -> impl Fn(Arc<DiagMutex<FakeModeDevice>>) -> TestFuture + Send + Sync {
+        move |device: Arc<DiagMutex<FakeModeDevice>>| -> TestFuture {
+            Box::pin(async move {
+                device.lock().await.command(target);
+                Ok(())
+            })
+        }
+    }
+
+    fn tester() -> StateMachineTester<FakeModeDevice, Mode> {
+        let bound = Duration::from_millis(50);
+        StateMachineTester::new(|device: Arc<DiagMutex<FakeModeDevice>>| -> StateFuture<Mode> {
+            Box::pin(async move { Ok(device.lock().await.mode()) })
+        })
+        .transition(Mode::Off, Mode::Idle, bound, command(Mode::Idle))
+        .transition(Mode::Idle, Mode::Measuring, bound, command(Mode::Measuring))
+        .transition(Mode::Measuring, Mode::Idle, bound, command(Mode::Idle))
+        .transition(Mode::Idle, Mode::Off, bound, command(Mode::Off))
+        .forbid(Mode::Measuring, Mode::Off)
+    }
+
+    fn runner() -> TestRunner<FakeModeDevice> {
+        let device = FakeModeDevice { mode: Mode::Off, pending: None };
+        TestRunner::new(device, Duration::from_secs(1), 0, Duration::ZERO)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_every_edge_reports_buggy_transition() {
+        let suite = runner().run_state_machine("mode", tester()).await;
+
+        let names: Vec<&str> = suite.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec![
+            "mode::0:Off->Idle",
+            "mode::1:Idle->Measuring",
+            "mode::2:Measuring->Idle",
+            "mode::3:Idle->Off",
+        ]);
+        assert_eq!(suite.passed_tests, 3);
+        assert!(matches!(suite.results[2].status, TestStatus::Error(_)));
+
+        let appendix = &suite.appendices[0];
+        assert_eq!(appendix.title, "State machine violations");
+        assert_eq!(
+            appendix.body,
+            "forbidden transition Measuring -> Off observed\nMeasuring -> Idle ended in Off\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_and_spontaneous_transition() {
+        let (cases, violations) = tester()
+            .transition(Mode::Off, Mode::Measuring, Duration::from_millis(50), |_| Box::pin(async { Ok(()) }))
+            .cases("mode");
+        let interface = Arc::new(DiagMutex::new(FakeModeDevice { mode: Mode::Off, pending: None }));
+        let mut cases = cases.into_iter();
+
+        (cases.next().unwrap().1)(interface.clone()).await.unwrap();
+        interface.lock().await.mode = Mode::Off;
+        let last = cases.last().unwrap();
+        assert!((last.1)(interface.clone()).await.is_err());
+
+        assert_eq!(*violations.lock().unwrap(), vec![
+            Violation::Spontaneous { expected: Mode::Idle, observed: Mode::Off },
+            Violation::Timeout { from: Mode::Off, to: Mode::Measuring, waited: Duration::from_millis(50) },
+        ]);
+    }
+
+    #[test]
+    fn test_random_walk_is_reproducible() {
+        let walk = |seed| tester().with_exploration(Exploration::RandomWalk { seed, steps: 20 }).plan();
+
+        let plan = walk(7);
+        assert_eq!(plan.len(), 20);
+        assert_eq!(plan, walk(7));
+
+        let transitions = tester().model.transitions;
+        assert_eq!(transitions[plan[0]].from, Mode::Off);
+        for pair in plan.windows(2) {
+            assert_eq!(transitions[pair[0]].to, transitions[pair[1]].from);
+        }
+    }
+}
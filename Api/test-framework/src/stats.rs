@@ -0,0 +1,181 @@
+/*
+ * Operation Statistics for Hardware Interface Testing
+ * Copyright (C) 2024
+ */
+
+use crate::{Bidirectional, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, CapabilitySet};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Shared operation counters for one or more interfaces
+#[derive(Debug, Default)]
+pub struct OperationStats {
+    operations: AtomicU64,
+    errors: AtomicU32,
+}
+
+impl OperationStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Record the outcome of a single bus operation
+    pub fn record<T>(&self, result: &HardwareResult<T>) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn operations(&self) -> u64 {
+        self.operations.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.operations.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            operations: self.operations(),
+            errors: self.errors(),
+        }
+    }
+}
+
+/// Counter values of an `OperationStats` at one point
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatsSnapshot {
+    pub operations: u64,
+    pub errors: u32,
+}
+
+/// Interface wrapper counting every read, write and transfer into shared stats
+pub struct Counted<T> {
+    inner: T,
+    stats: Arc<OperationStats>,
+}
+
+impl<T> Counted<T> {
+    pub fn new(inner: T, stats: Arc<OperationStats>) -> Self {
+        Self { inner, stats }
+    }
+
+    pub fn stats(&self) -> Arc<OperationStats> {
+        self.stats.clone()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Send + Sync> HardwareInterface for Counted<T> {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.inner.initialize().await
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.inner.deinitialize().await
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.inner.is_initialized()
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        self.inner.get_status().await
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.inner.capabilities()
+    }
+}
+
+#[async_trait]
+impl<T: Readable + Send> Readable for Counted<T> {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let result = self.inner.read(buffer, timeout).await;
+        self.stats.record(&result);
+        result
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        let result = self.inner.read_exact(buffer, timeout).await;
+        self.stats.record(&result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Writable + Send> Writable for Counted<T> {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        let result = self.inner.write(data).await;
+        self.stats.record(&result);
+        result
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        let result = self.inner.write_all(data).await;
+        self.stats.record(&result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Bidirectional + Send> Bidirectional for Counted<T> {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let result = self.inner.transfer(tx_data, rx_data, timeout).await;
+        self.stats.record(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::I2CInterface;
+
+    #[tokio::test]
+    async fn test_counted_records_operations() {
+        let stats = OperationStats::new();
+        let mut interface = Counted::new(I2CInterface::with_default_config(), stats.clone());
+        assert!(interface.initialize().await.is_ok());
+
+        let mut buffer = vec![0u8; 4];
+        assert!(interface.write(&[1, 2, 3]).await.is_ok());
+        assert!(interface.read(&mut buffer, Duration::from_millis(100)).await.is_ok());
+
+        assert_eq!(stats.operations(), 2);
+        assert_eq!(stats.errors(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_counted_records_errors() {
+        let stats = OperationStats::new();
+        let mut interface = Counted::new(I2CInterface::with_default_config(), stats.clone());
+
+        assert!(interface.write(&[1, 2, 3]).await.is_err());
+        assert_eq!(stats.operations(), 1);
+        assert_eq!(stats.errors(), 1);
+
+        stats.reset();
+        assert_eq!(stats.operations(), 0);
+    }
+}
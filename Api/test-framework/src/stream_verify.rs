@@ -0,0 +1,476 @@
+/*
+ * Bounded-Memory Verification of Large Device Reads
+ * Copyright (C) 2024
+ */
+
+use crate::{read_chunked, Crc32, HardwareError, HardwareResult, Readable, ReportAppendix};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Chunk size used unless configured otherwise
+pub const DEFAULT_VERIFY_CHUNK: usize = 64 * 1024;
+
+type PatternFn = Box<dyn FnMut(u64) -> u8 + Send>;
+type ProgressFn = Box<dyn FnMut(u64) + Send>;
+
+/// What a verified stream should contain
+pub enum ExpectedStream {
+    /// Byte at every absolute offset, e.g. an erased (0xFF) or test pattern
+    Pattern(PatternFn),
+    /// CRC-32 of the whole stream; only the final digest is compared
+    Crc32(u32),
+    /// Golden image read lazily from disk alongside the stream
+    GoldenFile(PathBuf),
+}
+
+impl ExpectedStream {
+    pub fn pattern<F: FnMut(u64) -> u8 + Send + 'static>(pattern: F) -> Self {
+        ExpectedStream::Pattern(Box::new(pattern))
+    }
+
+    pub fn golden_file(path: &Path) -> Self {
+        ExpectedStream::GoldenFile(path.to_path_buf())
+    }
+}
+
+/// First difference between a stream and its expectation
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamMismatch {
+    /// Byte at absolute `offset` differs
+    Byte { offset: u64, expected: u8, actual: u8 },
+    /// The stream ended before or ran past the golden image
+    Length { expected: u64, actual: u64 },
+    /// Final CRC-32 differs
+    Digest { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for StreamMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamMismatch::Byte { offset, expected, actual } => {
+                write!(f, "offset {} (0x{:X}): expected {:02x}, got {:02x}", offset, offset, expected, actual)
+            }
+            StreamMismatch::Length { expected, actual } => {
+                write!(f, "length: expected {} bytes, got {}", expected, actual)
+            }
+            StreamMismatch::Digest { expected, actual } => {
+                write!(f, "crc32: expected {:08x}, got {:08x}", expected, actual)
+            }
+        }
+    }
+}
+
+/// Outcome of a stream verification
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamReport {
+    /// Bytes read before the end of the stream or the first mismatch
+    pub bytes: u64,
+    pub mismatch: Option<StreamMismatch>,
+    pub elapsed: Duration,
+}
+
+impl StreamReport {
+    pub fn check(&self) -> HardwareResult<()> {
+        match &self.mismatch {
+            Some(mismatch) => Err(HardwareError::OperationFailed(format!("stream verification: {}", mismatch))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn appendix(&self) -> ReportAppendix {
+        ReportAppendix::new("Stream verification", &self.to_string())
+    }
+}
+
+impl fmt::Display for StreamReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rate = self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON) / 1024.0;
+        writeln!(f, "{} bytes verified in {:?} ({:.1} KiB/s)", self.bytes, self.elapsed, rate)?;
+        match &self.mismatch {
+            Some(mismatch) => writeln!(f, "mismatch: {}", mismatch),
+            None => writeln!(f, "no mismatch"),
+        }
+    }
+}
+
+/// Expectation state kept while a stream is fed through in chunks
+enum Checker {
+    Pattern(PatternFn),
+    Crc32 { expected: u32, crc: Crc32 },
+    GoldenFile { file: File, buffer: Vec<u8> },
+}
+
+impl Checker {
+    fn open(expected: ExpectedStream, chunk_size: usize) -> io::Result<Self> {
+        Ok(match expected {
+            ExpectedStream::Pattern(pattern) => Checker::Pattern(pattern),
+            ExpectedStream::Crc32(expected) => Checker::Crc32 { expected, crc: Crc32::new() },
+            ExpectedStream::GoldenFile(path) => Checker::GoldenFile {
+                file: File::open(path)?,
+                buffer: vec![0; chunk_size],
+            },
+        })
+    }
+
+    /// Compare `chunk` found at absolute `offset`
+    fn feed(&mut self, offset: u64, chunk: &[u8]) -> io::Result<Option<StreamMismatch>> {
+        match self {
+            Checker::Pattern(pattern) => Ok(chunk.iter().enumerate().find_map(|(i, &actual)| {
+                let expected = pattern(offset + i as u64);
+                (actual != expected).then_some(StreamMismatch::Byte { offset: offset + i as u64, expected, actual })
+            })),
+            Checker::Crc32 { crc, .. } => {
+                crc.update(chunk);
+                Ok(None)
+            }
+            Checker::GoldenFile { file, buffer } => {
+                let golden = &mut buffer[..chunk.len()];
+                let available = read_full(file, golden)?;
+                let compared = chunk[..available].iter().zip(&golden[..available]).position(|(a, e)| a != e);
+                if let Some(i) = compared {
+                    return Ok(Some(StreamMismatch::Byte {
+                        offset: offset + i as u64,
+                        expected: golden[i],
+                        actual: chunk[i],
+                    }));
+                }
+                if available < chunk.len() {
+                    return Ok(Some(StreamMismatch::Length {
+                        expected: offset + available as u64,
+                        actual: offset + chunk.len() as u64,
+                    }));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Final comparison once the stream ended after `total` bytes
+    fn finish(&mut self, total: u64) -> io::Result<Option<StreamMismatch>> {
+        match self {
+            Checker::Pattern(_) => Ok(None),
+            Checker::Crc32 { expected, crc } => {
+                let actual = crc.finish();
+                Ok((actual != *expected).then_some(StreamMismatch::Digest { expected: *expected, actual }))
+            }
+            Checker::GoldenFile { file, .. } => {
+                let remaining = io::copy(file, &mut io::sink())?;
+                Ok((remaining > 0).then_some(StreamMismatch::Length { expected: total + remaining, actual: total }))
+            }
+        }
+    }
+}
+
+/// Fill `buffer` from `file` as far as it goes, returning the bytes read
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn golden_error(e: io::Error) -> HardwareError {
+    HardwareError::OperationFailed(format!("reading golden file: {}", e))
+}
+
+/// Verifier comparing a stream against its expectation in fixed-size
+/// chunks, so memory stays O(chunk) however large the stream is. Stops at
+/// the first mismatch.
+pub struct StreamVerifier {
+    expected: ExpectedStream,
+    chunk_size: usize,
+    progress: Option<(u64, ProgressFn)>,
+}
+
+impl StreamVerifier {
+    pub fn new(expected: ExpectedStream) -> Self {
+        Self {
+            expected,
+            chunk_size: DEFAULT_VERIFY_CHUNK,
+            progress: None,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Call `callback` with the bytes verified so far every `interval` bytes
+    pub fn with_progress<F: FnMut(u64) + Send + 'static>(mut self, interval: u64, callback: F) -> Self {
+        self.progress = Some((interval, Box::new(callback)));
+        self
+    }
+
+    fn begin(self) -> HardwareResult<Run> {
+        if self.chunk_size == 0 {
+            return Err(HardwareError::InvalidParameter("chunk_size must be non-zero".to_string()));
+        }
+        let next_progress = self.progress.as_ref().map_or(0, |(interval, _)| *interval);
+        Ok(Run {
+            checker: Checker::open(self.expected, self.chunk_size).map_err(golden_error)?,
+            buffer: vec![0; self.chunk_size],
+            bytes: 0,
+            mismatch: None,
+            progress: self.progress,
+            next_progress,
+            start: Instant::now(),
+        })
+    }
+
+    /// Read chunks from `reader` until it returns 0
+    pub fn verify<R>(self, mut reader: R) -> HardwareResult<StreamReport>
+    where
+        R: FnMut(&mut [u8]) -> HardwareResult<usize>,
+    {
+        let mut run = self.begin()?;
+        loop {
+            let len = reader(&mut run.buffer)?;
+            if len > run.buffer.len() {
+                return Err(HardwareError::InvalidParameter(format!(
+                    "reader returned {} bytes for a {} byte chunk",
+                    len,
+                    run.buffer.len()
+                )));
+            }
+            if len == 0 || run.feed(len)? {
+                break;
+            }
+        }
+        run.finish()
+    }
+
+    /// Verify `length` bytes of `device`, reading every chunk with
+    /// `read_chunked` in transfers of at most `transfer_size` bytes
+    pub async fn verify_device<R>(
+        self,
+        device: &mut R,
+        length: u64,
+        transfer_size: usize,
+        timeout: Duration,
+    ) -> HardwareResult<StreamReport>
+    where
+        R: Readable + ?Sized,
+    {
+        let mut run = self.begin()?;
+        while run.bytes < length {
+            let len = (length - run.bytes).min(run.buffer.len() as u64) as usize;
+            read_chunked(device, &mut run.buffer[..len], transfer_size, timeout).await?;
+            if run.feed(len)? {
+                break;
+            }
+        }
+        run.finish()
+    }
+}
+
+/// Verify `reader` against `expected` in chunks of `DEFAULT_VERIFY_CHUNK`
+pub fn verify_stream<R>(reader: R, expected: ExpectedStream) -> HardwareResult<StreamReport>
+where
+    R: FnMut(&mut [u8]) -> HardwareResult<usize>,
+{
+    StreamVerifier::new(expected).verify(reader)
+}
+
+/// State of one verification run
+struct Run {
+    checker: Checker,
+    buffer: Vec<u8>,
+    bytes: u64,
+    mismatch: Option<StreamMismatch>,
+    progress: Option<(u64, ProgressFn)>,
+    next_progress: u64,
+    start: Instant,
+}
+
+impl Run {
+    /// Check the first `len` bytes of the buffer, returning whether they
+    /// held a mismatch
+    fn feed(&mut self, len: usize) -> HardwareResult<bool> {
+        let mismatch = self.checker.feed(self.bytes, &self.buffer[..len]).map_err(golden_error)?;
+        self.bytes = match &mismatch {
+            Some(StreamMismatch::Byte { offset, .. }) => *offset,
+            Some(StreamMismatch::Length { expected, .. }) => *expected,
+            _ => self.bytes + len as u64,
+        };
+        if let Some((interval, callback)) = &mut self.progress {
+            if *interval > 0 && self.bytes >= self.next_progress {
+                callback(self.bytes);
+                self.next_progress = (self.bytes / *interval + 1) * *interval;
+            }
+        }
+        self.mismatch = mismatch;
+        Ok(self.mismatch.is_some())
+    }
+
+    fn finish(mut self) -> HardwareResult<StreamReport> {
+        if self.mismatch.is_none() {
+            self.mismatch = self.checker.finish(self.bytes).map_err(golden_error)?;
+        }
+        Ok(StreamReport {
+            bytes: self.bytes,
+            mismatch: self.mismatch,
+            elapsed: self.start.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    const MIB: u64 = 1024 * 1024;
+    const CHUNK: usize = 4096;
+
+    fn pattern(offset: u64) -> u8 {
+        (offset % 251) as u8
+    }
+
+    /// Synthetic device stream of `len` pattern bytes, with `corrupt`
+    /// flipped. Panics if asked for more than one chunk at a time.
+    fn synthetic(len: u64, corrupt: Option<u64>) -> impl FnMut(&mut [u8]) -> HardwareResult<usize> {
+        let mut offset = 0;
+        move |buffer: &mut [u8]| {
+            assert!(buffer.len() <= CHUNK);
+            let n = (len - offset).min(buffer.len() as u64) as usize;
+            for (i, byte) in buffer[..n].iter_mut().enumerate() {
+                let at = offset + i as u64;
+                *byte = pattern(at) ^ if Some(at) == corrupt { 0x10 } else { 0 };
+            }
+            offset += n as u64;
+            Ok(n)
+        }
+    }
+
+    fn verifier(expected: ExpectedStream) -> StreamVerifier {
+        StreamVerifier::new(expected).with_chunk_size(CHUNK)
+    }
+
+    #[test]
+    fn test_pattern_stream_with_progress() {
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let seen = progress.clone();
+
+        let report = verifier(ExpectedStream::pattern(pattern))
+            .with_progress(MIB, move |bytes| seen.lock().unwrap().push(bytes / MIB))
+            .verify(synthetic(4 * MIB, None))
+            .unwrap();
+
+        assert_eq!(report.bytes, 4 * MIB);
+        assert!(report.check().is_ok());
+        assert_eq!(*progress.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_pattern_mismatch_aborts_at_exact_offset() {
+        let offset = 3 * MIB + 12_345;
+        let mut reads = 0u64;
+        let mut source = synthetic(4 * MIB, Some(offset));
+
+        let report = verifier(ExpectedStream::pattern(pattern))
+            .verify(|buffer: &mut [u8]| {
+                reads += 1;
+                source(buffer)
+            })
+            .unwrap();
+
+        assert_eq!(report.mismatch, Some(StreamMismatch::Byte {
+            offset,
+            expected: pattern(offset),
+            actual: pattern(offset) ^ 0x10,
+        }));
+        assert_eq!(report.bytes, offset);
+        assert_eq!(reads, offset / CHUNK as u64 + 1);
+    }
+
+    #[test]
+    fn test_crc_compares_final_digest() {
+        let mut crc = Crc32::new();
+        let mut source = synthetic(2 * MIB, None);
+        let mut buffer = vec![0; CHUNK];
+        while let Ok(n @ 1..) = source(&mut buffer) {
+            crc.update(&buffer[..n]);
+        }
+        let expected = crc.finish();
+
+        assert!(verifier(ExpectedStream::Crc32(expected)).verify(synthetic(2 * MIB, None)).unwrap().check().is_ok());
+        let report = verifier(ExpectedStream::Crc32(expected)).verify(synthetic(2 * MIB, Some(MIB))).unwrap();
+        assert!(matches!(report.mismatch, Some(StreamMismatch::Digest { expected: e, .. }) if e == expected));
+        assert_eq!(report.bytes, 2 * MIB);
+    }
+
+    #[test]
+    fn test_golden_file_mismatch_and_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("golden.bin");
+        let mut file = File::create(&path).unwrap();
+        let mut source = synthetic(2 * MIB, None);
+        let mut buffer = vec![0; CHUNK];
+        while let Ok(n @ 1..) = source(&mut buffer) {
+            file.write_all(&buffer[..n]).unwrap();
+        }
+        drop(file);
+
+        let golden = || verifier(ExpectedStream::golden_file(&path));
+        assert_eq!(golden().verify(synthetic(2 * MIB, None)).unwrap().mismatch, None);
+        assert_eq!(
+            golden().verify(synthetic(2 * MIB, Some(MIB + 1))).unwrap().mismatch,
+            Some(StreamMismatch::Byte { offset: MIB + 1, expected: pattern(MIB + 1), actual: pattern(MIB + 1) ^ 0x10 })
+        );
+        assert_eq!(
+            golden().verify(synthetic(MIB, None)).unwrap().mismatch,
+            Some(StreamMismatch::Length { expected: 2 * MIB, actual: MIB })
+        );
+        assert_eq!(
+            golden().verify(synthetic(2 * MIB + 10, None)).unwrap().mismatch,
+            Some(StreamMismatch::Length { expected: 2 * MIB, actual: 2 * MIB + 10 })
+        );
+    }
+
+    /// Device producing the pattern stream, at most `max_transfer` bytes a read
+    struct PatternDevice {
+        offset: u64,
+        max_transfer: usize,
+    }
+
+    #[async_trait]
+    impl Readable for PatternDevice {
+        async fn read(&mut self, buffer: &mut [u8], _timeout: Duration) -> HardwareResult<usize> {
+            assert!(buffer.len() <= self.max_transfer);
+            for byte in buffer.iter_mut() {
+                *byte = pattern(self.offset);
+                self.offset += 1;
+            }
+            Ok(buffer.len())
+        }
+
+        async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+            self.read(buffer, timeout).await.map(|_| ())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_device_in_chunked_reads() {
+        let mut device = PatternDevice { offset: 0, max_transfer: 256 };
+
+        let report = verifier(ExpectedStream::pattern(pattern))
+            .verify_device(&mut device, MIB + 100, 256, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        assert_eq!(report.bytes, MIB + 100);
+        assert_eq!(device.offset, MIB + 100);
+        assert!(report.to_string().ends_with("no mismatch\n"));
+    }
+}
@@ -0,0 +1,344 @@
+/*
+ * Streaming Sources for DMA-Style Peripherals
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareResult, Readable};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::{sleep, sleep_until, Instant};
+
+/// How often a `FramedReader` polls a source that returned no data
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Bytes read per poll by a `FramedReader`
+const READ_CHUNK: usize = 256;
+
+/// One frame pushed by a streaming peripheral
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Index of the frame since the source started, counting dropped frames
+    pub sequence: u64,
+    /// When the frame was produced or received
+    pub at: Instant,
+    pub data: Vec<u8>,
+}
+
+/// Gaps between consecutive frames, e.g. to check a sample rate
+pub fn frame_intervals(frames: &[Frame]) -> Vec<Duration> {
+    frames.windows(2).map(|pair| pair[1].at - pair[0].at).collect()
+}
+
+/// Peripheral pushing frames on its own schedule, such as an ADC sampling
+/// at 1 kHz, rather than answering requests
+#[async_trait]
+pub trait StreamingSource: Send {
+    /// Next frame, failing with `TimeoutError` if none arrives in `timeout`
+    async fn next_frame(&mut self, timeout: Duration) -> HardwareResult<Frame>;
+
+    /// Exactly `count` frames, allowing `timeout` for each
+    async fn collect_n(&mut self, count: usize, timeout: Duration) -> HardwareResult<Vec<Frame>> {
+        let mut frames = Vec::with_capacity(count);
+        for _ in 0..count {
+            frames.push(self.next_frame(timeout).await?);
+        }
+        Ok(frames)
+    }
+
+    /// Every frame arriving within `duration`
+    async fn collect_for(&mut self, duration: Duration) -> HardwareResult<Vec<Frame>> {
+        let deadline = Instant::now() + duration;
+        let mut frames = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.next_frame(remaining).await {
+                Ok(frame) => frames.push(frame),
+                Err(e) if matches!(e.root(), HardwareError::TimeoutError) => return Ok(frames),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Splits a byte stream into frames
+pub trait FrameCodec: Send {
+    /// Remove one complete frame from the front of `buffer`, `Ok(None)`
+    /// while more bytes are needed
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> HardwareResult<Option<Vec<u8>>>;
+}
+
+/// Frames of a fixed number of bytes
+pub struct FixedSizeCodec(pub usize);
+
+impl FrameCodec for FixedSizeCodec {
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> HardwareResult<Option<Vec<u8>>> {
+        if buffer.len() < self.0 {
+            return Ok(None);
+        }
+        let rest = buffer.split_off(self.0);
+        Ok(Some(std::mem::replace(buffer, rest)))
+    }
+}
+
+/// Frames ended by a delimiter byte, which is stripped
+pub struct DelimitedCodec {
+    pub delimiter: u8,
+    /// Longest frame accepted before the stream is considered corrupt
+    pub max_len: usize,
+}
+
+impl FrameCodec for DelimitedCodec {
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> HardwareResult<Option<Vec<u8>>> {
+        match buffer.iter().position(|&b| b == self.delimiter) {
+            Some(end) => {
+                let rest = buffer.split_off(end + 1);
+                let mut frame = std::mem::replace(buffer, rest);
+                frame.pop();
+                Ok(Some(frame))
+            }
+            None if buffer.len() > self.max_len => Err(HardwareError::CommunicationError(format!(
+                "no delimiter 0x{:02x} within {} bytes",
+                self.delimiter, self.max_len
+            ))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Streaming source over any `Readable`, split into frames by a codec
+pub struct FramedReader<R, C> {
+    reader: R,
+    codec: C,
+    buffer: Vec<u8>,
+    sequence: u64,
+}
+
+impl<R: Readable + Send, C: FrameCodec> FramedReader<R, C> {
+    pub fn new(reader: R, codec: C) -> Self {
+        Self {
+            reader,
+            codec,
+            buffer: Vec::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[async_trait]
+impl<R: Readable + Send, C: FrameCodec> StreamingSource for FramedReader<R, C> {
+    async fn next_frame(&mut self, timeout: Duration) -> HardwareResult<Frame> {
+        let deadline = Instant::now() + timeout;
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            if let Some(data) = self.codec.decode(&mut self.buffer)? {
+                self.sequence += 1;
+                return Ok(Frame {
+                    sequence: self.sequence - 1,
+                    at: Instant::now(),
+                    data,
+                });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(HardwareError::TimeoutError);
+            }
+            match self.reader.read(&mut chunk, remaining).await? {
+                0 => sleep(POLL_INTERVAL.min(remaining)).await,
+                n => self.buffer.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+}
+
+/// What a `FakeStreamer` does when the consumer falls behind and its
+/// buffer is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backpressure {
+    /// Discard the oldest buffered frame, counting it in `dropped()`
+    DropOldest,
+    /// Stall the producer until the consumer frees space, delaying later
+    /// frames, like a DMA engine waiting on a full ring
+    Block,
+}
+
+/// Streaming source producing a frame from `generate(sequence)` every
+/// `period` of tokio time, so tests can run it in virtual time
+pub struct FakeStreamer {
+    generate: Box<dyn FnMut(u64) -> Vec<u8> + Send>,
+    period: Duration,
+    capacity: usize,
+    backpressure: Backpressure,
+    queue: VecDeque<Frame>,
+    next_sequence: u64,
+    next_due: Instant,
+    dropped: u64,
+}
+
+impl FakeStreamer {
+    /// Start streaming now; the first frame is due after one `period`.
+    /// Buffers 16 frames, dropping the oldest, unless configured otherwise.
+    pub fn new<F: FnMut(u64) -> Vec<u8> + Send + 'static>(period: Duration, generate: F) -> Self {
+        Self {
+            generate: Box::new(generate),
+            period,
+            capacity: 16,
+            backpressure: Backpressure::DropOldest,
+            queue: VecDeque::new(),
+            next_sequence: 0,
+            next_due: Instant::now() + period,
+            dropped: 0,
+        }
+    }
+
+    pub fn with_buffer(mut self, capacity: usize, backpressure: Backpressure) -> Self {
+        self.capacity = capacity.max(1);
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Frames discarded under `Backpressure::DropOldest`
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Produce every frame due by `now`
+    fn catch_up(&mut self, now: Instant) {
+        while self.next_due <= now {
+            if self.queue.len() == self.capacity {
+                match self.backpressure {
+                    Backpressure::DropOldest => {
+                        self.queue.pop_front();
+                        self.dropped += 1;
+                    }
+                    Backpressure::Block => {
+                        // The stalled frame goes out as soon as there is room
+                        self.next_due = now;
+                        return;
+                    }
+                }
+            }
+            self.queue.push_back(Frame {
+                sequence: self.next_sequence,
+                at: self.next_due,
+                data: (self.generate)(self.next_sequence),
+            });
+            self.next_sequence += 1;
+            self.next_due += self.period;
+        }
+    }
+}
+
+#[async_trait]
+impl StreamingSource for FakeStreamer {
+    async fn next_frame(&mut self, timeout: Duration) -> HardwareResult<Frame> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            self.catch_up(now);
+            if let Some(frame) = self.queue.pop_front() {
+                self.catch_up(now);
+                return Ok(frame);
+            }
+            if self.next_due > deadline {
+                sleep_until(deadline).await;
+                return Err(HardwareError::TimeoutError);
+            }
+            sleep_until(self.next_due).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::FakeLoopback;
+    use crate::{HardwareInterface, Writable};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn adc() -> FakeStreamer {
+        FakeStreamer::new(MS, |sequence| (sequence as u16).to_le_bytes().to_vec())
+    }
+
+    fn sequences(frames: &[Frame]) -> Vec<u64> {
+        frames.iter().map(|f| f.sequence).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_pacing() {
+        let start = Instant::now();
+        let mut streamer = adc();
+
+        let frames = streamer.collect_n(10, Duration::from_millis(5)).await.unwrap();
+
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+        assert_eq!(frame_intervals(&frames), vec![MS; 9]);
+        assert_eq!(frames[3].data, vec![3, 0]);
+
+        let window = streamer.collect_for(Duration::from_millis(20)).await.unwrap();
+        assert_eq!(window.len(), 20);
+        assert_eq!(window[0].sequence, 10);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drop_oldest() {
+        let mut streamer = adc().with_buffer(4, Backpressure::DropOldest);
+        sleep(Duration::from_millis(10)).await;
+
+        let frames = streamer.collect_n(4, MS).await.unwrap();
+
+        assert_eq!(sequences(&frames), vec![6, 7, 8, 9]);
+        assert_eq!(streamer.dropped(), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block() {
+        let start = Instant::now();
+        let mut streamer = adc().with_buffer(4, Backpressure::Block);
+        sleep(Duration::from_millis(10)).await;
+
+        let frames = streamer.collect_n(6, Duration::from_millis(5)).await.unwrap();
+
+        assert_eq!(sequences(&frames), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(streamer.dropped(), 0);
+        // The stalled frame goes out when the consumer frees space, and
+        // pacing resumes from there
+        assert_eq!(frames[4].at - start, Duration::from_millis(10));
+        assert_eq!(frames[5].at - start, Duration::from_millis(11));
+        assert_eq!(
+            streamer.next_frame(Duration::ZERO).await.unwrap_err(),
+            HardwareError::TimeoutError
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_codec_over_loopback() {
+        let mut uart = FakeLoopback::new();
+        uart.initialize().await.unwrap();
+        uart.write(b"12.5\n13.0\n13").await.unwrap();
+        let codec = DelimitedCodec {
+            delimiter: b'\n',
+            max_len: 16,
+        };
+        let mut reader = FramedReader::new(uart, codec);
+
+        let frames = reader.collect_n(2, MS).await.unwrap();
+        assert_eq!(frames[0].data, b"12.5");
+        assert_eq!(frames[1].data, b"13.0");
+        assert_eq!(frames[1].sequence, 1);
+
+        let start = Instant::now();
+        assert_eq!(reader.next_frame(Duration::from_millis(5)).await, Err(HardwareError::TimeoutError));
+        assert_eq!(start.elapsed(), Duration::from_millis(5));
+
+        let mut uart = reader.into_inner();
+        uart.write(b".5\n").await.unwrap();
+        let mut reader = FramedReader::new(uart, FixedSizeCodec(2));
+        assert_eq!(reader.next_frame(MS).await.unwrap().data, b".5");
+    }
+}
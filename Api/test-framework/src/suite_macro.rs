@@ -0,0 +1,291 @@
+/*
+ * Declarative Suites with suite!
+ * Copyright (C) 2024
+ */
+
+use crate::runner::TestTags;
+use crate::{FixtureMap, FixtureTestFn, HardwareInterface, TestRunner, TestSuiteResult};
+
+/// Test declared with `suite!`
+pub struct TestCase<T> {
+    pub name: &'static str,
+    pub tags: &'static [&'static str],
+    pub test: FixtureTestFn<T>,
+}
+
+impl<T> TestCase<T> {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(&tag)
+    }
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run cases declared with `suite!`
+    pub async fn run_cases(&self, name: &str, cases: Vec<TestCase<T>>) -> TestSuiteResult {
+        self.run_cases_with_fixtures(name, FixtureMap::new(), cases).await
+    }
+
+    /// Run cases declared with `suite!` sharing `fixtures`; their tags
+    /// count for the runner's quarantine
+    pub async fn run_cases_with_fixtures(
+        &self,
+        name: &str,
+        fixtures: FixtureMap,
+        cases: Vec<TestCase<T>>,
+    ) -> TestSuiteResult {
+        let tags: TestTags = cases.iter().map(|case| (case.name.to_string(), case.tags)).collect();
+        let tests = cases.into_iter().map(|case| (case.name, case.test)).collect();
+        self.run_tagged_with_fixtures(name, fixtures, tests, &tags).await
+    }
+}
+
+/// Fail the enclosing test with `HardwareError::OperationFailed` unless
+/// `cond` holds
+#[macro_export]
+macro_rules! hw_assert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            return Err($crate::HardwareError::OperationFailed(
+                concat!("assertion failed: ", stringify!($cond)).to_string(),
+            ));
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::HardwareError::OperationFailed(format!($($arg)+)));
+        }
+    };
+}
+
+/// Fail the enclosing test with `HardwareError::OperationFailed` showing
+/// both values unless they are equal
+#[macro_export]
+macro_rules! hw_assert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    return Err($crate::HardwareError::OperationFailed(format!(
+                        "assertion `{} == {}` failed: {:?} != {:?}",
+                        stringify!($left),
+                        stringify!($right),
+                        left,
+                        right
+                    )));
+                }
+            }
+        }
+    };
+}
+
+/// Declare a function returning a suite's `TestCase`s
+///
+/// ```ignore
+/// suite!(pub smoke(iface: I2CInterface) {
+///     "init works" => { iface.initialize().await },
+///     "status clean" [tags: smoke, fast] => {
+///         hw_assert_eq!(iface.get_status().await?.error_count, 0);
+///         Ok(())
+///     },
+/// });
+///
+/// let result = runner.run_cases("smoke", smoke()).await;
+/// ```
+///
+/// Each body runs with `iface` bound to the locked shared interface and
+/// must evaluate to `HardwareResult<()>`, so `?` and `hw_assert!` work
+/// inside it. Naming a second parameter, as in `(iface: I2CInterface,
+/// fixtures)`, binds the suite's `Arc<FixtureMap>` for
+/// `TestRunner::run_cases_with_fixtures`.
+///
+/// `[requires: ReadExact, Transfer]` after the tags skips a case on
+/// interfaces lacking those `Capability`s instead of running its body.
+///
+/// Annotations other than `[tags: ...]` and `[requires: ...]` are rejected:
+///
+/// ```compile_fail
+/// use hardware_test_framework::{suite, MockHardwareInterface};
+/// suite!(broken(iface: MockHardwareInterface) {
+///     "init" [tag: smoke] => { iface.initialize().await },
+/// });
+/// ```
+///
+/// ```compile_fail
+/// use hardware_test_framework::{suite, MockHardwareInterface};
+/// suite!(broken(iface: MockHardwareInterface) {
+///     "init" [tags smoke] => { iface.initialize().await },
+/// });
+/// ```
+#[macro_export]
+macro_rules! suite {
+    (@case $ty:ty, $iface:ident, $fixtures:ident, $name:literal, [$($tag:ident)*], [$($cap:ident)*], $body:block) => {
+        $crate::TestCase::<$ty> {
+            name: $name,
+            tags: &[$(stringify!($tag)),*],
+            test: Box::new(
+                |interface: ::std::sync::Arc<$crate::DiagMutex<$ty>>,
+                 $fixtures: ::std::sync::Arc<$crate::FixtureMap>|
+                 -> $crate::TestFuture {
+                    Box::pin(async move {
+                        let _ = &$fixtures;
+                        #[allow(unused_mut, unused_variables)]
+                        let mut $iface = interface.lock().await;
+                        if $crate::skip_unless_supported(
+                            $crate::HardwareInterface::capabilities(&*$iface),
+                            $crate::capabilities![$($cap),*],
+                        ) {
+                            return Ok(());
+                        }
+                        $body
+                    })
+                },
+            ),
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        $vis:vis $suite:ident ( $iface:ident : $ty:ty $(,)? ) { $($cases:tt)* }
+    ) => {
+        $crate::suite! {
+            $(#[$meta])*
+            $vis $suite($iface: $ty, _fixtures) { $($cases)* }
+        }
+    };
+
+    (
+        $(#[$meta:meta])*
+        $vis:vis $suite:ident ( $iface:ident : $ty:ty, $fixtures:ident $(,)? ) {
+            $(
+                $name:literal
+                $( [tags: $($tag:ident),+ $(,)?] )?
+                $( [requires: $($cap:ident),+ $(,)?] )?
+                => $body:block
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis fn $suite() -> Vec<$crate::TestCase<$ty>> {
+            vec![
+                $( $crate::suite!(@case $ty, $iface, $fixtures, $name, [$($($tag)+)?], [$($($cap)+)?], $body) ),*
+            ]
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{
+        hw_assert, hw_assert_eq, suite, FixtureMap, HardwareError, HardwareInterface, HardwareResult, TestRunner,
+        TestStatus,
+    };
+    use std::time::Duration;
+
+    suite!(lifecycle(iface: MockHardwareInterface) {
+        "init works" => { iface.initialize().await },
+        "status clean" [tags: smoke, fast] => {
+            hw_assert_eq!(iface.get_status().await?.error_count, 0);
+            Ok(())
+        },
+        "still initialized" [tags: smoke] => {
+            hw_assert!(iface.is_initialized());
+            Ok(())
+        },
+    });
+
+    suite!(pub(crate) failing(iface: MockHardwareInterface) {
+        "early return" => {
+            iface.deinitialize().await?;
+            Err(HardwareError::TimeoutError)
+        },
+        "mismatch" => {
+            hw_assert_eq!(1 + 1, 3);
+            Ok(())
+        },
+        "custom message" => {
+            hw_assert!(false, "{} rails down", 2);
+            Ok(())
+        }
+    });
+
+    suite!(calibrated(iface: MockHardwareInterface, fixtures) {
+        "uses fixture" => {
+            let offset: std::sync::Arc<i32> = fixtures.require()?;
+            hw_assert_eq!(*offset, -3);
+            iface.initialize().await
+        },
+        "ignores fixtures" => { Ok(()) },
+    });
+
+    fn runner() -> TestRunner<MockHardwareInterface> {
+        TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO)
+    }
+
+    fn status_message(status: &TestStatus) -> &str {
+        match status {
+            TestStatus::Error(message) | TestStatus::Failed(message) | TestStatus::Skipped(message) => message,
+            TestStatus::Passed => "",
+        }
+    }
+
+    #[test]
+    fn test_names_and_tags() {
+        let cases = lifecycle();
+        let names: Vec<_> = cases.iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["init works", "status clean", "still initialized"]);
+        assert_eq!(cases[1].tags, &["smoke", "fast"]);
+        assert!(cases[0].tags.is_empty());
+        let smoke: Vec<_> = cases.iter().filter(|c| c.has_tag("smoke")).map(|c| c.name).collect();
+        assert_eq!(smoke, vec!["status clean", "still initialized"]);
+    }
+
+    #[tokio::test]
+    async fn test_cases_run() {
+        let result = runner().run_cases("lifecycle", lifecycle()).await;
+        assert_eq!(result.passed_tests, 3);
+    }
+
+    #[tokio::test]
+    async fn test_failing_bodies() {
+        let result = runner().run_cases("failing", failing()).await;
+
+        assert_eq!(result.error_tests, 3);
+        assert_eq!(status_message(&result.results[0].status), "Test failed: TimeoutError");
+        assert_eq!(
+            status_message(&result.results[1].status),
+            "Test failed: OperationFailed(\"assertion `1 + 1 == 3` failed: 2 != 3\")"
+        );
+        assert_eq!(
+            status_message(&result.results[2].status),
+            "Test failed: OperationFailed(\"2 rails down\")"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixtures_handle() {
+        let mut fixtures = FixtureMap::new();
+        fixtures.insert(-3i32);
+
+        let result = runner().run_cases_with_fixtures("calibrated", fixtures, calibrated()).await;
+        assert_eq!(result.passed_tests, 2);
+
+        let missing = runner().run_cases("calibrated", calibrated()).await;
+        assert_eq!(
+            status_message(&missing.results[0].status),
+            "Test failed: OperationFailed(\"missing fixture of type i32\")"
+        );
+    }
+
+    #[test]
+    fn test_hw_assert_outside_suites() {
+        fn check(value: u8) -> HardwareResult<()> {
+            hw_assert!(value < 10);
+            Ok(())
+        }
+        assert_eq!(
+            check(12),
+            Err(HardwareError::OperationFailed("assertion failed: value < 10".to_string()))
+        );
+    }
+}
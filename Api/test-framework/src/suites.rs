@@ -0,0 +1,218 @@
+/*
+ * Built-in Test Suites
+ * Copyright (C) 2024
+ */
+
+use crate::{
+    Bidirectional, DiagMutex, FakeLoopback, FakeRegisterMap, HardwareError, HardwareInterface, HardwareResult,
+    I2CInterface, Readable, SPIInterface, TestFn, UARTInterface, Writable, I2C_DEFAULT_MAX_TRANSFER,
+};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Names of the suites available to every target
+pub const BUILTIN_SUITES: [&str; 2] = ["lifecycle", "conformance"];
+
+const IO_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Interfaces the built-in suites can run against
+pub trait BuiltinTarget: HardwareInterface + Sized + 'static {
+    /// Interface-specific checks of the buffer size policy and basic I/O
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)>;
+}
+
+fn case<T, F, Fut>(name: &str, body: F) -> (String, TestFn<T>)
+where
+    T: HardwareInterface + 'static,
+    F: FnOnce(Arc<DiagMutex<T>>) -> Fut + Send + 'static,
+    Fut: Future<Output = HardwareResult<()>> + Send + 'static,
+{
+    (name.to_string(), Box::new(move |interface| Box::pin(body(interface))))
+}
+
+fn check(condition: bool, message: &str) -> HardwareResult<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(HardwareError::OperationFailed(message.to_string()))
+    }
+}
+
+/// Tests of suite `name`, prefixed `name::`, or `None` for unknown suites
+pub fn builtin_suite<T: BuiltinTarget>(name: &str) -> Option<Vec<(String, TestFn<T>)>> {
+    let tests = match name {
+        "lifecycle" => lifecycle_tests(),
+        "conformance" => T::conformance_tests(),
+        _ => return None,
+    };
+    Some(tests.into_iter().map(|(test, f)| (format!("{}::{}", name, test), f)).collect())
+}
+
+fn lifecycle_tests<T: HardwareInterface + 'static>() -> Vec<(String, TestFn<T>)> {
+    vec![
+        case("initialize", |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            check(interface.is_initialized(), "interface not initialized after initialize()")
+        }),
+        case("status", |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            let status = interface.get_status().await?;
+            check(status.initialized, "status does not report the interface as initialized")
+        }),
+        case("reinitialize", |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.deinitialize().await?;
+            check(!interface.is_initialized(), "interface still initialized after deinitialize()")?;
+            interface.initialize().await?;
+            check(interface.is_initialized(), "interface not initialized after reinitializing")
+        }),
+    ]
+}
+
+/// Conformance checks shared by interfaces with separate read and write paths
+fn read_write_conformance<T>(max_transfer_size: fn(&T) -> Option<usize>) -> Vec<(String, TestFn<T>)>
+where
+    T: HardwareInterface + Readable + Writable + 'static,
+{
+    vec![
+        case("zero_length_io", |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            check(interface.write(&[]).await? == 0, "zero-length write did not return 0")?;
+            check(interface.read(&mut [], IO_TIMEOUT).await? == 0, "zero-length read did not return 0")
+        }),
+        case("write_read", |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            interface.write_all(&[0x5A; 16]).await?;
+            interface.read_exact(&mut [0u8; 16], IO_TIMEOUT).await
+        }),
+        case("oversized_rejected", move |interface: Arc<DiagMutex<T>>| async move {
+            let mut interface = interface.lock().await;
+            interface.initialize().await?;
+            let max = match max_transfer_size(&*interface) {
+                Some(max) => max,
+                None => return Ok(()),
+            };
+            match interface.write(&vec![0u8; max + 1]).await {
+                Err(HardwareError::InvalidParameter(_)) => Ok(()),
+                other => Err(HardwareError::OperationFailed(format!(
+                    "write above max_transfer_size returned {:?}", other
+                ))),
+            }
+        }),
+    ]
+}
+
+/// Register-style transfers must have something to read back
+fn empty_rx_transfer_rejected<T>() -> (String, TestFn<T>)
+where
+    T: HardwareInterface + Bidirectional + 'static,
+{
+    case("empty_transfer_rejected", |interface: Arc<DiagMutex<T>>| async move {
+        let mut interface = interface.lock().await;
+        interface.initialize().await?;
+        match interface.transfer(&[0x00], &mut [], IO_TIMEOUT).await {
+            Err(HardwareError::InvalidParameter(_)) => Ok(()),
+            other => Err(HardwareError::OperationFailed(format!("empty RX transfer returned {:?}", other))),
+        }
+    })
+}
+
+impl BuiltinTarget for I2CInterface {
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)> {
+        let mut tests = read_write_conformance(I2CInterface::max_transfer_size);
+        tests.push(empty_rx_transfer_rejected());
+        tests
+    }
+}
+
+/// Lets suites written against an I2C target run without hardware
+impl BuiltinTarget for FakeRegisterMap {
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)> {
+        let mut tests = read_write_conformance(|_| Some(I2C_DEFAULT_MAX_TRANSFER));
+        tests.push(empty_rx_transfer_rejected());
+        tests
+    }
+}
+
+/// Lets suites written against a UART target run without hardware
+impl BuiltinTarget for FakeLoopback {
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)> {
+        read_write_conformance(|_| None)
+    }
+}
+
+impl BuiltinTarget for UARTInterface {
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)> {
+        read_write_conformance(UARTInterface::max_transfer_size)
+    }
+}
+
+impl BuiltinTarget for SPIInterface {
+    fn conformance_tests() -> Vec<(String, TestFn<Self>)> {
+        vec![
+            case("transfer", |interface: Arc<DiagMutex<Self>>| async move {
+                let mut interface = interface.lock().await;
+                interface.initialize().await?;
+                let received = interface.transfer(&[0x9F, 0, 0, 0], &mut [0u8; 4], IO_TIMEOUT).await?;
+                check(received == 4, "transfer did not report all bytes")
+            }),
+            case("empty_transfer_rejected", |interface: Arc<DiagMutex<Self>>| async move {
+                let mut interface = interface.lock().await;
+                interface.initialize().await?;
+                match interface.transfer(&[], &mut [], IO_TIMEOUT).await {
+                    Err(HardwareError::InvalidParameter(_)) => Ok(()),
+                    other => Err(HardwareError::OperationFailed(format!("empty transfer returned {:?}", other))),
+                }
+            }),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestRunner, TestSuiteResult};
+
+    #[test]
+    fn test_builtin_suite_names() {
+        let tests = builtin_suite::<I2CInterface>("conformance").unwrap();
+        let names: Vec<_> = tests.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "conformance::zero_length_io",
+                "conformance::write_read",
+                "conformance::oversized_rejected",
+                "conformance::empty_transfer_rejected",
+            ]
+        );
+        assert_eq!(builtin_suite::<SPIInterface>("lifecycle").unwrap().len(), 3);
+        assert!(builtin_suite::<UARTInterface>("memtest").is_none());
+    }
+
+    async fn run_builtin<T: BuiltinTarget>(interface: T) -> TestSuiteResult {
+        let runner = TestRunner::new(interface, Duration::from_secs(1), 0, Duration::ZERO);
+        let tests: Vec<_> = ["lifecycle", "conformance"]
+            .iter()
+            .flat_map(|suite| builtin_suite::<T>(suite).unwrap())
+            .collect();
+        let names: Vec<String> = tests.iter().map(|(name, _)| name.clone()).collect();
+        let tests = names.iter().map(String::as_str).zip(tests.into_iter().map(|(_, f)| f)).collect();
+        runner.run_test_suite("fake", tests).await
+    }
+
+    #[tokio::test]
+    async fn test_fakes_pass_builtin_suites() {
+        let registers = run_builtin(FakeRegisterMap::new()).await;
+        assert_eq!((registers.total_tests, registers.passed_tests), (7, 7), "{}", registers);
+
+        let loopback = run_builtin(FakeLoopback::new()).await;
+        assert_eq!((loopback.total_tests, loopback.passed_tests), (6, 6), "{}", loopback);
+    }
+}
@@ -0,0 +1,404 @@
+/*
+ * Parameter Sweeps for Configuration Matrices
+ * Copyright (C) 2024
+ */
+
+use crate::{DiagMutex, HardwareInterface, TestFn, TestFuture, TestRunner, TestSuiteResult};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// A single parameter value in a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl ParamValue {
+    /// Wrap any `Debug` value, e.g. a config enum, by its debug name
+    pub fn of_debug<V: fmt::Debug>(value: V) -> Self {
+        ParamValue::Text(format!("{:?}", value))
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Int(v) => write!(f, "{}", v),
+            ParamValue::UInt(v) => write!(f, "{}", v),
+            ParamValue::Float(v) => write!(f, "{}", v),
+            ParamValue::Bool(v) => write!(f, "{}", v),
+            ParamValue::Text(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+macro_rules! param_value_from {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(impl From<$t> for ParamValue {
+            fn from(value: $t) -> Self {
+                ParamValue::$variant(value as $target)
+            }
+        })*
+    };
+}
+
+param_value_from!(Int, i64, i8, i16, i32, i64);
+param_value_from!(UInt, u64, u8, u16, u32, u64, usize);
+param_value_from!(Float, f64, f32, f64);
+
+impl From<bool> for ParamValue {
+    fn from(value: bool) -> Self {
+        ParamValue::Bool(value)
+    }
+}
+
+impl From<&str> for ParamValue {
+    fn from(value: &str) -> Self {
+        ParamValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> Self {
+        ParamValue::Text(value)
+    }
+}
+
+/// One combination of axis values, in axis declaration order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSet {
+    values: Vec<(String, ParamValue)>,
+}
+
+impl ParamSet {
+    pub fn get(&self, name: &str) -> Option<&ParamValue> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            ParamValue::Int(v) => Some(*v),
+            ParamValue::UInt(v) => i64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn uint(&self, name: &str) -> Option<u64> {
+        match self.get(name)? {
+            ParamValue::UInt(v) => Some(*v),
+            ParamValue::Int(v) => u64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn float(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            ParamValue::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            ParamValue::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            ParamValue::Text(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ParamValue)> {
+        self.values.iter().map(|(n, v)| (n.as_str(), v))
+    }
+
+    /// Parameter map with rendered values, as stored on test results
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.values.iter().map(|(n, v)| (n.clone(), v.to_string())).collect()
+    }
+}
+
+impl fmt::Display for ParamSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.values.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// How a sweep combines its axes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SweepMode {
+    /// Every combination of every axis
+    Full,
+    /// A subset covering every pair of values from any two axes
+    Pairwise,
+}
+
+/// Generated test case carrying its parameter combination
+pub struct SweepCase<T: HardwareInterface> {
+    pub name: String,
+    pub params: ParamSet,
+    pub test: TestFn<T>,
+}
+
+/// Builder generating test cases from named parameter axes
+#[derive(Debug, Clone, Default)]
+pub struct ParamSweep {
+    axes: Vec<(String, Vec<ParamValue>)>,
+}
+
+impl ParamSweep {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn axis<V, I>(mut self, name: &str, values: I) -> Self
+    where
+        V: Into<ParamValue>,
+        I: IntoIterator<Item = V>,
+    {
+        self.axes.push((name.to_string(), values.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    pub fn generate(&self, mode: SweepMode) -> Vec<ParamSet> {
+        if self.axes.iter().any(|(_, values)| values.is_empty()) {
+            return Vec::new();
+        }
+        let combos = match mode {
+            SweepMode::Full => self.full_indices(),
+            SweepMode::Pairwise if self.axes.len() > 2 => self.pairwise_indices(),
+            SweepMode::Pairwise => self.full_indices(),
+        };
+        combos.into_iter().map(|combo| self.param_set(&combo)).collect()
+    }
+
+    /// Build named test cases, e.g. `echo[baud=115200,parity=Even]`
+    pub fn cases<T, F>(&self, base_name: &str, mode: SweepMode, test: F) -> Vec<SweepCase<T>>
+    where
+        T: HardwareInterface + 'static,
+        F: Fn(Arc<DiagMutex<T>>, ParamSet) -> TestFuture + Clone + Send + 'static,
+    {
+        self.generate(mode)
+            .into_iter()
+            .map(|params| {
+                let test = test.clone();
+                let case_params = params.clone();
+                SweepCase {
+                    name: format!("{}[{}]", base_name, params),
+                    params,
+                    test: Box::new(move |interface| test(interface, case_params)),
+                }
+            })
+            .collect()
+    }
+
+    fn param_set(&self, combo: &[usize]) -> ParamSet {
+        ParamSet {
+            values: self
+                .axes
+                .iter()
+                .zip(combo)
+                .map(|((name, values), &i)| (name.clone(), values[i].clone()))
+                .collect(),
+        }
+    }
+
+    fn full_indices(&self) -> Vec<Vec<usize>> {
+        let mut combos = vec![Vec::new()];
+        for (_, values) in &self.axes {
+            combos = combos
+                .into_iter()
+                .flat_map(|combo| {
+                    (0..values.len()).map(move |i| {
+                        let mut next = combo.clone();
+                        next.push(i);
+                        next
+                    })
+                })
+                .collect();
+        }
+        combos
+    }
+
+    /// Greedy pairwise cover: seed each case with the lowest uncovered pair,
+    /// then fill the other axes with the value covering the most new pairs
+    fn pairwise_indices(&self) -> Vec<Vec<usize>> {
+        let n = self.axes.len();
+        let mut uncovered = BTreeSet::new();
+        for i in 0..n {
+            for j in i + 1..n {
+                for a in 0..self.axes[i].1.len() {
+                    for b in 0..self.axes[j].1.len() {
+                        uncovered.insert((i, a, j, b));
+                    }
+                }
+            }
+        }
+
+        let pair = |x: usize, xv: usize, y: usize, yv: usize| {
+            if x < y { (x, xv, y, yv) } else { (y, yv, x, xv) }
+        };
+
+        let mut combos = Vec::new();
+        while let Some(&(i, a, j, b)) = uncovered.iter().next() {
+            let mut combo: Vec<Option<usize>> = vec![None; n];
+            combo[i] = Some(a);
+            combo[j] = Some(b);
+
+            for k in 0..n {
+                if combo[k].is_some() {
+                    continue;
+                }
+                let mut best = (0, 0);
+                for v in 0..self.axes[k].1.len() {
+                    let gain = (0..n)
+                        .filter_map(|m| combo[m].map(|mv| pair(m, mv, k, v)))
+                        .filter(|p| uncovered.contains(p))
+                        .count();
+                    if v == 0 || gain > best.1 {
+                        best = (v, gain);
+                    }
+                }
+                combo[k] = Some(best.0);
+            }
+
+            let combo: Vec<usize> = combo.into_iter().map(|v| v.unwrap_or(0)).collect();
+            for x in 0..n {
+                for y in x + 1..n {
+                    uncovered.remove(&(x, combo[x], y, combo[y]));
+                }
+            }
+            combos.push(combo);
+        }
+        combos
+    }
+}
+
+impl<T: HardwareInterface + 'static> TestRunner<T> {
+    /// Run generated sweep cases, storing each case's parameters on its result
+    pub async fn run_sweep(&self, name: &str, cases: Vec<SweepCase<T>>) -> TestSuiteResult {
+        let params: Vec<_> = cases.iter().map(|case| case.params.to_map()).collect();
+        let names: Vec<String> = cases.iter().map(|case| case.name.clone()).collect();
+        let tests = names.iter().map(String::as_str).zip(cases.into_iter().map(|case| case.test)).collect();
+
+        let mut suite = self.run_test_suite(name, tests).await;
+        for (result, params) in suite.results.iter_mut().zip(params) {
+            result.params = params;
+        }
+        suite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::create_mock_interface_with_defaults;
+    use crate::{MockHardwareInterface, TestStatus};
+    use std::time::Duration;
+
+    #[derive(Debug)]
+    enum Parity {
+        None,
+        Even,
+    }
+
+    fn uart_sweep() -> ParamSweep {
+        ParamSweep::new()
+            .axis("baud", [9600u32, 57600, 115200, 921600])
+            .axis("parity", [Parity::None, Parity::Even].map(ParamValue::of_debug))
+            .axis("size", [16usize, 64, 256])
+    }
+
+    fn covers_all_pairs(sweep: &ParamSweep, sets: &[ParamSet]) -> bool {
+        let axes = &sweep.axes;
+        for i in 0..axes.len() {
+            for j in i + 1..axes.len() {
+                for a in &axes[i].1 {
+                    for b in &axes[j].1 {
+                        let covered = sets.iter().any(|s| {
+                            s.get(&axes[i].0) == Some(a) && s.get(&axes[j].0) == Some(b)
+                        });
+                        if !covered {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn test_full_and_pairwise_counts() {
+        let sweep = uart_sweep();
+        let full = sweep.generate(SweepMode::Full);
+        let pairwise = sweep.generate(SweepMode::Pairwise);
+
+        assert_eq!(full.len(), 24);
+        assert_eq!(pairwise.len(), 12);
+        assert!(covers_all_pairs(&sweep, &full));
+        assert!(covers_all_pairs(&sweep, &pairwise));
+    }
+
+    #[test]
+    fn test_pairwise_covers_larger_matrix() {
+        let sweep = ParamSweep::new()
+            .axis("a", [1, 2, 3])
+            .axis("b", [1, 2, 3])
+            .axis("c", [1, 2, 3])
+            .axis("d", [1, 2, 3]);
+        let pairwise = sweep.generate(SweepMode::Pairwise);
+
+        assert!(pairwise.len() < 81);
+        assert!(covers_all_pairs(&sweep, &pairwise));
+    }
+
+    #[test]
+    fn test_case_naming() {
+        let sweep = uart_sweep();
+        let cases = sweep.cases("echo", SweepMode::Full, |_: Arc<DiagMutex<MockHardwareInterface>>, _| {
+            Box::pin(async { Ok(()) })
+        });
+
+        assert_eq!(cases[0].name, "echo[baud=9600,parity=None,size=16]");
+        assert_eq!(cases[23].name, "echo[baud=921600,parity=Even,size=256]");
+        assert_eq!(cases[23].params.uint("baud"), Some(921600));
+        assert_eq!(cases[23].params.text("parity"), Some("Even"));
+    }
+
+    #[tokio::test]
+    async fn test_run_sweep_records_params() {
+        let runner = TestRunner::new(
+            create_mock_interface_with_defaults(),
+            Duration::from_millis(100),
+            3,
+            Duration::from_millis(10),
+        );
+        let sweep = ParamSweep::new().axis("size", [8usize, 4096]);
+        let cases = sweep.cases("write", SweepMode::Full, |_, params: ParamSet| {
+            Box::pin(async move {
+                if params.uint("size") > Some(1024) {
+                    return Err(crate::HardwareError::InvalidParameter("too large".to_string()));
+                }
+                Ok(())
+            })
+        });
+
+        let result = runner.run_sweep("sizes", cases).await;
+
+        assert_eq!(result.passed_tests, 1);
+        assert_eq!(result.results[0].name, "write[size=8]");
+        assert_eq!(result.results[1].params.get("size").map(String::as_str), Some("4096"));
+        assert!(matches!(result.results[1].status, TestStatus::Error(_)));
+    }
+}
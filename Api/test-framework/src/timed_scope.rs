@@ -0,0 +1,256 @@
+/*
+ * Duration Budgets for Time-Critical Sequences
+ * Copyright (C) 2024
+ */
+
+use crate::{HardwareError, HardwareResult, ReportAppendix, TestResult};
+use std::fmt;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static SCOPE_LOG: Arc<StdMutex<ScopeLog>>;
+}
+
+/// Elapsed time of one `TimedScope` against its budget
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeMeasurement {
+    pub name: String,
+    /// Innermost scope still open when this one started
+    pub parent: Option<String>,
+    pub depth: usize,
+    pub budget: Duration,
+    pub elapsed: Duration,
+}
+
+impl ScopeMeasurement {
+    pub fn exceeded(&self) -> bool {
+        self.elapsed > self.budget
+    }
+}
+
+impl fmt::Display for ScopeMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:indent$}{}: {:?} of {:?}{}",
+            "",
+            self.name,
+            self.elapsed,
+            self.budget,
+            if self.exceeded() { " EXCEEDED" } else { "" },
+            indent = self.depth * 2
+        )
+    }
+}
+
+/// Scopes of the running test; measurements stay in start order so
+/// children follow their parent
+#[derive(Default)]
+struct ScopeLog {
+    measurements: Vec<ScopeMeasurement>,
+    open: Vec<usize>,
+}
+
+/// Budget for a time-critical stretch of a test, e.g. "the burn-wire
+/// command must be acknowledged within 50 ms of the separation signal"
+///
+/// Scopes started while another is open nest under it. Inside a
+/// `TestRunner` test every scope is recorded on the test's result and in
+/// the suite's "Timed scopes" appendix, whether or not it met its budget.
+/// Time comes from `tokio::time`, so paused-time tests are deterministic.
+pub struct TimedScope {
+    name: String,
+    budget: Duration,
+    start: Instant,
+    index: Option<usize>,
+    finished: bool,
+}
+
+impl TimedScope {
+    pub fn start(name: &str, budget: Duration) -> Self {
+        let index = SCOPE_LOG
+            .try_with(|log| {
+                let mut log = log.lock().unwrap();
+                let parent = log.open.last().map(|&i| log.measurements[i].name.clone());
+                let depth = log.open.len();
+                log.measurements.push(ScopeMeasurement {
+                    name: name.to_string(),
+                    parent,
+                    depth,
+                    budget,
+                    elapsed: Duration::ZERO,
+                });
+                let index = log.measurements.len() - 1;
+                log.open.push(index);
+                index
+            })
+            .ok();
+        Self {
+            name: name.to_string(),
+            budget,
+            start: Instant::now(),
+            index,
+            finished: false,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.budget.saturating_sub(self.elapsed())
+    }
+
+    /// End the scope, failing if it took longer than its budget
+    pub fn check(mut self) -> HardwareResult<()> {
+        let measurement = self.finish();
+        if measurement.exceeded() {
+            let within = match &measurement.parent {
+                Some(parent) => format!(" (within '{}')", parent),
+                None => String::new(),
+            };
+            return Err(HardwareError::OperationFailed(format!(
+                "timed scope '{}'{} took {:?}, exceeding its {:?} budget",
+                measurement.name, within, measurement.elapsed, measurement.budget
+            )));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ScopeMeasurement {
+        self.finished = true;
+        let elapsed = self.elapsed();
+        let recorded = self.index.and_then(|index| {
+            SCOPE_LOG
+                .try_with(|log| {
+                    let mut log = log.lock().unwrap();
+                    log.open.retain(|&i| i != index);
+                    let measurement = &mut log.measurements[index];
+                    measurement.elapsed = elapsed;
+                    measurement.clone()
+                })
+                .ok()
+        });
+        recorded.unwrap_or_else(|| ScopeMeasurement {
+            name: self.name.clone(),
+            parent: None,
+            depth: 0,
+            budget: self.budget,
+            elapsed,
+        })
+    }
+}
+
+impl Drop for TimedScope {
+    /// Scopes left by `?` or a panic are still measured, without failing
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish();
+        }
+    }
+}
+
+/// Run `future` collecting the scopes it starts
+pub(crate) async fn record_scopes<F: Future>(future: F) -> (F::Output, Vec<ScopeMeasurement>) {
+    let log = Arc::new(StdMutex::new(ScopeLog::default()));
+    let output = SCOPE_LOG.scope(log.clone(), future).await;
+    let measurements = std::mem::take(&mut log.lock().unwrap().measurements);
+    (output, measurements)
+}
+
+/// Appendix listing the timed scopes of every test, `None` if no test used
+/// any
+pub(crate) fn scope_appendix(results: &[TestResult]) -> Option<ReportAppendix> {
+    let mut body = String::new();
+    for result in results.iter().filter(|r| !r.scopes.is_empty()) {
+        writeln!(body, "{}", result.name).unwrap();
+        for scope in &result.scopes {
+            writeln!(body, "  {}", scope).unwrap();
+        }
+    }
+    (!body.is_empty()).then(|| ReportAppendix::new("Timed scopes", &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{create_mock_interface_with_defaults, MockHardwareInterface};
+    use crate::{TestFn, TestRunner, TestStatus};
+    use tokio::time::sleep;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pass_and_fail() {
+        let scope = TimedScope::start("ack after sep signal", Duration::from_millis(50));
+        sleep(Duration::from_millis(50)).await;
+        assert!(scope.check().is_ok());
+
+        let scope = TimedScope::start("ack after sep signal", Duration::from_millis(50));
+        sleep(Duration::from_millis(62)).await;
+        assert_eq!(
+            scope.check(),
+            Err(HardwareError::OperationFailed(
+                "timed scope 'ack after sep signal' took 62ms, exceeding its 50ms budget".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nesting() {
+        let ((), scopes) = record_scopes(async {
+            let deploy = TimedScope::start("deploy", Duration::from_millis(100));
+            let ack = TimedScope::start("ack", Duration::from_millis(10));
+            sleep(Duration::from_millis(20)).await;
+            let error = ack.check().unwrap_err();
+            assert_eq!(
+                error,
+                HardwareError::OperationFailed(
+                    "timed scope 'ack' (within 'deploy') took 20ms, exceeding its 10ms budget".to_string()
+                )
+            );
+            {
+                let _burn = TimedScope::start("burn", Duration::from_millis(40));
+                sleep(Duration::from_millis(30)).await;
+            }
+            deploy.check().unwrap();
+        })
+        .await;
+
+        let summary: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            summary,
+            vec!["deploy: 50ms of 100ms", "  ack: 20ms of 10ms EXCEEDED", "  burn: 30ms of 40ms"]
+        );
+        assert_eq!(scopes[2].parent.as_deref(), Some("deploy"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runner_records_scopes() {
+        let runner = TestRunner::new(create_mock_interface_with_defaults(), Duration::from_secs(1), 0, Duration::ZERO);
+        let timed = |name: &'static str, took: u64| -> TestFn<MockHardwareInterface> {
+            Box::new(move |_| {
+                Box::pin(async move {
+                    let scope = TimedScope::start(name, Duration::from_millis(50));
+                    sleep(Duration::from_millis(took)).await;
+                    scope.check()
+                })
+            })
+        };
+
+        let plain: TestFn<MockHardwareInterface> = Box::new(|_| Box::pin(async { Ok(()) }));
+
+        let suite = runner
+            .run_test_suite("deploy", vec![("fast", timed("ack", 10)), ("plain", plain), ("slow", timed("ack", 70))])
+            .await;
+
+        assert_eq!(suite.results[0].status, TestStatus::Passed);
+        assert!(matches!(suite.results[2].status, TestStatus::Error(_)));
+        assert_eq!(suite.results[2].scopes[0].elapsed, Duration::from_millis(70));
+        let appendix = suite.appendices.iter().find(|a| a.title == "Timed scopes").unwrap();
+        assert_eq!(appendix.body, "fast\n  ack: 10ms of 50ms\nslow\n  ack: 70ms of 50ms EXCEEDED\n");
+    }
+}
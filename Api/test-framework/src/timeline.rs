@@ -0,0 +1,514 @@
+/*
+ * Cross-Interface Event Timeline
+ * Copyright (C) 2024
+ */
+
+use crate::replay::hex_bytes;
+use crate::{Bidirectional, HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable, CapabilitySet};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Leading bytes of every transfer kept on the timeline
+const HEAD_BYTES: usize = 8;
+
+/// What an event on the timeline was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Tx,
+    Rx,
+    /// Edge or trigger without data, e.g. a GPIO line toggling
+    Marker,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Tx => "tx",
+            Direction::Rx => "rx",
+            Direction::Marker => "marker",
+        }
+    }
+}
+
+fn hex_string<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+/// Timestamped activity on one interface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    /// Microseconds since the timeline was created
+    pub time_us: u64,
+    pub interface: String,
+    pub direction: Direction,
+    /// Number of bytes moved, 0 for markers and failed operations
+    pub bytes: usize,
+    /// First bytes of the data, as hex in exports
+    #[serde(serialize_with = "hex_string", deserialize_with = "hex_bytes")]
+    pub head: Vec<u8>,
+    /// Marker label or error of a failed operation
+    pub note: Option<String>,
+    pub error: bool,
+}
+
+impl TimelineEvent {
+    pub fn time(&self) -> Duration {
+        Duration::from_micros(self.time_us)
+    }
+}
+
+/// Contents of a timeline at one point, with the query helpers and the
+/// exports for external viewers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimelineSnapshot {
+    /// Events discarded because the timeline was full
+    pub dropped: u64,
+    /// Events in recording order
+    pub events: Vec<TimelineEvent>,
+}
+
+impl TimelineSnapshot {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(path, text)
+    }
+
+    /// Events with `from <= time < to`
+    pub fn events_between(&self, from: Duration, to: Duration) -> Vec<&TimelineEvent> {
+        self.events.iter().filter(|e| e.time() >= from && e.time() < to).collect()
+    }
+
+    /// Silent stretches of at least `min_gap` between consecutive events of
+    /// `interface`, as start and end times. Events are taken in time order,
+    /// which recording order need not be, e.g. in a hand-edited snapshot.
+    pub fn find_gaps(&self, interface: &str, min_gap: Duration) -> Vec<(Duration, Duration)> {
+        let mut times: Vec<Duration> = self.events.iter().filter(|e| e.interface == interface).map(|e| e.time()).collect();
+        times.sort();
+        times
+            .windows(2)
+            .filter(|pair| pair[1] - pair[0] >= min_gap)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    /// One row per event, data bytes as hex
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time_us,interface,direction,bytes,head,error,note\n");
+        for event in &self.events {
+            let head: String = event.head.iter().map(|b| format!("{:02x}", b)).collect();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                event.time_us,
+                csv_field(&event.interface),
+                event.direction.label(),
+                event.bytes,
+                head,
+                event.error,
+                csv_field(event.note.as_deref().unwrap_or(""))
+            );
+        }
+        csv
+    }
+
+    /// Value change dump with one 1-bit signal per interface and direction
+    /// plus an error signal per interface, each pulsing high for 1us at
+    /// every event, for viewing in PulseView or GTKWave
+    pub fn to_vcd(&self) -> String {
+        let mut signals: BTreeMap<(String, &str), usize> = BTreeMap::new();
+        for event in &self.events {
+            signals.insert((event.interface.clone(), event.direction.label()), 0);
+            if event.error {
+                signals.insert((event.interface.clone(), "error"), 0);
+            }
+        }
+        for (index, id) in signals.values_mut().enumerate() {
+            *id = index;
+        }
+
+        let mut vcd = String::from("$timescale 1us $end\n$scope module timeline $end\n");
+        for ((interface, channel), &id) in &signals {
+            let name: String = interface.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+            let _ = writeln!(vcd, "$var wire 1 {} {}_{} $end", vcd_id(id), name, channel);
+        }
+        vcd.push_str("$upscope $end\n$enddefinitions $end\n");
+
+        // A rise at the same time as the previous pulse's fall wins
+        let mut changes: BTreeMap<u64, BTreeMap<usize, u8>> = BTreeMap::new();
+        changes.insert(0, signals.values().map(|&id| (id, 0)).collect());
+        for event in &self.events {
+            let mut ids = vec![signals[&(event.interface.clone(), event.direction.label())]];
+            if event.error {
+                ids.push(signals[&(event.interface.clone(), "error")]);
+            }
+            for id in ids {
+                changes.entry(event.time_us).or_default().insert(id, 1);
+                changes.entry(event.time_us + 1).or_default().entry(id).or_insert(0);
+            }
+        }
+        for (time, values) in changes {
+            let _ = writeln!(vcd, "#{}", time);
+            for (id, value) in values {
+                let _ = writeln!(vcd, "{}{}", value, vcd_id(id));
+            }
+        }
+        vcd
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// VCD identifier of the signal at `index`, made of printable characters
+fn vcd_id(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+struct TimelineState {
+    events: VecDeque<TimelineEvent>,
+    dropped: u64,
+}
+
+/// Bounded collector of events from several interfaces on one clock
+///
+/// Cloning shares the timeline, so each `Traced` wrapper and any trigger
+/// code can feed the same one. When full the oldest event is dropped and
+/// counted.
+#[derive(Clone)]
+pub struct EventTimeline {
+    start: Instant,
+    capacity: usize,
+    state: Arc<StdMutex<TimelineState>>,
+}
+
+impl EventTimeline {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            capacity,
+            state: Arc::new(StdMutex::new(TimelineState {
+                events: VecDeque::with_capacity(capacity.min(4096)),
+                dropped: 0,
+            })),
+        }
+    }
+
+    fn push(&self, event: TimelineEvent) {
+        let mut state = self.state.lock().unwrap();
+        if self.capacity == 0 {
+            state.dropped += 1;
+            return;
+        }
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+            state.dropped += 1;
+        }
+        state.events.push_back(event);
+    }
+
+    fn now_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    /// Record data moved over `interface`
+    pub fn record(&self, interface: &str, direction: Direction, data: &[u8]) {
+        self.push(TimelineEvent {
+            time_us: self.now_us(),
+            interface: interface.to_string(),
+            direction,
+            bytes: data.len(),
+            head: data[..data.len().min(HEAD_BYTES)].to_vec(),
+            note: None,
+            error: false,
+        });
+    }
+
+    /// Record a failed operation on `interface`
+    pub fn record_error(&self, interface: &str, direction: Direction, error: &str) {
+        self.push(TimelineEvent {
+            time_us: self.now_us(),
+            interface: interface.to_string(),
+            direction,
+            bytes: 0,
+            head: Vec::new(),
+            note: Some(error.to_string()),
+            error: true,
+        });
+    }
+
+    /// Record an edge or trigger, e.g. a GPIO line toggling
+    pub fn mark(&self, channel: &str, label: &str) {
+        self.push(TimelineEvent {
+            time_us: self.now_us(),
+            interface: channel.to_string(),
+            direction: Direction::Marker,
+            bytes: 0,
+            head: Vec::new(),
+            note: Some(label.to_string()),
+            error: false,
+        });
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn snapshot(&self) -> TimelineSnapshot {
+        let state = self.state.lock().unwrap();
+        TimelineSnapshot {
+            dropped: state.dropped,
+            events: state.events.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Interface wrapper putting every operation on an `EventTimeline`
+pub struct Traced<T> {
+    inner: T,
+    name: String,
+    timeline: EventTimeline,
+}
+
+impl<T> Traced<T> {
+    pub fn new(inner: T, name: &str, timeline: &EventTimeline) -> Self {
+        Self {
+            inner,
+            name: name.to_string(),
+            timeline: timeline.clone(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record<R>(&self, direction: Direction, data: &[u8], result: &HardwareResult<R>) {
+        match result {
+            Ok(_) => self.timeline.record(&self.name, direction, data),
+            Err(e) => self.timeline.record_error(&self.name, direction, &e.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: HardwareInterface + Send + Sync> HardwareInterface for Traced<T> {
+    async fn initialize(&mut self) -> HardwareResult<()> {
+        self.inner.initialize().await
+    }
+
+    async fn deinitialize(&mut self) -> HardwareResult<()> {
+        self.inner.deinitialize().await
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.inner.is_initialized()
+    }
+
+    async fn get_status(&self) -> HardwareResult<InterfaceStatus> {
+        self.inner.get_status().await
+    }
+
+    fn capabilities(&self) -> CapabilitySet {
+        self.inner.capabilities()
+    }
+}
+
+#[async_trait]
+impl<T: Readable + Send> Readable for Traced<T> {
+    async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let result = self.inner.read(buffer, timeout).await;
+        let received = *result.as_ref().unwrap_or(&0);
+        self.record(Direction::Rx, &buffer[..received], &result);
+        result
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> HardwareResult<()> {
+        let result = self.inner.read_exact(buffer, timeout).await;
+        self.record(Direction::Rx, buffer, &result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Writable + Send> Writable for Traced<T> {
+    async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+        let result = self.inner.write(data).await;
+        let written = *result.as_ref().unwrap_or(&0);
+        self.record(Direction::Tx, &data[..written], &result);
+        result
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+        let result = self.inner.write_all(data).await;
+        self.record(Direction::Tx, data, &result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: Bidirectional + Send> Bidirectional for Traced<T> {
+    async fn transfer(&mut self, tx_data: &[u8], rx_data: &mut [u8], timeout: Duration) -> HardwareResult<usize> {
+        let result = self.inner.transfer(tx_data, rx_data, timeout).await;
+        self.record(Direction::Tx, tx_data, &result);
+        if let Ok(received) = result {
+            self.timeline.record(&self.name, Direction::Rx, &rx_data[..received]);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{FakeLoopback, FakeRegisterMap};
+    use crate::HardwareError;
+    use tokio::time::sleep;
+
+    async fn interleaved() -> EventTimeline {
+        let timeline = EventTimeline::new(100);
+        let mut i2c = Traced::new(FakeRegisterMap::new(), "i2c", &timeline);
+        let mut uart = Traced::new(FakeLoopback::new(), "uart0", &timeline);
+        i2c.initialize().await.unwrap();
+        uart.initialize().await.unwrap();
+
+        i2c.write_all(&[0x10, 0x01]).await.unwrap();
+        sleep(Duration::from_millis(2)).await;
+        uart.write_all(b"PING").await.unwrap();
+        sleep(Duration::from_millis(1)).await;
+        let mut reply = [0u8; 4];
+        uart.read_exact(&mut reply, Duration::from_millis(10)).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+        timeline.mark("trigger", "rising");
+        i2c.deinitialize().await.unwrap();
+        assert_eq!(i2c.write_all(&[0x10]).await, Err(HardwareError::NotInitialized));
+        timeline
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interleaved_events_and_gaps() {
+        let snapshot = interleaved().await.snapshot();
+
+        let order: Vec<(&str, Direction, u64)> = snapshot
+            .events
+            .iter()
+            .map(|e| (e.interface.as_str(), e.direction, e.time_us))
+            .collect();
+        assert_eq!(order, vec![
+            ("i2c", Direction::Tx, 0),
+            ("uart0", Direction::Tx, 2000),
+            ("uart0", Direction::Rx, 3000),
+            ("trigger", Direction::Marker, 23000),
+            ("i2c", Direction::Tx, 23000),
+        ]);
+        assert_eq!(snapshot.events[2].head, b"PING");
+        assert!(snapshot.events[4].error);
+
+        let window = snapshot.events_between(Duration::from_millis(1), Duration::from_millis(23));
+        assert_eq!(window.len(), 2);
+        assert_eq!(snapshot.find_gaps("i2c", Duration::from_millis(10)), vec![
+            (Duration::ZERO, Duration::from_millis(23))
+        ]);
+        assert!(snapshot.find_gaps("uart0", Duration::from_millis(10)).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_csv_vcd_and_json_exports() {
+        let snapshot = interleaved().await.snapshot();
+
+        let csv = snapshot.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "time_us,interface,direction,bytes,head,error,note");
+        assert_eq!(lines[2], "2000,uart0,tx,4,50494e47,false,");
+        assert_eq!(lines[4], "23000,trigger,marker,0,,false,rising");
+        assert_eq!(lines.len(), 6);
+
+        let vcd = snapshot.to_vcd();
+        assert!(vcd.starts_with("$timescale 1us $end\n$scope module timeline $end\n"));
+        assert!(vcd.contains("$var wire 1 ! i2c_error $end\n$var wire 1 \" i2c_tx $end\n"));
+        assert!(vcd.contains("$enddefinitions $end\n#0\n0!\n1\"\n0#\n0$\n0%\n#1\n0\"\n#2000\n1%\n"));
+        assert!(vcd.contains("#23000\n1!\n1\"\n1#\n#23001\n0!\n0\"\n0#\n"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("timeline.json");
+        snapshot.save(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("\"head\": \"50494e47\""));
+        assert_eq!(TimelineSnapshot::load(&path).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_gaps_in_unordered_events() {
+        let event = |time_us: u64| TimelineEvent {
+            time_us,
+            interface: "spi".to_string(),
+            direction: Direction::Tx,
+            bytes: 1,
+            head: vec![0],
+            note: None,
+            error: false,
+        };
+        let snapshot = TimelineSnapshot {
+            dropped: 0,
+            events: vec![event(30_000), event(0), event(5_000)],
+        };
+
+        assert_eq!(snapshot.find_gaps("spi", Duration::from_millis(10)), vec![
+            (Duration::from_millis(5), Duration::from_millis(30))
+        ]);
+    }
+
+    #[test]
+    fn test_drop_oldest_when_full() {
+        let timeline = EventTimeline::new(3);
+        for byte in 0..5u8 {
+            timeline.record("spi", Direction::Tx, &[byte]);
+        }
+
+        let snapshot = timeline.snapshot();
+        assert_eq!(snapshot.dropped, 2);
+        let heads: Vec<u8> = snapshot.events.iter().map(|e| e.head[0]).collect();
+        assert_eq!(heads, vec![2, 3, 4]);
+    }
+}
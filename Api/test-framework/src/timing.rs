@@ -0,0 +1,281 @@
+/*
+ * Timing Baselines for Detecting Latency Regressions
+ * Copyright (C) 2024
+ */
+
+use crate::{OperationKind, Profiled, TestStatus, TestSuiteResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Version of the baseline file layout, bumped on incompatible changes
+pub const BASELINE_SCHEMA_VERSION: u32 = 1;
+
+/// Percentiles of each operation kind kept in a baseline
+const OPERATION_PERCENTILES: [f64; 2] = [50.0, 99.0];
+
+/// Latencies of one suite run, in microseconds, used as the reference for
+/// later runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimingBaseline {
+    pub schema_version: u32,
+    /// Duration of every passed test
+    pub tests: BTreeMap<String, u64>,
+    /// Operation latency percentiles keyed like `write.p99`
+    pub operations: BTreeMap<String, u64>,
+}
+
+impl TimingBaseline {
+    /// Durations of the passed tests of `suite`; failed tests are left out
+    /// as their timing says nothing about the device
+    pub fn from_suite(suite: &TestSuiteResult) -> Self {
+        Self {
+            schema_version: BASELINE_SCHEMA_VERSION,
+            tests: suite
+                .results
+                .iter()
+                .filter(|r| r.status == TestStatus::Passed)
+                .map(|r| (r.name.clone(), r.duration.as_micros() as u64))
+                .collect(),
+            operations: BTreeMap::new(),
+        }
+    }
+
+    /// Add the latency percentiles recorded by a profiled interface
+    pub fn with_operations<T>(mut self, profiled: &Profiled<T>) -> Self {
+        for op in OperationKind::ALL {
+            let histogram = profiled.histogram(op);
+            for p in OPERATION_PERCENTILES {
+                if let Some(latency) = histogram.percentile(p) {
+                    let key = format!("{}.p{}", format!("{:?}", op).to_lowercase(), p);
+                    self.operations.insert(key, latency.as_micros() as u64);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(path, text)
+    }
+
+    /// Metrics of `current` slower than this baseline beyond `thresholds`.
+    /// Metrics missing from either side are not compared.
+    pub fn compare(&self, current: &TimingBaseline, thresholds: &TimingThresholds) -> Vec<TimingRegression> {
+        let tests = self.tests.iter().map(|(name, &us)| (name.clone(), us, current.tests.get(name)));
+        let operations = self
+            .operations
+            .iter()
+            .map(|(name, &us)| (format!("op:{}", name), us, current.operations.get(name)));
+        tests
+            .chain(operations)
+            .filter_map(|(metric, baseline, current)| {
+                let regression = TimingRegression {
+                    metric,
+                    baseline: Duration::from_micros(baseline),
+                    current: Duration::from_micros(*current?),
+                };
+                thresholds.exceeded_by(&regression).then_some(regression)
+            })
+            .collect()
+    }
+}
+
+/// How much slower than the baseline a metric may get. Both limits must be
+/// exceeded, so short operations do not trip on scheduling jitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingThresholds {
+    /// Allowed slowdown relative to the baseline, e.g. 0.5 for 50 %
+    pub relative: f64,
+    /// Allowed slowdown in absolute terms
+    pub absolute: Duration,
+}
+
+impl Default for TimingThresholds {
+    fn default() -> Self {
+        Self {
+            relative: 0.5,
+            absolute: Duration::from_millis(5),
+        }
+    }
+}
+
+impl TimingThresholds {
+    fn exceeded_by(&self, regression: &TimingRegression) -> bool {
+        let baseline = regression.baseline.as_micros() as f64;
+        let current = regression.current.as_micros() as f64;
+        current > baseline * (1.0 + self.relative) && regression.current > regression.baseline + self.absolute
+    }
+}
+
+/// Test or operation slower than its baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingRegression {
+    /// Test name, or `op:<kind>.p<percentile>` for operation latencies
+    pub metric: String,
+    pub baseline: Duration,
+    pub current: Duration,
+}
+
+impl TimingRegression {
+    pub fn ratio(&self) -> f64 {
+        self.current.as_secs_f64() / self.baseline.as_secs_f64()
+    }
+}
+
+impl fmt::Display for TimingRegression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?} -> {:?} ({:.2}x)", self.metric, self.baseline, self.current, self.ratio())
+    }
+}
+
+/// Baseline file checked after every run, rewritten only when updating is
+/// explicitly requested
+#[derive(Debug, Clone)]
+pub struct TimingCheck {
+    path: PathBuf,
+    thresholds: TimingThresholds,
+    update: bool,
+}
+
+impl TimingCheck {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            thresholds: TimingThresholds::default(),
+            update: false,
+        }
+    }
+
+    pub fn with_thresholds(mut self, thresholds: TimingThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Write the current timings as the new baseline instead of comparing
+    pub fn with_update(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// Record the regressions of `current` against the baseline on `suite`,
+    /// or store `current` as the baseline when updating. Without a baseline
+    /// file nothing is compared.
+    pub fn apply(&self, suite: &mut TestSuiteResult, current: &TimingBaseline) -> io::Result<()> {
+        if self.update {
+            return current.save(&self.path);
+        }
+        let baseline = match TimingBaseline::load(&self.path) {
+            Ok(baseline) => baseline,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::warn!("No timing baseline at {}, skipping timing comparison", self.path.display());
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        suite.timing_regressions = baseline.compare(current, &self.thresholds);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HardwareResult, SuiteDiff, TestResult, Writable};
+    use async_trait::async_trait;
+
+    fn suite(timings: &[(&str, u64)]) -> TestSuiteResult {
+        let results = timings
+            .iter()
+            .map(|&(name, ms)| TestResult::new(name, TestStatus::Passed, Duration::from_millis(ms)))
+            .collect();
+        TestSuiteResult::from_results("payload", results, Duration::ZERO)
+    }
+
+    #[test]
+    fn test_threshold_edges() {
+        let baseline = TimingBaseline::from_suite(&suite(&[("mode", 10), ("ping", 10), ("status", 1), ("edge", 10)]));
+        // 4x slower; 40 % slower; 2x but only 1ms; exactly 50 % slower
+        let slower = TimingBaseline::from_suite(&suite(&[("mode", 40), ("ping", 14), ("status", 2), ("edge", 15)]));
+
+        let regressions = baseline.compare(&slower, &TimingThresholds::default());
+
+        assert_eq!(regressions, vec![TimingRegression {
+            metric: "mode".to_string(),
+            baseline: Duration::from_millis(10),
+            current: Duration::from_millis(40),
+        }]);
+        assert_eq!(regressions[0].to_string(), "mode: 10ms -> 40ms (4.00x)");
+
+        let strict = TimingThresholds { relative: 0.3, absolute: Duration::ZERO };
+        let names: Vec<String> = baseline.compare(&slower, &strict).into_iter().map(|r| r.metric).collect();
+        assert_eq!(names, vec!["edge", "mode", "ping", "status"]);
+    }
+
+    #[test]
+    fn test_check_requires_explicit_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("timing.json");
+        let fast = suite(&[("mode", 10)]);
+        let mut slow = suite(&[("mode", 40)]);
+
+        let check = TimingCheck::new(&path);
+        check.apply(&mut slow, &TimingBaseline::from_suite(&slow)).unwrap();
+        assert!(!path.exists());
+
+        check.clone().with_update(true).apply(&mut fast.clone(), &TimingBaseline::from_suite(&fast)).unwrap();
+        check.apply(&mut slow, &TimingBaseline::from_suite(&slow)).unwrap();
+
+        assert_eq!(slow.timing_regressions.len(), 1);
+        assert_eq!(slow.failed_tests, 0);
+        assert_eq!(slow.to_json()["timing_regressions"][0], "mode: 10ms -> 40ms (4.00x)");
+        assert!(SuiteDiff::between(&fast, &slow).to_string().contains("  timing: mode: 10ms -> 40ms (4.00x)\n"));
+    }
+
+    /// Writer whose every write takes `delay` of (virtual) time
+    struct Slow(Duration);
+
+    #[async_trait]
+    impl Writable for Slow {
+        async fn write(&mut self, data: &[u8]) -> HardwareResult<usize> {
+            tokio::time::sleep(self.0).await;
+            Ok(data.len())
+        }
+
+        async fn write_all(&mut self, data: &[u8]) -> HardwareResult<()> {
+            self.write(data).await.map(|_| ())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_operation_regressions() {
+        let mut before = Profiled::new(Slow(Duration::from_millis(3)));
+        let mut after = Profiled::new(Slow(Duration::from_millis(30)));
+        for _ in 0..4 {
+            before.write(&[0]).await.unwrap();
+            after.write(&[0]).await.unwrap();
+        }
+        let empty = suite(&[]);
+
+        let baseline = TimingBaseline::from_suite(&empty).with_operations(&before);
+        let current = TimingBaseline::from_suite(&empty).with_operations(&after);
+
+        assert_eq!(baseline.operations.keys().collect::<Vec<_>>(), vec!["write.p50", "write.p99"]);
+        let metrics: Vec<String> = baseline
+            .compare(&current, &TimingThresholds::default())
+            .into_iter()
+            .map(|r| r.metric)
+            .collect();
+        assert_eq!(metrics, vec!["op:write.p50", "op:write.p99"]);
+    }
+}
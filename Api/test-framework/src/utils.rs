@@ -3,7 +3,9 @@
  * Copyright (C) 2024
  */
 
-use crate::{HardwareInterface, HardwareResult, InterfaceStatus};
+use crate::{HardwareInterface, HardwareResult, InterfaceStatus, Readable, Writable};
+use std::future::Future;
+use std::pin::Pin;
 use std::time::Duration;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -104,6 +106,109 @@ where
     .map_err(|_| panic!("Test timed out"))
 }
 
+/// Helper function to poll a condition until it holds or the timeout expires
+///
+/// The condition is evaluated at least once, then every `poll_interval`
+/// until it returns `Ok(true)`. Errors from the condition abort the wait.
+pub async fn wait_for<S, F>(
+    state: &mut S,
+    mut condition: F,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> HardwareResult<()>
+where
+    F: for<'a> FnMut(&'a mut S) -> Pin<Box<dyn Future<Output = HardwareResult<bool>> + Send + 'a>>,
+{
+    let deadline = time::Instant::now() + timeout;
+    loop {
+        if condition(state).await? {
+            return Ok(());
+        }
+        if time::Instant::now() >= deadline {
+            return Err(crate::HardwareError::TimeoutError);
+        }
+        time::sleep(poll_interval).await;
+    }
+}
+
+/// Write `data` in operations of at most `chunk_size` bytes, the sanctioned
+/// way to move payloads above an interface's `max_transfer_size`
+pub async fn write_chunked<W>(device: &mut W, data: &[u8], chunk_size: usize) -> HardwareResult<()>
+where
+    W: Writable + ?Sized,
+{
+    if chunk_size == 0 {
+        return Err(crate::HardwareError::InvalidParameter("chunk_size must be non-zero".to_string()));
+    }
+    for chunk in data.chunks(chunk_size) {
+        device.write_all(chunk).await?;
+    }
+    Ok(())
+}
+
+/// Fill `buffer` with reads of at most `chunk_size` bytes, each bounded by `timeout`
+pub async fn read_chunked<R>(device: &mut R, buffer: &mut [u8], chunk_size: usize, timeout: Duration) -> HardwareResult<()>
+where
+    R: Readable + ?Sized,
+{
+    if chunk_size == 0 {
+        return Err(crate::HardwareError::InvalidParameter("chunk_size must be non-zero".to_string()));
+    }
+    for chunk in buffer.chunks_mut(chunk_size) {
+        device.read_exact(chunk, timeout).await?;
+    }
+    Ok(())
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF)
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Incremental CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320) for
+/// data too large to hold at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 { (self.0 >> 1) ^ 0xEDB8_8320 } else { self.0 >> 1 };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-32 of `data` in one go
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +283,70 @@ mod tests {
         assert!(context.setup().await.is_ok());
         assert!(context.teardown().await.is_ok());
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for() {
+        let mut polls = 0u32;
+        let result = wait_for(
+            &mut polls,
+            |polls| Box::pin(async move {
+                *polls += 1;
+                Ok(*polls >= 3)
+            }),
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        )
+        .await;
+        
+        assert!(result.is_ok());
+        assert_eq!(polls, 3);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_timeout() {
+        let mut polls = 0u32;
+        let result = wait_for(
+            &mut polls,
+            |polls| Box::pin(async move {
+                *polls += 1;
+                Ok(false)
+            }),
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+        )
+        .await;
+        
+        assert_eq!(result, Err(crate::HardwareError::TimeoutError));
+        assert_eq!(polls, 6);
+    }
+    
+    #[tokio::test]
+    async fn test_chunked_helpers_respect_transfer_limit() {
+        let mut i2c = crate::I2CInterface::with_default_config();
+        i2c.initialize().await.unwrap();
+        let max = i2c.max_transfer_size().unwrap();
+        let payload = vec![0u8; 3 * max];
+        
+        assert!(matches!(i2c.write(&payload).await, Err(crate::HardwareError::InvalidParameter(_))));
+        assert!(write_chunked(&mut i2c, &payload, max).await.is_ok());
+        
+        let mut buffer = vec![0u8; max + 1];
+        assert!(read_chunked(&mut i2c, &mut buffer, max, Duration::from_millis(10)).await.is_ok());
+        assert!(write_chunked(&mut i2c, &payload, 0).await.is_err());
+    }
+    
+    #[test]
+    fn test_crc16_ccitt() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc16_ccitt(&[]), 0xFFFF);
+    }
+    
+    #[test]
+    fn test_crc32_incremental() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * Watch Mode Re-Running Suites on File Changes
+ * Copyright (C) 2024
+ */
+
+use crate::{SuiteDiff, SuiteManifest, TargetInterfaces, TestSuiteResult};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+
+/// Source of file change notifications
+#[async_trait]
+pub trait ChangeWatcher: Send {
+    /// Wait for the next change and return the paths it touched
+    async fn changed(&mut self) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Watcher comparing modification times and sizes at a fixed interval.
+/// Directories are watched recursively.
+pub struct PollingWatcher {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    snapshot: BTreeMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl PollingWatcher {
+    pub fn new(paths: Vec<PathBuf>, interval: Duration) -> Self {
+        let snapshot = scan(&paths);
+        Self { paths, interval, snapshot }
+    }
+}
+
+fn scan(paths: &[PathBuf]) -> BTreeMap<PathBuf, (SystemTime, u64)> {
+    fn visit(path: &Path, snapshot: &mut BTreeMap<PathBuf, (SystemTime, u64)>) {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            // Missing paths are picked up once they appear
+            Err(_) => return,
+        };
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(path) {
+                for entry in entries.flatten() {
+                    visit(&entry.path(), snapshot);
+                }
+            }
+        } else if let Ok(modified) = metadata.modified() {
+            snapshot.insert(path.to_path_buf(), (modified, metadata.len()));
+        }
+    }
+
+    let mut snapshot = BTreeMap::new();
+    for path in paths {
+        visit(path, &mut snapshot);
+    }
+    snapshot
+}
+
+#[async_trait]
+impl ChangeWatcher for PollingWatcher {
+    async fn changed(&mut self) -> io::Result<Vec<PathBuf>> {
+        loop {
+            let current = scan(&self.paths);
+            let mut changed: Vec<PathBuf> = current
+                .iter()
+                .filter(|(path, stamp)| self.snapshot.get(*path) != Some(*stamp))
+                .map(|(path, _)| path.clone())
+                .collect();
+            changed.extend(self.snapshot.keys().filter(|path| !current.contains_key(*path)).cloned());
+            if !changed.is_empty() {
+                self.snapshot = current;
+                return Ok(changed);
+            }
+            sleep(self.interval).await;
+        }
+    }
+}
+
+/// Wait for a change, then gather further changes until none arrives
+/// within `debounce`
+pub async fn next_change_batch<W: ChangeWatcher>(watcher: &mut W, debounce: Duration) -> io::Result<Vec<PathBuf>> {
+    let mut batch = watcher.changed().await?;
+    while let Ok(more) = timeout(debounce, watcher.changed()).await {
+        for path in more? {
+            if !batch.contains(&path) {
+                batch.push(path);
+            }
+        }
+    }
+    Ok(batch)
+}
+
+fn write_run<O: Write>(out: &mut O, previous: Option<&[TestSuiteResult]>, results: &[TestSuiteResult]) -> io::Result<()> {
+    for result in results {
+        match previous.and_then(|p| p.iter().find(|r| r.name == result.name)) {
+            Some(before) => write!(out, "{}", SuiteDiff::between(before, result))?,
+            None => writeln!(out, "{}: {}/{} passed", result.name, result.passed_tests, result.total_tests)?,
+        }
+    }
+    Ok(())
+}
+
+/// Run `run` now and again after every debounced change batch, printing
+/// each run's differences to the previous one. Returns the number of runs
+/// once `cancel` completes, e.g. on Ctrl-C; an in-flight run is dropped,
+/// so hardware it holds is released by the caller, as `watch_manifest`
+/// does.
+///
+/// Run errors are printed and watching continues, so a broken manifest
+/// can be fixed without restarting.
+pub async fn watch<W, R, Fut, E, O, C>(
+    watcher: &mut W,
+    debounce: Duration,
+    mut run: R,
+    out: &mut O,
+    cancel: C,
+) -> io::Result<usize>
+where
+    W: ChangeWatcher,
+    R: FnMut() -> Fut,
+    Fut: Future<Output = Result<Vec<TestSuiteResult>, E>>,
+    E: fmt::Display,
+    O: Write,
+    C: Future<Output = ()>,
+{
+    tokio::pin!(cancel);
+    let mut previous: Option<Vec<TestSuiteResult>> = None;
+    let mut runs = 0;
+    loop {
+        let outcome = tokio::select! {
+            _ = &mut cancel => return Ok(runs),
+            outcome = run() => outcome,
+        };
+        runs += 1;
+        match outcome {
+            Ok(results) => {
+                write_run(out, previous.as_deref(), &results)?;
+                previous = Some(results);
+            }
+            Err(e) => writeln!(out, "hwtest: {}", e)?,
+        }
+        out.flush()?;
+
+        let changed = tokio::select! {
+            _ = &mut cancel => return Ok(runs),
+            changed = next_change_batch(watcher, debounce) => changed?,
+        };
+        let names: Vec<String> = changed.iter().map(|p| p.display().to_string()).collect();
+        writeln!(out, "\n{} changed, re-running", names.join(", "))?;
+    }
+}
+
+/// Watch a manifest, re-loading and running it as `watch` does
+///
+/// With `keep_initialized` the target interfaces stay initialized from one
+/// run to the next, saving the bring-up time of each run; only safe if
+/// tests leave the device in a state the next run can start from.
+/// Otherwise they are deinitialized after every run. Either way they are
+/// deinitialized once `cancel` completes, also when it interrupts a run.
+pub async fn watch_manifest<W, O, C>(
+    watcher: &mut W,
+    path: &Path,
+    debounce: Duration,
+    keep_initialized: bool,
+    console: bool,
+    out: &mut O,
+    cancel: C,
+) -> io::Result<usize>
+where
+    W: ChangeWatcher,
+    O: Write,
+    C: Future<Output = ()>,
+{
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let interfaces = Mutex::new(TargetInterfaces::new());
+    let run = || async {
+        let manifest = SuiteManifest::load(path)?;
+        let mut interfaces = interfaces.lock().await;
+        let results = manifest.run_with(base_dir, console, &mut interfaces).await;
+        if !keep_initialized {
+            if let Err(e) = interfaces.deinitialize().await {
+                log::warn!("Failed to deinitialize a target after the run: {}", e);
+            }
+        }
+        results
+    };
+
+    let runs = watch(watcher, debounce, run, out, cancel).await;
+    // The run future is gone, so its lock is free even if it was cut short
+    if let Err(e) = interfaces.lock().await.deinitialize().await {
+        writeln!(out, "hwtest: failed to deinitialize a target: {}", e)?;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestResult, TestStatus};
+    use tokio::sync::mpsc;
+
+    /// Watcher fed from a channel, pending forever once the sender is gone
+    struct ChannelWatcher(mpsc::UnboundedReceiver<&'static str>);
+
+    #[async_trait]
+    impl ChangeWatcher for ChannelWatcher {
+        async fn changed(&mut self) -> io::Result<Vec<PathBuf>> {
+            match self.0.recv().await {
+                Some(path) => Ok(vec![PathBuf::from(path)]),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn suite(read: TestStatus) -> TestSuiteResult {
+        let results = vec![
+            TestResult::new("init", TestStatus::Passed, Duration::ZERO),
+            TestResult::new("read", read, Duration::ZERO),
+        ];
+        TestSuiteResult::from_results("eeprom", results, Duration::ZERO)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debounce_merges_rapid_changes() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher = ChannelWatcher(receiver);
+        tokio::spawn(async move {
+            for (delay, path) in [(0, "a"), (20, "b"), (20, "a"), (500, "c")] {
+                sleep(Duration::from_millis(delay)).await;
+                sender.send(path).unwrap();
+            }
+            sleep(Duration::from_secs(10)).await;
+        });
+
+        let first = next_change_batch(&mut watcher, Duration::from_millis(50)).await.unwrap();
+        let second = next_change_batch(&mut watcher, Duration::from_millis(50)).await.unwrap();
+
+        assert_eq!(first, vec![PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(second, vec![PathBuf::from("c")]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_reruns_and_prints_diff() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher = ChannelWatcher(receiver);
+        tokio::spawn(async move {
+            for (delay, path) in [(100, "golden/a.bin"), (10, "golden/b.bin"), (1000, "bench.toml")] {
+                sleep(Duration::from_millis(delay)).await;
+                sender.send(path).unwrap();
+            }
+            sleep(Duration::from_secs(10)).await;
+        });
+
+        let mut attempt = 0;
+        let run = || {
+            attempt += 1;
+            let read = match attempt {
+                2 => TestStatus::Failed("crc".to_string()),
+                _ => TestStatus::Passed,
+            };
+            async move { Ok::<_, String>(vec![suite(read)]) }
+        };
+        let mut out = Vec::new();
+        let cancel = sleep(Duration::from_secs(5));
+
+        let runs = watch(&mut watcher, Duration::from_millis(50), run, &mut out, cancel).await.unwrap();
+
+        assert_eq!(runs, 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "eeprom: 2/2 passed\n\
+             \ngolden/a.bin, golden/b.bin changed, re-running\n\
+             eeprom: 1 changed, 1 unchanged\n  read: passed -> failed (crc)\n\
+             \nbench.toml changed, re-running\n\
+             eeprom: 1 changed, 1 unchanged\n  read: failed (crc) -> passed\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_during_run() {
+        let mut watcher = ChannelWatcher(mpsc::unbounded_channel().1);
+        let run = || async {
+            sleep(Duration::from_secs(60)).await;
+            Ok::<_, String>(Vec::new())
+        };
+        let mut out = Vec::new();
+
+        let runs = watch(&mut watcher, Duration::ZERO, run, &mut out, sleep(Duration::from_secs(1))).await.unwrap();
+
+        assert_eq!(runs, 0);
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_manifest_runs_until_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bench.toml");
+        fs::write(&path, "suites = [\"lifecycle\"]\n[[target]]\nname = \"console\"\ninterface = \"uart\"").unwrap();
+        let mut watcher = ChannelWatcher(mpsc::unbounded_channel().1);
+        let mut out = Vec::new();
+
+        let runs = watch_manifest(&mut watcher, &path, Duration::ZERO, true, false, &mut out, sleep(Duration::from_secs(5)))
+            .await
+            .unwrap();
+
+        assert_eq!(runs, 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "console: 3/3 passed\n");
+    }
+
+    #[tokio::test]
+    async fn test_polling_watcher_detects_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let golden = dir.path().join("golden");
+        fs::create_dir(&golden).unwrap();
+        fs::write(golden.join("a.bin"), [1]).unwrap();
+        let mut watcher = PollingWatcher::new(vec![golden.clone()], Duration::from_millis(10));
+
+        fs::write(golden.join("b.bin"), [2]).unwrap();
+        assert_eq!(watcher.changed().await.unwrap(), vec![golden.join("b.bin")]);
+
+        fs::remove_file(golden.join("a.bin")).unwrap();
+        assert_eq!(watcher.changed().await.unwrap(), vec![golden.join("a.bin")]);
+    }
+}
@@ -0,0 +1,335 @@
+/*
+ * Fixed-Layout Wire Frames for Device Telemetry
+ * Copyright (C) 2024
+ */
+
+use crate::HardwareError;
+use std::error::Error;
+use std::fmt;
+
+/// Value with a fixed size on the wire, decodable in either byte order
+///
+/// Implemented for the integer and float primitives, arrays of wire fields
+/// and `Reserved` byte ranges. `decode_*` get exactly `SIZE` bytes.
+pub trait WireField: Sized {
+    const SIZE: usize;
+
+    fn decode_le(bytes: &[u8]) -> Self;
+    fn decode_be(bytes: &[u8]) -> Self;
+    fn encode_le(&self, out: &mut Vec<u8>);
+    fn encode_be(&self, out: &mut Vec<u8>);
+}
+
+macro_rules! wire_number {
+    ($($ty:ty),*) => {
+        $(
+            impl WireField for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn decode_le(bytes: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(bytes.try_into().expect("field slice has the field size"))
+                }
+
+                fn decode_be(bytes: &[u8]) -> Self {
+                    <$ty>::from_be_bytes(bytes.try_into().expect("field slice has the field size"))
+                }
+
+                fn encode_le(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn encode_be(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+wire_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Arrays decode element by element, each in the array's byte order
+impl<T: WireField, const N: usize> WireField for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn decode_le(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| T::decode_le(&bytes[i * T::SIZE..(i + 1) * T::SIZE]))
+    }
+
+    fn decode_be(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| T::decode_be(&bytes[i * T::SIZE..(i + 1) * T::SIZE]))
+    }
+
+    fn encode_le(&self, out: &mut Vec<u8>) {
+        self.iter().for_each(|item| item.encode_le(out));
+    }
+
+    fn encode_be(&self, out: &mut Vec<u8>) {
+        self.iter().for_each(|item| item.encode_be(out));
+    }
+}
+
+/// `N` reserved bytes, skipped when decoding and written as zeros
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reserved<const N: usize>;
+
+impl<const N: usize> WireField for Reserved<N> {
+    const SIZE: usize = N;
+
+    fn decode_le(_bytes: &[u8]) -> Self {
+        Reserved
+    }
+
+    fn decode_be(_bytes: &[u8]) -> Self {
+        Reserved
+    }
+
+    fn encode_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[0; N]);
+    }
+
+    fn encode_be(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[0; N]);
+    }
+}
+
+/// Why a frame could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// The frame ended inside `field`
+    Truncated {
+        field: &'static str,
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// `field` decoded but failed its validation
+    Invalid { field: &'static str, value: String },
+    /// The frame is longer than the layout
+    TrailingBytes { expected: usize, found: usize },
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated { field, offset, needed, available } => write!(
+                f,
+                "frame truncated in field '{}' at offset {}: needs {} bytes, {} left",
+                field, offset, needed, available
+            ),
+            WireError::Invalid { field, value } => write!(f, "invalid value {} in field '{}'", value, field),
+            WireError::TrailingBytes { expected, found } => {
+                write!(f, "frame has {} bytes, layout has {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Error for WireError {}
+
+impl From<WireError> for HardwareError {
+    fn from(error: WireError) -> Self {
+        HardwareError::CommunicationError(error.to_string())
+    }
+}
+
+/// Describe a fixed frame layout once and generate its decoder and encoder
+///
+/// ```ignore
+/// wire_struct! {
+///     /// Payload housekeeping frame
+///     pub struct Housekeeping {
+///         status: le u16,
+///         field: be [f32; 3],
+///         _spare: le Reserved<2>,
+///         flags: le u8 where |flags: &u8| flags & 0xF0 == 0,
+///     }
+/// }
+/// ```
+///
+/// Fields are laid out in order without padding, each with an explicit
+/// byte order (`le` or `be`, also for single bytes) and any `WireField`
+/// type. A field may name a validation closure after `where`. The struct
+/// gets `WIRE_SIZE`, `try_decode`, which needs exactly `WIRE_SIZE` bytes and
+/// names the field a frame was truncated in, and `encode_to`/`to_bytes`.
+#[macro_export]
+macro_rules! wire_struct {
+    (@decode le $ty:ty, $raw:ident) => { <$ty as $crate::WireField>::decode_le($raw) };
+    (@decode be $ty:ty, $raw:ident) => { <$ty as $crate::WireField>::decode_be($raw) };
+
+    (@encode le $value:expr, $out:ident) => { $crate::WireField::encode_le($value, $out) };
+    (@encode be $value:expr, $out:ident) => { $crate::WireField::encode_be($value, $out) };
+
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $( $field:ident : $endian:ident $ty:ty $(where $check:expr)? ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        $vis struct $name {
+            $( pub $field: $ty, )*
+        }
+
+        impl $name {
+            /// Size of the frame on the wire in bytes
+            pub const WIRE_SIZE: usize = 0 $( + <$ty as $crate::WireField>::SIZE )*;
+
+            pub fn try_decode(bytes: &[u8]) -> Result<Self, $crate::WireError> {
+                let mut offset = 0;
+                $(
+                    let $field = {
+                        let needed = <$ty as $crate::WireField>::SIZE;
+                        let raw = bytes.get(offset..offset + needed).ok_or($crate::WireError::Truncated {
+                            field: stringify!($field),
+                            offset,
+                            needed,
+                            available: bytes.len().saturating_sub(offset),
+                        })?;
+                        offset += needed;
+                        let value: $ty = $crate::wire_struct!(@decode $endian $ty, raw);
+                        $(
+                            if !($check)(&value) {
+                                return Err($crate::WireError::Invalid {
+                                    field: stringify!($field),
+                                    value: format!("{:?}", value),
+                                });
+                            }
+                        )?
+                        value
+                    };
+                )*
+                if offset != bytes.len() {
+                    return Err($crate::WireError::TrailingBytes {
+                        expected: Self::WIRE_SIZE,
+                        found: bytes.len(),
+                    });
+                }
+                Ok(Self { $( $field, )* })
+            }
+
+            pub fn encode_to(&self, out: &mut Vec<u8>) {
+                $( $crate::wire_struct!(@encode $endian &self.$field, out); )*
+            }
+
+            pub fn to_bytes(&self) -> Vec<u8> {
+                let mut out = Vec::with_capacity(Self::WIRE_SIZE);
+                self.encode_to(&mut out);
+                out
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_machine::XorShift;
+
+    wire_struct! {
+        /// Magnetometer telemetry: u16 LE status, 3x f32 BE field, two
+        /// reserved bytes, u8 flags with the high nibble reserved
+        pub struct MagTelemetry {
+            status: le u16,
+            field: be [f32; 3],
+            _spare: le Reserved<2>,
+            flags: le u8 where |flags: &u8| flags & 0xF0 == 0,
+        }
+    }
+
+    wire_struct! {
+        struct Counters {
+            uptime: be u32,
+            resets: le [i16; 2],
+        }
+    }
+
+    const GOLDEN: [u8; 17] = [
+        0x34, 0x12, // status
+        0x3F, 0x80, 0x00, 0x00, // 1.0
+        0xC0, 0x00, 0x00, 0x00, // -2.0
+        0x41, 0x20, 0x00, 0x00, // 10.0
+        0xAA, 0xBB, // reserved
+        0x05, // flags
+    ];
+
+    #[test]
+    fn test_layout_golden_vector() {
+        assert_eq!(MagTelemetry::WIRE_SIZE, 17);
+        assert_eq!(Counters::WIRE_SIZE, 8);
+
+        let frame = MagTelemetry::try_decode(&GOLDEN).unwrap();
+        assert_eq!(
+            frame,
+            MagTelemetry {
+                status: 0x1234,
+                field: [1.0, -2.0, 10.0],
+                _spare: Reserved,
+                flags: 0x05,
+            }
+        );
+
+        let mut expected = GOLDEN.to_vec();
+        expected[14..16].copy_from_slice(&[0, 0]);
+        assert_eq!(frame.to_bytes(), expected);
+
+        let counters = Counters::try_decode(&[0, 0, 1, 0, 0xFF, 0xFF, 0x02, 0x00]).unwrap();
+        assert_eq!((counters.uptime, counters.resets), (256, [-1, 2]));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            MagTelemetry::try_decode(&GOLDEN[..9]),
+            Err(WireError::Truncated {
+                field: "field",
+                offset: 2,
+                needed: 12,
+                available: 7,
+            })
+        );
+        assert_eq!(
+            MagTelemetry::try_decode(&GOLDEN[..16]).unwrap_err().to_string(),
+            "frame truncated in field 'flags' at offset 16: needs 1 bytes, 0 left"
+        );
+
+        let mut reserved_bits = GOLDEN;
+        reserved_bits[16] = 0x85;
+        assert_eq!(
+            MagTelemetry::try_decode(&reserved_bits),
+            Err(WireError::Invalid {
+                field: "flags",
+                value: "133".to_string(),
+            })
+        );
+
+        let mut long = GOLDEN.to_vec();
+        long.push(0);
+        assert_eq!(
+            MagTelemetry::try_decode(&long),
+            Err(WireError::TrailingBytes { expected: 17, found: 18 })
+        );
+        assert_eq!(
+            HardwareError::from(WireError::TrailingBytes { expected: 17, found: 18 }),
+            HardwareError::CommunicationError("frame has 18 bytes, layout has 17".to_string())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = XorShift::new(7);
+        for _ in 0..500 {
+            let mut bytes: Vec<u8> = (0..MagTelemetry::WIRE_SIZE).map(|_| rng.next_u64() as u8).collect();
+            bytes[14..16].copy_from_slice(&[0, 0]);
+            bytes[16] &= 0x0F;
+
+            // Compared as bytes since random floats may be NaN
+            let frame = MagTelemetry::try_decode(&bytes).unwrap();
+            assert_eq!(frame.to_bytes(), bytes);
+
+            let counters = Counters::try_decode(&bytes[..Counters::WIRE_SIZE]).unwrap();
+            assert_eq!(Counters::try_decode(&counters.to_bytes()), Ok(counters));
+        }
+    }
+}